@@ -0,0 +1,112 @@
+//! Compares how long it takes for a waiting thread to wake up once new data
+//! is available, using `WakeSignal` versus the old design of polling with a
+//! short sleep.
+//!
+//! Run with `cargo bench --bench wake_latency`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dropseed::utils::wake_signal::WakeSignal;
+
+/// The poll interval used by the audio and process threads before they
+/// were switched over to `WakeSignal`.
+#[cfg(not(target_os = "windows"))]
+static POLL_INTERVAL: Duration = Duration::from_micros(100);
+#[cfg(target_os = "windows")]
+static POLL_INTERVAL: Duration = Duration::from_micros(1200);
+
+static NUM_ITERATIONS: usize = 2_000;
+
+/// Roughly the time between two blocks at a 128 frame buffer size.
+static TIME_BETWEEN_SIGNALS: Duration = Duration::from_micros(2_900);
+
+/// Runs `NUM_ITERATIONS` handoffs and returns the wake latency of each one.
+///
+/// `notify` is called right after the timestamp is published. The waiting
+/// thread calls `token` before checking for a new timestamp, and then calls
+/// `wait` with that token if there was none.
+fn measure<N, T, W>(notify: N, token: T, wait: W) -> Vec<Duration>
+where
+    N: Fn(),
+    T: Fn() -> u32 + Send + 'static,
+    W: Fn(u32) + Send + 'static,
+{
+    let epoch = Instant::now();
+    let published_ns = Arc::new(AtomicU64::new(0));
+    let run = Arc::new(AtomicBool::new(true));
+
+    let (latencies_tx, latencies_rx) = std::sync::mpsc::channel();
+
+    let waiter = {
+        let published_ns = Arc::clone(&published_ns);
+        let run = Arc::clone(&run);
+
+        std::thread::spawn(move || {
+            let mut last_seen = 0;
+
+            while run.load(Ordering::Relaxed) {
+                let token = (token)();
+
+                let published = published_ns.load(Ordering::SeqCst);
+                if published != last_seen {
+                    let now = epoch.elapsed().as_nanos() as u64;
+                    let _ = latencies_tx.send(Duration::from_nanos(now - published));
+                    last_seen = published;
+                    continue;
+                }
+
+                (wait)(token);
+            }
+        })
+    };
+
+    for _ in 0..NUM_ITERATIONS {
+        std::thread::sleep(TIME_BETWEEN_SIGNALS);
+
+        published_ns.store(epoch.elapsed().as_nanos() as u64, Ordering::SeqCst);
+        (notify)();
+    }
+
+    std::thread::sleep(TIME_BETWEEN_SIGNALS);
+    run.store(false, Ordering::Relaxed);
+    (notify)();
+    waiter.join().unwrap();
+
+    latencies_rx.try_iter().collect()
+}
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    latencies.sort();
+
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+
+    println!(
+        "{:<12} samples: {:>5} | mean: {:>10?} | median: {:>10?} | p99: {:>10?} | max: {:>10?}",
+        name,
+        latencies.len(),
+        mean,
+        percentile(0.5),
+        percentile(0.99),
+        latencies[latencies.len() - 1],
+    );
+}
+
+fn main() {
+    let polling = measure(|| {}, || 0, |_| std::thread::sleep(POLL_INTERVAL));
+    report("polling", polling);
+
+    let signal = Arc::new(WakeSignal::new());
+    let signal_token = Arc::clone(&signal);
+    let signal_wait = Arc::clone(&signal);
+    let wake_signal = measure(
+        move || signal.notify(),
+        move || signal_token.token(),
+        move |token| {
+            signal_wait.wait(token, Some(Instant::now() + Duration::from_millis(10)));
+        },
+    );
+    report("wake signal", wake_signal);
+}
@@ -426,6 +426,7 @@ impl EffectRackState {
                     src_port_channel: 0,
                     dst_port_id: EdgeReqPortID::Main,
                     dst_port_channel: 0,
+                    feedback: None,
                     log_error_on_fail: true,
                     check_for_cycles: true,
                 },
@@ -437,6 +438,7 @@ impl EffectRackState {
                     src_port_channel: 1,
                     dst_port_id: EdgeReqPortID::Main,
                     dst_port_channel: 1,
+                    feedback: None,
                     log_error_on_fail: true,
                     check_for_cycles: true,
                 },
@@ -448,6 +450,7 @@ impl EffectRackState {
                     src_port_channel: 0,
                     dst_port_id: EdgeReqPortID::Main,
                     dst_port_channel: 0,
+                    feedback: None,
                     log_error_on_fail: true,
                     check_for_cycles: true,
                 },
@@ -459,6 +462,7 @@ impl EffectRackState {
                     src_port_channel: 1,
                     dst_port_id: EdgeReqPortID::Main,
                     dst_port_channel: 1,
+                    feedback: None,
                     log_error_on_fail: true,
                     check_for_cycles: true,
                 },
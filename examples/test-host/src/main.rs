@@ -6,6 +6,7 @@ use dropseed::engine::{
     ActivateEngineSettings, ActivatedEngineInfo, DSEngineAudioThread, DSEngineMainThread,
     EngineDeactivatedStatus, EngineSettings, OnIdleEvent,
 };
+use dropseed::graph::export::ExportFormat;
 use dropseed::plugin_api::ext::gui::GuiSize;
 use dropseed::plugin_api::HostInfo;
 use dropseed::plugin_scanner::ScannedPluginInfo;
@@ -221,6 +222,10 @@ impl DSTestHostGUI {
                                 log::info!("Deactivated dropseed engine gracefully");
                             }
                         }
+
+                        if ui.button("dump graph").clicked() {
+                            dump_graph(&self.ds_engine);
+                        }
                     } else {
                         ui.colored_label(egui::Color32::RED, "inactive");
                         ui.label("engine status:");
@@ -422,6 +427,32 @@ impl DSTestHostGUI {
                     }
                 }
 
+                // Sent whenever the watchdog has disabled a plugin because it kept
+                // taking too long to process.
+                OnIdleEvent::PluginDisabledByWatchdog { plugin_id, action, peak_load } => {
+                    log::warn!(
+                        "Plugin {:?} was disabled by the watchdog ({:?}), peak load: {:.2}",
+                        &plugin_id,
+                        action,
+                        peak_load
+                    );
+                }
+
+                // Sent whenever the audio graph has finished compiling in the
+                // compiler thread.
+                OnIdleEvent::AudioGraphCompiled => {
+                    log::trace!("Audio graph compiled successfully");
+                }
+
+                // Sent whenever the audio graph failed to compile and was rolled
+                // back to the last state that compiled successfully.
+                OnIdleEvent::AudioGraphRecovered(status) => {
+                    log::error!(
+                        "Audio graph was rolled back after failing to compile: {}",
+                        status.error
+                    );
+                }
+
                 // Sent whenever the engine has been deactivated, whether gracefully or
                 // because of a crash.
                 OnIdleEvent::EngineDeactivated(status) => {
@@ -501,6 +532,26 @@ enum Tab {
     ScannedPlugins,
 }
 
+/// Write the audio graph and the compiled schedule to DOT and JSON files in
+/// the current directory.
+fn dump_graph(ds_engine: &DSEngineMainThread) {
+    let dumps = [
+        ("graph.dot", ds_engine.export_graph(ExportFormat::Dot)),
+        ("graph.json", ds_engine.export_graph(ExportFormat::Json)),
+        ("schedule.dot", ds_engine.export_schedule(ExportFormat::Dot)),
+        ("schedule.json", ds_engine.export_schedule(ExportFormat::Json)),
+    ];
+
+    for (path, contents) in dumps {
+        if let Some(contents) = contents {
+            match std::fs::write(path, contents) {
+                Ok(()) => log::info!("Dumped {}", path),
+                Err(e) => log::error!("Failed to write {}: {}", path, e),
+            }
+        }
+    }
+}
+
 fn activate_engine(
     ds_engine: &mut DSEngineMainThread,
     sample_rate: SampleRate,
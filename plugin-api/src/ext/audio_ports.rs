@@ -10,6 +10,7 @@ pub(crate) static EMPTY_AUDIO_PORTS_CONFIG: PluginAudioPortsExt = PluginAudioPor
 /// By default this returns a configuration with a main stereo
 /// input port and a main stereo output port.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PluginAudioPortsExt {
     /// The list of input audio ports, in order.
     pub inputs: Vec<AudioPortInfo>,
@@ -326,6 +327,7 @@ impl PluginAudioPortsExt {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Information about a custom audio port.
 pub struct AudioPortInfo {
     /// Stable identifier, it must never change.
@@ -355,6 +357,7 @@ pub struct AudioPortInfo {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Specifies which audio ports are "main" ports.
 pub enum MainPortsLayout {
     /// Both the first input port and the first output port are main ports.
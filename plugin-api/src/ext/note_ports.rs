@@ -7,6 +7,7 @@ use clack_extensions::note_ports::{NoteDialect, NoteDialects};
 pub(crate) static EMPTY_NOTE_PORTS_CONFIG: PluginNotePortsExt = PluginNotePortsExt::empty();
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// The layout of the audio ports of a plugin.
 pub struct PluginNotePortsExt {
     /// The list of input note ports.
@@ -23,14 +24,17 @@ impl PluginNotePortsExt {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NotePortInfo {
     /// stable identifier
     pub stable_id: u32,
 
     /// bitfield, see `NoteDialect`
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::note_dialects"))]
     pub supported_dialects: NoteDialects,
 
     /// one value of `NoteDialect`
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::note_dialect_opt"))]
     pub preferred_dialect: Option<NoteDialect>,
 
     /// displayable name
@@ -13,6 +13,11 @@ mod main_thread;
 mod process_info;
 mod processor;
 mod save_state;
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub mod serde_impls;
+#[cfg(feature = "serde")]
+mod versioned;
 
 pub use buffer::{AudioPortBuffer, AudioPortBufferMut};
 pub use descriptor::PluginDescriptor;
@@ -25,6 +30,8 @@ pub use main_thread::{PluginActivatedInfo, PluginMainThread};
 pub use process_info::{ProcBuffers, ProcInfo, ProcessStatus};
 pub use processor::PluginProcessor;
 pub use save_state::DSPluginSaveState;
+#[cfg(feature = "serde")]
+pub use versioned::{SaveStateMigration, VersionedSaveState, SAVE_STATE_SCHEMA_VERSION};
 
 pub use clack_host::events::event_types as event;
 pub use clack_host::utils::FixedPoint;
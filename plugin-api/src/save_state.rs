@@ -7,6 +7,7 @@ use super::ext::note_ports::PluginNotePortsExt;
 use crate::plugin_scanner::ScannedPluginKey;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DSPluginSaveState {
     pub key: ScannedPluginKey,
 
@@ -33,6 +34,7 @@ pub struct DSPluginSaveState {
     pub backup_note_ports_ext: Option<PluginNotePortsExt>,
 
     /// The latest recorded size of the plugin's GUI.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::gui_size_opt"))]
     pub gui_size: Option<GuiSize>,
 
     /// The plugin's state/preset as raw bytes.
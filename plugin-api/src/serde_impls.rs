@@ -0,0 +1,83 @@
+//! Serialization for the types from other crates that are used in save
+//! states.
+
+pub(crate) mod gui_size_opt {
+    use clack_extensions::gui::GuiSize;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<GuiSize>, s: S) -> Result<S::Ok, S::Error> {
+        value.map(|size| (size.width, size.height)).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<GuiSize>, D::Error> {
+        let size: Option<(u32, u32)> = Deserialize::deserialize(d)?;
+        Ok(size.map(|(width, height)| GuiSize { width, height }))
+    }
+}
+
+pub mod musical_time {
+    use meadowlark_core_types::time::MusicalTime;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &MusicalTime, s: S) -> Result<S::Ok, S::Error> {
+        value.as_beats_f64().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<MusicalTime, D::Error> {
+        let beats: f64 = Deserialize::deserialize(d)?;
+        Ok(MusicalTime::from_beats_f64(beats))
+    }
+}
+
+pub(crate) mod sample_rate {
+    use meadowlark_core_types::time::SampleRate;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &SampleRate, s: S) -> Result<S::Ok, S::Error> {
+        value.as_f64().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<SampleRate, D::Error> {
+        let sample_rate: f64 = Deserialize::deserialize(d)?;
+        Ok(SampleRate(sample_rate))
+    }
+}
+
+pub(crate) mod note_dialects {
+    use clack_extensions::note_ports::NoteDialects;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &NoteDialects, s: S) -> Result<S::Ok, S::Error> {
+        value.bits().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NoteDialects, D::Error> {
+        let bits: u32 = Deserialize::deserialize(d)?;
+        Ok(NoteDialects::from_bits_truncate(bits))
+    }
+}
+
+pub(crate) mod note_dialect_opt {
+    use clack_extensions::note_ports::NoteDialect;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    static DIALECTS: [NoteDialect; 4] =
+        [NoteDialect::Clap, NoteDialect::Midi, NoteDialect::MidiMpe, NoteDialect::Midi2];
+
+    pub fn serialize<S: Serializer>(value: &Option<NoteDialect>, s: S) -> Result<S::Ok, S::Error> {
+        value.map(|dialect| dialect as u32).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<NoteDialect>, D::Error> {
+        let raw: Option<u32> = Deserialize::deserialize(d)?;
+
+        raw.map(|raw| {
+            DIALECTS
+                .iter()
+                .copied()
+                .find(|dialect| *dialect as u32 == raw)
+                .ok_or_else(|| D::Error::custom(format!("unknown note dialect {}", raw)))
+        })
+        .transpose()
+    }
+}
@@ -177,14 +177,17 @@ impl RangeChecker {
 
 /// The status of looping on this transport.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LoopState {
     /// The transport is not currently looping.
     Inactive,
     /// The transport is currently looping.
     Active {
         /// The start of the loop (inclusive).
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::musical_time"))]
         loop_start: MusicalTime,
         /// The end of the loop (exclusive).
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::musical_time"))]
         loop_end: MusicalTime,
     },
 }
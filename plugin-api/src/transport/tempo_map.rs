@@ -22,7 +22,9 @@ use meadowlark_core_types::time::{Frames, MusicalTime, SampleRate, Seconds};
 /// should be seeked to the realtime thread. The realtime thread then uses it, the `TempoMap`, and the `SampleRate` to find
 /// the nearest (floored) frame to set as the new playhead.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TempoMap {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::sample_rate"))]
     pub sample_rate: SampleRate,
 
     /// Temporary static tempo
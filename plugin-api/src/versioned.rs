@@ -0,0 +1,291 @@
+use serde::de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::transport::{LoopState, TempoMap};
+use crate::DSPluginSaveState;
+
+/// The version of the schema of the save states written by this version of
+/// dropseed.
+///
+/// This is increased every time the serialized form of one of the save
+/// state types changes.
+pub const SAVE_STATE_SCHEMA_VERSION: u32 = 1;
+
+/// A save state along with the version of the schema it was saved with.
+///
+/// Store your save states in this container so that they can still be
+/// loaded after the schema has changed. When deserializing a state that was
+/// saved with an older schema, `SaveStateMigration::migrate()` is called
+/// instead of the regular `Deserialize` implementation.
+///
+/// Note that `schema_version` is always serialized before `state`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionedSaveState<T> {
+    /// The version of the schema `state` was saved with.
+    pub schema_version: u32,
+
+    pub state: T,
+}
+
+impl<T> VersionedSaveState<T> {
+    /// Wrap the given state with the current schema version.
+    pub fn new(state: T) -> Self {
+        Self { schema_version: SAVE_STATE_SCHEMA_VERSION, state }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.state
+    }
+}
+
+/// The hook used to load a state that was saved with an older schema.
+pub trait SaveStateMigration: DeserializeOwned {
+    /// Deserialize a state that was saved with the given (older) schema
+    /// version, and migrate it to the current schema.
+    ///
+    /// By default this returns an error.
+    fn migrate<'de, D: Deserializer<'de>>(
+        schema_version: u32,
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let _ = deserializer;
+
+        Err(de::Error::custom(format!(
+            "cannot migrate save state from schema version {} to version {}",
+            schema_version, SAVE_STATE_SCHEMA_VERSION
+        )))
+    }
+}
+
+impl SaveStateMigration for DSPluginSaveState {}
+impl SaveStateMigration for TempoMap {}
+impl SaveStateMigration for LoopState {}
+
+impl<T: SaveStateMigration> SaveStateMigration for Vec<T> {
+    fn migrate<'de, D: Deserializer<'de>>(
+        schema_version: u32,
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        struct VecVisitor<T>(u32, PhantomData<T>);
+
+        impl<'de, T: SaveStateMigration> Visitor<'de> for VecVisitor<T> {
+            type Value = Vec<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a sequence")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(state) = seq.next_element_seed(StateSeed::<T>::new(self.0))? {
+                    v.push(state);
+                }
+                Ok(v)
+            }
+        }
+
+        deserializer.deserialize_seq(VecVisitor(schema_version, PhantomData))
+    }
+}
+
+/// Deserializes a state with the given schema version.
+struct StateSeed<T> {
+    schema_version: u32,
+    _t: PhantomData<T>,
+}
+
+impl<T> StateSeed<T> {
+    fn new(schema_version: u32) -> Self {
+        Self { schema_version, _t: PhantomData }
+    }
+}
+
+impl<'de, T: SaveStateMigration> DeserializeSeed<'de> for StateSeed<T> {
+    type Value = T;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<T, D::Error> {
+        if self.schema_version == SAVE_STATE_SCHEMA_VERSION {
+            T::deserialize(deserializer)
+        } else {
+            T::migrate(self.schema_version, deserializer)
+        }
+    }
+}
+
+fn check_schema_version<E: de::Error>(schema_version: u32) -> Result<(), E> {
+    if schema_version > SAVE_STATE_SCHEMA_VERSION {
+        Err(E::custom(format!(
+            "save state schema version {} is newer than the supported version {}",
+            schema_version, SAVE_STATE_SCHEMA_VERSION
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum Field {
+    SchemaVersion,
+    State,
+}
+
+struct VersionedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: SaveStateMigration> Visitor<'de> for VersionedVisitor<T> {
+    type Value = VersionedSaveState<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("struct VersionedSaveState")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let schema_version: u32 =
+            seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        check_schema_version(schema_version)?;
+
+        let state = seq
+            .next_element_seed(StateSeed::new(schema_version))?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        Ok(VersionedSaveState { schema_version: SAVE_STATE_SCHEMA_VERSION, state })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut schema_version: Option<u32> = None;
+        let mut state: Option<T> = None;
+
+        while let Some(key) = map.next_key()? {
+            match key {
+                Field::SchemaVersion => {
+                    if schema_version.is_some() {
+                        return Err(de::Error::duplicate_field("schema_version"));
+                    }
+
+                    let v: u32 = map.next_value()?;
+                    check_schema_version(v)?;
+                    schema_version = Some(v);
+                }
+                Field::State => {
+                    if state.is_some() {
+                        return Err(de::Error::duplicate_field("state"));
+                    }
+
+                    // The schema version is needed to know how to read the state.
+                    let v =
+                        schema_version.ok_or_else(|| de::Error::missing_field("schema_version"))?;
+                    state = Some(map.next_value_seed(StateSeed::new(v))?);
+                }
+            }
+        }
+
+        let state = state.ok_or_else(|| de::Error::missing_field("state"))?;
+
+        Ok(VersionedSaveState { schema_version: SAVE_STATE_SCHEMA_VERSION, state })
+    }
+}
+
+impl<'de, T: SaveStateMigration> Deserialize<'de> for VersionedSaveState<T> {
+    // Deserializing a state saved with an older schema migrates it to the
+    // current schema, so `schema_version` is always the current version
+    // afterwards.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        const FIELDS: &[&str] = &["schema_version", "state"];
+
+        deserializer.deserialize_struct("VersionedSaveState", FIELDS, VersionedVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct State {
+        name: String,
+        gain: f32,
+    }
+
+    impl SaveStateMigration for State {
+        fn migrate<'de, D: Deserializer<'de>>(
+            schema_version: u32,
+            deserializer: D,
+        ) -> Result<Self, D::Error> {
+            // Pretend that the schema before the current one only stored the name.
+            if schema_version + 1 != SAVE_STATE_SCHEMA_VERSION {
+                return Err(de::Error::custom("unknown schema version"));
+            }
+
+            let name = String::deserialize(deserializer)?;
+            Ok(Self { name, gain: 1.0 })
+        }
+    }
+
+    #[derive(Debug, Serialize)]
+    struct OldState {
+        schema_version: u32,
+        state: String,
+    }
+
+    fn state() -> State {
+        State { name: String::from("synth"), gain: 0.5 }
+    }
+
+    #[test]
+    fn round_trip() {
+        let saved = VersionedSaveState::new(vec![state(), state()]);
+
+        let json = serde_json::to_string(&saved).unwrap();
+        let loaded: VersionedSaveState<Vec<State>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.schema_version, SAVE_STATE_SCHEMA_VERSION);
+        assert_eq!(loaded.state, saved.state);
+
+        let bytes = bincode::serialize(&saved).unwrap();
+        let loaded: VersionedSaveState<Vec<State>> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.schema_version, SAVE_STATE_SCHEMA_VERSION);
+        assert_eq!(loaded.state, saved.state);
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let saved =
+            VersionedSaveState { schema_version: SAVE_STATE_SCHEMA_VERSION + 1, state: state() };
+
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(serde_json::from_str::<VersionedSaveState<State>>(&json).is_err());
+
+        let bytes = bincode::serialize(&saved).unwrap();
+        assert!(bincode::deserialize::<VersionedSaveState<State>>(&bytes).is_err());
+    }
+
+    #[test]
+    fn older_version_is_migrated() {
+        let saved = OldState {
+            schema_version: SAVE_STATE_SCHEMA_VERSION - 1,
+            state: String::from("synth"),
+        };
+        let expected = State { name: String::from("synth"), gain: 1.0 };
+
+        let json = serde_json::to_string(&saved).unwrap();
+        let loaded: VersionedSaveState<State> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.schema_version, SAVE_STATE_SCHEMA_VERSION);
+        assert_eq!(loaded.state, expected);
+
+        let bytes = bincode::serialize(&saved).unwrap();
+        let loaded: VersionedSaveState<State> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.state, expected);
+    }
+
+    #[test]
+    fn migration_fails_by_default() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct NoMigration(u32);
+
+        impl SaveStateMigration for NoMigration {}
+
+        let json = format!(r#"{{"schema_version":{},"state":3}}"#, SAVE_STATE_SCHEMA_VERSION - 1);
+        assert!(serde_json::from_str::<VersionedSaveState<NoMigration>>(&json).is_err());
+    }
+}
@@ -2,44 +2,74 @@ use basedrop::Owned;
 use meadowlark_core_types::time::SampleRate;
 use rtrb::{Consumer, Producer, RingBuffer};
 use std::fmt::Debug;
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc,
-};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use super::main_thread::GraphPortInfo;
 use super::process_thread::DSEngineProcessThread;
+use super::telemetry::SharedEngineTelemetry;
 use crate::graph::shared_pools::SharedProcessorSchedule;
+use crate::processor_schedule::{GraphAutomationEvent, GraphNoteEvent};
+use crate::utils::wake_signal::WakeSignal;
 
 /// Allocate enough for at-least 3 seconds of buffer time at the
 /// highest possible sample rate.
 static ALLOCATED_FRAMES_PER_CHANNEL: usize = 192_000 * 3;
 
+/// The maximum number of process cycles that can be queued up between the
+/// audio thread and the process thread.
+static ALLOCATED_CYCLES: usize = 1024;
+
+/// The maximum number of note events that can be sent to or received from
+/// the process thread in a single process cycle.
+pub(super) static ALLOCATED_NOTE_EVENTS: usize = 1024;
+
+/// The maximum number of automation events that can be sent to the process
+/// thread in a single process cycle.
+pub(super) static ALLOCATED_AUTOMATION_EVENTS: usize = 1024;
+
 /// Make sure we have a bit of time to copy the engine's output buffer to the
 /// audio thread's output buffer.
 static COPY_OUT_TIME_WINDOW: f64 = 0.95;
 
-#[cfg(not(target_os = "windows"))]
-pub(crate) static AUDIO_THREAD_POLL_INTERVAL: Duration = Duration::from_micros(100);
-#[cfg(not(target_os = "windows"))]
-// Handle worst-case scenario for thread sleep.
-pub(crate) static AUDIO_THREAD_POLL_INTERVAL_BUFFERED: Duration = Duration::from_micros(140);
-#[cfg(target_os = "windows")]
-// The best we can do on Windows is around 1ms.
-pub(crate) static AUDIO_THREAD_POLL_INTERVAL: Duration = Duration::from_micros(1200);
-#[cfg(target_os = "windows")]
-// Handle worst-case scenario for Windows thread sleep.
-pub(crate) static AUDIO_THREAD_POLL_INTERVAL_BUFFERED: Duration = Duration::from_micros(1500);
-
 pub struct DSEngineAudioThread {
     audio_to_process_channel: Owned<AudioToProcessChannelTX>,
     process_to_audio_channel: Owned<ProcessToAudioChannelRX>,
+    note_in_rb_tx: Owned<Producer<GraphNoteEvent>>,
+    automation_in_rb_tx: Owned<Producer<GraphAutomationEvent>>,
 
     graph_audio_in_channels: usize,
     graph_audio_out_channels: usize,
+    graph_note_in_ports: usize,
+    graph_note_out_ports: usize,
+    graph_automation_in_port: bool,
+
+    graph_in_ports: Vec<GraphPortInfo>,
+    graph_out_ports: Vec<GraphPortInfo>,
+
+    /// The note events generated on the graph output in the last process
+    /// cycle.
+    note_out_events: Owned<Vec<GraphNoteEvent>>,
 
     sample_rate: SampleRate,
     sample_rate_recip: f64,
+
+    duplex_input_rx: Option<Consumer<f32>>,
+    event_input_rx: Option<EventInputRX>,
+
+    next_cycle_id: u64,
+    /// The number of events pushed to the process thread since the last
+    /// cycle was sent.
+    num_note_events_in: usize,
+    num_automation_events_in: usize,
+
+    /// Used to wake up the process thread when new input is available.
+    to_process_signal: Arc<WakeSignal>,
+    /// Used by the process thread to wake up this thread when new output
+    /// is available.
+    to_audio_signal: Arc<WakeSignal>,
+
+    telemetry: Arc<SharedEngineTelemetry>,
 }
 
 impl Debug for DSEngineAudioThread {
@@ -48,6 +78,9 @@ impl Debug for DSEngineAudioThread {
 
         f.field("graph_audio_in_channels", &self.graph_audio_in_channels);
         f.field("graph_audio_out_channels", &self.graph_audio_out_channels);
+        f.field("graph_note_in_ports", &self.graph_note_in_ports);
+        f.field("graph_note_out_ports", &self.graph_note_out_ports);
+        f.field("graph_automation_in_port", &self.graph_automation_in_port);
         f.field("sample_rate", &self.sample_rate);
         f.field("sample_rate_recip", &self.sample_rate_recip);
 
@@ -61,210 +94,943 @@ impl DSEngineAudioThread {
         sample_rate: SampleRate,
         graph_audio_in_channels: usize,
         graph_audio_out_channels: usize,
-        max_frames: usize,
+        graph_note_in_ports: usize,
+        graph_note_out_ports: usize,
+        graph_automation_in_port: bool,
+        graph_in_ports: Vec<GraphPortInfo>,
+        graph_out_ports: Vec<GraphPortInfo>,
+        telemetry: Arc<SharedEngineTelemetry>,
         coll_handle: &basedrop::Handle,
     ) -> (Self, DSEngineProcessThread) {
         let sample_rate_recip = 1.0 / sample_rate.as_f64();
 
-        let (audio_to_process_tx, audio_to_process_rx) = if graph_audio_in_channels == 0 {
-            let num_frames_wanted = Arc::new(AtomicUsize::new(0));
+        let (audio_to_process_tx, audio_to_process_rx) = {
+            let (cycle_rb_tx, cycle_rb_rx) = RingBuffer::new(ALLOCATED_CYCLES);
 
-            (
-                AudioToProcessChannelTX::NoInputAudio {
-                    num_frames_wanted: Arc::clone(&num_frames_wanted),
-                },
-                AudioToProcessChannelRX::NoInputAudio { num_frames_wanted },
-            )
-        } else {
-            let (audio_rb_tx, audio_rb_rx) =
-                RingBuffer::new(graph_audio_in_channels * ALLOCATED_FRAMES_PER_CHANNEL);
+            let (audio_rb_tx, audio_rb_rx) = if graph_audio_in_channels == 0 {
+                (None, None)
+            } else {
+                let (audio_rb_tx, audio_rb_rx) =
+                    RingBuffer::new(graph_audio_in_channels * ALLOCATED_FRAMES_PER_CHANNEL);
+
+                (Some(audio_rb_tx), Some(audio_rb_rx))
+            };
 
             (
-                AudioToProcessChannelTX::HasInputAudio { audio_rb_tx },
-                AudioToProcessChannelRX::HasInputAudio { audio_rb_rx },
+                AudioToProcessChannelTX { cycle_rb_tx, audio_rb_tx },
+                AudioToProcessChannelRX { cycle_rb_rx, audio_rb_rx },
             )
         };
 
         let (process_to_audio_tx, process_to_audio_rx) = {
+            let (cycle_rb_tx, cycle_rb_rx) = RingBuffer::new(ALLOCATED_CYCLES);
+
             let (audio_rb_tx, audio_rb_rx) =
                 RingBuffer::new(graph_audio_out_channels * ALLOCATED_FRAMES_PER_CHANNEL);
 
-            (ProcessToAudioChannelTX { audio_rb_tx }, ProcessToAudioChannelRX { audio_rb_rx })
+            let (note_rb_tx, note_rb_rx) = RingBuffer::new(ALLOCATED_NOTE_EVENTS);
+
+            (
+                ProcessToAudioChannelTX { cycle_rb_tx, audio_rb_tx, note_rb_tx },
+                ProcessToAudioChannelRX { cycle_rb_rx, audio_rb_rx, note_rb_rx },
+            )
         };
 
+        let (note_in_rb_tx, note_in_rb_rx) = RingBuffer::new(ALLOCATED_NOTE_EVENTS);
+        let (automation_in_rb_tx, automation_in_rb_rx) =
+            RingBuffer::new(ALLOCATED_AUTOMATION_EVENTS);
+
+        let to_process_signal = Arc::new(WakeSignal::new());
+        let to_audio_signal = Arc::new(WakeSignal::new());
+
         (
             Self {
                 audio_to_process_channel: Owned::new(coll_handle, audio_to_process_tx),
                 process_to_audio_channel: Owned::new(coll_handle, process_to_audio_rx),
+                note_in_rb_tx: Owned::new(coll_handle, note_in_rb_tx),
+                automation_in_rb_tx: Owned::new(coll_handle, automation_in_rb_tx),
                 graph_audio_in_channels,
                 graph_audio_out_channels,
+                graph_note_in_ports,
+                graph_note_out_ports,
+                graph_automation_in_port,
+                graph_in_ports,
+                graph_out_ports,
+                note_out_events: Owned::new(
+                    coll_handle,
+                    Vec::with_capacity(if graph_note_out_ports > 0 {
+                        ALLOCATED_NOTE_EVENTS
+                    } else {
+                        0
+                    }),
+                ),
                 sample_rate,
                 sample_rate_recip,
+                duplex_input_rx: None,
+                event_input_rx: None,
+                next_cycle_id: 0,
+                num_note_events_in: 0,
+                num_automation_events_in: 0,
+                to_process_signal: Arc::clone(&to_process_signal),
+                to_audio_signal: Arc::clone(&to_audio_signal),
+                telemetry,
             },
             DSEngineProcessThread::new(
                 audio_to_process_rx,
                 process_to_audio_tx,
+                note_in_rb_rx,
+                automation_in_rb_rx,
+                to_process_signal,
+                to_audio_signal,
                 graph_audio_in_channels,
                 graph_audio_out_channels,
-                max_frames,
                 schedule,
                 coll_handle,
             ),
         )
     }
 
+    /// The sample rate the engine was activated with.
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// The total number of input audio channels to the audio graph.
+    pub fn num_audio_in_channels(&self) -> usize {
+        self.graph_audio_in_channels
+    }
+
+    /// The total number of output audio channels from the audio graph.
+    pub fn num_audio_out_channels(&self) -> usize {
+        self.graph_audio_out_channels
+    }
+
+    /// The number of note ports on the input to the audio graph.
+    pub fn num_note_in_ports(&self) -> usize {
+        self.graph_note_in_ports
+    }
+
+    /// The number of note ports on the output of the audio graph.
+    pub fn num_note_out_ports(&self) -> usize {
+        self.graph_note_out_ports
+    }
+
+    /// Whether the input to the audio graph has an automation port.
+    pub fn has_automation_in_port(&self) -> bool {
+        self.graph_automation_in_port
+    }
+
+    /// The audio ports on the input to the audio graph, in the order of
+    /// their channels.
+    pub fn graph_in_ports(&self) -> &[GraphPortInfo] {
+        &self.graph_in_ports
+    }
+
+    /// The audio ports on the output of the audio graph, in the order of
+    /// their channels.
+    pub fn graph_out_ports(&self) -> &[GraphPortInfo] {
+        &self.graph_out_ports
+    }
+
+    /// Send a note event to a note port on the input to the audio graph.
+    ///
+    /// The event is sent in the next process cycle, and `event.frame` is
+    /// relative to the start of the buffer of that cycle. Any events past
+    /// the end of the buffer are discarded.
+    ///
+    /// This returns `false` if the note port does not exist or if too
+    /// many events have been pushed in this cycle.
+    pub fn push_note_event(&mut self, event: GraphNoteEvent) -> bool {
+        if usize::from(event.port) >= self.graph_note_in_ports {
+            return false;
+        }
+
+        if self.note_in_rb_tx.push(event).is_err() {
+            return false;
+        }
+        self.num_note_events_in += 1;
+
+        true
+    }
+
+    /// Send an automation event to the automation port on the input to the
+    /// audio graph.
+    ///
+    /// The event is sent in the next process cycle, and `event.frame` is
+    /// relative to the start of the buffer of that cycle. Any events past
+    /// the end of the buffer are discarded.
+    ///
+    /// This returns `false` if the audio graph has no automation input
+    /// port or if too many events have been pushed in this cycle.
+    pub fn push_automation_event(&mut self, event: GraphAutomationEvent) -> bool {
+        if !self.graph_automation_in_port {
+            return false;
+        }
+
+        if self.automation_in_rb_tx.push(event).is_err() {
+            return false;
+        }
+        self.num_automation_events_in += 1;
+
+        true
+    }
+
+    /// The note events generated on the note ports of the output of the
+    /// audio graph in the last process cycle, with `frame` relative to the
+    /// start of the buffer of that cycle.
+    ///
+    /// This is empty if the engine failed to render the output in time.
+    ///
+    /// The events of each note port are sorted by frame. Use
+    /// `GraphNoteEvent::to_midi()` to send them to a MIDI device.
+    pub fn note_out_events(&self) -> &[GraphNoteEvent] {
+        &self.note_out_events
+    }
+
+    /// Create the input half of this audio thread, for use when the input
+    /// and output callbacks of the audio device arrive on different
+    /// threads.
+    ///
+    /// Once created, `Self::process_cpal_interleaved_output()` will use the
+    /// audio pushed into the returned handle as the input to the audio
+    /// graph.
+    ///
+    /// This will return `None` if the audio graph has no input channels
+    /// or if the input half has already been created.
+    pub fn create_audio_input(&mut self) -> Option<DSEngineAudioInput> {
+        if self.graph_audio_in_channels == 0 || self.duplex_input_rx.is_some() {
+            return None;
+        }
+
+        let (input_rb_tx, input_rb_rx) =
+            RingBuffer::new(self.graph_audio_in_channels * ALLOCATED_FRAMES_PER_CHANNEL);
+
+        self.duplex_input_rx = Some(input_rb_rx);
+
+        Some(DSEngineAudioInput {
+            input_rb_tx,
+            graph_audio_in_channels: self.graph_audio_in_channels,
+        })
+    }
+
+    /// Create a handle for sending note and automation events to the input
+    /// of the audio graph from another thread (i.e. a GUI or a MIDI input
+    /// thread).
+    ///
+    /// The events pushed into the returned handle are sent in the next
+    /// process cycle of this audio thread, along with the events pushed
+    /// with `Self::push_note_event()` and `Self::push_automation_event()`.
+    ///
+    /// This will return `None` if the audio graph has no note input ports
+    /// and no automation input port, or if the handle has already been
+    /// created.
+    pub fn create_event_input(&mut self) -> Option<DSEngineEventInput> {
+        if (self.graph_note_in_ports == 0 && !self.graph_automation_in_port)
+            || self.event_input_rx.is_some()
+        {
+            return None;
+        }
+
+        let (note_tx, note_rx) = RingBuffer::new(ALLOCATED_NOTE_EVENTS);
+        let (automation_tx, automation_rx) = RingBuffer::new(ALLOCATED_AUTOMATION_EVENTS);
+
+        self.event_input_rx = Some(EventInputRX { note_rx, automation_rx });
+
+        Some(DSEngineEventInput {
+            note_tx,
+            automation_tx,
+            graph_note_in_ports: self.graph_note_in_ports,
+            graph_automation_in_port: self.graph_automation_in_port,
+        })
+    }
+
     #[cfg(feature = "cpal-backend")]
     pub fn process_cpal_interleaved_output_only<T: cpal::Sample>(
         &mut self,
         cpal_out_channels: usize,
         out: &mut [T],
     ) {
-        let clear_output = |out: &mut [T]| {
-            for s in out.iter_mut() {
-                *s = T::from(&0.0);
+        let _ = self.process_interleaved_output_only(cpal_out_channels, out, |s| T::from(&s));
+    }
+
+    #[cfg(feature = "cpal-backend")]
+    /// Process the audio graph with input and output audio from the same
+    /// cpal callback.
+    ///
+    /// If `cpal_in_channels` is `1`, then that channel is copied to every
+    /// input channel of the audio graph. Otherwise any extra channels are
+    /// ignored and any missing channels are filled with silence. If the
+    /// input buffer is shorter than the output buffer, then the rest of
+    /// the input is filled with silence.
+    pub fn process_cpal_interleaved_duplex<TIn: cpal::Sample, TOut: cpal::Sample>(
+        &mut self,
+        cpal_in_channels: usize,
+        input: &[TIn],
+        cpal_out_channels: usize,
+        out: &mut [TOut],
+    ) {
+        if cpal_in_channels == 0 {
+            let _ =
+                self.process_interleaved_output_only(cpal_out_channels, out, |s| TOut::from(&s));
+            return;
+        }
+
+        let in_frames = input.len() / cpal_in_channels;
+
+        let _ = self.process_interleaved_inner(
+            |frame, ch| {
+                if frame >= in_frames {
+                    0.0
+                } else if cpal_in_channels == 1 {
+                    input[frame].to_f32()
+                } else if ch < cpal_in_channels {
+                    input[(frame * cpal_in_channels) + ch].to_f32()
+                } else {
+                    0.0
+                }
+            },
+            cpal_out_channels,
+            out,
+            |s| TOut::from(&s),
+        );
+    }
+
+    #[cfg(feature = "cpal-backend")]
+    /// Process the audio graph with the audio pushed into the handle from
+    /// `Self::create_audio_input()` as the input.
+    ///
+    /// If no input handle was created, then the input is filled with
+    /// silence.
+    pub fn process_cpal_interleaved_output<T: cpal::Sample>(
+        &mut self,
+        cpal_out_channels: usize,
+        out: &mut [T],
+    ) {
+        let mut input_rx = match self.duplex_input_rx.take() {
+            Some(input_rx) => input_rx,
+            None => {
+                let _ =
+                    self.process_interleaved_output_only(cpal_out_channels, out, |s| T::from(&s));
+                return;
             }
         };
 
-        let proc_start_time = Instant::now();
-
-        if out.len() < self.graph_audio_out_channels || cpal_out_channels == 0 {
-            clear_output(out);
+        if cpal_out_channels == 0 {
+            self.duplex_input_rx = Some(input_rx);
+            let _ = self.process_interleaved_output_only(cpal_out_channels, out, |s| T::from(&s));
             return;
         }
 
-        let total_frames = out.len() / cpal_out_channels;
+        let in_channels = self.graph_audio_in_channels;
+        let num_in_samples = (out.len() / cpal_out_channels) * in_channels;
 
-        // Discard any output from previous cycles that failed to render on time.
-        if !self.process_to_audio_channel.audio_rb_rx.is_empty() {
-            let num_slots = self.process_to_audio_channel.audio_rb_rx.slots();
+        // The input and output callbacks may drift apart or use slightly
+        // different buffer sizes, so keep at most two cycles worth of input
+        // buffered to keep the latency bounded.
+        let num_slots = input_rx.slots();
+        if num_slots > num_in_samples * 2 {
+            let chunk = input_rx.read_chunk(num_slots - num_in_samples).unwrap();
+            chunk.commit_all();
+        }
 
-            let chunks = self.process_to_audio_channel.audio_rb_rx.read_chunk(num_slots).unwrap();
-            chunks.commit_all();
+        let num_available = input_rx.slots().min(num_in_samples);
+        if num_available < num_in_samples {
+            log::trace!("input underrun");
+            self.telemetry.record_xrun();
         }
 
-        match &mut *self.audio_to_process_channel {
-            AudioToProcessChannelTX::HasInputAudio { audio_rb_tx } => {
-                if !audio_rb_tx.is_abandoned() {
-                    match audio_rb_tx.write_chunk(total_frames * self.graph_audio_in_channels) {
-                        Ok(mut chunk) => {
-                            let (slice_1, slice_2) = chunk.as_mut_slices();
-                            slice_1.fill(0.0);
-                            slice_2.fill(0.0);
+        let chunk = input_rx.read_chunk(num_available).unwrap();
+        // Only the input that was actually sent to the engine is consumed, so
+        // no input is lost if processing bails out early.
+        let mut num_consumed = 0;
+        {
+            let (slice_1, slice_2) = chunk.as_slices();
 
-                            chunk.commit_all();
-                        }
-                        Err(_) => {
-                            log::error!(
-                                "Ran out of space in audio thread to process thread audio buffer"
-                            );
-                            clear_output(out);
-                            return;
-                        }
+            let _ = self.process_interleaved_inner(
+                |frame, ch| {
+                    let i = (frame * in_channels) + ch;
+
+                    if i < num_available {
+                        num_consumed = num_consumed.max(i + 1);
                     }
-                } else {
-                    clear_output(out);
-                    return;
+
+                    if i < slice_1.len() {
+                        slice_1[i]
+                    } else if i < num_available {
+                        slice_2[i - slice_1.len()]
+                    } else {
+                        0.0
+                    }
+                },
+                cpal_out_channels,
+                out,
+                |s| T::from(&s),
+            );
+        }
+        chunk.commit(num_consumed);
+
+        self.duplex_input_rx = Some(input_rx);
+    }
+
+    /// Process the audio graph with no input audio, and write its output
+    /// into the interleaved `out` buffer with `out_channels` channels.
+    ///
+    /// This returns `false` if the engine did not render the output, either
+    /// because it failed to render it in time (an underrun) or because the
+    /// engine is no longer running. In that case the output is filled with
+    /// silence.
+    pub(crate) fn process_interleaved_output_only<T, F: Fn(f32) -> T>(
+        &mut self,
+        out_channels: usize,
+        out: &mut [T],
+        convert: F,
+    ) -> bool {
+        self.process_interleaved_inner(|_, _| 0.0, out_channels, out, convert)
+    }
+
+    /// Process the audio graph, and write its output into the interleaved
+    /// `out` buffer with `out_channels` channels.
+    ///
+    /// `input_sample` returns the input sample for a given frame and
+    /// channel of the audio graph's input.
+    ///
+    /// This returns `false` if the engine did not render the output, either
+    /// because it failed to render it in time (an underrun) or because the
+    /// engine is no longer running. In that case the output is filled with
+    /// silence.
+    pub(crate) fn process_interleaved_inner<T, I: FnMut(usize, usize) -> f32, F: Fn(f32) -> T>(
+        &mut self,
+        mut input_sample: I,
+        out_channels: usize,
+        out: &mut [T],
+        convert: F,
+    ) -> bool {
+        if out.len() < self.graph_audio_out_channels || out_channels == 0 {
+            for s in out.iter_mut() {
+                *s = convert(0.0);
+            }
+            return false;
+        }
+
+        let total_frames = out.len() / out_channels;
+        let graph_out_channels = self.graph_audio_out_channels;
+
+        let rendered = self.process_inner(
+            total_frames,
+            |ch, frame_offset, buffer| {
+                for (i, s) in buffer.iter_mut().enumerate() {
+                    *s = input_sample(frame_offset + i, ch);
+                }
+            },
+            |ch, frame_offset, buffer| {
+                if ch < out_channels {
+                    for (i, s) in buffer.iter().enumerate() {
+                        out[((frame_offset + i) * out_channels) + ch] = convert(*s);
+                    }
+                }
+            },
+        );
+
+        if rendered {
+            for ch in graph_out_channels..out_channels {
+                for frame in 0..total_frames {
+                    out[(frame * out_channels) + ch] = convert(0.0);
                 }
             }
-            AudioToProcessChannelTX::NoInputAudio { num_frames_wanted } => {
-                if Arc::strong_count(num_frames_wanted) > 1 {
-                    num_frames_wanted.store(total_frames, Ordering::SeqCst);
+        } else {
+            for s in out.iter_mut() {
+                *s = convert(0.0);
+            }
+        }
+
+        rendered
+    }
+
+    /// Process the audio graph with non-interleaved (planar) buffers.
+    ///
+    /// The number of frames to process is the length of the shortest
+    /// channel. Any graph input channels not in `audio_in` are filled
+    /// with silence, and any channels in `audio_out` that are not in the
+    /// graph output are filled with silence.
+    ///
+    /// The audio is sent to and received from the process thread in planar
+    /// form, so each channel is copied straight into and out of the
+    /// buffers of the audio graph without being interleaved.
+    pub fn process_planar(&mut self, audio_in: &[&[f32]], audio_out: &mut [&mut [f32]]) {
+        let _ = self.process_planar_inner(
+            audio_in,
+            audio_out,
+            |ring, ch| ring.copy_from_slice(ch),
+            |ch, ring| ch.copy_from_slice(ring),
+        );
+    }
+
+    /// Process the audio graph with non-interleaved (planar) 64 bit
+    /// buffers.
+    ///
+    /// See `Self::process_planar()` for more details.
+    pub fn process_planar_f64(&mut self, audio_in: &[&[f64]], audio_out: &mut [&mut [f64]]) {
+        let _ = self.process_planar_inner(
+            audio_in,
+            audio_out,
+            |ring, ch| {
+                for (s_out, s_in) in ring.iter_mut().zip(ch.iter()) {
+                    *s_out = *s_in as f32;
+                }
+            },
+            |ch, ring| {
+                for (s_out, s_in) in ch.iter_mut().zip(ring.iter()) {
+                    *s_out = f64::from(*s_in);
+                }
+            },
+        );
+    }
+
+    /// * `copy_in` - Copy the given part of an input channel into the given
+    /// part of the buffer that is sent to the process thread.
+    /// * `copy_out` - Copy the given part of the buffer that was received
+    /// from the process thread into the given part of an output channel.
+    fn process_planar_inner<TIn, TOut, CIn, COut>(
+        &mut self,
+        audio_in: &[&[TIn]],
+        audio_out: &mut [&mut [TOut]],
+        mut copy_in: CIn,
+        mut copy_out: COut,
+    ) -> bool
+    where
+        TOut: Copy,
+        CIn: FnMut(&mut [f32], &[TIn]),
+        COut: FnMut(&mut [TOut], &[f32]),
+        f32: Into<TOut>,
+    {
+        let total_frames = audio_in
+            .iter()
+            .map(|ch| ch.len())
+            .chain(audio_out.iter().map(|ch| ch.len()))
+            .min()
+            .unwrap_or(0);
+        if total_frames == 0 {
+            return true;
+        }
+
+        let graph_out_channels = self.graph_audio_out_channels;
+
+        let rendered = self.process_inner(
+            total_frames,
+            |ch, frame_offset, buffer| {
+                if let Some(ch) = audio_in.get(ch) {
+                    (copy_in)(buffer, &ch[frame_offset..frame_offset + buffer.len()]);
                 } else {
-                    clear_output(out);
-                    return;
+                    buffer.fill(0.0);
+                }
+            },
+            |ch, frame_offset, buffer| {
+                if let Some(ch) = audio_out.get_mut(ch) {
+                    (copy_out)(&mut ch[frame_offset..frame_offset + buffer.len()], buffer);
+                }
+            },
+        );
+
+        let silent_channels = if rendered { graph_out_channels } else { 0 };
+        for ch in audio_out.iter_mut().skip(silent_channels) {
+            ch[0..total_frames].fill(0.0f32.into());
+        }
+
+        rendered
+    }
+
+    /// Process `total_frames` frames of the audio graph.
+    ///
+    /// The audio of each cycle is sent to and received from the process
+    /// thread in planar form (all of the frames of the first channel,
+    /// followed by all of the frames of the second channel, and so on).
+    ///
+    /// * `read_in` - Fill the given part of the given input channel,
+    /// starting at the given frame offset.
+    /// * `write_out` - Read the given part of the given output channel,
+    /// starting at the given frame offset. This is not called if the
+    /// output was not rendered.
+    ///
+    /// This returns `false` if the engine did not render the output, either
+    /// because it failed to render it in time (an underrun) or because the
+    /// engine is no longer running.
+    fn process_inner<I, O>(&mut self, total_frames: usize, mut read_in: I, mut write_out: O) -> bool
+    where
+        I: FnMut(usize, usize, &mut [f32]),
+        O: FnMut(usize, usize, &[f32]),
+    {
+        let proc_start_time = Instant::now();
+
+        self.note_out_events.clear();
+
+        // Discard any output from previous cycles that failed to render on time.
+        while let Ok(cycle) = self.process_to_audio_channel.cycle_rb_rx.pop() {
+            self.process_to_audio_channel.discard_cycle(&cycle, self.graph_audio_out_channels);
+        }
+
+        // Send the events from the event input handle along with the input of
+        // this cycle.
+        if let Some(event_input_rx) = &mut self.event_input_rx {
+            while let Ok(event) = event_input_rx.note_rx.pop() {
+                if self.note_in_rb_tx.push(event).is_err() {
+                    log::error!("Ran out of space in audio thread to process thread note buffer");
+                    break;
+                }
+                self.num_note_events_in += 1;
+            }
+            while let Ok(event) = event_input_rx.automation_rx.pop() {
+                if self.automation_in_rb_tx.push(event).is_err() {
+                    log::error!(
+                        "Ran out of space in audio thread to process thread automation buffer"
+                    );
+                    break;
                 }
+                self.num_automation_events_in += 1;
             }
         }
 
-        let num_out_samples = total_frames * self.graph_audio_out_channels;
-        if num_out_samples == 0 {
-            clear_output(out);
-            return;
+        let channel = &mut *self.audio_to_process_channel;
+        if channel.cycle_rb_tx.is_abandoned() {
+            // The engine is no longer running.
+            return false;
+        }
+        if channel.cycle_rb_tx.is_full() {
+            log::error!("Ran out of space in audio thread to process thread cycle buffer");
+            self.telemetry.record_xrun();
+            return false;
         }
 
-        let max_proc_time = Duration::from_secs_f64(
-            total_frames as f64 * self.sample_rate_recip * COPY_OUT_TIME_WINDOW,
-        );
+        if let Some(audio_rb_tx) = &mut channel.audio_rb_tx {
+            match audio_rb_tx.write_chunk(total_frames * self.graph_audio_in_channels) {
+                Ok(mut chunk) => {
+                    let (slice_1, slice_2) = chunk.as_mut_slices();
+                    for ch in 0..self.graph_audio_in_channels {
+                        let (part_1, part_2) =
+                            planar_channel_mut(slice_1, slice_2, ch * total_frames, total_frames);
+
+                        (read_in)(ch, 0, part_1);
+                        (read_in)(ch, part_1.len(), part_2);
+                    }
+
+                    chunk.commit_all();
+                }
+                Err(_) => {
+                    log::error!("Ran out of space in audio thread to process thread audio buffer");
+                    self.telemetry.record_xrun();
+                    return false;
+                }
+            }
+        }
+
+        let cycle_id = self.next_cycle_id;
+        self.next_cycle_id += 1;
+
+        // There is always space for this since only this thread pushes into
+        // the buffer.
+        let _ = channel.cycle_rb_tx.push(InputCycle {
+            id: cycle_id,
+            frames: total_frames,
+            num_note_events: self.num_note_events_in,
+            num_automation_events: self.num_automation_events_in,
+        });
+        self.num_note_events_in = 0;
+        self.num_automation_events_in = 0;
+
+        // Wake up the process thread now that there is input to process.
+        self.to_process_signal.notify();
 
-        #[cfg(target_os = "windows")]
-        let spin_sleeper = spin_sleep::SpinSleeper::default();
+        let deadline = proc_start_time
+            + Duration::from_secs_f64(
+                total_frames as f64 * self.sample_rate_recip * COPY_OUT_TIME_WINDOW,
+            );
 
         loop {
-            if let Ok(chunk) = self.process_to_audio_channel.audio_rb_rx.read_chunk(num_out_samples)
-            {
-                if cpal_out_channels == self.graph_audio_out_channels {
-                    // We can simply just convert the interlaced buffer over.
+            // Get the token before checking for output so we don't miss a
+            // notification from the process thread.
+            let token = self.to_audio_signal.token();
+
+            while let Ok(cycle) = self.process_to_audio_channel.cycle_rb_rx.pop() {
+                if cycle.id != cycle_id {
+                    // This is the output of an earlier cycle that failed to
+                    // render on time.
+                    self.process_to_audio_channel
+                        .discard_cycle(&cycle, self.graph_audio_out_channels);
+                    continue;
+                }
+
+                let num_out_samples = cycle.frames * self.graph_audio_out_channels;
+                let chunk =
+                    match self.process_to_audio_channel.audio_rb_rx.read_chunk(num_out_samples) {
+                        Ok(chunk) => chunk,
+                        Err(_) => {
+                            log::error!("Process thread sent less audio than expected");
+                            break;
+                        }
+                    };
 
+                {
                     let (slice_1, slice_2) = chunk.as_slices();
+                    for ch in 0..self.graph_audio_out_channels {
+                        let (part_1, part_2) =
+                            planar_channel(slice_1, slice_2, ch * cycle.frames, cycle.frames);
 
-                    let out_part = &mut out[0..slice_1.len()];
-                    for i in 0..slice_1.len() {
-                        out_part[i] = T::from(&slice_1[i]);
+                        (write_out)(ch, 0, part_1);
+                        (write_out)(ch, part_1.len(), part_2);
                     }
+                }
 
-                    let out_part = &mut out[slice_1.len()..slice_1.len() + slice_2.len()];
-                    for i in 0..slice_2.len() {
-                        out_part[i] = T::from(&slice_2[i]);
-                    }
-                } else {
-                    let (slice_1, slice_2) = chunk.as_slices();
+                chunk.commit_all();
 
-                    for ch_i in 0..cpal_out_channels {
-                        if ch_i < self.graph_audio_out_channels {
-                            for i in 0..total_frames {
-                                let i2 = (i * self.graph_audio_out_channels) + ch_i;
-
-                                let s = if i2 < slice_1.len() {
-                                    slice_1[i2]
-                                } else {
-                                    slice_2[i2 - slice_1.len()]
-                                };
-
-                                out[(i * cpal_out_channels) + ch_i] = T::from(&s);
-                            }
-                        } else {
-                            for i in 0..total_frames {
-                                out[(i * cpal_out_channels) + ch_i] = T::from(&0.0);
-                            }
+                for _ in 0..cycle.num_note_events {
+                    if let Ok(event) = self.process_to_audio_channel.note_rb_rx.pop() {
+                        if self.note_out_events.len() < self.note_out_events.capacity() {
+                            self.note_out_events.push(event);
                         }
                     }
                 }
 
-                chunk.commit_all();
-                return;
+                return true;
             }
 
-            if proc_start_time.elapsed() + AUDIO_THREAD_POLL_INTERVAL_BUFFERED >= max_proc_time {
+            if !self.to_audio_signal.wait(token, Some(deadline)) {
                 break;
             }
-
-            #[cfg(not(target_os = "windows"))]
-            std::thread::sleep(AUDIO_THREAD_POLL_INTERVAL);
-
-            #[cfg(target_os = "windows")]
-            spin_sleeper.sleep(AUDIO_THREAD_POLL_INTERVAL);
         }
 
         // The engine took too long to process.
         log::trace!("underrun");
-        clear_output(out);
+        self.telemetry.record_xrun();
+
+        false
+    }
+}
+
+/// The part of a planar ring buffer chunk (split into two slices) that
+/// starts at `start` and is `len` samples long, split into the part in the
+/// first slice and the part in the second slice.
+pub(super) fn planar_channel<'a>(
+    slice_1: &'a [f32],
+    slice_2: &'a [f32],
+    start: usize,
+    len: usize,
+) -> (&'a [f32], &'a [f32]) {
+    if start >= slice_1.len() {
+        let start = start - slice_1.len();
+        (&[], &slice_2[start..start + len])
+    } else if start + len <= slice_1.len() {
+        (&slice_1[start..start + len], &[])
+    } else {
+        (&slice_1[start..], &slice_2[0..start + len - slice_1.len()])
+    }
+}
+
+/// See `planar_channel()`.
+pub(super) fn planar_channel_mut<'a>(
+    slice_1: &'a mut [f32],
+    slice_2: &'a mut [f32],
+    start: usize,
+    len: usize,
+) -> (&'a mut [f32], &'a mut [f32]) {
+    if start >= slice_1.len() {
+        let start = start - slice_1.len();
+        (&mut [], &mut slice_2[start..start + len])
+    } else if start + len <= slice_1.len() {
+        (&mut slice_1[start..start + len], &mut [])
+    } else {
+        let slice_1_len = slice_1.len();
+        (&mut slice_1[start..], &mut slice_2[0..start + len - slice_1_len])
+    }
+}
+
+/// The input half of a full-duplex `DSEngineAudioThread`.
+///
+/// This can be sent to the thread of the audio device's input callback.
+pub struct DSEngineAudioInput {
+    input_rb_tx: Producer<f32>,
+    graph_audio_in_channels: usize,
+}
+
+impl Debug for DSEngineAudioInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("DSEngineAudioInput");
+
+        f.field("graph_audio_in_channels", &self.graph_audio_in_channels);
+
+        f.finish()
+    }
+}
+
+impl DSEngineAudioInput {
+    #[cfg(feature = "cpal-backend")]
+    /// Push the interleaved samples from a cpal input callback.
+    ///
+    /// If `cpal_in_channels` is `1`, then that channel is copied to every
+    /// input channel of the audio graph. Otherwise any extra channels are
+    /// ignored and any missing channels are filled with silence.
+    pub fn process_cpal_interleaved_input<T: cpal::Sample>(
+        &mut self,
+        cpal_in_channels: usize,
+        input: &[T],
+    ) {
+        self.push_interleaved(cpal_in_channels, input, |s| s.to_f32());
+    }
+
+    pub(crate) fn push_interleaved<T: Copy, F: Fn(T) -> f32>(
+        &mut self,
+        in_channels: usize,
+        input: &[T],
+        convert: F,
+    ) {
+        if in_channels == 0 {
+            return;
+        }
+
+        let total_frames = input.len() / in_channels;
+        let graph_in_channels = self.graph_audio_in_channels;
+
+        match self.input_rb_tx.write_chunk(total_frames * graph_in_channels) {
+            Ok(mut chunk) => {
+                let (slice_1, slice_2) = chunk.as_mut_slices();
+                for (i, s) in slice_1.iter_mut().chain(slice_2.iter_mut()).enumerate() {
+                    let frame = i / graph_in_channels;
+                    let ch = i % graph_in_channels;
+
+                    *s = if in_channels == 1 {
+                        convert(input[frame])
+                    } else if ch < in_channels {
+                        convert(input[(frame * in_channels) + ch])
+                    } else {
+                        0.0
+                    };
+                }
+
+                chunk.commit_all();
+            }
+            Err(_) => {
+                log::error!("Ran out of space in audio input to audio thread audio buffer");
+            }
+        }
+    }
+}
+
+/// A handle for sending note and automation events to the input of the
+/// audio graph from any thread.
+///
+/// Create this with `DSEngineAudioThread::create_event_input()`.
+pub struct DSEngineEventInput {
+    note_tx: Producer<GraphNoteEvent>,
+    automation_tx: Producer<GraphAutomationEvent>,
+    graph_note_in_ports: usize,
+    graph_automation_in_port: bool,
+}
+
+impl Debug for DSEngineEventInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("DSEngineEventInput");
+
+        f.field("graph_note_in_ports", &self.graph_note_in_ports);
+        f.field("graph_automation_in_port", &self.graph_automation_in_port);
+
+        f.finish()
+    }
+}
+
+impl DSEngineEventInput {
+    /// Send a note event to a note port on the input to the audio graph.
+    ///
+    /// See `DSEngineAudioThread::push_note_event()` for more details.
+    pub fn push_note_event(&mut self, event: GraphNoteEvent) -> bool {
+        if usize::from(event.port) >= self.graph_note_in_ports {
+            return false;
+        }
+
+        self.note_tx.push(event).is_ok()
+    }
+
+    /// Send an automation event to the automation port on the input to the
+    /// audio graph.
+    ///
+    /// See `DSEngineAudioThread::push_automation_event()` for more details.
+    pub fn push_automation_event(&mut self, event: GraphAutomationEvent) -> bool {
+        if !self.graph_automation_in_port {
+            return false;
+        }
+
+        self.automation_tx.push(event).is_ok()
+    }
+
+    /// Returns `true` if the audio thread this handle sends events to has
+    /// been dropped.
+    pub fn is_abandoned(&self) -> bool {
+        self.note_tx.is_abandoned()
     }
 }
 
-enum AudioToProcessChannelTX {
-    HasInputAudio { audio_rb_tx: Producer<f32> },
-    NoInputAudio { num_frames_wanted: Arc<AtomicUsize> },
+struct EventInputRX {
+    note_rx: Consumer<GraphNoteEvent>,
+    automation_rx: Consumer<GraphAutomationEvent>,
+}
+
+/// A process cycle sent from the audio thread to the process thread.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct InputCycle {
+    /// Used to match the output of the process thread with the cycle it
+    /// was rendered for.
+    pub id: u64,
+    pub frames: usize,
+    /// The number of note events that were sent along with this cycle.
+    pub num_note_events: usize,
+    /// The number of automation events that were sent along with this
+    /// cycle.
+    pub num_automation_events: usize,
+}
+
+/// A process cycle rendered by the process thread.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct OutputCycle {
+    /// The ID of the `InputCycle` this was rendered for.
+    pub id: u64,
+    pub frames: usize,
+    /// The number of note events that were sent along with this cycle.
+    pub num_note_events: usize,
+}
+
+/// The audio of each cycle is planar (see `DSEngineAudioThread::process_inner()`),
+/// and it is sent before the cycle itself.
+struct AudioToProcessChannelTX {
+    cycle_rb_tx: Producer<InputCycle>,
+    /// This is `None` if the audio graph has no input channels.
+    audio_rb_tx: Option<Producer<f32>>,
 }
 
-pub(super) enum AudioToProcessChannelRX {
-    HasInputAudio { audio_rb_rx: Consumer<f32> },
-    NoInputAudio { num_frames_wanted: Arc<AtomicUsize> },
+pub(super) struct AudioToProcessChannelRX {
+    pub cycle_rb_rx: Consumer<InputCycle>,
+    /// This is `None` if the audio graph has no input channels.
+    pub audio_rb_rx: Option<Consumer<f32>>,
 }
 
+/// The audio and note events of each cycle are sent before the cycle
+/// itself.
 pub(super) struct ProcessToAudioChannelTX {
+    pub cycle_rb_tx: Producer<OutputCycle>,
     pub audio_rb_tx: Producer<f32>,
+    pub note_rb_tx: Producer<GraphNoteEvent>,
 }
 
 struct ProcessToAudioChannelRX {
-    pub audio_rb_rx: Consumer<f32>,
+    cycle_rb_rx: Consumer<OutputCycle>,
+    audio_rb_rx: Consumer<f32>,
+    note_rb_rx: Consumer<GraphNoteEvent>,
+}
+
+impl ProcessToAudioChannelRX {
+    /// Discard the audio and note events of the given cycle.
+    fn discard_cycle(&mut self, cycle: &OutputCycle, out_channels: usize) {
+        if let Ok(chunk) = self.audio_rb_rx.read_chunk(cycle.frames * out_channels) {
+            chunk.commit_all();
+        }
+        for _ in 0..cycle.num_note_events {
+            let _ = self.note_rb_rx.pop();
+        }
+    }
 }
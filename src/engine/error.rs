@@ -1,8 +1,12 @@
 use std::error::Error;
 
 use dropseed_plugin_api::plugin_scanner::PluginFormat;
+use dropseed_plugin_api::PluginInstanceID;
 
-use crate::graph::error::GraphCompilerError;
+use crate::engine::DSGraphEdgeSaveState;
+use crate::graph::error::{ConnectEdgeError, GraphCompilerError};
+use crate::graph::DSEdgeID;
+use crate::plugin_host::error::ActivatePluginError;
 
 #[derive(Debug)]
 #[non_exhaustive]
@@ -59,3 +63,200 @@ impl std::fmt::Display for NewPluginInstanceError {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconfigureEngineError {
+    EngineDeactivated,
+    AudioChannelsChanged,
+    NotePortsChanged,
+    AutomationPortChanged,
+    /// One of the feedback edges in the audio graph has a delay that is
+    /// shorter than the new `max_frames`.
+    FeedbackDelayTooShort,
+    EngineCrashed,
+}
+
+impl Error for ReconfigureEngineError {}
+
+impl std::fmt::Display for ReconfigureEngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReconfigureEngineError::EngineDeactivated => {
+                write!(f, "Could not reconfigure engine: engine is deactivated")
+            }
+            ReconfigureEngineError::AudioChannelsChanged => {
+                write!(f, "Could not reconfigure engine: the audio channels and audio ports of the audio graph cannot be changed without reactivating the engine")
+            }
+            ReconfigureEngineError::NotePortsChanged => {
+                write!(f, "Could not reconfigure engine: the number of note ports in the audio graph cannot be changed without reactivating the engine")
+            }
+            ReconfigureEngineError::AutomationPortChanged => {
+                write!(f, "Could not reconfigure engine: the automation port of the audio graph cannot be added or removed without reactivating the engine")
+            }
+            ReconfigureEngineError::FeedbackDelayTooShort => {
+                write!(f, "Could not reconfigure engine: the delay of a feedback edge in the audio graph is shorter than the new maximum block size")
+            }
+            ReconfigureEngineError::EngineCrashed => {
+                write!(f, "Could not finish reconfiguring engine: engine crashed")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfflineRenderError {
+    EngineDeactivated,
+    NotInOfflineMode,
+    InvalidRange,
+    EngineCrashed,
+}
+
+impl Error for OfflineRenderError {}
+
+impl std::fmt::Display for OfflineRenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OfflineRenderError::EngineDeactivated => {
+                write!(f, "Could not render offline: engine is deactivated")
+            }
+            OfflineRenderError::NotInOfflineMode => {
+                write!(f, "Could not render offline: engine was not activated in offline mode")
+            }
+            OfflineRenderError::InvalidRange => {
+                write!(f, "Could not render offline: the end of the range is before the start")
+            }
+            OfflineRenderError::EngineCrashed => {
+                write!(f, "Could not finish rendering offline: engine crashed")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ModifyGraphError {
+    EngineDeactivated,
+    /// An edge to disconnect does not exist in the audio graph.
+    EdgeDoesNotExist(DSEdgeID),
+    /// A plugin to remove does not exist in the audio graph.
+    PluginDoesNotExist(PluginInstanceID),
+    /// A plugin appears more than once in the list of plugins to remove.
+    PluginRemovedTwice(PluginInstanceID),
+    /// The graph in/out nodes cannot be removed.
+    CannotRemoveGraphInOut,
+    /// The new plugin at this index in `ModifyGraphRequest::add_plugin_instances`
+    /// failed to load.
+    PluginFailedToLoad(usize, NewPluginInstanceError),
+    /// The new plugin at this index in `ModifyGraphRequest::add_plugin_instances`
+    /// failed to activate.
+    PluginFailedToActivate(usize, ActivatePluginError),
+    /// One of the new edges could not be connected.
+    ConnectEdgeError(ConnectEdgeError),
+}
+
+impl Error for ModifyGraphError {}
+
+impl std::fmt::Display for ModifyGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModifyGraphError::EngineDeactivated => {
+                write!(f, "Could not modify audio graph: engine is deactivated")
+            }
+            ModifyGraphError::EdgeDoesNotExist(id) => {
+                write!(f, "Could not modify audio graph: edge {:?} does not exist", id)
+            }
+            ModifyGraphError::PluginDoesNotExist(id) => {
+                write!(f, "Could not modify audio graph: plugin {:?} does not exist", id)
+            }
+            ModifyGraphError::PluginRemovedTwice(id) => {
+                write!(f, "Could not modify audio graph: plugin {:?} is removed more than once", id)
+            }
+            ModifyGraphError::CannotRemoveGraphInOut => {
+                write!(f, "Could not modify audio graph: the graph in/out nodes cannot be removed")
+            }
+            ModifyGraphError::PluginFailedToLoad(i, e) => {
+                write!(
+                    f,
+                    "Could not modify audio graph: new plugin at index {} failed to load: {}",
+                    i, e
+                )
+            }
+            ModifyGraphError::PluginFailedToActivate(i, e) => {
+                write!(
+                    f,
+                    "Could not modify audio graph: new plugin at index {} failed to activate: {}",
+                    i, e
+                )
+            }
+            ModifyGraphError::ConnectEdgeError(e) => {
+                write!(f, "Could not modify audio graph: {}", e)
+            }
+        }
+    }
+}
+
+/// An error that occurred while restoring the audio graph from a
+/// `DSGraphSaveState`. The audio graph is left unchanged when this is
+/// returned.
+#[derive(Debug)]
+pub enum RestoreGraphError {
+    EngineDeactivated,
+    /// A plugin with this `DSGraphPluginSaveState::id` appears more than
+    /// once in the save state.
+    PluginAppearsTwice(u64),
+    /// This edge refers to a plugin that is not in the save state.
+    EdgeNodeDoesNotExist(DSGraphEdgeSaveState),
+    /// The plugin with this `DSGraphPluginSaveState::id` failed to load.
+    PluginFailedToLoad(u64, NewPluginInstanceError),
+    /// The plugin with this `DSGraphPluginSaveState::id` failed to activate.
+    PluginFailedToActivate(u64, ActivatePluginError),
+    /// One of the edges in the save state could not be connected.
+    ConnectEdgeError(ConnectEdgeError),
+    /// Any other error that occurred while modifying the audio graph.
+    ModifyGraphError(ModifyGraphError),
+}
+
+impl Error for RestoreGraphError {}
+
+impl std::fmt::Display for RestoreGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestoreGraphError::EngineDeactivated => {
+                write!(f, "Could not restore audio graph: engine is deactivated")
+            }
+            RestoreGraphError::PluginAppearsTwice(id) => {
+                write!(
+                    f,
+                    "Could not restore audio graph: plugin with ID {} appears more than once in the save state",
+                    id
+                )
+            }
+            RestoreGraphError::EdgeNodeDoesNotExist(edge) => {
+                write!(
+                    f,
+                    "Could not restore audio graph: edge {:?} refers to a plugin that is not in the save state",
+                    edge
+                )
+            }
+            RestoreGraphError::PluginFailedToLoad(id, e) => {
+                write!(
+                    f,
+                    "Could not restore audio graph: plugin with ID {} failed to load: {}",
+                    id, e
+                )
+            }
+            RestoreGraphError::PluginFailedToActivate(id, e) => {
+                write!(
+                    f,
+                    "Could not restore audio graph: plugin with ID {} failed to activate: {}",
+                    id, e
+                )
+            }
+            RestoreGraphError::ConnectEdgeError(e) => {
+                write!(f, "Could not restore audio graph: {}", e)
+            }
+            RestoreGraphError::ModifyGraphError(e) => {
+                write!(f, "Could not restore audio graph: {}", e)
+            }
+        }
+    }
+}
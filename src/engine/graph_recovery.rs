@@ -0,0 +1,249 @@
+use dropseed_plugin_api::{DSPluginSaveState, PluginInstanceID, PluginInstanceType};
+use fnv::{FnvHashMap, FnvHashSet};
+use std::collections::VecDeque;
+
+use crate::graph::{DSEdgeID, Edge, PortType};
+
+use super::modify_request::{ConnectEdgeReq, EdgeReqPortID, ModifyGraphRequest, PluginIDReq};
+
+/// The state of the audio graph at the time a compile was requested.
+struct GraphSnapshot {
+    schedule_version: u64,
+    plugins: Vec<PluginInstanceID>,
+    edges: Vec<Edge>,
+}
+
+/// Keeps track of the last state of the audio graph that compiled
+/// successfully, so that the graph can be rolled back to it when a
+/// modification causes the compiler to fail.
+pub(crate) struct GraphRecovery {
+    last_good: GraphSnapshot,
+
+    /// The snapshots of the graph for the compiles that haven't finished
+    /// yet.
+    pending: VecDeque<GraphSnapshot>,
+
+    /// The IDs of the requests that have been made since the last
+    /// successful compile, along with the version of the schedule they
+    /// were compiled with.
+    unconfirmed_requests: Vec<(u64, u64)>,
+
+    /// The save states of the plugins that have been removed since the
+    /// last successful compile, so they can be re-created if the graph is
+    /// rolled back.
+    removed_save_states: FnvHashMap<u64, DSPluginSaveState>,
+}
+
+/// The request that rolls the audio graph back to the last state that
+/// compiled successfully.
+pub(crate) struct RollbackRequest {
+    pub request: ModifyGraphRequest,
+
+    /// The old IDs of the plugins that are re-created by this request, in
+    /// the same order as `ModifyGraphRequest::add_plugin_instances`.
+    pub recreated_plugins: Vec<PluginInstanceID>,
+
+    /// The IDs of the requests that have been rejected.
+    pub rejected_requests: Vec<u64>,
+}
+
+impl GraphRecovery {
+    pub fn new() -> Self {
+        Self {
+            last_good: GraphSnapshot {
+                schedule_version: 0,
+                plugins: Vec::new(),
+                edges: Vec::new(),
+            },
+            pending: VecDeque::new(),
+            unconfirmed_requests: Vec::new(),
+            removed_save_states: FnvHashMap::default(),
+        }
+    }
+
+    /// Called after the request with the given ID has been applied to the
+    /// graph and queued to be compiled.
+    pub fn on_request(&mut self, request_id: u64, schedule_version: u64) {
+        self.unconfirmed_requests.push((request_id, schedule_version));
+    }
+
+    /// Called right before a plugin is removed from the graph.
+    pub fn on_plugin_removed(
+        &mut self,
+        plugin_id: &PluginInstanceID,
+        save_state: DSPluginSaveState,
+    ) {
+        self.removed_save_states.insert(plugin_id.unique_id(), save_state);
+    }
+
+    /// Called whenever the graph is queued to be compiled.
+    pub fn on_compile_requested(
+        &mut self,
+        schedule_version: u64,
+        plugins: Vec<PluginInstanceID>,
+        edges: Vec<Edge>,
+    ) {
+        self.pending.push_back(GraphSnapshot { schedule_version, plugins, edges });
+    }
+
+    /// Called whenever the graph has compiled successfully.
+    pub fn on_compiled(&mut self, schedule_version: u64) {
+        // Earlier requests may have been skipped by the compiler thread.
+        while let Some(snapshot) = self.pending.front() {
+            if snapshot.schedule_version > schedule_version {
+                break;
+            }
+
+            let snapshot = self.pending.pop_front().unwrap();
+            if snapshot.schedule_version == schedule_version {
+                self.last_good = snapshot;
+            }
+        }
+
+        self.unconfirmed_requests.retain(|(_, version)| *version > schedule_version);
+
+        // Only the plugins in the last good state can ever be re-created.
+        let last_good = &self.last_good;
+        self.removed_save_states.retain(|unique_id, _| {
+            last_good.plugins.iter().any(|plugin_id| plugin_id.unique_id() == *unique_id)
+        });
+    }
+
+    /// Construct the request that rolls the graph back to the last state
+    /// that compiled successfully.
+    ///
+    /// Plugins that are still in the graph are kept as is. Plugins that
+    /// have been removed since are re-created from their save states, which
+    /// means they will have new IDs.
+    ///
+    /// Every request that hasn't been confirmed by a successful compile is
+    /// rejected.
+    pub fn rollback(
+        &mut self,
+        current_plugins: &[PluginInstanceID],
+        current_edges: &[Edge],
+    ) -> RollbackRequest {
+        let current_plugin_ids: FnvHashSet<u64> =
+            current_plugins.iter().map(|plugin_id| plugin_id.unique_id()).collect();
+        let good_plugin_ids: FnvHashSet<u64> =
+            self.last_good.plugins.iter().map(|plugin_id| plugin_id.unique_id()).collect();
+
+        // Remove the plugins that were added since.
+        let remove_plugin_instances: Vec<PluginInstanceID> = current_plugins
+            .iter()
+            .filter(|plugin_id| !good_plugin_ids.contains(&plugin_id.unique_id()))
+            .cloned()
+            .collect();
+
+        // Re-create the plugins that were removed since.
+        let mut add_plugin_instances: Vec<DSPluginSaveState> = Vec::new();
+        let mut recreated_plugins: Vec<PluginInstanceID> = Vec::new();
+        let mut recreated_indexes: FnvHashMap<u64, usize> = FnvHashMap::default();
+        for plugin_id in self.last_good.plugins.iter() {
+            if current_plugin_ids.contains(&plugin_id.unique_id()) {
+                continue;
+            }
+
+            if let Some(save_state) = self.removed_save_states.remove(&plugin_id.unique_id()) {
+                recreated_indexes.insert(plugin_id.unique_id(), add_plugin_instances.len());
+                add_plugin_instances.push(save_state);
+                recreated_plugins.push(plugin_id.clone());
+            } else {
+                log::error!("Could not re-create plugin {:?}: no save state was found", plugin_id);
+            }
+        }
+
+        let plugin_id_req = |plugin_id: &PluginInstanceID| -> Option<PluginIDReq> {
+            match plugin_id.format() {
+                PluginInstanceType::GraphInput | PluginInstanceType::GraphOutput => {
+                    Some(PluginIDReq::Existing(plugin_id.clone()))
+                }
+                _ => {
+                    if current_plugin_ids.contains(&plugin_id.unique_id()) {
+                        Some(PluginIDReq::Existing(plugin_id.clone()))
+                    } else {
+                        recreated_indexes
+                            .get(&plugin_id.unique_id())
+                            .map(|i| PluginIDReq::Added(*i))
+                    }
+                }
+            }
+        };
+
+        // Disconnect the edges that were connected since, and reconnect the
+        // edges that were disconnected since.
+        let current_edge_ids: FnvHashSet<DSEdgeID> =
+            current_edges.iter().map(|edge| edge.id).collect();
+        let good_edge_ids: FnvHashSet<DSEdgeID> =
+            self.last_good.edges.iter().map(|edge| edge.id).collect();
+
+        let disconnect_edges: Vec<DSEdgeID> = current_edges
+            .iter()
+            .filter(|edge| !good_edge_ids.contains(&edge.id))
+            .map(|edge| edge.id)
+            .collect();
+
+        let connect_new_edges: Vec<ConnectEdgeReq> = self
+            .last_good
+            .edges
+            .iter()
+            .filter(|edge| !current_edge_ids.contains(&edge.id))
+            .filter_map(|edge| {
+                let src_plugin_id = plugin_id_req(&edge.src_plugin_id)?;
+                let dst_plugin_id = plugin_id_req(&edge.dst_plugin_id)?;
+
+                Some(ConnectEdgeReq {
+                    edge_type: edge.edge_type,
+                    src_plugin_id,
+                    dst_plugin_id,
+                    src_port_id: edge_req_port_id(edge, &edge.src_plugin_id, edge.src_port_id),
+                    src_port_channel: edge.src_port_channel,
+                    dst_port_id: edge_req_port_id(edge, &edge.dst_plugin_id, edge.dst_port_id),
+                    dst_port_channel: edge.dst_port_channel,
+                    feedback: edge.feedback,
+                    // This edge was part of a graph that compiled successfully.
+                    check_for_cycles: false,
+                    log_error_on_fail: true,
+                })
+            })
+            .collect();
+
+        self.pending.clear();
+
+        RollbackRequest {
+            request: ModifyGraphRequest {
+                add_plugin_instances,
+                remove_plugin_instances,
+                connect_new_edges,
+                disconnect_edges,
+            },
+            recreated_plugins,
+            rejected_requests: self.unconfirmed_requests.drain(..).map(|(id, _)| id).collect(),
+        }
+    }
+}
+
+pub(super) fn edge_req_port_id(
+    edge: &Edge,
+    plugin_id: &PluginInstanceID,
+    stable_id: u32,
+) -> EdgeReqPortID {
+    match plugin_id.format() {
+        // Note and automation ports on the graph input/output nodes are
+        // identified by their index.
+        PluginInstanceType::GraphInput | PluginInstanceType::GraphOutput => {
+            if edge.edge_type == PortType::Audio {
+                EdgeReqPortID::StableID(stable_id)
+            } else {
+                EdgeReqPortID::Main
+            }
+        }
+        _ => {
+            if edge.edge_type == PortType::Automation {
+                EdgeReqPortID::Main
+            } else {
+                EdgeReqPortID::StableID(stable_id)
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use dropseed_plugin_api::{DSPluginSaveState, PluginInstanceID, PluginInstanceType};
+use fnv::{FnvHashMap, FnvHashSet};
+
+use crate::graph::{DSEdgeID, Edge, PortType};
+use crate::processor_schedule::TransportSaveState;
+
+use super::error::RestoreGraphError;
+use super::graph_recovery::edge_req_port_id;
+use super::modify_request::{
+    ConnectEdgeReq, EdgeReqPortID, FeedbackDelay, ModifyGraphRequest, PluginIDReq,
+};
+
+/// The save state of the whole audio graph.
+///
+/// Collect this with `DSEngineMainThread::collect_graph_save_state()`, and
+/// restore it with `DSEngineMainThread::restore_graph()`.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DSGraphSaveState {
+    /// All of the plugins in the audio graph.
+    pub plugins: Vec<DSGraphPluginSaveState>,
+
+    /// All of the edges (port connections) in the audio graph.
+    pub edges: Vec<DSGraphEdgeSaveState>,
+
+    /// The playhead and the loop state of the transport.
+    pub transport: TransportSaveState,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DSGraphPluginSaveState {
+    /// The unique ID of the plugin at the time the save state was collected
+    /// (`PluginInstanceID::unique_id()`).
+    ///
+    /// This is only used to refer to this plugin in
+    /// `DSGraphSaveState::edges`. The plugin gets a new ID when it is
+    /// restored.
+    pub id: u64,
+
+    /// The save state of the plugin. This also contains whether the plugin
+    /// is active and bypassed.
+    pub save_state: DSPluginSaveState,
+}
+
+/// A node in a `DSGraphSaveState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DSGraphNodeID {
+    /// The input to the audio graph.
+    GraphIn,
+    /// The output of the audio graph.
+    GraphOut,
+    /// The plugin with this `DSGraphPluginSaveState::id`.
+    Plugin(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DSGraphEdgeSaveState {
+    pub edge_type: PortType,
+
+    pub src_node: DSGraphNodeID,
+    pub src_port_id: EdgeReqPortID,
+    pub src_port_channel: u16,
+
+    pub dst_node: DSGraphNodeID,
+    pub dst_port_id: EdgeReqPortID,
+    pub dst_port_channel: u16,
+
+    /// The delay of the edge if this is a feedback edge.
+    pub feedback: Option<FeedbackDelay>,
+}
+
+#[cfg(feature = "serde")]
+impl dropseed_plugin_api::SaveStateMigration for DSGraphSaveState {}
+
+impl DSGraphSaveState {
+    pub(crate) fn new(
+        plugins: Vec<(PluginInstanceID, DSPluginSaveState)>,
+        edges: &[Edge],
+        transport: TransportSaveState,
+    ) -> Self {
+        let node_id = |plugin_id: &PluginInstanceID| match plugin_id.format() {
+            PluginInstanceType::GraphInput => DSGraphNodeID::GraphIn,
+            PluginInstanceType::GraphOutput => DSGraphNodeID::GraphOut,
+            _ => DSGraphNodeID::Plugin(plugin_id.unique_id()),
+        };
+
+        Self {
+            plugins: plugins
+                .into_iter()
+                .map(|(plugin_id, save_state)| DSGraphPluginSaveState {
+                    id: plugin_id.unique_id(),
+                    save_state,
+                })
+                .collect(),
+            edges: edges
+                .iter()
+                .map(|edge| DSGraphEdgeSaveState {
+                    edge_type: edge.edge_type,
+                    src_node: node_id(&edge.src_plugin_id),
+                    src_port_id: edge_req_port_id(edge, &edge.src_plugin_id, edge.src_port_id),
+                    src_port_channel: edge.src_port_channel,
+                    dst_node: node_id(&edge.dst_plugin_id),
+                    dst_port_id: edge_req_port_id(edge, &edge.dst_plugin_id, edge.dst_port_id),
+                    dst_port_channel: edge.dst_port_channel,
+                    feedback: edge.feedback,
+                })
+                .collect(),
+            transport,
+        }
+    }
+
+    /// Check that no plugin appears more than once in this save state, and
+    /// that every edge refers to a node in this save state.
+    pub(crate) fn validate(&self) -> Result<(), RestoreGraphError> {
+        let mut ids: FnvHashSet<u64> = FnvHashSet::default();
+        for plugin in self.plugins.iter() {
+            if !ids.insert(plugin.id) {
+                return Err(RestoreGraphError::PluginAppearsTwice(plugin.id));
+            }
+        }
+
+        let node_exists = |node_id: DSGraphNodeID| match node_id {
+            DSGraphNodeID::GraphIn | DSGraphNodeID::GraphOut => true,
+            DSGraphNodeID::Plugin(id) => ids.contains(&id),
+        };
+        for edge in self.edges.iter() {
+            if !node_exists(edge.src_node) || !node_exists(edge.dst_node) {
+                return Err(RestoreGraphError::EdgeNodeDoesNotExist(edge.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Construct the request that replaces the given plugins and edges with
+    /// the plugins and edges in this save state.
+    ///
+    /// This also returns the old IDs of the plugins in the same order as
+    /// `ModifyGraphRequest::add_plugin_instances`.
+    pub(crate) fn into_request(
+        self,
+        graph_in_id: &PluginInstanceID,
+        graph_out_id: &PluginInstanceID,
+        remove_plugin_instances: Vec<PluginInstanceID>,
+        disconnect_edges: Vec<DSEdgeID>,
+    ) -> (ModifyGraphRequest, Vec<u64>) {
+        let mut add_plugin_instances: Vec<DSPluginSaveState> = Vec::new();
+        let mut old_ids: Vec<u64> = Vec::new();
+        let mut added_indexes: FnvHashMap<u64, usize> = FnvHashMap::default();
+        for plugin in self.plugins.into_iter() {
+            if added_indexes.insert(plugin.id, add_plugin_instances.len()).is_some() {
+                log::warn!(
+                    "Plugin with ID {} appears more than once in graph save state",
+                    plugin.id
+                );
+                continue;
+            }
+
+            add_plugin_instances.push(plugin.save_state);
+            old_ids.push(plugin.id);
+        }
+
+        let plugin_id_req = |node_id: DSGraphNodeID| -> Option<PluginIDReq> {
+            match node_id {
+                DSGraphNodeID::GraphIn => Some(PluginIDReq::Existing(graph_in_id.clone())),
+                DSGraphNodeID::GraphOut => Some(PluginIDReq::Existing(graph_out_id.clone())),
+                DSGraphNodeID::Plugin(id) => added_indexes.get(&id).map(|i| PluginIDReq::Added(*i)),
+            }
+        };
+
+        let connect_new_edges: Vec<ConnectEdgeReq> = self
+            .edges
+            .into_iter()
+            .filter_map(|edge| {
+                let (src_plugin_id, dst_plugin_id) =
+                    match (plugin_id_req(edge.src_node), plugin_id_req(edge.dst_node)) {
+                        (Some(src), Some(dst)) => (src, dst),
+                        _ => {
+                            log::warn!(
+                                "Ignored edge {:?} in graph save state: Plugin does not exist",
+                                &edge
+                            );
+                            return None;
+                        }
+                    };
+
+                Some(ConnectEdgeReq {
+                    edge_type: edge.edge_type,
+                    src_plugin_id,
+                    dst_plugin_id,
+                    src_port_id: edge.src_port_id,
+                    src_port_channel: edge.src_port_channel,
+                    dst_port_id: edge.dst_port_id,
+                    dst_port_channel: edge.dst_port_channel,
+                    feedback: edge.feedback,
+                    // This edge was part of a graph that compiled successfully.
+                    check_for_cycles: false,
+                    log_error_on_fail: true,
+                })
+            })
+            .collect();
+
+        (
+            ModifyGraphRequest {
+                add_plugin_instances,
+                remove_plugin_instances,
+                connect_new_edges,
+                disconnect_edges,
+            },
+            old_ids,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_to_missing_plugin_is_rejected() {
+        let edge = DSGraphEdgeSaveState {
+            edge_type: PortType::Audio,
+            src_node: DSGraphNodeID::Plugin(1),
+            src_port_id: EdgeReqPortID::Main,
+            src_port_channel: 0,
+            dst_node: DSGraphNodeID::GraphOut,
+            dst_port_id: EdgeReqPortID::Main,
+            dst_port_channel: 0,
+            feedback: None,
+        };
+
+        let save_state = DSGraphSaveState { edges: vec![edge.clone()], ..Default::default() };
+
+        assert!(matches!(
+            save_state.validate(),
+            Err(RestoreGraphError::EdgeNodeDoesNotExist(e)) if e == edge
+        ));
+    }
+}
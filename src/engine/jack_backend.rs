@@ -0,0 +1,280 @@
+use meadowlark_core_types::time::SampleRate;
+use smallvec::SmallVec;
+
+use super::audio_thread::DSEngineAudioThread;
+use super::main_thread::GraphPortInfo;
+use crate::processor_schedule::GraphNoteEvent;
+
+/// An audio backend which runs a `DSEngineAudioThread` from the process
+/// callback of a JACK client.
+///
+/// A JACK port is registered for every input and output channel of the
+/// audio graph (named after the `GraphPortInfo` of that channel), along
+/// with a MIDI input port for every note port on the input of the audio
+/// graph and a MIDI output port for every note port on the output of the
+/// audio graph.
+pub struct JackAudioBackend {
+    client: Option<jack::Client>,
+    active_client: Option<jack::AsyncClient<(), JackProcessHandler>>,
+}
+
+impl JackAudioBackend {
+    /// Open a new JACK client with the given name.
+    ///
+    /// This will not start the JACK server if it is not already running.
+    ///
+    /// Use `Self::sample_rate()` and `Self::buffer_size()` to get the
+    /// settings to activate the engine with.
+    pub fn new(client_name: &str) -> Result<Self, jack::Error> {
+        let (client, status) =
+            jack::Client::new(client_name, jack::ClientOptions::NO_START_SERVER)?;
+
+        log::info!("Opened JACK client {} with status {:?}", client.name(), status);
+
+        Ok(Self { client: Some(client), active_client: None })
+    }
+
+    /// The sample rate of the JACK server.
+    pub fn sample_rate(&self) -> SampleRate {
+        let sample_rate = if let Some(client) = &self.client {
+            client.sample_rate()
+        } else {
+            self.active_client.as_ref().unwrap().as_client().sample_rate()
+        };
+
+        (sample_rate as u32).into()
+    }
+
+    /// The current buffer size of the JACK server.
+    pub fn buffer_size(&self) -> u32 {
+        if let Some(client) = &self.client {
+            client.buffer_size()
+        } else {
+            self.active_client.as_ref().unwrap().as_client().buffer_size()
+        }
+    }
+
+    /// Register the ports and start calling the given audio thread from the
+    /// JACK process callback.
+    ///
+    /// The engine must be activated with the same sample rate as the JACK
+    /// server.
+    ///
+    /// This will return an error if the backend is already running.
+    pub fn start(&mut self, audio_thread: DSEngineAudioThread) -> Result<(), jack::Error> {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => return Err(jack::Error::ClientActivationError),
+        };
+
+        let mut in_ports = Vec::with_capacity(audio_thread.num_audio_in_channels());
+        for name in channel_port_names(audio_thread.graph_in_ports()) {
+            in_ports.push(client.register_port(&name, jack::AudioIn::default())?);
+        }
+
+        let mut out_ports = Vec::with_capacity(audio_thread.num_audio_out_channels());
+        for name in channel_port_names(audio_thread.graph_out_ports()) {
+            out_ports.push(client.register_port(&name, jack::AudioOut::default())?);
+        }
+
+        let mut midi_in_ports = Vec::with_capacity(audio_thread.num_note_in_ports());
+        for i in 0..audio_thread.num_note_in_ports() {
+            midi_in_ports.push(
+                client.register_port(&format!("midi_in_{}", i + 1), jack::MidiIn::default())?,
+            );
+        }
+
+        let mut midi_out_ports = Vec::with_capacity(audio_thread.num_note_out_ports());
+        for i in 0..audio_thread.num_note_out_ports() {
+            midi_out_ports.push(
+                client.register_port(&format!("midi_out_{}", i + 1), jack::MidiOut::default())?,
+            );
+        }
+
+        let process_handler =
+            JackProcessHandler { audio_thread, in_ports, out_ports, midi_in_ports, midi_out_ports };
+
+        self.active_client = Some(client.activate_async((), process_handler)?);
+
+        log::info!("Started JACK audio backend");
+
+        Ok(())
+    }
+
+    /// Stop calling the audio thread and unregister all ports.
+    ///
+    /// This returns the audio thread if the backend was running.
+    pub fn stop(&mut self) -> Option<DSEngineAudioThread> {
+        if let Some(active_client) = self.active_client.take() {
+            match active_client.deactivate() {
+                Ok((client, _, process_handler)) => {
+                    self.client = Some(client);
+
+                    log::info!("Stopped JACK audio backend");
+
+                    Some(process_handler.audio_thread)
+                }
+                Err(e) => {
+                    log::error!("Failed to deactivate JACK client: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for JackAudioBackend {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+struct JackProcessHandler {
+    audio_thread: DSEngineAudioThread,
+
+    in_ports: Vec<jack::Port<jack::AudioIn>>,
+    out_ports: Vec<jack::Port<jack::AudioOut>>,
+    midi_in_ports: Vec<jack::Port<jack::MidiIn>>,
+    midi_out_ports: Vec<jack::Port<jack::MidiOut>>,
+}
+
+impl jack::ProcessHandler for JackProcessHandler {
+    fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+        // The events are sent to the audio graph along with the audio of
+        // this cycle, so the frame of each event is kept as is.
+        for (port_i, midi_in_port) in self.midi_in_ports.iter().enumerate() {
+            for raw_event in midi_in_port.iter(ps) {
+                if let Some(event) =
+                    GraphNoteEvent::from_midi(port_i as u16, raw_event.time, raw_event.bytes)
+                {
+                    if !self.audio_thread.push_note_event(event) {
+                        log::error!("Ran out of space for JACK MIDI input events");
+                        break;
+                    }
+                }
+            }
+        }
+
+        let in_buffers: SmallVec<[&[f32]; 8]> =
+            self.in_ports.iter().map(|p| p.as_slice(ps)).collect();
+        let mut out_buffers: SmallVec<[&mut [f32]; 8]> =
+            self.out_ports.iter_mut().map(|p| p.as_mut_slice(ps)).collect();
+
+        self.audio_thread.process_planar(&in_buffers, &mut out_buffers);
+
+        // The events of each note port are already sorted by frame.
+        for (port_i, midi_out_port) in self.midi_out_ports.iter_mut().enumerate() {
+            let mut writer = midi_out_port.writer(ps);
+
+            for event in
+                self.audio_thread.note_out_events().iter().filter(|e| usize::from(e.port) == port_i)
+            {
+                if let Some(bytes) = event.to_midi() {
+                    if writer.write(&jack::RawMidi { time: event.frame, bytes: &bytes }).is_err() {
+                        log::error!("Ran out of space in JACK MIDI output buffer");
+                        break;
+                    }
+                }
+            }
+        }
+
+        jack::Control::Continue
+    }
+}
+
+/// The name of the JACK port for each channel of the given graph ports.
+///
+/// Ports with more than one channel get the number of the channel appended
+/// to their name (i.e. "Main Out 1" and "Main Out 2").
+fn channel_port_names(ports: &[GraphPortInfo]) -> Vec<String> {
+    let mut names = Vec::new();
+    for port in ports.iter() {
+        if port.channels == 1 {
+            names.push(port.display_name.clone());
+        } else {
+            for ch in 0..port.channels {
+                names.push(format!("{} {}", &port.display_name, ch + 1));
+            }
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use dropseed_plugin_api::HostInfo;
+
+    use super::*;
+    use crate::engine::{ActivateEngineSettings, DSEngineMainThread, EngineSettings};
+
+    #[test]
+    fn port_names_use_display_names() {
+        let ports = vec![
+            GraphPortInfo { stable_id: 0, channels: 2, display_name: "Main Out".into() },
+            GraphPortInfo { stable_id: 1, channels: 1, display_name: "Click".into() },
+        ];
+
+        assert_eq!(channel_port_names(&ports), vec!["Main Out 1", "Main Out 2", "Click"]);
+    }
+
+    /// This needs a running JACK server. The dummy driver works without any
+    /// audio hardware:
+    ///
+    /// `jackd -d dummy -r 48000 -p 256`
+    #[test]
+    #[ignore = "requires a running JACK server (i.e. `jackd -d dummy`)"]
+    fn dummy_driver() {
+        let mut backend = JackAudioBackend::new("dropseed-test").unwrap();
+
+        let (mut ds_engine, _, _) = DSEngineMainThread::new(
+            HostInfo::new("Dropseed Test".into(), "0.0.0".into(), None, None),
+            EngineSettings::default(),
+            vec![],
+        );
+
+        let (_, audio_thread) = ds_engine
+            .activate_engine(ActivateEngineSettings {
+                sample_rate: backend.sample_rate(),
+                max_frames: backend.buffer_size(),
+                graph_out_ports: vec![GraphPortInfo {
+                    stable_id: 0,
+                    channels: 2,
+                    display_name: "Main Out".into(),
+                }],
+                num_note_in_ports: 2,
+                num_note_out_ports: 1,
+                ..Default::default()
+            })
+            .unwrap();
+
+        backend.start(audio_thread).unwrap();
+
+        {
+            let client = backend.active_client.as_ref().unwrap().as_client();
+            for port in [
+                "Input 1",
+                "Input 2",
+                "Main Out 1",
+                "Main Out 2",
+                "midi_in_1",
+                "midi_in_2",
+                "midi_out_1",
+            ] {
+                let name = format!("{}:{}", client.name(), port);
+                assert!(client.port_by_name(&name).is_some(), "missing JACK port {}", name);
+            }
+        }
+
+        // Let the JACK server call the process callback a few times.
+        std::thread::sleep(Duration::from_millis(200));
+
+        assert!(backend.stop().is_some());
+
+        ds_engine.deactivate_engine();
+    }
+}
@@ -1,6 +1,6 @@
 use basedrop::{Collector, Shared, SharedCell};
 use fnv::FnvHashSet;
-use meadowlark_core_types::time::{SampleRate, Seconds};
+use meadowlark_core_types::time::{Frames, SampleRate, Seconds};
 use smallvec::SmallVec;
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -18,27 +18,248 @@ use dropseed_plugin_api::transport::TempoMap;
 use dropseed_plugin_api::{DSPluginSaveState, HostInfo, PluginFactory, PluginInstanceID};
 
 use crate::engine::audio_thread::DSEngineAudioThread;
+use crate::engine::offline_render::{OfflineEventInput, OfflineRenderRange, OfflineRenderer};
+use crate::graph::error::GraphCompilerError;
+use crate::graph::export::{self, ExportFormat, ScheduleSnapshot};
+use crate::graph::shared_pools::SharedProcessorSchedule;
 use crate::graph::{AudioGraph, DSEdgeID, Edge};
 use crate::plugin_host::error::{ActivatePluginError, RescanParamListError};
-use crate::plugin_host::{ParamModifiedInfo, PluginHostMainThread};
+use crate::plugin_host::{
+    ParamModifiedInfo, PluginHostMainThread, PluginWatchdogSettings, WatchdogAction,
+};
 use crate::plugin_scanner::{PluginScanner, ScanExternalPluginsRes};
-use crate::processor_schedule::TransportHandle;
+use crate::processor_schedule::parallel::WorkerPool;
+use crate::processor_schedule::{GraphNoteEvent, TransportHandle};
 use crate::utils::thread_id::SharedThreadIDs;
 
-use super::error::{EngineCrashError, NewPluginInstanceError};
-use super::modify_request::{ModifyGraphRequest, PluginIDReq};
+use super::error::{
+    EngineCrashError, ModifyGraphError, NewPluginInstanceError, OfflineRenderError,
+    ReconfigureEngineError, RestoreGraphError,
+};
+use super::graph_recovery::GraphRecovery;
+use super::graph_save_state::DSGraphSaveState;
+use super::modify_request::ModifyGraphRequest;
+use super::rack::RackInfo;
+use super::telemetry::{EngineTelemetry, SharedEngineTelemetry};
 use super::timer_wheel::{EngineTimerWheel, TimerEntry, TimerEntryKey};
+use super::undo::{GraphChange, UndoStack};
 use super::{DEFAULT_GARBAGE_COLLECT_INTERVAL_MS, DEFAULT_IDLE_INTERVAL_MS};
 
 struct ActivatedState {
+    settings: ActivateEngineSettings,
+    undo_stack: Option<UndoStack>,
     audio_graph: AudioGraph,
     run_process_thread: Arc<AtomicBool>,
     process_thread_handle: Option<JoinHandle<()>>,
+    offline_renderer: Option<OfflineRenderer>,
+    worker_pool: Option<WorkerPool>,
     tempo_map_shared: Shared<SharedCell<(Shared<TempoMap>, u64)>>,
+    transport_handle: TransportHandle,
+    graph_recovery: Option<GraphRecovery>,
+    telemetry: Arc<SharedEngineTelemetry>,
+    last_telemetry_instant: Instant,
 }
 
-impl Drop for ActivatedState {
-    fn drop(&mut self) {
+impl ActivatedState {
+    /// Apply the given request to the audio graph.
+    ///
+    /// The first new plugins in the request reuse the IDs in `reuse_ids`
+    /// (see `AudioGraph::add_new_plugin_instance()`).
+    ///
+    /// If `collect_inverse` is `true`, then this also returns the change
+    /// that undoes this modification.
+    fn modify_graph(
+        &mut self,
+        mut request: ModifyGraphRequest,
+        reuse_ids: &[PluginInstanceID],
+        request_id: u64,
+        collect_inverse: bool,
+        timer_wheel: &mut EngineTimerWheel,
+        plugin_scanner: &mut PluginScanner,
+    ) -> (ModifyGraphRes, Option<GraphChange>) {
+        let new_plugins_res: Vec<NewPluginRes> = request
+            .add_plugin_instances
+            .drain(..)
+            .enumerate()
+            .map(|(i, save_state)| {
+                self.audio_graph.add_new_plugin_instance(
+                    save_state,
+                    reuse_ids.get(i),
+                    plugin_scanner,
+                    true,
+                    timer_wheel,
+                )
+            })
+            .collect();
+
+        self.apply_modify_request(
+            request,
+            request_id,
+            new_plugins_res,
+            collect_inverse,
+            timer_wheel,
+        )
+    }
+
+    /// Apply the given request to the audio graph only if the whole request
+    /// is valid. Otherwise the audio graph is left unchanged.
+    ///
+    /// If `collect_inverse` is `true`, then this also returns the change
+    /// that undoes this modification.
+    fn modify_graph_strict(
+        &mut self,
+        mut request: ModifyGraphRequest,
+        request_id: u64,
+        collect_inverse: bool,
+        timer_wheel: &mut EngineTimerWheel,
+        plugin_scanner: &mut PluginScanner,
+    ) -> Result<(ModifyGraphRes, Option<GraphChange>), ModifyGraphError> {
+        self.audio_graph.validate_modify_request(&request)?;
+
+        // The new plugins need to be created before the new edges can be
+        // validated, since their ports are not known until they are
+        // activated.
+        let new_plugins_res: Vec<NewPluginRes> = request
+            .add_plugin_instances
+            .drain(..)
+            .map(|save_state| {
+                self.audio_graph.add_new_plugin_instance(
+                    save_state,
+                    None,
+                    plugin_scanner,
+                    true,
+                    timer_wheel,
+                )
+            })
+            .collect();
+
+        let new_plugin_ids: Vec<PluginInstanceID> =
+            new_plugins_res.iter().map(|res| res.plugin_id.clone()).collect();
+
+        let failed_plugin = new_plugins_res.iter().position(|res| {
+            matches!(res.status, PluginStatus::LoadError(_) | PluginStatus::ActivationError(_))
+        });
+        if let Some(i) = failed_plugin {
+            self.audio_graph.remove_plugin_instances(&new_plugin_ids, timer_wheel);
+
+            return Err(match new_plugins_res.into_iter().nth(i).unwrap().status {
+                PluginStatus::LoadError(e) => ModifyGraphError::PluginFailedToLoad(i, e),
+                PluginStatus::ActivationError(e) => ModifyGraphError::PluginFailedToActivate(i, e),
+                _ => unreachable!(),
+            });
+        }
+
+        if let Err(e) = self.audio_graph.validate_new_edges(&request, &new_plugin_ids) {
+            self.audio_graph.remove_plugin_instances(&new_plugin_ids, timer_wheel);
+
+            return Err(ModifyGraphError::ConnectEdgeError(e));
+        }
+
+        Ok(self.apply_modify_request(
+            request,
+            request_id,
+            new_plugins_res,
+            collect_inverse,
+            timer_wheel,
+        ))
+    }
+
+    /// Apply the given request to the audio graph, where the new plugins in
+    /// the request have already been added to the audio graph.
+    fn apply_modify_request(
+        &mut self,
+        request: ModifyGraphRequest,
+        request_id: u64,
+        new_plugins_res: Vec<NewPluginRes>,
+        collect_inverse: bool,
+        timer_wheel: &mut EngineTimerWheel,
+    ) -> (ModifyGraphRes, Option<GraphChange>) {
+        let mut removed_edges: FnvHashSet<DSEdgeID> = FnvHashSet::default();
+
+        let edges_before = if collect_inverse { self.audio_graph.edges() } else { Vec::new() };
+
+        for ds_edge_id in request.disconnect_edges.iter() {
+            if self.audio_graph.disconnect_edge(*ds_edge_id) {
+                removed_edges.insert(*ds_edge_id);
+            }
+        }
+
+        // Keep the save states of the removed plugins in case the graph
+        // needs to be rolled back, or the removal needs to be undone.
+        let mut removed_save_states: Vec<(PluginInstanceID, DSPluginSaveState)> = Vec::new();
+        if collect_inverse || self.graph_recovery.is_some() {
+            for plugin_id in request.remove_plugin_instances.iter() {
+                if let Some(plugin_host) = self.audio_graph.get_plugin_host_mut(plugin_id) {
+                    if !plugin_host.is_remove_requested() {
+                        let save_state = plugin_host.collect_save_state();
+
+                        if let Some(graph_recovery) = &mut self.graph_recovery {
+                            graph_recovery.on_plugin_removed(plugin_id, save_state.clone());
+                        }
+
+                        removed_save_states.push((plugin_id.clone(), save_state));
+                    }
+                }
+            }
+        }
+
+        let (mut removed_plugins, removed_plugin_edges) =
+            self.audio_graph.remove_plugin_instances(&request.remove_plugin_instances, timer_wheel);
+        removed_edges.extend(removed_plugin_edges);
+
+        let new_plugin_ids: Vec<PluginInstanceID> =
+            new_plugins_res.iter().map(|res| res.plugin_id.clone()).collect();
+
+        let new_edges =
+            self.audio_graph.connect_new_edges(&request.connect_new_edges, &new_plugin_ids);
+
+        let inverse = if collect_inverse {
+            removed_save_states.retain(|(plugin_id, _)| removed_plugins.contains(plugin_id));
+
+            Some(GraphChange::inverse_of(
+                removed_save_states,
+                edges_before.into_iter().filter(|edge| removed_edges.contains(&edge.id)).collect(),
+                new_plugin_ids,
+                new_edges.clone(),
+            ))
+        } else {
+            None
+        };
+
+        (
+            ModifyGraphRes {
+                request_id,
+                new_plugins: new_plugins_res,
+                removed_plugins: removed_plugins.drain().collect(),
+                new_edges,
+                removed_edges: removed_edges.drain().collect(),
+            },
+            inverse,
+        )
+    }
+
+    /// Queue the audio graph to be compiled in the compiler thread.
+    ///
+    /// This returns the version of the schedule that will be compiled.
+    fn compile_audio_graph(&mut self) -> u64 {
+        let schedule_version = self.audio_graph.compile();
+
+        if let Some(graph_recovery) = &mut self.graph_recovery {
+            graph_recovery.on_compile_requested(
+                schedule_version,
+                self.audio_graph.plugin_ids(),
+                self.audio_graph.edges(),
+            );
+        }
+
+        schedule_version
+    }
+}
+
+impl ActivatedState {
+    /// Stop the process thread (and the worker threads), making sure that
+    /// all plugin processors have been dropped in the process thread.
+    fn stop_process_thread(&mut self) {
         // Attempt to gracefully stop the process thread.
         self.run_process_thread.store(false, Ordering::Relaxed);
 
@@ -47,6 +268,22 @@ impl Drop for ActivatedState {
                 log::error!("Failed to join process thread handle: {:?}", e);
             }
         }
+
+        // In offline mode this thread is the process thread, so make sure
+        // that all plugin processors are dropped here.
+        if let Some(offline_renderer) = &mut self.offline_renderer {
+            offline_renderer.deactivate();
+        }
+
+        // The worker threads are only used by the process thread, so it is
+        // safe to stop them now.
+        self.worker_pool = None;
+    }
+}
+
+impl Drop for ActivatedState {
+    fn drop(&mut self) {
+        self.stop_process_thread();
     }
 }
 
@@ -74,6 +311,8 @@ pub struct DSEngineMainThread {
     collector: Collector,
     crash_msg: Option<EngineCrashError>,
     cached_elapsed_entries: Option<Vec<Rc<TimerEntry>>>,
+    queued_events: Vec<OnIdleEvent>,
+    next_modify_request_id: u64,
 }
 
 impl DSEngineMainThread {
@@ -127,6 +366,8 @@ impl DSEngineMainThread {
                 collector,
                 crash_msg: None,
                 cached_elapsed_entries: None,
+                queued_events: Vec::new(),
+                next_modify_request_id: 0,
             },
             next_timer_callback_instant,
             internal_plugins_res,
@@ -171,6 +412,109 @@ impl DSEngineMainThread {
         self.activated_state.as_mut().and_then(|a| a.audio_graph.get_plugin_host_mut(id))
     }
 
+    /// The IDs of all the plugins in the audio graph, not including the
+    /// graph input/output nodes.
+    ///
+    /// This will return an empty list if the engine is deactivated.
+    pub fn plugin_ids(&self) -> Vec<PluginInstanceID> {
+        self.activated_state.as_ref().map(|a| a.audio_graph.plugin_ids()).unwrap_or_default()
+    }
+
+    /// All of the edges (port connections) in the audio graph.
+    ///
+    /// This will return an empty list if the engine is deactivated.
+    pub fn edges(&self) -> Vec<Edge> {
+        self.activated_state
+            .as_ref()
+            .map(|a| a.audio_graph.connected_edges().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// All of the edges (port connections) to and from the given plugin.
+    ///
+    /// This will return an empty list if the engine is deactivated.
+    pub fn plugin_edges(&self, id: &PluginInstanceID) -> Vec<Edge> {
+        self.activated_state
+            .as_ref()
+            .map(|a| {
+                a.audio_graph
+                    .connected_edges()
+                    .filter(|edge| &edge.src_plugin_id == id || &edge.dst_plugin_id == id)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The plugins (including the graph input node) that are connected to
+    /// the inputs of the given plugin.
+    ///
+    /// This will return an empty list if the engine is deactivated.
+    pub fn upstream_plugins(&self, id: &PluginInstanceID) -> Vec<PluginInstanceID> {
+        self.activated_state
+            .as_ref()
+            .map(|a| a.audio_graph.upstream_plugins(id))
+            .unwrap_or_default()
+    }
+
+    /// The plugins (including the graph output node) that are connected to
+    /// the outputs of the given plugin.
+    ///
+    /// This will return an empty list if the engine is deactivated.
+    pub fn downstream_plugins(&self, id: &PluginInstanceID) -> Vec<PluginInstanceID> {
+        self.activated_state
+            .as_ref()
+            .map(|a| a.audio_graph.downstream_plugins(id))
+            .unwrap_or_default()
+    }
+
+    /// All of the plugins in the audio graph (including the graph
+    /// input/output nodes) in topological order, meaning that every plugin
+    /// comes after all of the plugins connected to its inputs.
+    ///
+    /// Note that this is not necessarily the order that the plugins are
+    /// processed in.
+    ///
+    /// This will return an empty list if the engine is deactivated.
+    pub fn topological_order(&self) -> Vec<PluginInstanceID> {
+        self.activated_state.as_ref().map(|a| a.audio_graph.topological_order()).unwrap_or_default()
+    }
+
+    /// Render the audio graph (including the graph input/output nodes) in
+    /// the given format. This is useful for debugging.
+    ///
+    /// This will return `None` if the engine is deactivated.
+    pub fn export_graph(&self, format: ExportFormat) -> Option<String> {
+        self.activated_state.as_ref().map(|a| {
+            let mut plugin_ids = vec![a.audio_graph.graph_in_id().clone()];
+            plugin_ids.append(&mut a.audio_graph.plugin_ids());
+            plugin_ids.push(a.audio_graph.graph_out_id().clone());
+
+            let edges: Vec<Edge> = a.audio_graph.connected_edges().cloned().collect();
+
+            export::export_graph(&plugin_ids, &edges, format)
+        })
+    }
+
+    /// A description of the latest compiled schedule that was sent to the
+    /// process thread. This is useful for debugging delay compensation,
+    /// the insertion of sum tasks and the assignment of buffers.
+    ///
+    /// Note that this blocks the current thread while the audio graph is
+    /// being compiled.
+    ///
+    /// This will return `None` if the engine is deactivated.
+    pub fn schedule_snapshot(&self) -> Option<ScheduleSnapshot> {
+        self.activated_state.as_ref().and_then(|a| a.audio_graph.schedule_snapshot())
+    }
+
+    /// Render the latest compiled schedule in the given format.
+    ///
+    /// See `Self::schedule_snapshot()` for more details.
+    pub fn export_schedule(&self, format: ExportFormat) -> Option<String> {
+        self.schedule_snapshot().map(|snapshot| snapshot.export(format))
+    }
+
     /// This must be called periodically.
     ///
     /// This will return a list of events that have occured, as well as the next
@@ -178,12 +522,17 @@ impl DSEngineMainThread {
     pub fn on_timer(&mut self) -> (SmallVec<[OnIdleEvent; 32]>, Instant) {
         let mut events_out: SmallVec<[OnIdleEvent; 32]> = SmallVec::new();
 
+        self.poll_audio_graph_compiler(false);
+
         if let Some(msg) = self.crash_msg.take() {
             events_out.push(OnIdleEvent::EngineDeactivated(
                 EngineDeactivatedStatus::EngineCrashed(Box::new(msg)),
             ));
         }
 
+        // Events that occured while rendering in offline mode.
+        events_out.extend(self.queued_events.drain(..));
+
         let mut elapsed_entries =
             self.cached_elapsed_entries.take().unwrap_or_else(|| Vec::with_capacity(32));
         elapsed_entries.clear();
@@ -199,8 +548,15 @@ impl DSEngineMainThread {
                             .on_idle(&mut events_out, &mut self.timer_wheel);
 
                         if recompile {
-                            self.compile_audio_graph();
+                            activated_state.compile_audio_graph();
                         }
+
+                        activated_state.audio_graph.poll_racks(
+                            &mut events_out,
+                            &mut self.plugin_scanner,
+                            &mut self.timer_wheel,
+                            false,
+                        );
                     }
                 }
                 TimerEntryKey::GarbageCollectTimer => {
@@ -208,12 +564,11 @@ impl DSEngineMainThread {
                 }
                 TimerEntryKey::PluginRegisteredTimer { plugin_unique_id, timer_id } => {
                     if let Some(activated_state) = &mut self.activated_state {
-                        if let Some(plugin_host) = activated_state
-                            .audio_graph
-                            .get_plugin_host_by_unique_id_mut(plugin_unique_id)
-                        {
-                            plugin_host.on_timer(timer_id, &mut self.timer_wheel);
-                        }
+                        activated_state.audio_graph.on_plugin_timer(
+                            plugin_unique_id,
+                            timer_id,
+                            &mut self.timer_wheel,
+                        );
                     }
                 }
             }
@@ -256,6 +611,30 @@ impl DSEngineMainThread {
         &mut self,
         settings: ActivateEngineSettings,
     ) -> Option<(ActivatedEngineInfo, DSEngineAudioThread)> {
+        self.activate_engine_inner(settings, false)
+            .map(|(info, audio_thread)| (info, audio_thread.unwrap()))
+    }
+
+    /// Activate the engine in offline mode.
+    ///
+    /// Instead of spawning a process thread, the audio graph is processed
+    /// on the calling thread as fast as possible with
+    /// `Self::render_offline()`. This is useful for exporting projects to
+    /// disk and for tests that need deterministic output.
+    ///
+    /// This will return `None` if the engine is already activated.
+    pub fn activate_engine_offline(
+        &mut self,
+        settings: ActivateEngineSettings,
+    ) -> Option<ActivatedEngineInfo> {
+        self.activate_engine_inner(settings, true).map(|(info, _)| info)
+    }
+
+    fn activate_engine_inner(
+        &mut self,
+        settings: ActivateEngineSettings,
+        offline: bool,
+    ) -> Option<(ActivatedEngineInfo, Option<DSEngineAudioThread>)> {
         if self.activated_state.is_some() {
             log::warn!("Ignored request to activate RustyDAW engine: Engine is already activated");
             return None;
@@ -263,6 +642,11 @@ impl DSEngineMainThread {
 
         log::info!("Activating RustyDAW engine...");
 
+        let graph_in_ports =
+            graph_io_ports(&settings.graph_in_ports, settings.num_audio_in_channels, true)?;
+        let graph_out_ports =
+            graph_io_ports(&settings.graph_out_ports, settings.num_audio_out_channels, false)?;
+
         let num_audio_in_channels = settings.num_audio_in_channels;
         let num_audio_out_channels = settings.num_audio_out_channels;
         let min_frames = settings.min_frames;
@@ -272,10 +656,27 @@ impl DSEngineMainThread {
         let event_buffer_size = settings.event_buffer_size;
         let transport_declick_time = settings.transport_declick_time;
 
+        let worker_pool = if settings.num_worker_threads > 0 {
+            Some(WorkerPool::new(
+                settings.num_worker_threads,
+                &self.thread_ids,
+                &self.collector.handle(),
+            ))
+        } else {
+            None
+        };
+
+        let telemetry = Arc::new(SharedEngineTelemetry::new(sample_rate));
+
         let (audio_graph, shared_schedule, transport_handle) = AudioGraph::new(
             self.collector.handle(),
             usize::from(num_audio_in_channels),
             usize::from(num_audio_out_channels),
+            graph_in_ports.clone(),
+            graph_out_ports.clone(),
+            usize::from(settings.num_note_in_ports),
+            usize::from(settings.num_note_out_ports),
+            settings.automation_in_port,
             sample_rate,
             min_frames,
             max_frames,
@@ -283,31 +684,22 @@ impl DSEngineMainThread {
             event_buffer_size,
             self.thread_ids.clone(),
             transport_declick_time,
+            settings.plugin_watchdog,
+            worker_pool.as_ref().map(|p| p.handle()),
+            Arc::clone(&telemetry),
             &mut self.timer_wheel,
         );
 
-        let (audio_thread, mut process_thread) = DSEngineAudioThread::new(
-            shared_schedule,
-            sample_rate,
-            num_audio_in_channels as usize,
-            num_audio_out_channels as usize,
-            max_frames as usize,
-            &self.collector.handle(),
-        );
-
-        let run_process_thread = Arc::new(AtomicBool::new(true));
-        let run_process_thread_clone = Arc::clone(&run_process_thread);
-
-        let process_thread_handle =
-            thread_priority::spawn(ThreadPriority::Max, move |priority_res| {
-                if let Err(e) = priority_res {
-                    log::error!("Failed to set process thread priority to max: {:?}", e);
-                } else {
-                    log::info!("Successfully set process thread priority to max");
-                }
-
-                process_thread.run(run_process_thread_clone);
-            });
+        let (audio_thread, run_process_thread, process_thread_handle, offline_renderer) = self
+            .start_process_thread(
+                shared_schedule,
+                &transport_handle,
+                &settings,
+                &graph_in_ports,
+                &graph_out_ports,
+                offline,
+                &telemetry,
+            );
 
         let tempo_map_shared = transport_handle.tempo_map_shared();
         let tempo_map = (*tempo_map_shared.get().0).clone();
@@ -321,17 +713,41 @@ impl DSEngineMainThread {
             transport_handle,
             num_audio_in_channels,
             num_audio_out_channels,
+            num_note_in_ports: settings.num_note_in_ports,
+            num_note_out_ports: settings.num_note_out_ports,
+            automation_in_port: settings.automation_in_port,
+            graph_in_ports,
+            graph_out_ports,
             tempo_map,
         };
 
-        self.activated_state = Some(ActivatedState {
+        let undo_stack = if settings.undo_stack_size > 0 {
+            Some(UndoStack::new(settings.undo_stack_size))
+        } else {
+            None
+        };
+        let graph_recovery =
+            if settings.recover_from_compiler_errors { Some(GraphRecovery::new()) } else { None };
+
+        let mut activated_state = ActivatedState {
+            settings,
             audio_graph,
             run_process_thread,
-            process_thread_handle: Some(process_thread_handle),
+            process_thread_handle,
+            offline_renderer,
+            worker_pool,
             tempo_map_shared,
-        });
+            transport_handle: info.transport_handle.clone_handle(),
+            undo_stack,
+            graph_recovery,
+            telemetry,
+            last_telemetry_instant: Instant::now(),
+        };
+
+        activated_state.compile_audio_graph();
 
-        self.compile_audio_graph();
+        self.activated_state = Some(activated_state);
+        self.poll_audio_graph_compiler(true);
 
         if self.activated_state.is_none() {
             panic!("Unexpected error: Empty audio graph failed to compile a schedule.");
@@ -342,98 +758,649 @@ impl DSEngineMainThread {
         Some((info, audio_thread))
     }
 
+    /// Reconfigure the engine with new settings (i.e. a new sample rate or
+    /// block size) without removing any plugins from the audio graph.
+    ///
+    /// Every plugin is deactivated and then re-activated with the new
+    /// settings, and the buffers of the audio graph are re-allocated. The
+    /// IDs of all plugins and edges stay the same. The result of
+    /// re-activating each plugin is sent as an `OnIdleEvent::PluginActivated`
+    /// or an `OnIdleEvent::PluginDeactivated` event in `Self::on_timer()`.
+    ///
+    /// The tempo map and the undo history are kept. The oldest entries of
+    /// the undo history are forgotten if `undo_stack_size` is now smaller.
+    ///
+    /// The old audio thread and transport handle stop working after this,
+    /// so replace them with the new ones that are returned. The returned
+    /// audio thread is `None` if the engine was activated in offline mode.
+    ///
+    /// Note that the audio channels, audio ports, and note ports of the
+    /// audio graph cannot be changed this way.
+    pub fn reconfigure_engine(
+        &mut self,
+        settings: ActivateEngineSettings,
+    ) -> Result<(ActivatedEngineInfo, Option<DSEngineAudioThread>), ReconfigureEngineError> {
+        let activated_state =
+            self.activated_state.as_ref().ok_or(ReconfigureEngineError::EngineDeactivated)?;
+
+        if settings.num_audio_in_channels != activated_state.settings.num_audio_in_channels
+            || settings.num_audio_out_channels != activated_state.settings.num_audio_out_channels
+            || settings.graph_in_ports != activated_state.settings.graph_in_ports
+            || settings.graph_out_ports != activated_state.settings.graph_out_ports
+        {
+            return Err(ReconfigureEngineError::AudioChannelsChanged);
+        }
+        if settings.num_note_in_ports != activated_state.settings.num_note_in_ports
+            || settings.num_note_out_ports != activated_state.settings.num_note_out_ports
+        {
+            return Err(ReconfigureEngineError::NotePortsChanged);
+        }
+        if settings.automation_in_port != activated_state.settings.automation_in_port {
+            return Err(ReconfigureEngineError::AutomationPortChanged);
+        }
+        if !activated_state.audio_graph.feedback_delays_fit(settings.max_frames) {
+            return Err(ReconfigureEngineError::FeedbackDelayTooShort);
+        }
+
+        // Make sure that all changes to the audio graph have been compiled.
+        self.poll_audio_graph_compiler(true);
+
+        let activated_state =
+            self.activated_state.as_mut().ok_or(ReconfigureEngineError::EngineCrashed)?;
+
+        log::info!("Reconfiguring RustyDAW engine...");
+
+        let offline = activated_state.offline_renderer.is_some();
+
+        let was_playing = activated_state.transport_handle.is_playing();
+        let transport_save_state = activated_state.transport_handle.save_state();
+        // The tempo map depends on the sample rate.
+        let mut tempo_map = (*activated_state.tempo_map_shared.get().0).clone();
+        tempo_map.sample_rate = settings.sample_rate;
+
+        let active_plugins = activated_state.audio_graph.deactivate_all_plugins();
+
+        activated_state.stop_process_thread();
+
+        let worker_pool = if settings.num_worker_threads > 0 {
+            Some(WorkerPool::new(
+                settings.num_worker_threads,
+                &self.thread_ids,
+                &self.collector.handle(),
+            ))
+        } else {
+            None
+        };
+
+        let telemetry = Arc::new(SharedEngineTelemetry::new(settings.sample_rate));
+
+        let (shared_schedule, mut transport_handle, activation_results) =
+            activated_state.audio_graph.reconfigure(
+                settings.sample_rate,
+                settings.min_frames,
+                settings.max_frames,
+                settings.note_buffer_size,
+                settings.event_buffer_size,
+                transport_save_state,
+                Some(tempo_map.clone()),
+                settings.transport_declick_time,
+                settings.plugin_watchdog,
+                worker_pool.as_ref().map(|p| p.handle()),
+                Arc::clone(&telemetry),
+                &active_plugins,
+            );
+
+        for (plugin_id, res) in activation_results {
+            self.queued_events.push(match res {
+                Ok(status) => OnIdleEvent::PluginActivated { plugin_id, status },
+                Err(e) => OnIdleEvent::PluginDeactivated { plugin_id, status: Err(e) },
+            });
+        }
+
+        transport_handle.set_playing(was_playing);
+
+        // Give the plugins the tempo map of the new transport before the
+        // process thread is started.
+        activated_state
+            .audio_graph
+            .update_tempo_map(Shared::clone(&transport_handle.tempo_map_shared().get().0));
+
+        let graph_in_ports = activated_state.audio_graph.graph_in_ports().to_vec();
+        let graph_out_ports = activated_state.audio_graph.graph_out_ports().to_vec();
+
+        let (audio_thread, run_process_thread, process_thread_handle, offline_renderer) = self
+            .start_process_thread(
+                shared_schedule,
+                &transport_handle,
+                &settings,
+                &graph_in_ports,
+                &graph_out_ports,
+                offline,
+                &telemetry,
+            );
+
+        let activated_state = self.activated_state.as_mut().unwrap();
+
+        activated_state.settings = settings.clone();
+        activated_state.run_process_thread = run_process_thread;
+        activated_state.process_thread_handle = process_thread_handle;
+        activated_state.offline_renderer = offline_renderer;
+        activated_state.worker_pool = worker_pool;
+        activated_state.tempo_map_shared = transport_handle.tempo_map_shared();
+        activated_state.transport_handle = transport_handle.clone_handle();
+        activated_state.telemetry = telemetry;
+        activated_state.last_telemetry_instant = Instant::now();
+
+        if settings.undo_stack_size == 0 {
+            activated_state.undo_stack = None;
+        } else if let Some(undo_stack) = &mut activated_state.undo_stack {
+            undo_stack.set_max_len(settings.undo_stack_size);
+        } else {
+            activated_state.undo_stack = Some(UndoStack::new(settings.undo_stack_size));
+        }
+
+        if !settings.recover_from_compiler_errors {
+            activated_state.graph_recovery = None;
+        } else if activated_state.graph_recovery.is_none() {
+            activated_state.graph_recovery = Some(GraphRecovery::new());
+        }
+
+        let graph_in_id = activated_state.audio_graph.graph_in_id().clone();
+        let graph_out_id = activated_state.audio_graph.graph_out_id().clone();
+
+        activated_state.compile_audio_graph();
+        self.poll_audio_graph_compiler(true);
+
+        if self.activated_state.is_none() {
+            return Err(ReconfigureEngineError::EngineCrashed);
+        }
+
+        log::info!("Successfully reconfigured RustyDAW engine");
+
+        Ok((
+            ActivatedEngineInfo {
+                graph_in_id,
+                graph_out_id,
+                transport_handle,
+                tempo_map,
+                sample_rate: settings.sample_rate,
+                min_frames: settings.min_frames,
+                max_frames: settings.max_frames,
+                num_audio_in_channels: settings.num_audio_in_channels,
+                num_audio_out_channels: settings.num_audio_out_channels,
+                num_note_in_ports: settings.num_note_in_ports,
+                num_note_out_ports: settings.num_note_out_ports,
+                automation_in_port: settings.automation_in_port,
+                graph_in_ports,
+                graph_out_ports,
+            },
+            audio_thread,
+        ))
+    }
+
+    /// Start processing the given schedule in a new process thread, or in
+    /// the calling thread if `offline` is `true`.
+    fn start_process_thread(
+        &mut self,
+        shared_schedule: SharedProcessorSchedule,
+        transport_handle: &TransportHandle,
+        settings: &ActivateEngineSettings,
+        graph_in_ports: &[GraphPortInfo],
+        graph_out_ports: &[GraphPortInfo],
+        offline: bool,
+        telemetry: &Arc<SharedEngineTelemetry>,
+    ) -> (
+        Option<DSEngineAudioThread>,
+        Arc<AtomicBool>,
+        Option<JoinHandle<()>>,
+        Option<OfflineRenderer>,
+    ) {
+        let run_process_thread = Arc::new(AtomicBool::new(true));
+
+        if offline {
+            // The calling thread acts as the process thread in offline mode.
+            self.thread_ids.set_process_thread_id(thread::current().id(), &self.collector.handle());
+
+            let offline_renderer = OfflineRenderer::new(
+                shared_schedule,
+                transport_handle.clone_handle(),
+                settings.num_audio_in_channels as usize,
+                settings.num_audio_out_channels as usize,
+                settings.num_note_in_ports as usize,
+                settings.automation_in_port,
+                settings.max_frames as usize,
+            );
+
+            (None, run_process_thread, None, Some(offline_renderer))
+        } else {
+            let (audio_thread, mut process_thread) = DSEngineAudioThread::new(
+                shared_schedule,
+                settings.sample_rate,
+                settings.num_audio_in_channels as usize,
+                settings.num_audio_out_channels as usize,
+                settings.num_note_in_ports as usize,
+                settings.num_note_out_ports as usize,
+                settings.automation_in_port,
+                graph_in_ports.to_vec(),
+                graph_out_ports.to_vec(),
+                Arc::clone(telemetry),
+                &self.collector.handle(),
+            );
+
+            let run_process_thread_clone = Arc::clone(&run_process_thread);
+
+            let process_thread_handle =
+                thread_priority::spawn(ThreadPriority::Max, move |priority_res| {
+                    if let Err(e) = priority_res {
+                        log::error!("Failed to set process thread priority to max: {:?}", e);
+                    } else {
+                        log::info!("Successfully set process thread priority to max");
+                    }
+
+                    process_thread.run(run_process_thread_clone);
+                });
+
+            (Some(audio_thread), run_process_thread, Some(process_thread_handle), None)
+        }
+    }
+
+    /// Render the given range of the project in offline mode.
+    ///
+    /// The transport is seeked to the start of the range and played until
+    /// the end of the range, after which it is paused again. The graph
+    /// input is fed silence, and no events are sent to it (use
+    /// `Self::render_offline_with_events()` for that). The interleaved
+    /// output of each processed block (at most `max_frames` frames long) is
+    /// passed to `on_block` as soon as it is rendered, along with the note
+    /// events generated on the note ports of the graph output in that block
+    /// (with `frame` relative to the start of the block).
+    ///
+    /// Plugin requests are polled in between blocks, so the output does
+    /// not depend on how fast the blocks are rendered.
+    ///
+    /// This will return an error if the engine was not activated with
+    /// `Self::activate_engine_offline()`.
+    pub fn render_offline<F: FnMut(&[f32], &[GraphNoteEvent])>(
+        &mut self,
+        range: OfflineRenderRange,
+        on_block: F,
+    ) -> Result<(), OfflineRenderError> {
+        self.render_offline_with_events(range, |_, _| {}, on_block)
+    }
+
+    /// Render the given range of the project in offline mode, while sending
+    /// note and automation events to the input of the audio graph.
+    ///
+    /// Before each block is rendered, `on_block_start` is called with the
+    /// frame on the transport's timeline where the block starts and a handle
+    /// to send events to the graph input in that block. The frames of the
+    /// events are relative to the start of the block, just like they are
+    /// when sending events to a `DSEngineAudioThread`.
+    ///
+    /// See `Self::render_offline()` for more details.
+    pub fn render_offline_with_events<E, F>(
+        &mut self,
+        range: OfflineRenderRange,
+        mut on_block_start: E,
+        mut on_block: F,
+    ) -> Result<(), OfflineRenderError>
+    where
+        E: FnMut(Frames, &mut OfflineEventInput),
+        F: FnMut(&[f32], &[GraphNoteEvent]),
+    {
+        let activated_state =
+            self.activated_state.as_mut().ok_or(OfflineRenderError::EngineDeactivated)?;
+
+        let tempo_map = Shared::clone(&activated_state.tempo_map_shared.get().0);
+
+        let offline_renderer = activated_state
+            .offline_renderer
+            .as_mut()
+            .ok_or(OfflineRenderError::NotInOfflineMode)?;
+
+        let (start, end) = match range {
+            OfflineRenderRange::Frames { start, end } => (start, end),
+            OfflineRenderRange::Musical { start, end } => (
+                tempo_map.musical_to_nearest_frame_round(start),
+                tempo_map.musical_to_nearest_frame_round(end),
+            ),
+        };
+        if end < start {
+            return Err(OfflineRenderError::InvalidRange);
+        }
+
+        offline_renderer.transport_handle.seek_to(tempo_map.frame_to_musical(start));
+        offline_renderer.transport_handle.set_playing(true);
+
+        let total_frames = (end - start).0 as usize;
+        let mut rendered_frames = 0;
+        let mut events_out: SmallVec<[OnIdleEvent; 32]> = SmallVec::new();
+
+        while rendered_frames < total_frames {
+            // Make sure that the latest changes to the graph are processed.
+            self.poll_audio_graph_compiler(true);
+
+            let activated_state =
+                self.activated_state.as_mut().ok_or(OfflineRenderError::EngineCrashed)?;
+            let offline_renderer = activated_state.offline_renderer.as_mut().unwrap();
+
+            let frames = (total_frames - rendered_frames).min(offline_renderer.max_frames());
+
+            (on_block_start)(
+                start + Frames(rendered_frames as u64),
+                &mut offline_renderer.event_input(frames),
+            );
+
+            let (audio_out, note_out) = offline_renderer.process_block(frames);
+            (on_block)(audio_out, note_out);
+
+            rendered_frames += frames;
+
+            // Give plugins a chance to have their requests handled in between
+            // blocks.
+            let recompile =
+                activated_state.audio_graph.on_idle(&mut events_out, &mut self.timer_wheel);
+
+            if recompile {
+                activated_state.compile_audio_graph();
+            }
+
+            activated_state.audio_graph.poll_racks(
+                &mut events_out,
+                &mut self.plugin_scanner,
+                &mut self.timer_wheel,
+                true,
+            );
+            self.queued_events.extend(events_out.drain(..));
+        }
+
+        let activated_state =
+            self.activated_state.as_mut().ok_or(OfflineRenderError::EngineCrashed)?;
+        activated_state.offline_renderer.as_mut().unwrap().transport_handle.set_playing(false);
+
+        Ok(())
+    }
+
+    /// Render the given range of the project in offline mode, and return
+    /// the interleaved output of the whole range.
+    ///
+    /// Any note events generated on the graph output are discarded.
+    ///
+    /// See `Self::render_offline()` for more details.
+    pub fn render_offline_to_vec(
+        &mut self,
+        range: OfflineRenderRange,
+    ) -> Result<Vec<f32>, OfflineRenderError> {
+        let mut out: Vec<f32> = Vec::new();
+
+        self.render_offline(range, |block, _| out.extend_from_slice(block))?;
+
+        Ok(out)
+    }
+
     /// Modify the audio graph.
     ///
+    /// The modified graph is compiled in a separate thread. Once it is done,
+    /// `OnIdleEvent::AudioGraphCompiled` will be sent in `Self::on_timer()`.
+    ///
     /// This will return `None` if the engine is deactivated.
-    pub fn modify_graph(&mut self, mut request: ModifyGraphRequest) -> Option<ModifyGraphRes> {
-        if let Some(activated_state) = &mut self.activated_state {
-            let mut removed_edges: FnvHashSet<DSEdgeID> = FnvHashSet::default();
-            let mut new_edges: Vec<Edge> = Vec::new();
-
-            for ds_edge_id in request.disconnect_edges.iter() {
-                if activated_state.audio_graph.disconnect_edge(*ds_edge_id) {
-                    removed_edges.insert(*ds_edge_id);
-                }
+    pub fn modify_graph(&mut self, request: ModifyGraphRequest) -> Option<ModifyGraphRes> {
+        match self.modify_graph_inner(request, false, false) {
+            Ok((res, _)) => Some(res),
+            Err(e) => {
+                log::warn!("{}", e);
+                None
             }
+        }
+    }
 
-            let (mut removed_plugins, removed_edges) = activated_state
-                .audio_graph
-                .remove_plugin_instances(&request.remove_plugin_instances, &mut self.timer_wheel);
-
-            let new_plugins_res: Vec<NewPluginRes> = request
-                .add_plugin_instances
-                .drain(..)
-                .map(|save_state| {
-                    activated_state.audio_graph.add_new_plugin_instance(
-                        save_state,
-                        &mut self.plugin_scanner,
-                        true,
-                    )
-                })
-                .collect();
-
-            let new_plugin_ids: Vec<PluginInstanceID> =
-                new_plugins_res.iter().map(|res| res.plugin_id.clone()).collect();
-
-            for edge in request.connect_new_edges.iter() {
-                let src_plugin_id = match &edge.src_plugin_id {
-                    PluginIDReq::Added(index) => {
-                        if let Some(new_plugin_id) = new_plugin_ids.get(*index) {
-                            new_plugin_id
-                        } else {
-                            log::error!(
-                                "Could not connect edge {:?}: Source plugin index out of bounds",
-                                edge
-                            );
-                            continue;
-                        }
-                    }
-                    PluginIDReq::Existing(id) => id,
-                };
-
-                let dst_plugin_id = match &edge.dst_plugin_id {
-                    PluginIDReq::Added(index) => {
-                        if let Some(new_plugin_id) = new_plugin_ids.get(*index) {
-                            new_plugin_id
-                        } else {
-                            log::error!(
-                                "Could not connect edge {:?}: Destination plugin index out of bounds",
-                                edge
-                            );
-                            continue;
-                        }
-                    }
-                    PluginIDReq::Existing(id) => id,
-                };
-
-                match activated_state.audio_graph.connect_edge(edge, src_plugin_id, dst_plugin_id) {
-                    Ok(new_edge) => new_edges.push(new_edge),
-                    Err(e) => {
-                        if edge.log_error_on_fail {
-                            log::warn!("Could not connect edge: {}", e);
-                        } else {
-                            #[cfg(debug_assertions)]
-                            log::debug!("Could not connect edge: {}", e);
-                        }
-                    }
-                }
+    /// Modify the audio graph in strict mode.
+    ///
+    /// Unlike `Self::modify_graph()`, the whole request is validated before
+    /// the audio graph is modified. This includes checking that all of the
+    /// edges and plugins to remove exist, that all of the new plugins load
+    /// and activate successfully, and that all of the new edges can be
+    /// connected without creating a cycle. If any of these checks fail,
+    /// then the new plugins are removed again, the audio graph is left
+    /// unchanged, and the first error that was found is returned.
+    ///
+    /// Note that all new edges are checked for cycles in strict mode,
+    /// regardless of `ConnectEdgeReq::check_for_cycles`, except for feedback
+    /// edges which can never create a cycle.
+    ///
+    /// The modified graph is compiled in a separate thread. Once it is done,
+    /// `OnIdleEvent::AudioGraphCompiled` will be sent in `Self::on_timer()`.
+    pub fn modify_graph_strict(
+        &mut self,
+        request: ModifyGraphRequest,
+    ) -> Result<ModifyGraphRes, ModifyGraphError> {
+        self.modify_graph_inner(request, false, true).map(|(res, _)| res)
+    }
+
+    /// Modify the audio graph, and return the request that undoes this
+    /// modification.
+    ///
+    /// The inverse request re-creates the removed plugins from their save
+    /// states, reconnects the removed edges, and removes the added plugins
+    /// and edges.
+    ///
+    /// Unlike `Self::undo()`, applying the inverse request gives the
+    /// re-created plugins new IDs, so any other inverse request or
+    /// `ModifyGraphRes` that refers to the old IDs of those plugins is no
+    /// longer valid afterwards. The inverse request is also only valid as
+    /// long as no other modifications have been made to the audio graph in
+    /// the meantime. Use the undo stack (see
+    /// `ActivateEngineSettings::undo_stack_size`) to undo and redo any
+    /// number of modifications while keeping the IDs of the plugins.
+    ///
+    /// This will return `None` if the engine is deactivated.
+    pub fn modify_graph_with_inverse(
+        &mut self,
+        request: ModifyGraphRequest,
+    ) -> Option<(ModifyGraphRes, ModifyGraphRequest)> {
+        match self.modify_graph_inner(request, true, false) {
+            Ok((res, inverse)) => Some((res, inverse.unwrap())),
+            Err(e) => {
+                log::warn!("{}", e);
+                None
             }
+        }
+    }
 
-            let res = ModifyGraphRes {
-                new_plugins: new_plugins_res,
-                removed_plugins: removed_plugins.drain().collect(),
-                new_edges,
-                removed_edges,
-            };
+    /// Modify the audio graph inside of the given rack plugin (see
+    /// `RackConfig`).
+    ///
+    /// `PluginIDReq::Existing` refers to the plugins inside of the rack,
+    /// including the graph input/output nodes of the rack (see
+    /// `Self::rack_info()`). Modifications to racks are not recorded in the
+    /// undo stack.
+    ///
+    /// This will return `None` if the engine is deactivated or if a rack
+    /// with the given ID does not exist.
+    pub fn modify_rack_graph(
+        &mut self,
+        rack_id: &PluginInstanceID,
+        request: ModifyGraphRequest,
+    ) -> Option<ModifyGraphRes> {
+        let rack = self.activated_state.as_ref()?.audio_graph.rack(rack_id)?;
+
+        let request_id = self.next_modify_request_id;
+        self.next_modify_request_id += 1;
+
+        let res = rack.borrow_mut().modify(
+            request,
+            request_id,
+            &mut self.plugin_scanner,
+            &mut self.timer_wheel,
+        );
+
+        Some(res)
+    }
+
+    /// The plugins and edges inside of the given rack plugin.
+    ///
+    /// This will return `None` if the engine is deactivated or if a rack
+    /// with the given ID does not exist.
+    pub fn rack_info(&self, rack_id: &PluginInstanceID) -> Option<RackInfo> {
+        let rack = self.activated_state.as_ref()?.audio_graph.rack(rack_id)?;
+        let info = rack.borrow().info();
+        Some(info)
+    }
+
+    /// Call `f` with the host of a plugin inside of the given rack plugin.
+    ///
+    /// This will return `None` if the engine is deactivated, or if the rack
+    /// or the plugin does not exist.
+    pub fn with_rack_plugin_host_mut<R, F: FnOnce(&mut PluginHostMainThread) -> R>(
+        &mut self,
+        rack_id: &PluginInstanceID,
+        plugin_id: &PluginInstanceID,
+        f: F,
+    ) -> Option<R> {
+        let rack = self.activated_state.as_ref()?.audio_graph.rack(rack_id)?;
+        let mut rack = rack.borrow_mut();
+        rack.graph_mut().get_plugin_host_mut(plugin_id).map(f)
+    }
+
+    fn modify_graph_inner(
+        &mut self,
+        request: ModifyGraphRequest,
+        return_inverse: bool,
+        strict: bool,
+    ) -> Result<(ModifyGraphRes, Option<ModifyGraphRequest>), ModifyGraphError> {
+        let activated_state =
+            self.activated_state.as_mut().ok_or(ModifyGraphError::EngineDeactivated)?;
+
+        let request_id = self.next_modify_request_id;
+        self.next_modify_request_id += 1;
+
+        let collect_inverse = return_inverse || activated_state.undo_stack.is_some();
+
+        let (res, inverse) = if strict {
+            activated_state.modify_graph_strict(
+                request,
+                request_id,
+                collect_inverse,
+                &mut self.timer_wheel,
+                &mut self.plugin_scanner,
+            )?
+        } else {
+            activated_state.modify_graph(
+                request,
+                &[],
+                request_id,
+                collect_inverse,
+                &mut self.timer_wheel,
+                &mut self.plugin_scanner,
+            )
+        };
+
+        let schedule_version = activated_state.compile_audio_graph();
 
-            // TODO: Compile audio graph in a separate thread?
-            self.compile_audio_graph();
+        if let Some(graph_recovery) = &mut activated_state.graph_recovery {
+            graph_recovery.on_request(request_id, schedule_version);
+        }
 
-            Some(res)
+        let inverse_request = if return_inverse {
+            inverse.as_ref().map(|inverse| inverse.to_request(&activated_state.audio_graph.edges()))
         } else {
-            log::warn!("Cannot modify audio graph: Engine is deactivated");
             None
+        };
+
+        if let (Some(undo_stack), Some(inverse)) = (&mut activated_state.undo_stack, inverse) {
+            undo_stack.push(inverse);
         }
+
+        Ok((res, inverse_request))
+    }
+
+    /// Undo the last modification made to the audio graph.
+    ///
+    /// Removed plugins are re-created from their save states with the same
+    /// IDs they had before they were removed.
+    ///
+    /// This will return `None` if there is nothing to undo, if the undo
+    /// stack is disabled, or if the engine is deactivated.
+    pub fn undo(&mut self) -> Option<UndoRedoRes> {
+        let change = self.activated_state.as_mut()?.undo_stack.as_mut()?.pop_undo()?;
+
+        let (res, inverse) = self.apply_graph_change(change);
+
+        self.activated_state.as_mut()?.undo_stack.as_mut()?.push_redo(inverse);
+
+        Some(res)
+    }
+
+    /// Redo the last modification that was undone with `Self::undo()`.
+    ///
+    /// This will return `None` if there is nothing to redo, if the undo
+    /// stack is disabled, or if the engine is deactivated.
+    pub fn redo(&mut self) -> Option<UndoRedoRes> {
+        let change = self.activated_state.as_mut()?.undo_stack.as_mut()?.pop_redo()?;
+
+        let (res, inverse) = self.apply_graph_change(change);
+
+        self.activated_state.as_mut()?.undo_stack.as_mut()?.push_undo(inverse);
+
+        Some(res)
+    }
+
+    /// Returns `true` if there is a modification that can be undone.
+    pub fn can_undo(&self) -> bool {
+        self.activated_state
+            .as_ref()
+            .and_then(|s| s.undo_stack.as_ref())
+            .map(|undo_stack| undo_stack.can_undo())
+            .unwrap_or(false)
+    }
+
+    /// Returns `true` if there is a modification that can be redone.
+    pub fn can_redo(&self) -> bool {
+        self.activated_state
+            .as_ref()
+            .and_then(|s| s.undo_stack.as_ref())
+            .map(|undo_stack| undo_stack.can_redo())
+            .unwrap_or(false)
+    }
+
+    /// Clear the undo and redo history.
+    pub fn clear_undo_history(&mut self) {
+        if let Some(undo_stack) = self.activated_state.as_mut().and_then(|s| s.undo_stack.as_mut())
+        {
+            undo_stack.clear();
+        }
+    }
+
+    /// Apply a change from the undo stack to the audio graph, and return
+    /// the change that reverts it.
+    ///
+    /// Plugins that are re-created get the same ID they had before they
+    /// were removed, so the rest of the history stays valid.
+    fn apply_graph_change(&mut self, change: GraphChange) -> (UndoRedoRes, GraphChange) {
+        let activated_state = self.activated_state.as_mut().unwrap();
+
+        let request_id = self.next_modify_request_id;
+        self.next_modify_request_id += 1;
+
+        let recreated_plugins = change.recreated_plugins();
+        let request = change.to_request(&activated_state.audio_graph.edges());
+
+        let (changes, inverse) = activated_state.modify_graph(
+            request,
+            &recreated_plugins,
+            request_id,
+            true,
+            &mut self.timer_wheel,
+            &mut self.plugin_scanner,
+        );
+
+        let schedule_version = activated_state.compile_audio_graph();
+
+        if let Some(graph_recovery) = &mut activated_state.graph_recovery {
+            graph_recovery.on_request(request_id, schedule_version);
+        }
+
+        (UndoRedoRes { changes }, inverse.unwrap())
     }
 
     /// Gracefully deactivate the engine. This will also reset the audio
@@ -488,22 +1455,155 @@ impl DSEngineMainThread {
         self.activated_state.as_mut().unwrap().audio_graph.collect_save_states()
     }
 
+    /// Collect the save state of the whole audio graph. This includes the
+    /// save states of all plugins (whether they have changed or not), the
+    /// edges between them, and the state of the transport.
+    ///
+    /// This will return `None` if the engine is deactivated.
+    pub fn collect_graph_save_state(&mut self) -> Option<DSGraphSaveState> {
+        // Make sure the edges are up to date with any changes to the plugins'
+        // ports.
+        self.poll_audio_graph_compiler(false);
+        let activated_state = self.activated_state.as_mut()?;
+
+        let plugins = activated_state.audio_graph.collect_all_save_states();
+        let edges = activated_state.audio_graph.edges();
+        let transport = activated_state.transport_handle.save_state();
+
+        Some(DSGraphSaveState::new(plugins, &edges, transport))
+    }
+
+    /// Replace the whole audio graph with the given save state in one step.
+    ///
+    /// All plugins that are currently in the audio graph are removed, and
+    /// the plugins, edges, and transport state in the save state are
+    /// restored.
+    ///
+    /// The save state is restored in strict mode (see
+    /// `Self::modify_graph_strict()`). If any plugin fails to load or
+    /// activate, or if any edge cannot be connected, then the audio graph
+    /// and the transport are left unchanged and the error is returned.
+    pub fn restore_graph(
+        &mut self,
+        save_state: DSGraphSaveState,
+    ) -> Result<RestoreGraphRes, RestoreGraphError> {
+        let activated_state =
+            self.activated_state.as_mut().ok_or(RestoreGraphError::EngineDeactivated)?;
+
+        save_state.validate()?;
+
+        let transport = save_state.transport;
+
+        let remove_plugin_instances = activated_state
+            .audio_graph
+            .plugin_ids()
+            .into_iter()
+            .filter(|plugin_id| {
+                plugin_id != activated_state.audio_graph.graph_in_id()
+                    && plugin_id != activated_state.audio_graph.graph_out_id()
+            })
+            .collect();
+        let disconnect_edges =
+            activated_state.audio_graph.edges().iter().map(|edge| edge.id).collect();
+
+        let (request, old_ids) = save_state.into_request(
+            activated_state.audio_graph.graph_in_id(),
+            activated_state.audio_graph.graph_out_id(),
+            remove_plugin_instances,
+            disconnect_edges,
+        );
+
+        let changes = self.modify_graph_strict(request).map_err(|e| match e {
+            ModifyGraphError::EngineDeactivated => RestoreGraphError::EngineDeactivated,
+            ModifyGraphError::PluginFailedToLoad(i, e) => {
+                RestoreGraphError::PluginFailedToLoad(old_ids[i], e)
+            }
+            ModifyGraphError::PluginFailedToActivate(i, e) => {
+                RestoreGraphError::PluginFailedToActivate(old_ids[i], e)
+            }
+            ModifyGraphError::ConnectEdgeError(e) => RestoreGraphError::ConnectEdgeError(e),
+            e => RestoreGraphError::ModifyGraphError(e),
+        })?;
+
+        let activated_state =
+            self.activated_state.as_mut().ok_or(RestoreGraphError::EngineDeactivated)?;
+        activated_state.transport_handle.seek_to(transport.seek_to);
+        activated_state.transport_handle.set_loop_state(transport.loop_state);
+
+        let plugin_ids = old_ids
+            .into_iter()
+            .zip(changes.new_plugins.iter().map(|res| res.plugin_id.clone()))
+            .collect();
+
+        Ok(RestoreGraphRes { changes, plugin_ids })
+    }
+
+    /// Collect the performance telemetry of the engine since the last call
+    /// to this method (or since the engine was activated).
+    ///
+    /// The counters are updated lock-free by the process thread, so this
+    /// can be called as often as needed (i.e. to update a DSP load meter).
+    ///
+    /// This will return `None` if the engine is deactivated.
+    pub fn collect_telemetry(&mut self) -> Option<EngineTelemetry> {
+        let activated_state = self.activated_state.as_mut()?;
+
+        let now = Instant::now();
+        let period = now - activated_state.last_telemetry_instant;
+        activated_state.last_telemetry_instant = now;
+
+        let plugins = activated_state.audio_graph.take_plugin_process_times();
+
+        Some(activated_state.telemetry.take(period, plugins))
+    }
+
     fn collect_garbage(&mut self) {
         self.plugin_scanner.unload_unused_binaries();
         self.collector.collect();
     }
 
-    fn compile_audio_graph(&mut self) {
-        if let Some(mut activated_state) = self.activated_state.take() {
-            match activated_state.audio_graph.compile() {
-                Ok(_) => {
-                    self.activated_state = Some(activated_state);
+    /// Handle the results of the audio graph compiler thread.
+    ///
+    /// If `block` is `true`, then this will wait until all queued requests
+    /// have finished compiling.
+    fn poll_audio_graph_compiler(&mut self, block: bool) {
+        while let Some(activated_state) = &mut self.activated_state {
+            let res = if block {
+                activated_state.audio_graph.wait_for_compiler()
+            } else {
+                activated_state.audio_graph.poll_compiler()
+            };
+
+            match res {
+                Some((schedule_version, Ok(()))) => {
+                    if let Some(graph_recovery) = &mut activated_state.graph_recovery {
+                        graph_recovery.on_compiled(schedule_version);
+                    }
+
+                    self.queued_events.push(OnIdleEvent::AudioGraphCompiled);
                 }
-                Err(e) => {
+                Some((_, Err(e))) => {
                     log::error!("{}", e);
 
+                    let e = if activated_state.graph_recovery.is_some() {
+                        match self.recover_audio_graph(e) {
+                            Ok(status) => {
+                                self.queued_events
+                                    .push(OnIdleEvent::AudioGraphRecovered(Box::new(status)));
+                                continue;
+                            }
+                            Err(e) => {
+                                log::error!("Failed to recover audio graph: {}", e);
+                                e
+                            }
+                        }
+                    } else {
+                        e
+                    };
+
                     // Audio graph is in an invalid state. Drop it and have the user restore
                     // from the last working save state.
+                    let mut activated_state = self.activated_state.take().unwrap();
 
                     // Attempt to remove all plugins gracefully.
                     activated_state.audio_graph.reset(&mut self.timer_wheel);
@@ -515,9 +1615,74 @@ impl DSEngineMainThread {
 
                     self.crash_msg = Some(EngineCrashError::CompilerError(e));
                 }
+                None => break,
             }
         }
     }
+
+    /// Roll the audio graph back to the last state that compiled
+    /// successfully.
+    fn recover_audio_graph(
+        &mut self,
+        error: GraphCompilerError,
+    ) -> Result<AudioGraphRecoveredStatus, GraphCompilerError> {
+        let activated_state = self.activated_state.as_mut().unwrap();
+
+        // Any changes made after the failed compile are rolled back too.
+        activated_state.audio_graph.discard_pending_compiles();
+
+        let rollback = activated_state.graph_recovery.as_mut().unwrap().rollback(
+            &activated_state.audio_graph.plugin_ids(),
+            &activated_state.audio_graph.edges(),
+        );
+
+        log::warn!(
+            "Rolling back audio graph, rejected requests: {:?}",
+            &rollback.rejected_requests
+        );
+
+        let request_id = self.next_modify_request_id;
+        self.next_modify_request_id += 1;
+
+        let (changes, _) = activated_state.modify_graph(
+            rollback.request,
+            &[],
+            request_id,
+            false,
+            &mut self.timer_wheel,
+            &mut self.plugin_scanner,
+        );
+
+        // The history may refer to changes that have been rolled back.
+        if let Some(undo_stack) = &mut activated_state.undo_stack {
+            undo_stack.clear();
+        }
+
+        let schedule_version = activated_state.compile_audio_graph();
+
+        // Wait for the rolled back graph to compile.
+        while let Some((version, res)) = activated_state.audio_graph.wait_for_compiler() {
+            if version == schedule_version {
+                res?;
+
+                activated_state.graph_recovery.as_mut().unwrap().on_compiled(schedule_version);
+                break;
+            }
+        }
+
+        let recreated_plugins = rollback
+            .recreated_plugins
+            .into_iter()
+            .zip(changes.new_plugins.iter().map(|res| res.plugin_id.clone()))
+            .collect();
+
+        Ok(AudioGraphRecoveredStatus {
+            error,
+            rejected_requests: rollback.rejected_requests,
+            changes,
+            recreated_plugins,
+        })
+    }
 }
 
 impl Drop for DSEngineMainThread {
@@ -530,7 +1695,26 @@ impl Drop for DSEngineMainThread {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Describes an audio port on the input or output of the audio graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphPortInfo {
+    /// Stable identifier, it must never change.
+    ///
+    /// Edges connected with `EdgeReqPortID::StableID` refer to this ID, so
+    /// keep it the same between sessions even if the channels of the audio
+    /// device change.
+    pub stable_id: u32,
+
+    /// The number of channels in this port.
+    ///
+    /// This cannot be `0`.
+    pub channels: u16,
+
+    /// The displayable name (i.e. "Main Out", "Headphones", or "Input 1/2").
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone)]
 pub struct ActivateEngineSettings {
     /// The sample rate of the project.
     pub sample_rate: SampleRate,
@@ -549,6 +1733,55 @@ pub struct ActivateEngineSettings {
     /// The total number of output audio channels from the audio graph.
     pub num_audio_out_channels: u16,
 
+    /// The audio ports on the input to the audio graph, in the order of
+    /// their channels. The total number of channels in these ports must be
+    /// equal to `num_audio_in_channels`.
+    ///
+    /// If this is empty, then each channel gets its own port with the
+    /// index of the channel as its stable ID.
+    ///
+    /// By default this is empty.
+    pub graph_in_ports: Vec<GraphPortInfo>,
+
+    /// The audio ports on the output of the audio graph, in the order of
+    /// their channels. The total number of channels in these ports must be
+    /// equal to `num_audio_out_channels`.
+    ///
+    /// If this is empty, then each channel gets its own port with the
+    /// index of the channel as its stable ID.
+    ///
+    /// By default this is empty.
+    pub graph_out_ports: Vec<GraphPortInfo>,
+
+    /// The number of note ports on the input to the audio graph.
+    ///
+    /// Connect to these ports with `EdgeReqPortID::Main` and the index of
+    /// the note port as the channel. Events are sent to these ports with
+    /// `DSEngineAudioThread::push_note_event()`, or from another thread
+    /// with `DSEngineAudioThread::create_event_input()`.
+    ///
+    /// By default this is set to `0`.
+    pub num_note_in_ports: u16,
+
+    /// The number of note ports on the output of the audio graph.
+    ///
+    /// Connect to these ports with `EdgeReqPortID::Main` and the index of
+    /// the note port as the channel. Events generated on these ports can be
+    /// read with `DSEngineAudioThread::note_out_events()`.
+    ///
+    /// By default this is set to `0`.
+    pub num_note_out_ports: u16,
+
+    /// Whether the input to the audio graph has an automation port.
+    ///
+    /// Connect to this port with `EdgeReqPortID::Main`. Events are sent to
+    /// this port with `DSEngineAudioThread::push_automation_event()`, or
+    /// from another thread with `DSEngineAudioThread::create_event_input()`.
+    /// Each event is only received by the plugin it is addressed to.
+    ///
+    /// By default this is set to `false`.
+    pub automation_in_port: bool,
+
     /// The pre-allocated capacity for note buffers in the audio graph.
     ///
     /// By default this is set to `256`.
@@ -566,6 +1799,50 @@ pub struct ActivateEngineSettings {
     ///
     /// By default this is set to `None`.
     pub transport_declick_time: Option<Seconds>,
+
+    /// The number of realtime worker threads used to process independent
+    /// parts of the audio graph in parallel with the process thread.
+    ///
+    /// Set this to `0` to process the whole audio graph in the process
+    /// thread.
+    ///
+    /// By default this is set to `0`.
+    pub num_worker_threads: usize,
+
+    /// If `true`, then the engine will keep track of the last state of the
+    /// audio graph that compiled successfully. If a modification causes the
+    /// audio graph to fail to compile, then the audio graph is rolled back
+    /// to that state and `OnIdleEvent::AudioGraphRecovered` is sent,
+    /// instead of deactivating the engine.
+    ///
+    /// Plugins that are still in the graph keep their current state, and
+    /// plugins that were removed since are re-created from the save state
+    /// they had when they were removed.
+    ///
+    /// By default this is set to `false`.
+    pub recover_from_compiler_errors: bool,
+
+    /// The settings for the watchdog that disables plugins which keep
+    /// exceeding their share of the realtime deadline. When a plugin is
+    /// disabled, `OnIdleEvent::PluginDisabledByWatchdog` is sent.
+    ///
+    /// Set this to `None` to disable the watchdog.
+    ///
+    /// By default this is set to `None`.
+    pub plugin_watchdog: Option<PluginWatchdogSettings>,
+
+    /// The maximum number of modifications to the audio graph that can be
+    /// undone with `DSEngineMainThread::undo()`.
+    ///
+    /// Plugins that are re-created by undoing or redoing a modification keep
+    /// the IDs they had before they were removed. This is not the case for
+    /// the inverse requests returned by `DSEngineMainThread::modify_graph_with_inverse()`,
+    /// which re-create plugins with new IDs.
+    ///
+    /// Set this to `0` to disable the undo stack.
+    ///
+    /// By default this is set to `0`.
+    pub undo_stack_size: usize,
 }
 
 impl Default for ActivateEngineSettings {
@@ -576,11 +1853,82 @@ impl Default for ActivateEngineSettings {
             max_frames: 512,
             num_audio_in_channels: 2,
             num_audio_out_channels: 2,
+            graph_in_ports: Vec::new(),
+            graph_out_ports: Vec::new(),
+            num_note_in_ports: 0,
+            num_note_out_ports: 0,
+            automation_in_port: false,
             note_buffer_size: 256,
             event_buffer_size: 256,
             transport_declick_time: None,
+            num_worker_threads: 0,
+            recover_from_compiler_errors: false,
+            plugin_watchdog: None,
+            undo_stack_size: 0,
+        }
+    }
+}
+
+/// Returns the audio ports of the graph input/output node, or `None` if the
+/// given ports are invalid.
+fn graph_io_ports(
+    ports: &[GraphPortInfo],
+    num_channels: u16,
+    is_input: bool,
+) -> Option<Vec<GraphPortInfo>> {
+    let direction = if is_input { "input" } else { "output" };
+
+    if ports.is_empty() {
+        return Some(
+            (0..num_channels)
+                .map(|i| GraphPortInfo {
+                    stable_id: u32::from(i),
+                    channels: 1,
+                    display_name: if is_input {
+                        format!("Input {}", i + 1)
+                    } else {
+                        format!("Output {}", i + 1)
+                    },
+                })
+                .collect(),
+        );
+    }
+
+    let mut stable_ids: FnvHashSet<u32> = FnvHashSet::default();
+    let mut total_channels: usize = 0;
+    for port in ports.iter() {
+        if port.channels == 0 {
+            log::error!(
+                "Could not activate RustyDAW engine: Graph {} port {:?} has no channels",
+                direction,
+                &port.display_name
+            );
+            return None;
+        }
+        if !stable_ids.insert(port.stable_id) {
+            log::error!(
+                "Could not activate RustyDAW engine: More than one graph {} port has the stable ID {}",
+                direction,
+                port.stable_id
+            );
+            return None;
         }
+
+        total_channels += usize::from(port.channels);
     }
+
+    if total_channels != usize::from(num_channels) {
+        log::error!(
+            "Could not activate RustyDAW engine: The graph {} ports have {} channels in total, but the audio graph has {} {} channels",
+            direction,
+            total_channels,
+            num_channels,
+            direction
+        );
+        return None;
+    }
+
+    Some(ports.to_vec())
 }
 
 pub struct ActivatedEngineInfo {
@@ -614,6 +1962,21 @@ pub struct ActivatedEngineInfo {
 
     /// The total number of output audio channels from the audio graph.
     pub num_audio_out_channels: u16,
+
+    /// The number of note ports on the input to the audio graph.
+    pub num_note_in_ports: u16,
+
+    /// The number of note ports on the output of the audio graph.
+    pub num_note_out_ports: u16,
+
+    /// Whether the input to the audio graph has an automation port.
+    pub automation_in_port: bool,
+
+    /// The audio ports on the input to the audio graph.
+    pub graph_in_ports: Vec<GraphPortInfo>,
+
+    /// The audio ports on the output of the audio graph.
+    pub graph_out_ports: Vec<GraphPortInfo>,
 }
 
 impl std::fmt::Debug for ActivatedEngineInfo {
@@ -627,6 +1990,11 @@ impl std::fmt::Debug for ActivatedEngineInfo {
         f.field("max_frames", &self.max_frames);
         f.field("num_audio_in_channels", &self.num_audio_in_channels);
         f.field("num_audio_out_channels", &self.num_audio_out_channels);
+        f.field("num_note_in_ports", &self.num_note_in_ports);
+        f.field("num_note_out_ports", &self.num_note_out_ports);
+        f.field("automation_in_port", &self.automation_in_port);
+        f.field("graph_in_ports", &self.graph_in_ports);
+        f.field("graph_out_ports", &self.graph_out_ports);
 
         f.finish()
     }
@@ -642,6 +2010,29 @@ pub enum EngineDeactivatedStatus {
     EngineCrashed(Box<EngineCrashError>),
 }
 
+#[derive(Debug)]
+/// Sent whenever the audio graph has been rolled back to the last state
+/// that compiled successfully.
+pub struct AudioGraphRecoveredStatus {
+    /// The error that caused the audio graph to be rolled back.
+    pub error: GraphCompilerError,
+
+    /// The `ModifyGraphRes::request_id` of every request that was
+    /// rejected. None of the changes made by these requests are in the
+    /// audio graph anymore.
+    pub rejected_requests: Vec<u64>,
+
+    /// The changes that were made to the audio graph in order to roll it
+    /// back.
+    pub changes: ModifyGraphRes,
+
+    /// Plugins that were removed by a rejected request are re-created
+    /// from their save states, which gives them a new ID.
+    ///
+    /// This is a list of (old ID, new ID).
+    pub recreated_plugins: Vec<(PluginInstanceID, PluginInstanceID)>,
+}
+
 #[derive(Debug)]
 pub struct PluginActivatedStatus {
     /// Returns `true` if the plugin has updated its list of audio ports.
@@ -712,6 +2103,13 @@ pub struct NewPluginRes {
 
 #[derive(Debug)]
 pub struct ModifyGraphRes {
+    /// The unique ID of this request.
+    ///
+    /// This is used to report which requests were rejected when the audio
+    /// graph is rolled back (see
+    /// `ActivateEngineSettings::recover_from_compiler_errors`).
+    pub request_id: u64,
+
     /// Any new plugins that were added to the graph.
     pub new_plugins: Vec<NewPluginRes>,
 
@@ -727,6 +2125,27 @@ pub struct ModifyGraphRes {
     pub removed_edges: Vec<DSEdgeID>,
 }
 
+#[derive(Debug)]
+pub struct UndoRedoRes {
+    /// The changes that were made to the audio graph.
+    ///
+    /// Plugins that are re-created from their save states keep the ID they
+    /// had before they were removed.
+    pub changes: ModifyGraphRes,
+}
+
+#[derive(Debug)]
+pub struct RestoreGraphRes {
+    /// The changes that were made to the audio graph.
+    pub changes: ModifyGraphRes,
+
+    /// The restored plugins get new IDs. This maps the IDs in the save
+    /// state (`DSGraphPluginSaveState::id`) to the new IDs.
+    ///
+    /// This is a list of (old ID, new ID).
+    pub plugin_ids: Vec<(u64, PluginInstanceID)>,
+}
+
 #[derive(Debug)]
 pub enum OnIdleEvent {
     /// The plugin's parameters have been modified via the plugin's custom
@@ -804,7 +2223,467 @@ pub enum OnIdleEvent {
         status: Result<(), ActivatePluginError>,
     },
 
+    /// Sent when the watchdog has disabled a plugin because it took too
+    /// long to process too often.
+    ///
+    /// This is only sent if `ActivateEngineSettings::plugin_watchdog` is
+    /// not `None`.
+    PluginDisabledByWatchdog {
+        plugin_id: PluginInstanceID,
+        /// What was done with the plugin.
+        action: WatchdogAction,
+        /// The highest fraction of a block's duration the plugin spent
+        /// processing before it was disabled.
+        peak_load: f64,
+    },
+
+    /// Sent whenever the audio graph has finished compiling in the
+    /// compiler thread, and the new schedule has been sent to the audio
+    /// thread.
+    ///
+    /// If several modifications are made before the compiler thread gets
+    /// to them, then they will all be compiled at once and only one event
+    /// will be sent.
+    ///
+    /// If the audio graph fails to compile, then an `EngineDeactivated`
+    /// event will be sent with `EngineCrashError::CompilerError` instead.
+    AudioGraphCompiled,
+
+    /// Sent when the audio graph failed to compile and was rolled back to
+    /// the last state that compiled successfully.
+    ///
+    /// This is only sent if `ActivateEngineSettings::recover_from_compiler_errors`
+    /// is `true`.
+    AudioGraphRecovered(Box<AudioGraphRecoveredStatus>),
+
     /// Sent whenever the engine has been deactivated, whether gracefully or
     /// because of a crash.
     EngineDeactivated(EngineDeactivatedStatus),
 }
+
+#[cfg(test)]
+mod tests {
+    use dropseed_plugin_api::buffer::EventBuffer;
+    use dropseed_plugin_api::ext::audio_ports::{
+        AudioPortInfo, MainPortsLayout, PluginAudioPortsExt,
+    };
+    use dropseed_plugin_api::plugin_scanner::PluginFormat;
+    use dropseed_plugin_api::{
+        HostRequestChannelSender, PluginActivatedInfo, PluginDescriptor, PluginMainThread,
+        PluginProcessor, ProcBuffers, ProcInfo, ProcessStatus,
+    };
+
+    use super::*;
+    use crate::engine::modify_request::{
+        ConnectEdgeReq, EdgeReqPortID, FeedbackDelay, PluginIDReq,
+    };
+    use crate::engine::RackConfig;
+    use crate::graph::PortType;
+
+    fn offline_engine(
+        settings: ActivateEngineSettings,
+    ) -> (DSEngineMainThread, ActivatedEngineInfo) {
+        let (mut ds_engine, _, _) = DSEngineMainThread::new(
+            HostInfo::new("Dropseed Test".into(), "0.0.0".into(), None, None),
+            EngineSettings::default(),
+            vec![],
+        );
+
+        let info = ds_engine.activate_engine_offline(settings).unwrap();
+
+        (ds_engine, info)
+    }
+
+    fn audio_edge(
+        src_plugin_id: PluginIDReq,
+        dst_plugin_id: PluginIDReq,
+        dst_port_channel: u16,
+    ) -> ConnectEdgeReq {
+        ConnectEdgeReq {
+            edge_type: PortType::Audio,
+            src_plugin_id,
+            dst_plugin_id,
+            src_port_id: EdgeReqPortID::Main,
+            src_port_channel: 0,
+            dst_port_id: EdgeReqPortID::Main,
+            dst_port_channel,
+            feedback: None,
+            check_for_cycles: true,
+            log_error_on_fail: false,
+        }
+    }
+
+    fn note_edge(src_plugin_id: PluginIDReq, dst_plugin_id: PluginIDReq) -> ConnectEdgeReq {
+        ConnectEdgeReq { edge_type: PortType::Note, ..audio_edge(src_plugin_id, dst_plugin_id, 0) }
+    }
+
+    fn graph_snapshot(
+        ds_engine: &DSEngineMainThread,
+    ) -> (FnvHashSet<PluginInstanceID>, FnvHashSet<DSEdgeID>) {
+        (
+            ds_engine.plugin_ids().into_iter().collect(),
+            ds_engine.edges().into_iter().map(|edge| edge.id).collect(),
+        )
+    }
+
+    #[test]
+    fn strict_mode_leaves_graph_unchanged_on_error() {
+        let (mut ds_engine, info) = offline_engine(ActivateEngineSettings::default());
+        let graph_in = PluginIDReq::Existing(info.graph_in_id.clone());
+        let graph_out = PluginIDReq::Existing(info.graph_out_id.clone());
+
+        let res = ds_engine
+            .modify_graph_strict(ModifyGraphRequest {
+                add_plugin_instances: vec![RackConfig::default().new_save_state()],
+                remove_plugin_instances: vec![],
+                connect_new_edges: vec![
+                    audio_edge(graph_in.clone(), PluginIDReq::Added(0), 0),
+                    audio_edge(PluginIDReq::Added(0), graph_out.clone(), 0),
+                ],
+                disconnect_edges: vec![],
+            })
+            .unwrap();
+        let rack_id = res.new_plugins[0].plugin_id.clone();
+
+        let before = graph_snapshot(&ds_engine);
+        assert_eq!(before.0.len(), 1);
+        assert_eq!(before.1.len(), 2);
+
+        // The second new plugin does not exist, so the rack that is removed
+        // and the rack that is added must be left alone.
+        let missing_plugin = DSPluginSaveState::new_with_default_state(ScannedPluginKey {
+            rdn: String::from("app.meadowlark.does-not-exist"),
+            format: PluginFormat::Internal,
+        });
+        let res = ds_engine.modify_graph_strict(ModifyGraphRequest {
+            add_plugin_instances: vec![RackConfig::default().new_save_state(), missing_plugin],
+            remove_plugin_instances: vec![rack_id.clone()],
+            connect_new_edges: vec![audio_edge(graph_in.clone(), PluginIDReq::Added(0), 0)],
+            disconnect_edges: vec![],
+        });
+        assert!(matches!(res, Err(ModifyGraphError::PluginFailedToLoad(1, _))));
+        assert_eq!(graph_snapshot(&ds_engine), before);
+
+        // The graph output only has two audio channels.
+        let edge_id = ds_engine.edges()[0].id;
+        let res = ds_engine.modify_graph_strict(ModifyGraphRequest {
+            add_plugin_instances: vec![RackConfig::default().new_save_state()],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![
+                audio_edge(PluginIDReq::Added(0), graph_out.clone(), 1),
+                audio_edge(PluginIDReq::Existing(rack_id), graph_out, 5),
+            ],
+            disconnect_edges: vec![edge_id],
+        });
+        assert!(matches!(res, Err(ModifyGraphError::ConnectEdgeError(_))));
+        assert_eq!(graph_snapshot(&ds_engine), before);
+
+        // The graph can still be rendered.
+        assert!(ds_engine
+            .render_offline_to_vec(OfflineRenderRange::Frames {
+                start: Frames(0),
+                end: Frames(1024)
+            })
+            .is_ok());
+
+        ds_engine.deactivate_engine();
+    }
+
+    /// Render the first 300 frames while sending the given MIDI messages
+    /// (at absolute frames) to the graph input, and return the audio output
+    /// along with the MIDI messages (at absolute frames) on the graph output.
+    fn render_with_notes(
+        ds_engine: &mut DSEngineMainThread,
+        notes_in: &[(u64, [u8; 3])],
+    ) -> (Vec<f32>, Vec<(u64, [u8; 3])>) {
+        let mut audio_out: Vec<f32> = Vec::new();
+        let mut notes_out: Vec<(u64, [u8; 3])> = Vec::new();
+        let block_start = std::cell::Cell::new(0);
+
+        ds_engine
+            .render_offline_with_events(
+                OfflineRenderRange::Frames { start: Frames(0), end: Frames(300) },
+                |start, input| {
+                    block_start.set(start.0);
+
+                    let block = start.0..start.0 + input.frames() as u64;
+                    for (frame, bytes) in notes_in.iter().filter(|(frame, _)| block.contains(frame))
+                    {
+                        let event =
+                            GraphNoteEvent::from_midi(0, (frame - start.0) as u32, bytes).unwrap();
+                        assert!(input.push_note_event(event));
+                    }
+                },
+                |block, note_events| {
+                    for event in note_events {
+                        notes_out.push((
+                            block_start.get() + u64::from(event.frame),
+                            event.to_midi().unwrap(),
+                        ));
+                    }
+
+                    audio_out.extend_from_slice(block);
+                },
+            )
+            .unwrap();
+
+        (audio_out, notes_out)
+    }
+
+    #[test]
+    fn offline_render_keeps_note_timing() {
+        let (mut ds_engine, info) = offline_engine(ActivateEngineSettings {
+            max_frames: 64,
+            num_note_in_ports: 1,
+            num_note_out_ports: 1,
+            ..Default::default()
+        });
+
+        ds_engine
+            .modify_graph_strict(ModifyGraphRequest {
+                add_plugin_instances: vec![],
+                remove_plugin_instances: vec![],
+                connect_new_edges: vec![note_edge(
+                    PluginIDReq::Existing(info.graph_in_id.clone()),
+                    PluginIDReq::Existing(info.graph_out_id.clone()),
+                )],
+                disconnect_edges: vec![],
+            })
+            .unwrap();
+
+        // These land on the first frame of a block, in the middle of a block,
+        // on the last frame of a block, and in the last (shorter) block.
+        let notes_in = [
+            (0, [0x90, 60, 100]),
+            (70, [0x80, 60, 0]),
+            (127, [0x90, 64, 90]),
+            (299, [0x80, 64, 0]),
+        ];
+
+        let (audio_out, notes_out) = render_with_notes(&mut ds_engine, &notes_in);
+
+        assert_eq!(audio_out.len(), 300 * 2);
+        assert_eq!(notes_out, notes_in);
+
+        ds_engine.deactivate_engine();
+    }
+
+    /// Render a project with a `TestOscillator` that is fed its own output
+    /// through a feedback edge in a fresh engine, and return the output.
+    fn render_feedback_project() -> Vec<f32> {
+        let (mut ds_engine, _, scanned_plugins) = DSEngineMainThread::new(
+            HostInfo::new("Dropseed Test".into(), "0.0.0".into(), None, None),
+            EngineSettings::default(),
+            vec![Box::new(TestOscillatorFactory)],
+        );
+        let key = scanned_plugins[0].clone().unwrap();
+
+        let info = ds_engine
+            .activate_engine_offline(ActivateEngineSettings {
+                max_frames: 64,
+                ..Default::default()
+            })
+            .unwrap();
+        let graph_out = PluginIDReq::Existing(info.graph_out_id.clone());
+
+        ds_engine
+            .modify_graph_strict(ModifyGraphRequest {
+                add_plugin_instances: vec![DSPluginSaveState::new_with_default_state(key)],
+                remove_plugin_instances: vec![],
+                connect_new_edges: vec![
+                    ConnectEdgeReq {
+                        feedback: Some(FeedbackDelay::Frames(FEEDBACK_DELAY as u32)),
+                        ..audio_edge(PluginIDReq::Added(0), PluginIDReq::Added(0), 0)
+                    },
+                    audio_edge(PluginIDReq::Added(0), graph_out.clone(), 0),
+                    audio_edge(PluginIDReq::Added(0), graph_out, 1),
+                ],
+                disconnect_edges: vec![],
+            })
+            .unwrap();
+
+        let out = ds_engine
+            .render_offline_to_vec(OfflineRenderRange::Frames {
+                start: Frames(0),
+                end: Frames(1000),
+            })
+            .unwrap();
+
+        ds_engine.deactivate_engine();
+
+        out
+    }
+
+    #[test]
+    fn offline_render_is_deterministic() {
+        let out_a = render_feedback_project();
+        let out_b = render_feedback_project();
+
+        assert_eq!(out_a.len(), 1000 * 2);
+        assert!(out_a.iter().any(|s| *s != 0.0));
+        assert_eq!(out_a, out_b);
+
+        // The feedback edge delays the output of the oscillator by exactly
+        // `FEEDBACK_DELAY` frames, no matter how the blocks are split up.
+        let mut phase = 0.0;
+        let mut expected: Vec<f32> = Vec::new();
+        for i in 0..1000 {
+            let feedback = if i >= FEEDBACK_DELAY { expected[i - FEEDBACK_DELAY] } else { 0.0 };
+            expected.push(TestOscillatorProcessor::next_sample(&mut phase, feedback));
+        }
+        for (i, (s_out, s_expected)) in out_a.chunks(2).map(|f| f[0]).zip(expected).enumerate() {
+            assert!(
+                (s_out - s_expected).abs() < 1.0e-6,
+                "frame {}: {} != {}",
+                i,
+                s_out,
+                s_expected
+            );
+        }
+    }
+
+    #[test]
+    fn offline_render_of_note_only_graph() {
+        let (mut ds_engine, mut info) = offline_engine(ActivateEngineSettings {
+            max_frames: 64,
+            num_audio_in_channels: 0,
+            num_audio_out_channels: 0,
+            num_note_in_ports: 1,
+            num_note_out_ports: 1,
+            ..Default::default()
+        });
+
+        ds_engine
+            .modify_graph_strict(ModifyGraphRequest {
+                add_plugin_instances: vec![],
+                remove_plugin_instances: vec![],
+                connect_new_edges: vec![note_edge(
+                    PluginIDReq::Existing(info.graph_in_id.clone()),
+                    PluginIDReq::Existing(info.graph_out_id.clone()),
+                )],
+                disconnect_edges: vec![],
+            })
+            .unwrap();
+
+        let notes_in = [(10, [0x90, 60, 100]), (130, [0x80, 60, 0]), (299, [0x90, 62, 1])];
+
+        let (audio_out, notes_out) = render_with_notes(&mut ds_engine, &notes_in);
+
+        assert!(audio_out.is_empty());
+        assert_eq!(notes_out, notes_in);
+        // The transport moves even though there is no audio to process.
+        assert_eq!(
+            info.transport_handle.playhead_position(),
+            info.tempo_map.frame_to_musical(Frames(300))
+        );
+
+        ds_engine.deactivate_engine();
+    }
+
+    const FEEDBACK_DELAY: usize = 100;
+
+    /// An internal plugin that adds a sawtooth to half of its input, so
+    /// that its output depends on its own state as well as on its input.
+    struct TestOscillatorFactory;
+
+    impl PluginFactory for TestOscillatorFactory {
+        fn description(&self) -> PluginDescriptor {
+            PluginDescriptor {
+                id: String::from("app.meadowlark.test-oscillator"),
+                version: String::from("0.1.0"),
+                name: String::from("Test Oscillator"),
+                vendor: String::new(),
+                description: String::new(),
+                features: String::new(),
+                url: String::new(),
+                manual_url: String::new(),
+                support_url: String::new(),
+            }
+        }
+
+        fn instantiate(
+            &mut self,
+            _host_request_channel: HostRequestChannelSender,
+            _host_info: Shared<HostInfo>,
+            _plugin_id: PluginInstanceID,
+            _coll_handle: &basedrop::Handle,
+        ) -> Result<Box<dyn PluginMainThread>, String> {
+            Ok(Box::new(TestOscillator))
+        }
+    }
+
+    struct TestOscillator;
+
+    impl PluginMainThread for TestOscillator {
+        fn activate(
+            &mut self,
+            _sample_rate: SampleRate,
+            _min_frames: u32,
+            max_frames: u32,
+            _coll_handle: &basedrop::Handle,
+        ) -> Result<PluginActivatedInfo, String> {
+            Ok(PluginActivatedInfo {
+                processor: Box::new(TestOscillatorProcessor {
+                    phase: 0.0,
+                    input: Vec::with_capacity(max_frames as usize),
+                }),
+                internal_handle: None,
+            })
+        }
+
+        fn audio_ports_ext(&mut self) -> Result<PluginAudioPortsExt, String> {
+            let port =
+                AudioPortInfo { stable_id: 0, channels: 1, port_type: None, display_name: None };
+
+            Ok(PluginAudioPortsExt {
+                inputs: vec![port.clone()],
+                outputs: vec![port],
+                main_ports_layout: MainPortsLayout::InOut,
+            })
+        }
+    }
+
+    struct TestOscillatorProcessor {
+        phase: f32,
+        /// A copy of the input, in case the host processes the plugin in
+        /// place.
+        input: Vec<f32>,
+    }
+
+    impl TestOscillatorProcessor {
+        fn next_sample(phase: &mut f32, input: f32) -> f32 {
+            let s = *phase + (0.5 * input);
+            *phase = (*phase + 0.01) % 1.0;
+            s
+        }
+    }
+
+    impl PluginProcessor for TestOscillatorProcessor {
+        fn process(
+            &mut self,
+            proc_info: &ProcInfo,
+            buffers: &mut ProcBuffers,
+            _in_events: &EventBuffer,
+            _out_events: &mut EventBuffer,
+        ) -> ProcessStatus {
+            self.input.clear();
+            self.input.extend_from_slice(
+                &buffers.audio_in[0].channel_f32(0).unwrap()[0..proc_info.frames],
+            );
+
+            {
+                let mut output = buffers.audio_out[0].channel_f32_mut(0).unwrap();
+
+                for (s_out, s_in) in output[0..proc_info.frames].iter_mut().zip(self.input.iter()) {
+                    *s_out = Self::next_sample(&mut self.phase, *s_in);
+                }
+            }
+
+            for out_channel in buffers.audio_out[0]._iter_raw_f32_mut().unwrap() {
+                out_channel.set_constant(false);
+            }
+
+            ProcessStatus::Continue
+        }
+    }
+}
@@ -1,12 +1,37 @@
 pub(crate) mod audio_thread;
+pub(crate) mod rack;
+pub(crate) mod telemetry;
 pub(crate) mod timer_wheel;
 
+mod graph_recovery;
+mod graph_save_state;
+#[cfg(feature = "jack-backend")]
+mod jack_backend;
 mod main_thread;
+#[cfg(feature = "null-backend")]
+mod null_backend;
+mod offline_render;
 mod process_thread;
+mod undo;
 
 pub mod error;
 pub mod modify_request;
 
-pub use audio_thread::DSEngineAudioThread;
+pub use audio_thread::{DSEngineAudioInput, DSEngineAudioThread, DSEngineEventInput};
+pub use graph_save_state::{
+    DSGraphEdgeSaveState, DSGraphNodeID, DSGraphPluginSaveState, DSGraphSaveState,
+};
 pub use main_thread::*;
+pub use offline_render::{OfflineEventInput, OfflineRenderRange};
+pub use rack::{RackConfig, RackInfo, RACK_PLUGIN_RDN};
+pub use telemetry::{EngineTelemetry, PluginTelemetry, ProcessTimes};
+
+pub use crate::processor_schedule::{GraphAutomationEvent, GraphNoteEvent, TransportSaveState};
+
+#[cfg(feature = "jack-backend")]
+pub use jack_backend::JackAudioBackend;
+#[cfg(feature = "null-backend")]
+pub use null_backend::{
+    NullAudioBackend, NullBackendCapture, NullBackendResult, NullBackendSettings,
+};
 pub use timer_wheel::{DEFAULT_GARBAGE_COLLECT_INTERVAL_MS, DEFAULT_IDLE_INTERVAL_MS};
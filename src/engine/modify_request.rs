@@ -27,6 +27,7 @@ pub enum PluginIDReq {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EdgeReqPortID {
     /// Use the main port.
     ///
@@ -34,6 +35,11 @@ pub enum EdgeReqPortID {
     /// (because the plugin hasn't been added to the graph yet and activated).
     Main,
     /// Use the port with this specific stable ID.
+    ///
+    /// On the graph input/output nodes, this is the stable ID of one of the
+    /// audio ports in `ActivateEngineSettings::graph_in_ports` or
+    /// `ActivateEngineSettings::graph_out_ports`, and the channel is the
+    /// channel on that port.
     StableID(u32),
 }
 
@@ -50,12 +56,21 @@ pub struct ConnectEdgeReq {
     pub dst_port_id: EdgeReqPortID,
     pub dst_port_channel: u16,
 
+    /// If this is `Some`, then this is a feedback edge, meaning the signal is
+    /// delayed by the given amount before it reaches the destination port.
+    ///
+    /// Feedback edges are allowed to create cycles in the graph. Only audio
+    /// edges can be feedback edges.
+    pub feedback: Option<FeedbackDelay>,
+
     /// If `true`, then the engine will check if adding this edge will create a cycle
     /// in the graph, and not connect this edge if it does.
     ///
     /// This should always be set to `true` unless you are certain that adding this edge
     /// won't create a cycle, such as when repopulating the audio graph from a previously
     /// valid save state.
+    ///
+    /// This is ignored for feedback edges.
     pub check_for_cycles: bool,
 
     /// If `true`, then the engine should log the error if it failed to connect this edge
@@ -68,3 +83,37 @@ pub struct ConnectEdgeReq {
     /// to try and connect any main stereo inputs/outputs to the graph.
     pub log_error_on_fail: bool,
 }
+
+/// The delay of a feedback edge.
+///
+/// The delay can never be shorter than one block (`max_frames`), since the
+/// delayed signal must be read before the source of the edge is processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FeedbackDelay {
+    /// Delay the signal by one block (`max_frames`).
+    OneBlock,
+    /// Delay the signal by the given number of frames.
+    ///
+    /// An edge with a delay that is less than `max_frames` is not connected
+    /// (see `ConnectEdgeErrorType::FeedbackDelayTooShort`), and the engine
+    /// cannot be reconfigured to a `max_frames` that is larger than the
+    /// delay of any of its feedback edges.
+    Frames(u32),
+}
+
+impl FeedbackDelay {
+    /// The delay in frames.
+    pub(crate) fn frames(&self, max_frames: u32) -> u32 {
+        match self {
+            FeedbackDelay::OneBlock => max_frames,
+            FeedbackDelay::Frames(frames) => *frames,
+        }
+    }
+
+    /// Returns `true` if this delay can be used with the given maximum
+    /// block size.
+    pub(crate) fn is_valid(&self, max_frames: u32) -> bool {
+        self.frames(max_frames) >= max_frames
+    }
+}
@@ -0,0 +1,347 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thread_priority::ThreadPriority;
+
+use super::audio_thread::DSEngineAudioThread;
+
+/// Where to capture the output of the null backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NullBackendCapture {
+    /// Discard the output.
+    None,
+    /// Capture the interleaved output into memory. It is returned from
+    /// `NullAudioBackend::stop()`.
+    Memory,
+    /// Write the output into a 32 bit float WAV file at the given path.
+    ///
+    /// A WAV file can hold at most 4 GiB of data. Once that limit is
+    /// reached, the file is finished and the backend stops with an error.
+    WavFile(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NullBackendSettings {
+    /// The number of frames to process in each block.
+    ///
+    /// By default this is set to `512`.
+    pub block_size: usize,
+
+    /// The number of output channels of the simulated audio device.
+    ///
+    /// If this differs from the number of output channels in the audio
+    /// graph, then the channels are remapped just like they are with a
+    /// real audio device.
+    ///
+    /// By default this is set to `2`.
+    pub num_out_channels: usize,
+
+    /// Where to capture the output.
+    ///
+    /// By default this is set to `NullBackendCapture::None`.
+    pub capture: NullBackendCapture,
+}
+
+impl Default for NullBackendSettings {
+    fn default() -> Self {
+        Self { block_size: 512, num_out_channels: 2, capture: NullBackendCapture::None }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NullBackendResult {
+    /// The total number of blocks that were processed.
+    pub num_blocks: u64,
+
+    /// The number of blocks where the engine did not render its output,
+    /// either because it failed to render it in time or because the engine
+    /// was no longer running.
+    pub num_underruns: u64,
+
+    /// The captured interleaved output if the backend was started with
+    /// `NullBackendCapture::Memory`.
+    pub captured: Option<Vec<f32>>,
+}
+
+/// A headless audio backend which runs a `DSEngineAudioThread` from a
+/// software clock instead of an audio device.
+///
+/// The clock runs at the sample rate the engine was activated with.
+pub struct NullAudioBackend {
+    run: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Result<NullBackendResult, std::io::Error>>>,
+}
+
+impl NullAudioBackend {
+    /// Start running the given audio thread in a new thread.
+    pub fn start(
+        mut audio_thread: DSEngineAudioThread,
+        settings: NullBackendSettings,
+    ) -> Result<Self, std::io::Error> {
+        assert_ne!(settings.block_size, 0);
+        assert_ne!(settings.num_out_channels, 0);
+
+        let sample_rate = audio_thread.sample_rate();
+
+        let mut wav_writer = if let NullBackendCapture::WavFile(path) = &settings.capture {
+            Some(WavWriter::new(
+                path,
+                settings.num_out_channels as u16,
+                sample_rate.as_f64() as u32,
+            )?)
+        } else {
+            None
+        };
+
+        let run = Arc::new(AtomicBool::new(true));
+        let run_clone = Arc::clone(&run);
+
+        let handle = thread_priority::spawn(
+            ThreadPriority::Max,
+            move |priority_res| -> Result<NullBackendResult, std::io::Error> {
+                if let Err(e) = priority_res {
+                    log::error!("Failed to set null backend thread priority to max: {:?}", e);
+                }
+
+                let mut res = NullBackendResult::default();
+                if settings.capture == NullBackendCapture::Memory {
+                    res.captured = Some(Vec::new());
+                }
+
+                let mut out_buffer: Vec<f32> =
+                    vec![0.0; settings.block_size * settings.num_out_channels];
+
+                let block_duration =
+                    Duration::from_secs_f64(settings.block_size as f64 / sample_rate.as_f64());
+
+                #[cfg(target_os = "windows")]
+                let spin_sleeper = spin_sleep::SpinSleeper::default();
+
+                let mut next_deadline = Instant::now();
+
+                while run_clone.load(Ordering::Relaxed) {
+                    if !audio_thread.process_interleaved_output_only(
+                        settings.num_out_channels,
+                        &mut out_buffer,
+                        |s| s,
+                    ) {
+                        res.num_underruns += 1;
+                    }
+                    res.num_blocks += 1;
+
+                    if let Some(captured) = &mut res.captured {
+                        captured.extend_from_slice(&out_buffer);
+                    }
+                    if let Some(writer) = &mut wav_writer {
+                        if let Err(e) = writer.write_samples(&out_buffer) {
+                            // Keep the file valid up to the point where
+                            // writing stopped.
+                            if let Some(writer) = wav_writer.take() {
+                                writer.finish()?;
+                            }
+
+                            return Err(e);
+                        }
+                    }
+
+                    // Wait until the simulated device would ask for the next block.
+                    next_deadline += block_duration;
+                    let now = Instant::now();
+                    if next_deadline > now {
+                        #[cfg(not(target_os = "windows"))]
+                        std::thread::sleep(next_deadline - now);
+
+                        #[cfg(target_os = "windows")]
+                        spin_sleeper.sleep(next_deadline - now);
+                    } else {
+                        // Don't try to catch up on blocks we are late on, just
+                        // like a real audio device.
+                        next_deadline = now;
+                    }
+                }
+
+                if let Some(wav_writer) = wav_writer.take() {
+                    wav_writer.finish()?;
+                }
+
+                Ok(res)
+            },
+        );
+
+        Ok(Self { run, handle: Some(handle) })
+    }
+
+    /// Stop the backend and return the results.
+    pub fn stop(mut self) -> Result<NullBackendResult, std::io::Error> {
+        self.stop_inner()
+    }
+
+    fn stop_inner(&mut self) -> Result<NullBackendResult, std::io::Error> {
+        self.run.store(false, Ordering::Relaxed);
+
+        if let Some(handle) = self.handle.take() {
+            match handle.join() {
+                Ok(res) => res,
+                Err(e) => Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Null backend thread panicked: {:?}", e),
+                )),
+            }
+        } else {
+            Ok(NullBackendResult::default())
+        }
+    }
+}
+
+impl Drop for NullAudioBackend {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop_inner() {
+            log::error!("Error while stopping null audio backend: {}", e);
+        }
+    }
+}
+
+/// The maximum number of bytes in the data chunk of a WAV file, since the
+/// size of the whole file (minus the first 8 bytes) must fit in a `u32`.
+const WAV_MAX_DATA_BYTES: u32 = u32::MAX - 36;
+
+/// A minimal writer for 32 bit float WAV files.
+struct WavWriter {
+    file: BufWriter<File>,
+    num_data_bytes: u32,
+}
+
+impl WavWriter {
+    fn new(path: &Path, num_channels: u16, sample_rate: u32) -> Result<Self, std::io::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let block_align = u32::from(num_channels) * 4;
+
+        file.write_all(b"RIFF")?;
+        // The size of the file is filled in once writing has finished.
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // WAVE_FORMAT_IEEE_FLOAT
+        file.write_all(&3u16.to_le_bytes())?;
+        file.write_all(&num_channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align).to_le_bytes())?;
+        file.write_all(&(block_align as u16).to_le_bytes())?;
+        file.write_all(&32u16.to_le_bytes())?;
+
+        file.write_all(b"data")?;
+        // The size of the data is filled in once writing has finished.
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self { file, num_data_bytes: 0 })
+    }
+
+    /// Write the given samples, or return an error without writing anything
+    /// if that would go over the maximum size of a WAV file.
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), std::io::Error> {
+        let num_data_bytes = u32::try_from(samples.len() * 4)
+            .ok()
+            .and_then(|num_bytes| self.num_data_bytes.checked_add(num_bytes))
+            .filter(|num_data_bytes| *num_data_bytes <= WAV_MAX_DATA_BYTES)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "Reached the maximum size of a WAV file",
+                )
+            })?;
+
+        for s in samples.iter() {
+            self.file.write_all(&s.to_le_bytes())?;
+        }
+        self.num_data_bytes = num_data_bytes;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), std::io::Error> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.num_data_bytes).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.num_data_bytes.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_wav_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dropseed-{}-{}.wav", name, std::process::id()))
+    }
+
+    #[test]
+    fn wav_writer_writes_header_and_samples() {
+        let path = temp_wav_path("wav-writer");
+
+        let mut writer = WavWriter::new(&path, 2, 48_000).unwrap();
+        writer.write_samples(&[0.5, -0.5, 1.0, -1.0]).unwrap();
+        writer.write_samples(&[0.25, -0.25]).unwrap();
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_le_bytes(bytes[i..i + 2].try_into().unwrap());
+
+        assert_eq!(bytes.len(), 44 + 6 * 4);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(4), 36 + 6 * 4);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(20), 3);
+        assert_eq!(u16_at(22), 2);
+        assert_eq!(u32_at(24), 48_000);
+        assert_eq!(u32_at(28), 48_000 * 8);
+        assert_eq!(u16_at(32), 8);
+        assert_eq!(u16_at(34), 32);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(40), 6 * 4);
+
+        let samples: Vec<f32> = bytes[44..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(samples, vec![0.5, -0.5, 1.0, -1.0, 0.25, -0.25]);
+    }
+
+    #[test]
+    fn wav_writer_stops_at_max_size() {
+        let path = temp_wav_path("wav-writer-max-size");
+
+        let mut writer = WavWriter::new(&path, 1, 48_000).unwrap();
+        // Pretend that the file is almost full.
+        writer.num_data_bytes = WAV_MAX_DATA_BYTES - 4;
+
+        assert!(writer.write_samples(&[1.0, 1.0]).is_err());
+        assert_eq!(writer.num_data_bytes, WAV_MAX_DATA_BYTES - 4);
+
+        writer.write_samples(&[1.0]).unwrap();
+        assert_eq!(writer.num_data_bytes, WAV_MAX_DATA_BYTES);
+
+        assert!(writer.write_samples(&[1.0]).is_err());
+
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(bytes.len(), 44 + 4);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), u32::MAX);
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), WAV_MAX_DATA_BYTES);
+    }
+}
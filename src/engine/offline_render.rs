@@ -0,0 +1,189 @@
+use meadowlark_core_types::time::{Frames, MusicalTime};
+
+use crate::graph::shared_pools::SharedProcessorSchedule;
+use crate::processor_schedule::{GraphAutomationEvent, GraphNoteEvent, TransportHandle};
+
+use super::audio_thread::{ALLOCATED_AUTOMATION_EVENTS, ALLOCATED_NOTE_EVENTS};
+use super::process_thread::sort_events;
+
+/// The range of the project to render in offline mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OfflineRenderRange {
+    /// Render the range between two frames on the transport's timeline.
+    Frames { start: Frames, end: Frames },
+    /// Render the range between two musical times on the transport's
+    /// timeline.
+    Musical { start: MusicalTime, end: MusicalTime },
+}
+
+/// Used to send note and automation events to the input of the audio graph
+/// in offline mode.
+///
+/// See `DSEngineMainThread::render_offline_with_events()`.
+pub struct OfflineEventInput<'a> {
+    note_in: &'a mut Vec<GraphNoteEvent>,
+    automation_in: &'a mut Vec<GraphAutomationEvent>,
+    graph_note_in_ports: usize,
+    graph_automation_in_port: bool,
+    frames: usize,
+}
+
+impl<'a> OfflineEventInput<'a> {
+    /// Send a note event to a note port on the input to the audio graph.
+    ///
+    /// `event.frame` is relative to the start of the block that is about to
+    /// be rendered.
+    ///
+    /// This returns `false` if the note port does not exist, if the event
+    /// is past the end of the block, or if too many events have been pushed
+    /// in this block.
+    pub fn push_note_event(&mut self, event: GraphNoteEvent) -> bool {
+        if usize::from(event.port) >= self.graph_note_in_ports
+            || event.frame as usize >= self.frames
+            || self.note_in.len() >= self.note_in.capacity()
+        {
+            return false;
+        }
+
+        self.note_in.push(event);
+        true
+    }
+
+    /// Send an automation event to the automation port on the input to the
+    /// audio graph.
+    ///
+    /// `event.frame` is relative to the start of the block that is about to
+    /// be rendered.
+    ///
+    /// This returns `false` if the audio graph has no automation input port,
+    /// if the event is past the end of the block, or if too many events have
+    /// been pushed in this block.
+    pub fn push_automation_event(&mut self, event: GraphAutomationEvent) -> bool {
+        if !self.graph_automation_in_port
+            || event.frame as usize >= self.frames
+            || self.automation_in.len() >= self.automation_in.capacity()
+        {
+            return false;
+        }
+
+        self.automation_in.push(event);
+        true
+    }
+
+    /// The number of frames in the block that is about to be rendered.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+/// Drives the processor schedule from the main thread instead of from
+/// a separate process thread.
+pub(crate) struct OfflineRenderer {
+    pub schedule: SharedProcessorSchedule,
+    pub transport_handle: TransportHandle,
+
+    graph_audio_in_channels: usize,
+    graph_audio_out_channels: usize,
+    graph_note_in_ports: usize,
+    graph_automation_in_port: bool,
+    max_frames: usize,
+
+    audio_in_temp_buffer: Vec<f32>,
+    audio_out_temp_buffer: Vec<f32>,
+    note_in_temp_buffer: Vec<GraphNoteEvent>,
+    automation_in_temp_buffer: Vec<GraphAutomationEvent>,
+    note_in_scratch_buffer: Vec<GraphNoteEvent>,
+    automation_in_scratch_buffer: Vec<GraphAutomationEvent>,
+    note_out_temp_buffer: Vec<GraphNoteEvent>,
+}
+
+impl OfflineRenderer {
+    pub fn new(
+        schedule: SharedProcessorSchedule,
+        transport_handle: TransportHandle,
+        graph_audio_in_channels: usize,
+        graph_audio_out_channels: usize,
+        graph_note_in_ports: usize,
+        graph_automation_in_port: bool,
+        max_frames: usize,
+    ) -> Self {
+        Self {
+            schedule,
+            transport_handle,
+            graph_audio_in_channels,
+            graph_audio_out_channels,
+            graph_note_in_ports,
+            graph_automation_in_port,
+            max_frames,
+            audio_in_temp_buffer: Vec::with_capacity(graph_audio_in_channels * max_frames),
+            audio_out_temp_buffer: Vec::with_capacity(graph_audio_out_channels * max_frames),
+            note_in_temp_buffer: Vec::with_capacity(ALLOCATED_NOTE_EVENTS),
+            automation_in_temp_buffer: Vec::with_capacity(ALLOCATED_AUTOMATION_EVENTS),
+            note_in_scratch_buffer: Vec::with_capacity(ALLOCATED_NOTE_EVENTS),
+            automation_in_scratch_buffer: Vec::with_capacity(ALLOCATED_AUTOMATION_EVENTS),
+            note_out_temp_buffer: Vec::with_capacity(ALLOCATED_NOTE_EVENTS),
+        }
+    }
+
+    pub fn max_frames(&self) -> usize {
+        self.max_frames
+    }
+
+    /// Get a handle to send events to the input of the audio graph in the
+    /// next block of `frames` frames.
+    pub fn event_input(&mut self, frames: usize) -> OfflineEventInput<'_> {
+        OfflineEventInput {
+            note_in: &mut self.note_in_temp_buffer,
+            automation_in: &mut self.automation_in_temp_buffer,
+            graph_note_in_ports: self.graph_note_in_ports,
+            graph_automation_in_port: self.graph_automation_in_port,
+            frames,
+        }
+    }
+
+    /// Process a single block of `frames` frames with silence as the
+    /// graph input, and return the interleaved output of the graph along
+    /// with the note events generated on the graph output.
+    ///
+    /// The events that were sent with `Self::event_input()` are sent to the
+    /// graph input in this block.
+    pub fn process_block(&mut self, frames: usize) -> (&[f32], &[GraphNoteEvent]) {
+        debug_assert!(frames <= self.max_frames);
+
+        self.audio_in_temp_buffer.clear();
+        self.audio_in_temp_buffer.resize(frames * self.graph_audio_in_channels, 0.0);
+
+        self.audio_out_temp_buffer.clear();
+        self.audio_out_temp_buffer.resize(frames * self.graph_audio_out_channels, 0.0);
+
+        self.note_out_temp_buffer.clear();
+
+        // The events are sorted by frame while keeping the order they were
+        // pushed in.
+        sort_events(&mut self.note_in_temp_buffer, &mut self.note_in_scratch_buffer, |e| e.frame);
+        sort_events(
+            &mut self.automation_in_temp_buffer,
+            &mut self.automation_in_scratch_buffer,
+            |e| e.frame,
+        );
+
+        self.schedule.process_interleaved(
+            frames,
+            &self.audio_in_temp_buffer,
+            &mut self.audio_out_temp_buffer,
+            &self.note_in_temp_buffer,
+            &self.automation_in_temp_buffer,
+            &mut self.note_out_temp_buffer,
+        );
+
+        self.note_in_temp_buffer.clear();
+        self.automation_in_temp_buffer.clear();
+
+        (&self.audio_out_temp_buffer, &self.note_out_temp_buffer)
+    }
+
+    /// Make sure that all plugin processors are dropped on this thread.
+    pub fn deactivate(&mut self) {
+        self.schedule.deactivate();
+    }
+}
@@ -1,24 +1,41 @@
 use basedrop::Owned;
+use rtrb::Consumer;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
+use std::time::{Duration, Instant};
 
 use crate::graph::shared_pools::SharedProcessorSchedule;
+use crate::processor_schedule::{GraphAutomationEvent, GraphNoteEvent};
+use crate::utils::wake_signal::WakeSignal;
 
 use super::audio_thread::{
-    AudioToProcessChannelRX, ProcessToAudioChannelTX, AUDIO_THREAD_POLL_INTERVAL,
+    planar_channel, planar_channel_mut, AudioToProcessChannelRX, OutputCycle,
+    ProcessToAudioChannelTX, ALLOCATED_AUTOMATION_EVENTS, ALLOCATED_NOTE_EVENTS,
 };
 
+/// The maximum amount of time the process thread waits for new input before
+/// checking if it should stop running.
+static PROCESS_THREAD_WAIT_TIMEOUT: Duration = Duration::from_millis(10);
+
 pub(crate) struct DSEngineProcessThread {
     audio_to_process_channel: Owned<AudioToProcessChannelRX>,
     process_to_audio_channel: Owned<ProcessToAudioChannelTX>,
+    note_in_rb_rx: Owned<Consumer<GraphNoteEvent>>,
+    automation_in_rb_rx: Owned<Consumer<GraphAutomationEvent>>,
+
+    to_process_signal: Arc<WakeSignal>,
+    to_audio_signal: Arc<WakeSignal>,
 
     graph_audio_in_channels: usize,
     graph_audio_out_channels: usize,
 
-    audio_in_temp_buffer: Owned<Vec<f32>>,
-    audio_out_temp_buffer: Owned<Vec<f32>>,
+    note_in_temp_buffer: Owned<Vec<GraphNoteEvent>>,
+    automation_in_temp_buffer: Owned<Vec<GraphAutomationEvent>>,
+    note_in_scratch_buffer: Owned<Vec<GraphNoteEvent>>,
+    automation_in_scratch_buffer: Owned<Vec<GraphAutomationEvent>>,
+    note_out_temp_buffer: Owned<Vec<GraphNoteEvent>>,
 
     schedule: SharedProcessorSchedule,
 }
@@ -27,115 +44,205 @@ impl DSEngineProcessThread {
     pub(super) fn new(
         audio_to_process_channel: AudioToProcessChannelRX,
         process_to_audio_channel: ProcessToAudioChannelTX,
+        note_in_rb_rx: Consumer<GraphNoteEvent>,
+        automation_in_rb_rx: Consumer<GraphAutomationEvent>,
+        to_process_signal: Arc<WakeSignal>,
+        to_audio_signal: Arc<WakeSignal>,
         graph_audio_in_channels: usize,
         graph_audio_out_channels: usize,
-        max_frames: usize,
         schedule: SharedProcessorSchedule,
         coll_handle: &basedrop::Handle,
     ) -> Self {
         Self {
             audio_to_process_channel: Owned::new(coll_handle, audio_to_process_channel),
             process_to_audio_channel: Owned::new(coll_handle, process_to_audio_channel),
+            note_in_rb_rx: Owned::new(coll_handle, note_in_rb_rx),
+            automation_in_rb_rx: Owned::new(coll_handle, automation_in_rb_rx),
+            to_process_signal,
+            to_audio_signal,
             graph_audio_in_channels,
             graph_audio_out_channels,
-            audio_in_temp_buffer: Owned::new(
+            note_in_temp_buffer: Owned::new(coll_handle, Vec::with_capacity(ALLOCATED_NOTE_EVENTS)),
+            automation_in_temp_buffer: Owned::new(
+                coll_handle,
+                Vec::with_capacity(ALLOCATED_AUTOMATION_EVENTS),
+            ),
+            note_in_scratch_buffer: Owned::new(
+                coll_handle,
+                Vec::with_capacity(ALLOCATED_NOTE_EVENTS),
+            ),
+            automation_in_scratch_buffer: Owned::new(
                 coll_handle,
-                Vec::with_capacity(graph_audio_in_channels * max_frames),
+                Vec::with_capacity(ALLOCATED_AUTOMATION_EVENTS),
             ),
-            audio_out_temp_buffer: Owned::new(
+            note_out_temp_buffer: Owned::new(
                 coll_handle,
-                Vec::with_capacity(graph_audio_out_channels * max_frames),
+                Vec::with_capacity(ALLOCATED_NOTE_EVENTS),
             ),
             schedule,
         }
     }
 
     pub fn run(&mut self, run: Arc<AtomicBool>) {
-        #[cfg(target_os = "windows")]
-        let spin_sleeper = spin_sleep::SpinSleeper::default();
-
         while run.load(Ordering::Relaxed) {
-            let num_frames = match &mut *self.audio_to_process_channel {
-                AudioToProcessChannelRX::HasInputAudio { audio_rb_rx } => {
-                    if !audio_rb_rx.is_abandoned() {
-                        let num_samples = audio_rb_rx.slots();
-
-                        if num_samples == 0 {
-                            #[cfg(not(target_os = "windows"))]
-                            std::thread::sleep(AUDIO_THREAD_POLL_INTERVAL);
+            // Get the token before checking for input so we don't miss a
+            // notification from the audio thread.
+            let token = self.to_process_signal.token();
 
-                            #[cfg(target_os = "windows")]
-                            spin_sleeper.sleep(AUDIO_THREAD_POLL_INTERVAL);
-
-                            continue;
-                        }
-
-                        let chunk = audio_rb_rx.read_chunk(num_samples).unwrap();
-
-                        let (slice_1, slice_2) = chunk.as_slices();
-
-                        self.audio_in_temp_buffer.clear();
-                        self.audio_in_temp_buffer.extend_from_slice(slice_1);
-                        self.audio_in_temp_buffer.extend_from_slice(slice_2);
-
-                        chunk.commit_all();
-
-                        num_samples / self.graph_audio_in_channels
-                    } else {
+            let cycle = match self.audio_to_process_channel.cycle_rb_rx.pop() {
+                Ok(cycle) => cycle,
+                Err(_) => {
+                    if self.audio_to_process_channel.cycle_rb_rx.is_abandoned() {
                         run.store(false, Ordering::Relaxed);
                         break;
                     }
-                }
-                AudioToProcessChannelRX::NoInputAudio { num_frames_wanted } => {
-                    if Arc::strong_count(num_frames_wanted) > 1 {
-                        let num_frames = num_frames_wanted.swap(0, Ordering::SeqCst);
 
-                        if num_frames == 0 {
-                            #[cfg(not(target_os = "windows"))]
-                            std::thread::sleep(AUDIO_THREAD_POLL_INTERVAL);
+                    self.to_process_signal
+                        .wait(token, Some(Instant::now() + PROCESS_THREAD_WAIT_TIMEOUT));
 
-                            #[cfg(target_os = "windows")]
-                            spin_sleeper.sleep(AUDIO_THREAD_POLL_INTERVAL);
+                    continue;
+                }
+            };
 
-                            continue;
+            // Make sure the events are in order, since the schedule expects
+            // them to be sorted by frame.
+            self.note_in_temp_buffer.clear();
+            for _ in 0..cycle.num_note_events {
+                match self.note_in_rb_rx.pop() {
+                    Ok(event) => {
+                        if self.note_in_temp_buffer.len() < self.note_in_temp_buffer.capacity() {
+                            self.note_in_temp_buffer.push(event);
                         }
+                    }
+                    Err(_) => break,
+                }
+            }
+            sort_events(&mut self.note_in_temp_buffer, &mut self.note_in_scratch_buffer, |e| {
+                e.frame
+            });
 
-                        num_frames
-                    } else {
-                        run.store(false, Ordering::Relaxed);
-                        break;
+            self.automation_in_temp_buffer.clear();
+            for _ in 0..cycle.num_automation_events {
+                match self.automation_in_rb_rx.pop() {
+                    Ok(event) => {
+                        if self.automation_in_temp_buffer.len()
+                            < self.automation_in_temp_buffer.capacity()
+                        {
+                            self.automation_in_temp_buffer.push(event);
+                        }
                     }
+                    Err(_) => break,
                 }
-            };
+            }
+            sort_events(
+                &mut self.automation_in_temp_buffer,
+                &mut self.automation_in_scratch_buffer,
+                |e| e.frame,
+            );
+
+            self.note_out_temp_buffer.clear();
 
-            self.audio_out_temp_buffer.clear();
-            self.audio_out_temp_buffer.resize(num_frames * self.graph_audio_out_channels, 0.0);
+            let frames = cycle.frames;
+            let in_channels = self.graph_audio_in_channels;
+            let out_channels = self.graph_audio_out_channels;
 
-            self.schedule
-                .process_interleaved(&*self.audio_in_temp_buffer, &mut *self.audio_out_temp_buffer);
+            // The audio thread sends the audio of a cycle before the cycle
+            // itself.
+            let in_chunk = match &mut self.audio_to_process_channel.audio_rb_rx {
+                Some(audio_rb_rx) => match audio_rb_rx.read_chunk(frames * in_channels) {
+                    Ok(chunk) => Some(chunk),
+                    Err(_) => {
+                        log::error!("Audio thread sent less audio than expected");
+                        None
+                    }
+                },
+                None => None,
+            };
 
-            match self
+            let mut out_chunk = match self
                 .process_to_audio_channel
                 .audio_rb_tx
-                .write_chunk(num_frames * self.graph_audio_out_channels)
+                .write_chunk(frames * out_channels)
             {
-                Ok(mut chunk) => {
-                    let (slice_1, slice_2) = chunk.as_mut_slices();
-
-                    let out_part = &self.audio_out_temp_buffer[0..slice_1.len()];
-                    slice_1.copy_from_slice(&out_part[..slice_1.len()]);
-
-                    let out_part =
-                        &self.audio_out_temp_buffer[slice_1.len()..slice_1.len() + slice_2.len()];
-                    slice_2.copy_from_slice(&out_part[..slice_2.len()]);
-
-                    chunk.commit_all();
-                }
+                Ok(chunk) => chunk,
                 Err(_) => {
                     log::error!("Ran out of space in process thread to audio thread audio buffer");
                     return;
                 }
+            };
+
+            {
+                // Copy the planar audio straight into and out of the buffers
+                // of the graph input and output nodes.
+                let (in_slice_1, in_slice_2) =
+                    in_chunk.as_ref().map(|chunk| chunk.as_slices()).unwrap_or((&[], &[]));
+                let (out_slice_1, out_slice_2) = out_chunk.as_mut_slices();
+
+                self.schedule.process_with(
+                    frames,
+                    &*self.note_in_temp_buffer,
+                    &*self.automation_in_temp_buffer,
+                    &mut *self.note_out_temp_buffer,
+                    |ch, processed_frames, buffer| {
+                        if in_chunk.is_none() || ch >= in_channels {
+                            buffer.fill(0.0);
+                            return;
+                        }
+
+                        let (part_1, part_2) = planar_channel(
+                            in_slice_1,
+                            in_slice_2,
+                            (ch * frames) + processed_frames,
+                            buffer.len(),
+                        );
+                        buffer[0..part_1.len()].copy_from_slice(part_1);
+                        buffer[part_1.len()..].copy_from_slice(part_2);
+                    },
+                    |ch, processed_frames, buffer| {
+                        if ch >= out_channels {
+                            return;
+                        }
+
+                        let (part_1, part_2) = planar_channel_mut(
+                            out_slice_1,
+                            out_slice_2,
+                            (ch * frames) + processed_frames,
+                            buffer.len(),
+                        );
+                        part_1.copy_from_slice(&buffer[0..part_1.len()]);
+                        part_2.copy_from_slice(&buffer[part_1.len()..]);
+                    },
+                );
+            }
+
+            if let Some(in_chunk) = in_chunk {
+                in_chunk.commit_all();
+            }
+
+            // Send the note events and the audio before the cycle so they
+            // are available once the audio thread receives the cycle.
+            let mut num_note_events = 0;
+            for event in self.note_out_temp_buffer.drain(..) {
+                if self.process_to_audio_channel.note_rb_tx.push(event).is_err() {
+                    log::error!("Ran out of space in process thread to audio thread note buffer");
+                    break;
+                }
+                num_note_events += 1;
             }
+
+            out_chunk.commit_all();
+
+            if self
+                .process_to_audio_channel
+                .cycle_rb_tx
+                .push(OutputCycle { id: cycle.id, frames, num_note_events })
+                .is_err()
+            {
+                log::error!("Ran out of space in process thread to audio thread cycle buffer");
+                return;
+            }
+
+            self.to_audio_signal.notify();
         }
 
         // Make sure we drop all plugin processors in the process thread
@@ -143,3 +250,75 @@ impl DSEngineProcessThread {
         self.schedule.deactivate();
     }
 }
+
+/// Sort the events by frame, while keeping the relative order of the events
+/// on the same frame (so that events pushed from different sources stay in
+/// the order they were pushed in).
+///
+/// This is a bottom-up merge sort that merges into `scratch` instead of
+/// allocating, so `scratch` must have at least the same capacity as
+/// `events`. Note that the two buffers may be swapped.
+pub(super) fn sort_events<T: Copy>(
+    events: &mut Vec<T>,
+    scratch: &mut Vec<T>,
+    frame: impl Fn(&T) -> u32,
+) {
+    // The events are usually already sorted.
+    if events.windows(2).all(|w| frame(&w[0]) <= frame(&w[1])) {
+        return;
+    }
+
+    let len = events.len();
+    let mut width = 1;
+    while width < len {
+        scratch.clear();
+
+        let mut start = 0;
+        while start < len {
+            let mid = (start + width).min(len);
+            let end = (start + (2 * width)).min(len);
+
+            let (mut i, mut j) = (start, mid);
+            while i < mid && j < end {
+                // Take the event from the first run on the same frame to keep
+                // the sort stable.
+                if frame(&events[j]) < frame(&events[i]) {
+                    scratch.push(events[j]);
+                    j += 1;
+                } else {
+                    scratch.push(events[i]);
+                    i += 1;
+                }
+            }
+            scratch.extend_from_slice(&events[i..mid]);
+            scratch.extend_from_slice(&events[j..end]);
+
+            start = end;
+        }
+
+        std::mem::swap(events, scratch);
+        width *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_events_is_stable() {
+        let mut events: Vec<(u32, usize)> = Vec::with_capacity(64);
+        let mut scratch: Vec<(u32, usize)> = Vec::with_capacity(64);
+
+        // Two sources that each pushed their events in order.
+        let frames = [0, 3, 3, 7, 12, 12, 40, 1, 3, 3, 12, 13, 39, 40, 40, 2, 0, 7, 5];
+        events.extend(frames.iter().enumerate().map(|(i, frame)| (*frame, i)));
+
+        let mut expected = events.clone();
+        expected.sort_by_key(|e| e.0);
+
+        sort_events(&mut events, &mut scratch, |e| e.0);
+
+        assert_eq!(events, expected);
+    }
+}
@@ -0,0 +1,608 @@
+//! Racks are internal plugins that contain their own audio graph, so that
+//! a group of plugins can be inserted into the audio graph (and saved and
+//! loaded) as a single plugin.
+//!
+//! The configuration and the contents of a rack are stored in its raw state
+//! with `serde`, so the `serde` feature is required to create racks with a
+//! non-default `RackConfig` and to save and load the state of racks.
+
+use basedrop::Shared;
+use fnv::FnvHashSet;
+use meadowlark_core_types::time::SampleRate;
+use smallvec::SmallVec;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use dropseed_plugin_api::ext::audio_ports::{
+    AudioPortInfo, MainPortsLayout, PluginAudioPortsExt, PORT_TYPE_MONO, PORT_TYPE_STEREO,
+};
+use dropseed_plugin_api::ext::note_ports::{NoteDialects, NotePortInfo, PluginNotePortsExt};
+use dropseed_plugin_api::plugin_scanner::{PluginFormat, ScannedPluginKey};
+use dropseed_plugin_api::transport::TempoMap;
+use dropseed_plugin_api::{
+    DSPluginSaveState, HostRequestChannelSender, HostRequestFlags, PluginActivatedInfo,
+    PluginInstanceID, PluginMainThread,
+};
+
+use crate::graph::shared_pools::SharedProcessorSchedule;
+use crate::graph::{AudioGraph, DSEdgeID, Edge};
+use crate::plugin_host::PluginWatchdogSettings;
+use crate::plugin_scanner::PluginScanner;
+use crate::utils::thread_id::SharedThreadIDs;
+
+use super::graph_save_state::DSGraphSaveState;
+use super::modify_request::ModifyGraphRequest;
+use super::telemetry::SharedEngineTelemetry;
+use super::timer_wheel::EngineTimerWheel;
+use super::{GraphPortInfo, ModifyGraphRes, NewPluginRes, OnIdleEvent, TransportSaveState};
+
+mod processor;
+mod save_state;
+
+use processor::RackPluginProcessor;
+
+/// The reverse domain name of rack plugins.
+///
+/// Racks don't need to be scanned. Add a new rack to the audio graph with
+/// the save state returned by `RackConfig::new_save_state()`.
+pub const RACK_PLUGIN_RDN: &str = "app.meadowlark.rack";
+
+/// The ports of a rack.
+///
+/// These cannot be changed once the rack has been added to the audio graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RackConfig {
+    /// The number of channels on the main audio input port of the rack.
+    ///
+    /// Set this to `0` for a rack without an audio input port (i.e. an
+    /// instrument rack).
+    pub num_audio_in_channels: u16,
+
+    /// The number of channels on the main audio output port of the rack.
+    ///
+    /// This cannot be `0`.
+    pub num_audio_out_channels: u16,
+
+    /// Whether the rack has a note input port.
+    pub note_in_port: bool,
+
+    /// Whether the rack has a note output port.
+    pub note_out_port: bool,
+}
+
+impl Default for RackConfig {
+    fn default() -> Self {
+        Self {
+            num_audio_in_channels: 2,
+            num_audio_out_channels: 2,
+            note_in_port: true,
+            note_out_port: true,
+        }
+    }
+}
+
+impl RackConfig {
+    /// The save state of a new, empty rack with this configuration.
+    ///
+    /// Add this to the audio graph with `ModifyGraphRequest::add_plugin_instances`
+    /// to create a new rack.
+    ///
+    /// Without the `serde` feature the configuration cannot be stored, so
+    /// an error is logged and the rack uses `RackConfig::default()` instead.
+    pub fn new_save_state(&self) -> DSPluginSaveState {
+        let mut save_state = DSPluginSaveState::new_with_default_state(ScannedPluginKey {
+            rdn: String::from(RACK_PLUGIN_RDN),
+            format: PluginFormat::Internal,
+        });
+
+        match save_state::encode(*self, DSGraphSaveState::default()) {
+            Ok(raw_state) => save_state.raw_state = Some(raw_state),
+            Err(e) => log::error!("Could not store the configuration of the rack: {}", e),
+        }
+
+        save_state
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.num_audio_out_channels == 0 {
+            return Err("A rack must have at least one audio output channel".into());
+        }
+
+        Ok(())
+    }
+
+    fn audio_ports_ext(&self) -> PluginAudioPortsExt {
+        let port = |channels: u16| AudioPortInfo {
+            stable_id: 0,
+            channels,
+            port_type: match channels {
+                1 => Some(PORT_TYPE_MONO.into()),
+                2 => Some(PORT_TYPE_STEREO.into()),
+                _ => None,
+            },
+            display_name: None,
+        };
+
+        if self.num_audio_in_channels == 0 {
+            PluginAudioPortsExt {
+                inputs: Vec::new(),
+                outputs: vec![port(self.num_audio_out_channels)],
+                main_ports_layout: MainPortsLayout::OutOnly,
+            }
+        } else {
+            PluginAudioPortsExt {
+                inputs: vec![port(self.num_audio_in_channels)],
+                outputs: vec![port(self.num_audio_out_channels)],
+                main_ports_layout: MainPortsLayout::InOut,
+            }
+        }
+    }
+
+    fn note_ports_ext(&self) -> PluginNotePortsExt {
+        let port = || NotePortInfo {
+            stable_id: 0,
+            supported_dialects: NoteDialects::CLAP | NoteDialects::MIDI,
+            preferred_dialect: None,
+            display_name: None,
+        };
+
+        PluginNotePortsExt {
+            inputs: if self.note_in_port { vec![port()] } else { Vec::new() },
+            outputs: if self.note_out_port { vec![port()] } else { Vec::new() },
+        }
+    }
+
+    /// The audio ports of the graph input/output node inside of the rack,
+    /// which mirror the main ports of the rack.
+    fn graph_ports(num_channels: u16, display_name: &str) -> Vec<GraphPortInfo> {
+        if num_channels == 0 {
+            return Vec::new();
+        }
+
+        vec![GraphPortInfo {
+            stable_id: 0,
+            channels: num_channels,
+            display_name: String::from(display_name),
+        }]
+    }
+}
+
+/// Returns `true` if the given key refers to a rack.
+pub(crate) fn is_rack_key(key: &ScannedPluginKey) -> bool {
+    key.format == PluginFormat::Internal && key.rdn == RACK_PLUGIN_RDN
+}
+
+/// Returns the configuration stored in the raw state of a rack.
+pub(crate) fn decode_rack_config(raw_state: Option<&[u8]>) -> Result<RackConfig, String> {
+    match raw_state {
+        Some(raw_state) => save_state::decode_config(raw_state),
+        None => Ok(RackConfig::default()),
+    }
+}
+
+/// Information about the audio graph inside of a rack.
+#[derive(Debug, Clone)]
+pub struct RackInfo {
+    pub config: RackConfig,
+
+    /// The ID of the input node of the graph inside of the rack. Edges from
+    /// this node carry the signals on the input ports of the rack.
+    pub graph_in_id: PluginInstanceID,
+    /// The ID of the output node of the graph inside of the rack. Edges to
+    /// this node carry the signals on the output ports of the rack.
+    pub graph_out_id: PluginInstanceID,
+
+    /// The IDs of all the plugins in the rack, not including the graph
+    /// input/output nodes.
+    pub plugin_ids: Vec<PluginInstanceID>,
+
+    /// All of the edges (port connections) in the rack.
+    pub edges: Vec<Edge>,
+
+    /// The latency of the rack in frames, meaning the latency of the
+    /// longest path through the graph inside of the rack.
+    pub latency: i64,
+}
+
+/// The audio graph inside of a rack.
+///
+/// This is owned by both the audio graph that contains the rack, and the
+/// main thread part of the rack plugin.
+///
+/// Note that the processors of plugins that are removed from an inactive
+/// rack are only dropped once the rack is processed again.
+pub(crate) struct RackGraph {
+    id: PluginInstanceID,
+    config: RackConfig,
+
+    graph: AudioGraph,
+    shared_schedule: SharedProcessorSchedule,
+
+    /// Whether the processor of the rack plugin currently exists.
+    processor_alive: Arc<AtomicBool>,
+    host_request: HostRequestChannelSender,
+
+    /// The latency that was reported to the audio graph containing this
+    /// rack when the rack was last activated.
+    latency: i64,
+
+    /// A save state that was loaded before the rack was added to the audio
+    /// graph, or while the plugin scanner was not available.
+    pending_state: Option<DSGraphSaveState>,
+
+    sample_rate: SampleRate,
+    min_frames: u32,
+    max_frames: u32,
+    note_buffer_size: usize,
+    event_buffer_size: usize,
+    plugin_watchdog: Option<PluginWatchdogSettings>,
+}
+
+impl RackGraph {
+    pub fn new(
+        id: PluginInstanceID,
+        config: RackConfig,
+        host_request: HostRequestChannelSender,
+        coll_handle: basedrop::Handle,
+        sample_rate: SampleRate,
+        min_frames: u32,
+        max_frames: u32,
+        note_buffer_size: usize,
+        event_buffer_size: usize,
+        thread_ids: SharedThreadIDs,
+        plugin_watchdog: Option<PluginWatchdogSettings>,
+        engine_timer: &mut EngineTimerWheel,
+    ) -> Self {
+        // The transport of the audio graph containing this rack is used
+        // instead of the transport of this graph.
+        let (mut graph, shared_schedule, _transport_handle) = AudioGraph::new(
+            coll_handle,
+            usize::from(config.num_audio_in_channels),
+            usize::from(config.num_audio_out_channels),
+            RackConfig::graph_ports(config.num_audio_in_channels, "Rack In"),
+            RackConfig::graph_ports(config.num_audio_out_channels, "Rack Out"),
+            usize::from(config.note_in_port),
+            usize::from(config.note_out_port),
+            false,
+            sample_rate,
+            min_frames,
+            max_frames,
+            note_buffer_size,
+            event_buffer_size,
+            thread_ids,
+            None,
+            plugin_watchdog,
+            None,
+            Arc::new(SharedEngineTelemetry::new(sample_rate)),
+            engine_timer,
+        );
+
+        let processor_alive = Arc::new(AtomicBool::new(false));
+        graph.set_parent_processor(Arc::clone(&processor_alive));
+
+        Self {
+            id,
+            config,
+            graph,
+            shared_schedule,
+            processor_alive,
+            host_request,
+            latency: 0,
+            pending_state: None,
+            sample_rate,
+            min_frames,
+            max_frames,
+            note_buffer_size,
+            event_buffer_size,
+            plugin_watchdog,
+        }
+    }
+
+    pub fn graph(&self) -> &AudioGraph {
+        &self.graph
+    }
+
+    pub fn graph_mut(&mut self) -> &mut AudioGraph {
+        &mut self.graph
+    }
+
+    pub fn info(&self) -> RackInfo {
+        RackInfo {
+            config: self.config,
+            graph_in_id: self.graph.graph_in_id().clone(),
+            graph_out_id: self.graph.graph_out_id().clone(),
+            plugin_ids: self.graph.plugin_ids(),
+            edges: self.graph.connected_edges().cloned().collect(),
+            latency: self.graph.latency(),
+        }
+    }
+
+    /// Apply the given request to the audio graph inside of the rack.
+    pub fn modify(
+        &mut self,
+        request: ModifyGraphRequest,
+        request_id: u64,
+        plugin_scanner: &mut PluginScanner,
+        engine_timer: &mut EngineTimerWheel,
+    ) -> ModifyGraphRes {
+        // Make sure the request refers to the latest state of the rack.
+        self.restore_pending_state(plugin_scanner, engine_timer);
+
+        let (new_plugins, removed_plugins, new_edges, removed_edges) =
+            self.apply_request(request, plugin_scanner, engine_timer);
+
+        self.host_request.request(HostRequestFlags::MARK_DIRTY);
+
+        ModifyGraphRes { request_id, new_plugins, removed_plugins, new_edges, removed_edges }
+    }
+
+    /// Replace the plugins and edges in the rack with the ones in the save
+    /// state that was last loaded, if there is one.
+    pub fn restore_pending_state(
+        &mut self,
+        plugin_scanner: &mut PluginScanner,
+        engine_timer: &mut EngineTimerWheel,
+    ) {
+        if let Some(save_state) = self.pending_state.take() {
+            let disconnect_edges = self.graph.edges().iter().map(|edge| edge.id).collect();
+
+            let (request, _) = save_state.into_request(
+                self.graph.graph_in_id(),
+                self.graph.graph_out_id(),
+                self.graph.plugin_ids(),
+                disconnect_edges,
+            );
+
+            self.apply_request(request, plugin_scanner, engine_timer);
+        }
+    }
+
+    fn apply_request(
+        &mut self,
+        mut request: ModifyGraphRequest,
+        plugin_scanner: &mut PluginScanner,
+        engine_timer: &mut EngineTimerWheel,
+    ) -> (Vec<NewPluginRes>, Vec<PluginInstanceID>, Vec<Edge>, Vec<DSEdgeID>) {
+        let new_plugins_res: Vec<NewPluginRes> = request
+            .add_plugin_instances
+            .drain(..)
+            .map(|save_state| {
+                self.graph.add_new_plugin_instance(
+                    save_state,
+                    None,
+                    plugin_scanner,
+                    true,
+                    engine_timer,
+                )
+            })
+            .collect();
+
+        let mut removed_edges: FnvHashSet<DSEdgeID> = FnvHashSet::default();
+        for ds_edge_id in request.disconnect_edges.iter() {
+            if self.graph.disconnect_edge(*ds_edge_id) {
+                removed_edges.insert(*ds_edge_id);
+            }
+        }
+
+        let (mut removed_plugins, removed_plugin_edges) =
+            self.graph.remove_plugin_instances(&request.remove_plugin_instances, engine_timer);
+        removed_edges.extend(removed_plugin_edges);
+
+        let new_plugin_ids: Vec<PluginInstanceID> =
+            new_plugins_res.iter().map(|res| res.plugin_id.clone()).collect();
+
+        let new_edges = self.graph.connect_new_edges(&request.connect_new_edges, &new_plugin_ids);
+
+        self.graph.compile();
+
+        (
+            new_plugins_res,
+            removed_plugins.drain().collect(),
+            new_edges,
+            removed_edges.drain().collect(),
+        )
+    }
+
+    /// Handle the requests from the plugins inside of the rack, and the
+    /// results of the compiler of the rack.
+    ///
+    /// If `block` is `true`, then this will wait until the audio graph of
+    /// the rack has finished compiling.
+    pub fn on_idle(
+        &mut self,
+        events_out: &mut SmallVec<[OnIdleEvent; 32]>,
+        plugin_scanner: &mut PluginScanner,
+        engine_timer: &mut EngineTimerWheel,
+        block: bool,
+    ) {
+        self.restore_pending_state(plugin_scanner, engine_timer);
+
+        if self.graph.on_idle(events_out, engine_timer) {
+            self.graph.compile();
+        }
+
+        self.graph.poll_racks(events_out, plugin_scanner, engine_timer, block);
+
+        loop {
+            let res =
+                if block { self.graph.wait_for_compiler() } else { self.graph.poll_compiler() };
+
+            match res {
+                Some((_, Ok(()))) => {}
+                Some((_, Err(e))) => {
+                    log::error!("Could not compile the audio graph of rack {:?}: {}", &self.id, e);
+                }
+                None => break,
+            }
+        }
+
+        // The audio graph containing this rack needs to be recompiled when
+        // the latency of the rack changes.
+        let latency = self.graph.latency();
+        if latency != self.latency && self.processor_alive.load(Ordering::SeqCst) {
+            self.latency = latency;
+            self.host_request.request(HostRequestFlags::RESTART);
+        }
+
+        if self.graph.is_save_state_dirty() {
+            self.host_request.request(HostRequestFlags::MARK_DIRTY);
+        }
+    }
+
+    /// Gracefully remove all of the plugins in the rack. This is called
+    /// once the rack itself has been removed.
+    pub fn remove(&mut self, engine_timer: &mut EngineTimerWheel) {
+        self.graph.reset(engine_timer);
+    }
+
+    fn activate(
+        &mut self,
+        sample_rate: SampleRate,
+        min_frames: u32,
+        max_frames: u32,
+    ) -> Result<PluginActivatedInfo, String> {
+        if !self.graph.feedback_delays_fit(max_frames) {
+            return Err(format!(
+                "A feedback edge in rack {:?} has a delay that is shorter than the maximum block size of {} frames",
+                &self.id, max_frames
+            ));
+        }
+
+        if sample_rate.as_f64() != self.sample_rate.as_f64()
+            || min_frames != self.min_frames
+            || max_frames != self.max_frames
+        {
+            self.reconfigure(sample_rate, min_frames, max_frames);
+        }
+
+        self.latency = self.graph.latency();
+        self.processor_alive.store(true, Ordering::SeqCst);
+
+        Ok(PluginActivatedInfo {
+            processor: Box::new(RackPluginProcessor::new(
+                self.shared_schedule.clone(),
+                Arc::clone(&self.processor_alive),
+                self.note_buffer_size,
+            )),
+            internal_handle: None,
+        })
+    }
+
+    fn reconfigure(&mut self, sample_rate: SampleRate, min_frames: u32, max_frames: u32) {
+        let active_plugins = self.graph.deactivate_all_plugins();
+
+        let (shared_schedule, _transport_handle, activation_results) = self.graph.reconfigure(
+            sample_rate,
+            min_frames,
+            max_frames,
+            self.note_buffer_size,
+            self.event_buffer_size,
+            TransportSaveState::default(),
+            None,
+            None,
+            self.plugin_watchdog,
+            None,
+            Arc::new(SharedEngineTelemetry::new(sample_rate)),
+            &active_plugins,
+        );
+
+        for (plugin_id, res) in activation_results {
+            if let Err(e) = res {
+                log::error!(
+                    "Failed to re-activate plugin {:?} in rack {:?}: {}",
+                    &plugin_id,
+                    &self.id,
+                    e
+                );
+            }
+        }
+
+        self.shared_schedule = shared_schedule;
+        self.sample_rate = sample_rate;
+        self.min_frames = min_frames;
+        self.max_frames = max_frames;
+
+        self.graph.compile();
+    }
+
+    fn collect_save_state(&mut self) -> Result<Vec<u8>, String> {
+        let graph_save_state = if let Some(save_state) = &self.pending_state {
+            save_state.clone()
+        } else {
+            DSGraphSaveState::new(
+                self.graph.collect_all_save_states(),
+                &self.graph.edges(),
+                TransportSaveState::default(),
+            )
+        };
+
+        save_state::encode(self.config, graph_save_state)
+    }
+
+    fn load_save_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let (config, graph_save_state) = save_state::decode(state)?;
+
+        if config != self.config {
+            return Err(format!(
+                "The ports of a rack cannot be changed (expected {:?}, got {:?})",
+                &self.config, &config
+            ));
+        }
+
+        // The plugins are restored the next time the rack is polled, since
+        // the plugin scanner is needed to create them.
+        self.pending_state = Some(graph_save_state);
+
+        Ok(())
+    }
+}
+
+/// The main thread part of a rack plugin.
+pub(crate) struct RackPluginMainThread {
+    rack: Rc<RefCell<RackGraph>>,
+}
+
+impl RackPluginMainThread {
+    pub fn new(rack: Rc<RefCell<RackGraph>>) -> Self {
+        Self { rack }
+    }
+}
+
+impl PluginMainThread for RackPluginMainThread {
+    fn activate(
+        &mut self,
+        sample_rate: SampleRate,
+        min_frames: u32,
+        max_frames: u32,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        self.rack.borrow_mut().activate(sample_rate, min_frames, max_frames)
+    }
+
+    fn collect_save_state(&mut self) -> Result<Option<Vec<u8>>, String> {
+        self.rack.borrow_mut().collect_save_state().map(Some)
+    }
+
+    fn load_save_state(&mut self, state: Vec<u8>) -> Result<(), String> {
+        self.rack.borrow_mut().load_save_state(&state)
+    }
+
+    fn audio_ports_ext(&mut self) -> Result<PluginAudioPortsExt, String> {
+        Ok(self.rack.borrow().config.audio_ports_ext())
+    }
+
+    fn note_ports_ext(&mut self) -> Result<PluginNotePortsExt, String> {
+        Ok(self.rack.borrow().config.note_ports_ext())
+    }
+
+    fn latency(&self) -> i64 {
+        self.rack.borrow().graph.latency()
+    }
+
+    fn update_tempo_map(&mut self, new_tempo_map: &Shared<TempoMap>) {
+        self.rack.borrow_mut().graph.update_tempo_map(Shared::clone(new_tempo_map));
+    }
+}
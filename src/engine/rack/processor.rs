@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use dropseed_plugin_api::buffer::EventBuffer;
+use dropseed_plugin_api::{PluginProcessor, ProcBuffers, ProcInfo, ProcessStatus};
+
+use crate::graph::shared_pools::SharedProcessorSchedule;
+use crate::plugin_host::event_io_buffers::PluginIoEvent;
+use crate::processor_schedule::GraphNoteEvent;
+
+pub(super) struct RackPluginProcessor {
+    schedule: SharedProcessorSchedule,
+
+    /// Set to `false` once this processor is dropped, so that the main
+    /// thread knows that the schedule of the rack is no longer being
+    /// processed.
+    alive: Arc<AtomicBool>,
+
+    note_in: Vec<GraphNoteEvent>,
+    note_out: Vec<GraphNoteEvent>,
+}
+
+impl RackPluginProcessor {
+    pub fn new(
+        schedule: SharedProcessorSchedule,
+        alive: Arc<AtomicBool>,
+        note_buffer_size: usize,
+    ) -> Self {
+        Self {
+            schedule,
+            alive,
+            note_in: Vec::with_capacity(note_buffer_size),
+            note_out: Vec::with_capacity(note_buffer_size),
+        }
+    }
+}
+
+impl PluginProcessor for RackPluginProcessor {
+    fn process(
+        &mut self,
+        proc_info: &ProcInfo,
+        buffers: &mut ProcBuffers,
+        in_events: &EventBuffer,
+        out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        self.note_in.clear();
+        for event in in_events.iter().filter_map(PluginIoEvent::read_from_clap) {
+            if self.note_in.len() >= self.note_in.capacity() {
+                break;
+            }
+
+            // A rack only has a single note input port.
+            if let PluginIoEvent::NoteEvent { note_port_index: 0, event } = event {
+                self.note_in.push(GraphNoteEvent { port: 0, frame: event.header.time, event });
+            }
+        }
+
+        self.note_out.clear();
+
+        let ProcBuffers { audio_in, audio_out, .. } = buffers;
+
+        self.schedule.process_nested(
+            proc_info,
+            &self.note_in,
+            &mut self.note_out,
+            |channel_i, buffer| match audio_in.get(0).and_then(|port| port.channel_f32(channel_i)) {
+                Some(in_channel) => buffer.copy_from_slice(&in_channel[0..buffer.len()]),
+                None => buffer.fill(0.0),
+            },
+            |channel_i, buffer| {
+                if let Some(mut out_channel) =
+                    audio_out.get_mut(0).and_then(|port| port.channel_f32_mut(channel_i))
+                {
+                    out_channel[0..buffer.len()].copy_from_slice(buffer);
+                }
+            },
+        );
+
+        if let Some(out_port) = audio_out.get_mut(0) {
+            if let Some(out_channels) = out_port._iter_raw_f32_mut() {
+                for out_channel in out_channels {
+                    out_channel.set_constant(false);
+                }
+            }
+        }
+
+        for note_event in self.note_out.iter() {
+            let mut event = note_event.event;
+            event.header.time = note_event.frame;
+
+            PluginIoEvent::NoteEvent { note_port_index: 0, event }.write_to_clap_buffer(out_events);
+        }
+
+        ProcessStatus::Continue
+    }
+}
+
+impl Drop for RackPluginProcessor {
+    fn drop(&mut self) {
+        // Make sure that the processors of the plugins that were removed
+        // from the rack are dropped in the process thread.
+        self.schedule.deactivate();
+
+        self.alive.store(false, Ordering::SeqCst);
+    }
+}
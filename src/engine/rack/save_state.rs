@@ -0,0 +1,104 @@
+//! The raw state of a rack plugin, which holds the configuration of the
+//! rack along with the save state of the audio graph inside of it.
+//!
+//! The state is stored in a `VersionedSaveState` so that it uses the same
+//! schema version and migrations as all of the other save states.
+
+use crate::engine::graph_save_state::DSGraphSaveState;
+
+use super::RackConfig;
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RackSaveState {
+    config: RackConfig,
+    graph: DSGraphSaveState,
+}
+
+#[cfg(feature = "serde")]
+impl dropseed_plugin_api::SaveStateMigration for RackSaveState {}
+
+#[cfg(feature = "serde")]
+pub(super) fn encode(config: RackConfig, graph: DSGraphSaveState) -> Result<Vec<u8>, String> {
+    use dropseed_plugin_api::VersionedSaveState;
+
+    bincode::serialize(&VersionedSaveState::new(RackSaveState { config, graph }))
+        .map_err(|e| format!("Could not serialize rack state: {}", e))
+}
+
+#[cfg(feature = "serde")]
+pub(super) fn decode(bytes: &[u8]) -> Result<(RackConfig, DSGraphSaveState), String> {
+    use dropseed_plugin_api::VersionedSaveState;
+
+    let state: VersionedSaveState<RackSaveState> =
+        bincode::deserialize(bytes).map_err(|e| format!("Could not read rack state: {}", e))?;
+    let RackSaveState { config, graph } = state.into_inner();
+
+    config.validate()?;
+
+    Ok((config, graph))
+}
+
+#[cfg(not(feature = "serde"))]
+pub(super) fn encode(_config: RackConfig, _graph: DSGraphSaveState) -> Result<Vec<u8>, String> {
+    Err("Saving the state of a rack requires the `serde` feature".into())
+}
+
+#[cfg(not(feature = "serde"))]
+pub(super) fn decode(_bytes: &[u8]) -> Result<(RackConfig, DSGraphSaveState), String> {
+    Err("Loading the state of a rack requires the `serde` feature".into())
+}
+
+/// Read only the configuration of the rack.
+pub(super) fn decode_config(bytes: &[u8]) -> Result<RackConfig, String> {
+    decode(bytes).map(|(config, _)| config)
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    use crate::engine::graph_save_state::{DSGraphEdgeSaveState, DSGraphNodeID};
+    use crate::engine::modify_request::{EdgeReqPortID, FeedbackDelay};
+    use crate::graph::PortType;
+
+    #[test]
+    fn round_trip() {
+        let config = RackConfig {
+            num_audio_in_channels: 0,
+            num_audio_out_channels: 4,
+            note_in_port: true,
+            note_out_port: false,
+        };
+        let graph = DSGraphSaveState {
+            edges: vec![DSGraphEdgeSaveState {
+                edge_type: PortType::Audio,
+                src_node: DSGraphNodeID::GraphIn,
+                src_port_id: EdgeReqPortID::Main,
+                src_port_channel: 1,
+                dst_node: DSGraphNodeID::GraphOut,
+                dst_port_id: EdgeReqPortID::StableID(3),
+                dst_port_channel: 2,
+                feedback: Some(FeedbackDelay::Frames(1024)),
+            }],
+            ..Default::default()
+        };
+
+        let bytes = encode(config, graph.clone()).unwrap();
+        let (decoded_config, decoded_graph) = decode(&bytes).unwrap();
+
+        assert_eq!(decoded_config, config);
+        assert_eq!(decoded_graph.edges, graph.edges);
+        assert!(decoded_graph.plugins.is_empty());
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        let config = RackConfig { num_audio_out_channels: 0, ..Default::default() };
+
+        let bytes = encode(config, DSGraphSaveState::default()).unwrap();
+
+        assert!(decode(&bytes).is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
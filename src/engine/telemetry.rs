@@ -0,0 +1,213 @@
+use dropseed_plugin_api::PluginInstanceID;
+use meadowlark_core_types::time::SampleRate;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// DSP load values are stored in atomics as fixed-point numbers with this
+/// scale.
+static LOAD_SCALE: f64 = 1_000_000.0;
+
+/// Accumulates process times lock-free.
+///
+/// This is written to by the process thread (and the worker threads), and
+/// is periodically read and reset by the main thread.
+#[derive(Default)]
+pub(crate) struct ProcessTimeCounter {
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    count: AtomicU64,
+}
+
+impl ProcessTimeCounter {
+    pub fn record(&self, elapsed: Duration) {
+        let ns = elapsed.as_nanos() as u64;
+
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Return the process times since the last call and reset the counter.
+    pub fn take(&self) -> ProcessTimes {
+        ProcessTimes {
+            total: Duration::from_nanos(self.total_ns.swap(0, Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_ns.swap(0, Ordering::Relaxed)),
+            count: self.count.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// The performance counters of a running engine, shared between the process
+/// thread, the worker threads, the audio thread and the main thread.
+pub(crate) struct SharedEngineTelemetry {
+    ns_per_frame: f64,
+
+    blocks: ProcessTimeCounter,
+    frames: AtomicU64,
+    peak_load: AtomicU64,
+    num_overruns: AtomicU64,
+    num_xruns: AtomicU64,
+
+    pub plugin_tasks: ProcessTimeCounter,
+    pub sum_tasks: ProcessTimeCounter,
+    pub delay_comp_tasks: ProcessTimeCounter,
+}
+
+impl SharedEngineTelemetry {
+    pub fn new(sample_rate: SampleRate) -> Self {
+        Self {
+            ns_per_frame: 1_000_000_000.0 / sample_rate.as_f64(),
+            blocks: ProcessTimeCounter::default(),
+            frames: AtomicU64::new(0),
+            peak_load: AtomicU64::new(0),
+            num_overruns: AtomicU64::new(0),
+            num_xruns: AtomicU64::new(0),
+            plugin_tasks: ProcessTimeCounter::default(),
+            sum_tasks: ProcessTimeCounter::default(),
+            delay_comp_tasks: ProcessTimeCounter::default(),
+        }
+    }
+
+    /// Called by the process thread after it has processed a block of
+    /// `frames` frames.
+    pub fn record_block(&self, frames: usize, elapsed: Duration) {
+        self.blocks.record(elapsed);
+        self.frames.fetch_add(frames as u64, Ordering::Relaxed);
+
+        let load = elapsed.as_nanos() as f64 / (frames as f64 * self.ns_per_frame);
+
+        self.peak_load.fetch_max((load * LOAD_SCALE) as u64, Ordering::Relaxed);
+
+        if load > 1.0 {
+            self.num_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Called by the audio thread whenever it failed to get the output of
+    /// the engine in time.
+    pub fn record_xrun(&self) {
+        self.num_xruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Return the telemetry collected since the last call and reset all
+    /// counters.
+    pub fn take(
+        &self,
+        period: Duration,
+        plugins: Vec<(PluginInstanceID, ProcessTimes)>,
+    ) -> EngineTelemetry {
+        let blocks = self.blocks.take();
+        let frames = self.frames.swap(0, Ordering::Relaxed);
+        let audio_ns = frames as f64 * self.ns_per_frame;
+
+        let load = |times: &ProcessTimes| -> f64 {
+            if audio_ns > 0.0 {
+                times.total.as_nanos() as f64 / audio_ns
+            } else {
+                0.0
+            }
+        };
+
+        EngineTelemetry {
+            period,
+            dsp_load: load(&blocks),
+            peak_dsp_load: self.peak_load.swap(0, Ordering::Relaxed) as f64 / LOAD_SCALE,
+            blocks,
+            frames,
+            num_overruns: self.num_overruns.swap(0, Ordering::Relaxed),
+            num_xruns: self.num_xruns.swap(0, Ordering::Relaxed),
+            plugin_tasks: self.plugin_tasks.take(),
+            sum_tasks: self.sum_tasks.take(),
+            delay_comp_tasks: self.delay_comp_tasks.take(),
+            plugins: plugins
+                .into_iter()
+                .map(|(plugin_id, times)| PluginTelemetry {
+                    plugin_id,
+                    dsp_load: load(&times),
+                    times,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessTimes {
+    /// The total amount of time spent processing.
+    pub total: Duration,
+
+    /// The longest amount of time spent in a single call.
+    pub max: Duration,
+
+    /// The number of calls.
+    pub count: u64,
+}
+
+impl ProcessTimes {
+    /// The average amount of time spent in a single call.
+    pub fn average(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos((self.total.as_nanos() / u128::from(self.count)) as u64)
+        }
+    }
+}
+
+/// The performance of the engine over a period of time.
+///
+/// A DSP load of `1.0` means that processing took exactly as long as the
+/// duration of the audio that was processed.
+#[derive(Debug, Clone)]
+pub struct EngineTelemetry {
+    /// The amount of time these numbers were collected over.
+    pub period: Duration,
+
+    /// The time spent processing each block in the process thread.
+    ///
+    /// Note that a single process cycle may be split into multiple blocks
+    /// of at most `max_frames` frames.
+    pub blocks: ProcessTimes,
+
+    /// The total number of frames that were processed.
+    pub frames: u64,
+
+    /// The average DSP load of the process thread.
+    pub dsp_load: f64,
+
+    /// The highest DSP load of a single block.
+    pub peak_dsp_load: f64,
+
+    /// The number of blocks that took longer to process than the duration
+    /// of the audio in that block.
+    pub num_overruns: u64,
+
+    /// The number of times the audio thread did not receive the output of
+    /// the engine in time and had to output silence instead.
+    pub num_xruns: u64,
+
+    /// The time spent in plugin tasks (including unloaded plugins).
+    pub plugin_tasks: ProcessTimes,
+
+    /// The time spent in tasks that sum the outputs of multiple ports.
+    pub sum_tasks: ProcessTimes,
+
+    /// The time spent in delay compensation tasks (including the delay
+    /// lines of feedback edges).
+    pub delay_comp_tasks: ProcessTimes,
+
+    /// The performance of each plugin in the audio graph.
+    pub plugins: Vec<PluginTelemetry>,
+}
+
+/// The performance of a single plugin over a period of time.
+#[derive(Debug, Clone)]
+pub struct PluginTelemetry {
+    pub plugin_id: PluginInstanceID,
+
+    /// The time spent in the plugin's process method.
+    pub times: ProcessTimes,
+
+    /// The DSP load of this plugin.
+    pub dsp_load: f64,
+}
@@ -0,0 +1,170 @@
+use dropseed_plugin_api::{DSPluginSaveState, PluginInstanceID};
+use std::collections::VecDeque;
+
+use crate::graph::{DSEdgeID, Edge};
+
+use super::graph_recovery::edge_req_port_id;
+use super::modify_request::{ConnectEdgeReq, ModifyGraphRequest, PluginIDReq};
+
+/// A modification to the audio graph that can be replayed at a later time.
+///
+/// Edges are stored by their endpoints instead of their IDs, since
+/// re-connecting an edge gives it a new ID.
+pub(crate) struct GraphChange {
+    /// The plugins to re-create, along with the ID they had when they were
+    /// removed.
+    add_plugins: Vec<(PluginInstanceID, DSPluginSaveState)>,
+    remove_plugins: Vec<PluginInstanceID>,
+    connect_edges: Vec<Edge>,
+    disconnect_edges: Vec<Edge>,
+}
+
+impl GraphChange {
+    /// Construct the change that undoes a modification of the audio graph.
+    pub fn inverse_of(
+        removed_plugins: Vec<(PluginInstanceID, DSPluginSaveState)>,
+        removed_edges: Vec<Edge>,
+        added_plugins: Vec<PluginInstanceID>,
+        added_edges: Vec<Edge>,
+    ) -> Self {
+        Self {
+            add_plugins: removed_plugins,
+            remove_plugins: added_plugins,
+            connect_edges: removed_edges,
+            disconnect_edges: added_edges,
+        }
+    }
+
+    /// The IDs the re-created plugins had when they were removed, in the
+    /// same order as `ModifyGraphRequest::add_plugin_instances`. The
+    /// re-created plugins reuse these IDs.
+    pub fn recreated_plugins(&self) -> Vec<PluginInstanceID> {
+        self.add_plugins.iter().map(|(plugin_id, _)| plugin_id.clone()).collect()
+    }
+
+    /// Construct the request that applies this change to the audio graph,
+    /// given the edges that are currently in the audio graph.
+    pub fn to_request(&self, current_edges: &[Edge]) -> ModifyGraphRequest {
+        let plugin_id_req = |plugin_id: &PluginInstanceID| -> PluginIDReq {
+            if let Some(i) = self.add_plugins.iter().position(|(id, _)| id == plugin_id) {
+                PluginIDReq::Added(i)
+            } else {
+                PluginIDReq::Existing(plugin_id.clone())
+            }
+        };
+
+        let connect_new_edges = self
+            .connect_edges
+            .iter()
+            .map(|edge| ConnectEdgeReq {
+                edge_type: edge.edge_type,
+                src_plugin_id: plugin_id_req(&edge.src_plugin_id),
+                dst_plugin_id: plugin_id_req(&edge.dst_plugin_id),
+                src_port_id: edge_req_port_id(edge, &edge.src_plugin_id, edge.src_port_id),
+                src_port_channel: edge.src_port_channel,
+                dst_port_id: edge_req_port_id(edge, &edge.dst_plugin_id, edge.dst_port_id),
+                dst_port_channel: edge.dst_port_channel,
+                feedback: edge.feedback,
+                // This edge was part of a valid graph before.
+                check_for_cycles: false,
+                log_error_on_fail: true,
+            })
+            .collect();
+
+        let disconnect_edges: Vec<DSEdgeID> = self
+            .disconnect_edges
+            .iter()
+            .filter_map(|edge| {
+                current_edges.iter().find(|e| is_same_connection(e, edge)).map(|e| e.id)
+            })
+            .collect();
+
+        ModifyGraphRequest {
+            add_plugin_instances: self
+                .add_plugins
+                .iter()
+                .map(|(_, save_state)| save_state.clone())
+                .collect(),
+            remove_plugin_instances: self.remove_plugins.clone(),
+            connect_new_edges,
+            disconnect_edges,
+        }
+    }
+}
+
+fn is_same_connection(a: &Edge, b: &Edge) -> bool {
+    a.edge_type == b.edge_type
+        && a.src_plugin_id == b.src_plugin_id
+        && a.dst_plugin_id == b.dst_plugin_id
+        && a.src_port_id == b.src_port_id
+        && a.dst_port_id == b.dst_port_id
+        && a.src_port_channel == b.src_port_channel
+        && a.dst_port_channel == b.dst_port_channel
+        && a.feedback == b.feedback
+}
+
+/// The history of modifications made to the audio graph.
+pub(crate) struct UndoStack {
+    undo: VecDeque<GraphChange>,
+    redo: Vec<GraphChange>,
+    max_len: usize,
+}
+
+impl UndoStack {
+    pub fn new(max_len: usize) -> Self {
+        Self { undo: VecDeque::new(), redo: Vec::new(), max_len }
+    }
+
+    /// Change the maximum number of modifications that can be undone,
+    /// forgetting the oldest ones if there are now too many.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+
+        while self.undo.len() > max_len {
+            self.undo.pop_front();
+        }
+        if self.redo.len() > max_len {
+            // The redo stack is popped from the back, so the modifications
+            // furthest away from the current state are at the front.
+            self.redo.drain(0..self.redo.len() - max_len);
+        }
+    }
+
+    /// Called after a new modification has been made to the audio graph.
+    pub fn push(&mut self, inverse: GraphChange) {
+        self.redo.clear();
+        self.push_undo(inverse);
+    }
+
+    pub fn push_undo(&mut self, inverse: GraphChange) {
+        if self.undo.len() >= self.max_len {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(inverse);
+    }
+
+    pub fn push_redo(&mut self, inverse: GraphChange) {
+        self.redo.push(inverse);
+    }
+
+    pub fn pop_undo(&mut self) -> Option<GraphChange> {
+        self.undo.pop_back()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<GraphChange> {
+        self.redo.pop()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
@@ -1,6 +1,12 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::hash::Hash;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use audio_graph::{error::AddEdgeError, AudioGraphHelper, EdgeID, PortID, TypeIdx};
+use audio_graph::{error::AddEdgeError, AudioGraphHelper, EdgeID, NodeID, PortID, TypeIdx};
 use basedrop::Shared;
 use fnv::{FnvHashMap, FnvHashSet};
 use meadowlark_core_types::time::SampleRate;
@@ -10,30 +16,51 @@ use smallvec::SmallVec;
 mod compiler;
 
 pub mod error;
+pub mod export;
 
 pub(crate) mod shared_pools;
 
+use dropseed_plugin_api::ext::timer::TimerID;
 use dropseed_plugin_api::transport::TempoMap;
 use dropseed_plugin_api::{DSPluginSaveState, PluginInstanceID, PluginInstanceType};
 
-use crate::engine::modify_request::{ConnectEdgeReq, EdgeReqPortID};
+use crate::engine::error::ModifyGraphError;
+use crate::engine::modify_request::{
+    ConnectEdgeReq, EdgeReqPortID, FeedbackDelay, ModifyGraphRequest, PluginIDReq,
+};
+use crate::engine::rack::{self, RackGraph, RackPluginMainThread};
+use crate::engine::telemetry::{ProcessTimes, SharedEngineTelemetry};
 use crate::engine::timer_wheel::EngineTimerWheel;
-use crate::engine::{NewPluginRes, OnIdleEvent, PluginStatus};
+use crate::engine::{
+    GraphPortInfo, NewPluginRes, OnIdleEvent, PluginActivatedStatus, PluginStatus,
+};
+use crate::plugin_host::error::ActivatePluginError;
 use crate::plugin_host::PluginHostProcessorWrapper;
-use crate::plugin_host::{OnIdleResult, PluginHostMainThread};
+use crate::plugin_host::{OnIdleResult, PluginHostMainThread, PluginWatchdogSettings};
 use crate::plugin_scanner::PluginScanner;
-use crate::processor_schedule::tasks::{TransportHandle, TransportTask};
+use crate::processor_schedule::parallel::WorkerPoolHandle;
+use crate::processor_schedule::tasks::{TransportHandle, TransportSaveState, TransportTask};
 use crate::processor_schedule::ProcessorSchedule;
 use crate::utils::thread_id::SharedThreadIDs;
 
-use compiler::verifier::Verifier;
-use shared_pools::{GraphSharedPools, SharedProcessorSchedule};
+use compiler::compiler_thread::GraphCompilerThread;
+use compiler::{CompileRequest, FeedbackCompileInfo, PluginCompileInfo};
+use shared_pools::{GraphSharedPools, PluginHostPool, SharedProcessorSchedule, SharedSteadyClock};
 
 use error::{ConnectEdgeError, ConnectEdgeErrorType, GraphCompilerError};
+use export::ScheduleSnapshot;
+
+/// The maximum amount of time to wait for the process thread to drop the
+/// processors of plugins that are being deactivated.
+const DROP_PROCESSORS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The port on the nodes of a feedback edge that carries the signal.
+const FEEDBACK_SIGNAL_PORT: PortID = PortID(0);
 
 /// A default port type for general purpose applications
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PortType {
     /// Audio ports
     Audio = 0,
@@ -97,8 +124,8 @@ pub struct PortChannelID {
 }
 
 pub(crate) struct AudioGraph {
-    shared_pools: GraphSharedPools,
-    verifier: Verifier,
+    plugin_hosts: PluginHostPool,
+    compiler_thread: GraphCompilerThread,
 
     graph_helper: AudioGraphHelper,
     coll_handle: basedrop::Handle,
@@ -107,18 +134,45 @@ pub(crate) struct AudioGraph {
     graph_out_id: PluginInstanceID,
     graph_in_num_audio_channels: usize,
     graph_out_num_audio_channels: usize,
+    graph_in_ports: Vec<GraphPortInfo>,
+    graph_out_ports: Vec<GraphPortInfo>,
+    graph_in_num_note_ports: usize,
+    graph_out_num_note_ports: usize,
+    /// Whether the graph input node has an automation output port.
+    graph_in_has_automation_port: bool,
 
     edge_id_to_ds_edge_id: FnvHashMap<EdgeID, DSEdgeID>,
+    feedback_edges: FnvHashMap<DSEdgeID, FeedbackEdgeNodes>,
     next_ds_edge_id: u64,
+    /// The edges that have been connected in the graph.
+    ///
+    /// This may still contain edges that were removed when a plugin
+    /// changed its ports. Use `AudioGraph::edges()` to get the edges that
+    /// are actually in the graph.
+    edges: FnvHashMap<DSEdgeID, Edge>,
 
     sample_rate: SampleRate,
     min_frames: u32,
     max_frames: u32,
+    note_buffer_size: usize,
+    event_buffer_size: usize,
+
+    plugin_watchdog: Option<PluginWatchdogSettings>,
 
     /// For the plugins that are queued to be removed, make sure that
     /// the plugin's processor part is dropped in the process thread.
     plugin_processors_to_drop: Vec<Shared<PluginHostProcessorWrapper>>,
 
+    /// The graphs inside of the racks in this graph.
+    racks: FnvHashMap<PluginInstanceID, Rc<RefCell<RackGraph>>>,
+    /// The racks that are still being removed, but whose ID has been reused
+    /// by a new rack, from oldest to newest.
+    replaced_racks: Vec<(PluginInstanceID, Rc<RefCell<RackGraph>>)>,
+
+    /// If this is the graph inside of a rack, then this is whether the
+    /// processor of that rack currently exists.
+    parent_processor: Option<Arc<AtomicBool>>,
+
     thread_ids: SharedThreadIDs,
 
     schedule_version: u64,
@@ -129,6 +183,11 @@ impl AudioGraph {
         coll_handle: basedrop::Handle,
         graph_in_channels: usize,
         graph_out_channels: usize,
+        graph_in_ports: Vec<GraphPortInfo>,
+        graph_out_ports: Vec<GraphPortInfo>,
+        graph_in_note_ports: usize,
+        graph_out_note_ports: usize,
+        graph_in_automation_port: bool,
         sample_rate: SampleRate,
         min_frames: u32,
         max_frames: u32,
@@ -136,6 +195,9 @@ impl AudioGraph {
         event_buffer_size: usize,
         thread_ids: SharedThreadIDs,
         transport_declick_time: Option<Seconds>,
+        plugin_watchdog: Option<PluginWatchdogSettings>,
+        worker_pool: Option<WorkerPoolHandle>,
+        telemetry: Arc<SharedEngineTelemetry>,
         engine_timer: &mut EngineTimerWheel,
     ) -> (Self, SharedProcessorSchedule, TransportHandle) {
         //assert!(graph_in_channels > 0);
@@ -144,6 +206,7 @@ impl AudioGraph {
         let graph_helper = AudioGraphHelper::new(PortType::NUM_TYPES);
 
         let (transport_task, transport_handle) = TransportTask::new(
+            None,
             None,
             sample_rate,
             max_frames as usize,
@@ -157,7 +220,10 @@ impl AudioGraph {
             note_buffer_size,
             event_buffer_size,
             transport_task,
+            SharedSteadyClock::new(&coll_handle),
             0,
+            worker_pool,
+            telemetry,
             coll_handle.clone(),
         );
 
@@ -171,20 +237,33 @@ impl AudioGraph {
             PluginInstanceID::_new(1, 1, PluginInstanceType::GraphOutput, graph_out_rdn);
 
         let mut new_self = Self {
-            shared_pools,
-            verifier: Verifier::new(),
+            plugin_hosts: PluginHostPool::new(),
+            compiler_thread: GraphCompilerThread::new(shared_pools, coll_handle.clone()),
             graph_helper,
             coll_handle,
             graph_in_num_audio_channels: graph_in_channels,
             graph_out_num_audio_channels: graph_out_channels,
+            graph_in_ports,
+            graph_out_ports,
+            graph_in_num_note_ports: graph_in_note_ports,
+            graph_out_num_note_ports: graph_out_note_ports,
+            graph_in_has_automation_port: graph_in_automation_port,
             graph_in_id,
             graph_out_id,
             edge_id_to_ds_edge_id: FnvHashMap::default(),
+            feedback_edges: FnvHashMap::default(),
             next_ds_edge_id: 0,
+            edges: FnvHashMap::default(),
             sample_rate,
             min_frames,
             max_frames,
+            note_buffer_size,
+            event_buffer_size,
+            plugin_watchdog,
             plugin_processors_to_drop: Vec::new(),
+            racks: FnvHashMap::default(),
+            replaced_racks: Vec::new(),
+            parent_processor: None,
             thread_ids,
             schedule_version: 0,
         };
@@ -194,21 +273,73 @@ impl AudioGraph {
         (new_self, shared_schedule, transport_handle)
     }
 
+    /// Add a new plugin instance to the graph.
+    ///
+    /// If `reuse_id` is `Some`, then the new plugin gets that ID instead of
+    /// a new one. This is only valid for the ID of a plugin that has been
+    /// removed from this graph (i.e. when undoing its removal).
     pub fn add_new_plugin_instance(
         &mut self,
         save_state: DSPluginSaveState,
+        reuse_id: Option<&PluginInstanceID>,
         plugin_scanner: &mut PluginScanner,
         fallback_to_other_formats: bool,
+        engine_timer: &mut EngineTimerWheel,
     ) -> NewPluginRes {
         let do_activate_plugin = save_state.active;
 
+        debug_assert!(reuse_id.map(|id| !self.plugin_exists(id)).unwrap_or(true));
+        let unique_id = reuse_id.map(|id| id.unique_id());
+
         let node_id = self.graph_helper.add_node(0.0);
-        let res = plugin_scanner.create_plugin(save_state, node_id, fallback_to_other_formats);
+
+        let mut new_rack: Option<Rc<RefCell<RackGraph>>> = None;
+        let res = if rack::is_rack_key(&save_state.key) {
+            let config = rack::decode_rack_config(save_state.raw_state.as_deref());
+
+            plugin_scanner.create_engine_plugin(
+                save_state,
+                node_id,
+                unique_id,
+                |id, host_request| {
+                    let rack = Rc::new(RefCell::new(RackGraph::new(
+                        id.clone(),
+                        config?,
+                        host_request,
+                        self.coll_handle.clone(),
+                        self.sample_rate,
+                        self.min_frames,
+                        self.max_frames,
+                        self.note_buffer_size,
+                        self.event_buffer_size,
+                        self.thread_ids.clone(),
+                        self.plugin_watchdog,
+                        engine_timer,
+                    )));
+                    new_rack = Some(Rc::clone(&rack));
+
+                    Ok(Box::new(RackPluginMainThread::new(rack)))
+                },
+            )
+        } else {
+            plugin_scanner.create_plugin(save_state, node_id, unique_id, fallback_to_other_formats)
+        };
         let plugin_id = res.plugin_host.id().clone();
 
-        match res.status {
+        if let Some(rack) = new_rack {
+            // Add the plugins from the save state of the rack.
+            rack.borrow_mut().restore_pending_state(plugin_scanner, engine_timer);
+
+            if let Some(old_rack) = self.racks.insert(plugin_id.clone(), rack) {
+                // The old rack is still being removed.
+                self.replaced_racks.push((plugin_id.clone(), old_rack));
+            }
+        }
+
+        let load_error = match res.status {
             Ok(()) => {
                 log::debug!("Loaded plugin {:?} successfully", &res.plugin_host.id());
+                None
             }
             Err(e) => {
                 log::error!(
@@ -216,17 +347,20 @@ impl AudioGraph {
                     &res.plugin_host.id(),
                     e
                 );
+                Some(e)
             }
-        }
+        };
 
         let supports_floating_gui = res.plugin_host.supports_floating_gui();
         let supports_embedded_gui = res.plugin_host.supports_embedded_gui();
 
-        if self.shared_pools.plugin_hosts.insert(plugin_id.clone(), res.plugin_host).is_some() {
+        if self.plugin_hosts.insert(plugin_id.clone(), res.plugin_host).is_some() {
             panic!("Something went wrong when allocating a new slot for a plugin");
         }
 
-        let activation_status = if do_activate_plugin {
+        let activation_status = if let Some(e) = load_error {
+            PluginStatus::LoadError(e)
+        } else if do_activate_plugin {
             self.activate_plugin_instance(&plugin_id).unwrap()
         } else {
             PluginStatus::Inactive
@@ -241,7 +375,7 @@ impl AudioGraph {
     }
 
     pub fn activate_plugin_instance(&mut self, id: &PluginInstanceID) -> Result<PluginStatus, ()> {
-        let plugin_host = self.shared_pools.plugin_hosts.get_mut(id).ok_or(())?;
+        let plugin_host = self.plugin_hosts.get_mut(id).ok_or(())?;
 
         if let Err(e) = plugin_host.can_activate() {
             return Ok(PluginStatus::ActivationError(e));
@@ -255,6 +389,7 @@ impl AudioGraph {
             &mut self.edge_id_to_ds_edge_id,
             self.thread_ids.clone(),
             self.schedule_version,
+            self.plugin_watchdog,
             &self.coll_handle,
         ) {
             Ok(res) => PluginStatus::Activated(res),
@@ -290,18 +425,21 @@ impl AudioGraph {
             }
 
             if removed_plugins.insert(id.clone()) {
-                if let Some(plugin_host) = self.shared_pools.plugin_hosts.get_mut(id) {
+                if let Some(plugin_host) = self.plugin_hosts.get_mut(id) {
                     if let Some(plugin_proc_to_drop) =
                         plugin_host.schedule_remove(&self.coll_handle, engine_timer)
                     {
                         self.plugin_processors_to_drop.push(plugin_proc_to_drop);
                     }
 
-                    let removed_edges_res =
-                        self.graph_helper.remove_node(id._node_id().into()).unwrap();
+                    let node_id = plugin_host.id()._node_id();
+                    let removed_edges_res = self.graph_helper.remove_node(node_id.into()).unwrap();
                     for edge_id in removed_edges_res.iter() {
                         if let Some(ds_edge_id) = self.edge_id_to_ds_edge_id.remove(edge_id) {
-                            removed_edges.push(ds_edge_id);
+                            // Both ends of a feedback edge may be removed.
+                            if self.edges.remove(&ds_edge_id).is_some() {
+                                removed_edges.push(ds_edge_id);
+                            }
                         } else {
                             panic!(
                                 "Helper disconnected an edge that doesn't exist in graph: {:?}",
@@ -321,6 +459,8 @@ impl AudioGraph {
             }
         }
 
+        self.remove_broken_feedback_edges();
+
         (removed_plugins, removed_edges)
     }
 
@@ -330,12 +470,284 @@ impl AudioGraph {
         src_plugin_id: &PluginInstanceID,
         dst_plugin_id: &PluginInstanceID,
     ) -> Result<Edge, ConnectEdgeError> {
-        let (src_port_id, src_port_stable_id) = if src_plugin_id == &self.graph_in_id {
+        let src_plugin_id = &self.stored_plugin_id(src_plugin_id).clone();
+        let dst_plugin_id = &self.stored_plugin_id(dst_plugin_id).clone();
+
+        let (src_port_id, src_port_stable_id) = self.edge_src_port(edge, src_plugin_id)?;
+        let (dst_port_id, dst_port_stable_id) = self.edge_dst_port(edge, dst_plugin_id)?;
+
+        let src_port_channel = self.port_channel(src_plugin_id, src_port_id, edge.src_port_channel);
+        let dst_port_channel = self.port_channel(dst_plugin_id, dst_port_id, edge.dst_port_channel);
+
+        let (edge_id, feedback_nodes) = if let Some(feedback) = edge.feedback {
+            let (edge_id, feedback_nodes) = self.add_feedback_nodes(
+                edge,
+                feedback,
+                src_plugin_id,
+                src_port_id,
+                dst_plugin_id,
+                dst_port_id,
+            )?;
+
+            (edge_id, Some(feedback_nodes))
+        } else {
+            let edge_id = self
+                .graph_helper
+                .add_edge(
+                    src_plugin_id._node_id().into(),
+                    src_port_id,
+                    dst_plugin_id._node_id().into(),
+                    dst_port_id,
+                    edge.check_for_cycles,
+                )
+                .map_err(|e| add_edge_error(e, edge))?;
+
+            (edge_id, None)
+        };
+
+        let ds_edge_id = DSEdgeID { unique_id: self.next_ds_edge_id, edge_id };
+        self.next_ds_edge_id += 1;
+
+        if self.edge_id_to_ds_edge_id.insert(edge_id, ds_edge_id).is_some() {
+            panic!("Something went wrong while connecting edge {:?}", edge_id);
+        }
+
+        if let Some(feedback_nodes) = feedback_nodes {
+            if self
+                .edge_id_to_ds_edge_id
+                .insert(feedback_nodes.return_edge_id, ds_edge_id)
+                .is_some()
+            {
+                panic!(
+                    "Something went wrong while connecting edge {:?}",
+                    feedback_nodes.return_edge_id
+                );
+            }
+
+            self.feedback_edges.insert(ds_edge_id, feedback_nodes);
+        }
+
+        let new_edge = Edge {
+            id: ds_edge_id,
+
+            edge_type: edge.edge_type,
+
+            src_plugin_id: src_plugin_id.clone(),
+            dst_plugin_id: dst_plugin_id.clone(),
+
+            src_port_id: src_port_stable_id,
+            dst_port_id: dst_port_stable_id,
+
+            src_port_channel,
+            dst_port_channel,
+
+            feedback: edge.feedback,
+        };
+
+        self.edges.insert(ds_edge_id, new_edge.clone());
+
+        Ok(new_edge)
+    }
+
+    /// A feedback edge is not added to the abstract graph directly, since
+    /// that could create a cycle. Instead the source port is connected to
+    /// a new "send" node which writes the signal into a delay line, and a
+    /// new "return" node which reads the delayed signal from the delay line
+    /// is connected to the destination port.
+    ///
+    /// The two nodes are not connected to each other in the abstract graph.
+    /// Instead the tasks of the two nodes depend on each other (see
+    /// `compute_task_dependencies()`), since they share the same delay line.
+    ///
+    /// This returns the ID of the edge to the send node.
+    fn add_feedback_nodes(
+        &mut self,
+        edge: &ConnectEdgeReq,
+        delay: FeedbackDelay,
+        src_plugin_id: &PluginInstanceID,
+        src_port_id: PortID,
+        dst_plugin_id: &PluginInstanceID,
+        dst_port_id: PortID,
+    ) -> Result<(EdgeID, FeedbackEdgeNodes), ConnectEdgeError> {
+        if edge.edge_type != PortType::Audio {
+            return Err(ConnectEdgeError {
+                error_type: ConnectEdgeErrorType::UnsupportedFeedbackEdge,
+                edge: edge.clone(),
+            });
+        }
+        if !delay.is_valid(self.max_frames) {
+            return Err(ConnectEdgeError {
+                error_type: ConnectEdgeErrorType::FeedbackDelayTooShort,
+                edge: edge.clone(),
+            });
+        }
+
+        let send_node = self.graph_helper.add_node(0.0);
+        let return_node = self.graph_helper.add_node(0.0);
+
+        self.graph_helper
+            .add_port(send_node, FEEDBACK_SIGNAL_PORT, PortType::Audio.as_type_idx(), true)
+            .unwrap();
+        self.graph_helper
+            .add_port(return_node, FEEDBACK_SIGNAL_PORT, PortType::Audio.as_type_idx(), false)
+            .unwrap();
+
+        // The send node has no outputs and the return node has no inputs, so
+        // none of these edges can create a cycle.
+        let res = self
+            .graph_helper
+            .add_edge(
+                src_plugin_id._node_id().into(),
+                src_port_id,
+                send_node,
+                FEEDBACK_SIGNAL_PORT,
+                false,
+            )
+            .and_then(|send_edge_id| {
+                self.graph_helper
+                    .add_edge(
+                        return_node,
+                        FEEDBACK_SIGNAL_PORT,
+                        dst_plugin_id._node_id().into(),
+                        dst_port_id,
+                        false,
+                    )
+                    .map(|return_edge_id| (send_edge_id, return_edge_id))
+            });
+
+        match res {
+            Ok((send_edge_id, return_edge_id)) => Ok((
+                send_edge_id,
+                FeedbackEdgeNodes { send_node, return_node, return_edge_id, delay },
+            )),
+            Err(e) => {
+                let _ = self.graph_helper.remove_node(send_node);
+                let _ = self.graph_helper.remove_node(return_node);
+
+                Err(add_edge_error(e, edge))
+            }
+        }
+    }
+
+    /// Remove the nodes of the given feedback edge from the abstract graph.
+    fn remove_feedback_nodes(&mut self, ds_edge_id: &DSEdgeID) {
+        if let Some(feedback_nodes) = self.feedback_edges.remove(ds_edge_id) {
+            for edge_id in [ds_edge_id.edge_id, feedback_nodes.return_edge_id] {
+                if self.edge_id_to_ds_edge_id.get(&edge_id) == Some(ds_edge_id) {
+                    self.edge_id_to_ds_edge_id.remove(&edge_id);
+                }
+            }
+
+            let _ = self.graph_helper.remove_node(feedback_nodes.send_node);
+            let _ = self.graph_helper.remove_node(feedback_nodes.return_node);
+        }
+    }
+
+    /// Remove the nodes of the feedback edges where one end of the edge was
+    /// disconnected, such as when the plugin on that end was removed.
+    fn remove_broken_feedback_edges(&mut self) {
+        let broken_edges: Vec<DSEdgeID> = self
+            .feedback_edges
+            .keys()
+            .filter(|ds_edge_id| !self.is_edge_connected(ds_edge_id))
+            .copied()
+            .collect();
+
+        for ds_edge_id in broken_edges.iter() {
+            self.remove_feedback_nodes(ds_edge_id);
+        }
+    }
+
+    /// Returns `true` if the edge with the given ID is still connected.
+    fn is_edge_connected(&self, ds_edge_id: &DSEdgeID) -> bool {
+        if self.edge_id_to_ds_edge_id.get(&ds_edge_id.edge_id) != Some(ds_edge_id) {
+            return false;
+        }
+
+        // Both ends of a feedback edge must be connected.
+        if let Some(feedback_nodes) = self.feedback_edges.get(ds_edge_id) {
+            self.edge_id_to_ds_edge_id.get(&feedback_nodes.return_edge_id) == Some(ds_edge_id)
+        } else {
+            true
+        }
+    }
+
+    /// Connect the given new edges, where `PluginIDReq::Added` refers to
+    /// the plugins in `new_plugin_ids`.
+    ///
+    /// Edges that could not be connected are logged and skipped. This
+    /// returns the edges that were connected.
+    pub fn connect_new_edges(
+        &mut self,
+        edges: &[ConnectEdgeReq],
+        new_plugin_ids: &[PluginInstanceID],
+    ) -> Vec<Edge> {
+        let mut new_edges: Vec<Edge> = Vec::new();
+
+        for edge in edges.iter() {
+            let src_plugin_id = match &edge.src_plugin_id {
+                PluginIDReq::Added(index) => {
+                    if let Some(new_plugin_id) = new_plugin_ids.get(*index) {
+                        new_plugin_id
+                    } else {
+                        log::error!(
+                            "Could not connect edge {:?}: Source plugin index out of bounds",
+                            edge
+                        );
+                        continue;
+                    }
+                }
+                PluginIDReq::Existing(id) => id,
+            };
+
+            let dst_plugin_id = match &edge.dst_plugin_id {
+                PluginIDReq::Added(index) => {
+                    if let Some(new_plugin_id) = new_plugin_ids.get(*index) {
+                        new_plugin_id
+                    } else {
+                        log::error!(
+                            "Could not connect edge {:?}: Destination plugin index out of bounds",
+                            edge
+                        );
+                        continue;
+                    }
+                }
+                PluginIDReq::Existing(id) => id,
+            };
+
+            match self.connect_edge(edge, src_plugin_id, dst_plugin_id) {
+                Ok(new_edge) => new_edges.push(new_edge),
+                Err(e) => {
+                    if edge.log_error_on_fail {
+                        log::warn!("Could not connect edge: {}", e);
+                    } else {
+                        #[cfg(debug_assertions)]
+                        log::debug!("Could not connect edge: {}", e);
+                    }
+                }
+            }
+        }
+
+        new_edges
+    }
+
+    /// Find the port in the graph that the source of the given edge refers
+    /// to.
+    ///
+    /// This returns the ID of the port along with its stable ID.
+    fn edge_src_port(
+        &self,
+        edge: &ConnectEdgeReq,
+        src_plugin_id: &PluginInstanceID,
+    ) -> Result<(PortID, u32), ConnectEdgeError> {
+        let (port_id, stable_id) = if src_plugin_id == &self.graph_in_id {
             match &edge.src_port_id {
                 EdgeReqPortID::Main => match edge.edge_type {
                     PortType::Audio => {
-                        if usize::from(edge.src_port_channel) < self.graph_in_num_audio_channels {
-                            (PortID(edge.src_port_channel as u32), edge.src_port_channel as u32)
+                        if let Some((stable_id, _)) =
+                            graph_io_port(&self.graph_in_ports, usize::from(edge.src_port_channel))
+                        {
+                            (PortID(edge.src_port_channel as u32), stable_id)
                         } else {
                             return Err(ConnectEdgeError {
                                 error_type: ConnectEdgeErrorType::SrcPortDoesNotExist,
@@ -344,28 +756,54 @@ impl AudioGraph {
                         }
                     }
                     PortType::Automation => {
-                        return Err(ConnectEdgeError {
-                            error_type: ConnectEdgeErrorType::SrcPortDoesNotExist,
-                            edge: edge.clone(),
-                        });
+                        // The automation port comes after the note ports.
+                        if self.graph_in_has_automation_port {
+                            (self.graph_in_automation_port_id(), 0) // Automation ports always have a stable ID of 0
+                        } else {
+                            return Err(ConnectEdgeError {
+                                error_type: ConnectEdgeErrorType::SrcPortDoesNotExist,
+                                edge: edge.clone(),
+                            });
+                        }
                     }
                     PortType::Note => {
-                        // TODO: Note in/out ports on graph in/out nodes.
+                        // The note ports come after the audio ports, and are
+                        // addressed by their index.
+                        if usize::from(edge.src_port_channel) < self.graph_in_num_note_ports {
+                            (
+                                PortID(
+                                    (self.graph_in_num_audio_channels
+                                        + usize::from(edge.src_port_channel))
+                                        as u32,
+                                ),
+                                edge.src_port_channel as u32,
+                            )
+                        } else {
+                            return Err(ConnectEdgeError {
+                                error_type: ConnectEdgeErrorType::SrcPortDoesNotExist,
+                                edge: edge.clone(),
+                            });
+                        }
+                    }
+                },
+                EdgeReqPortID::StableID(id) => {
+                    let channel = if edge.edge_type == PortType::Audio {
+                        graph_io_channel(&self.graph_in_ports, *id, edge.src_port_channel)
+                    } else {
+                        None
+                    };
+
+                    if let Some(channel) = channel {
+                        (PortID(channel as u32), *id)
+                    } else {
                         return Err(ConnectEdgeError {
                             error_type: ConnectEdgeErrorType::SrcPortDoesNotExist,
                             edge: edge.clone(),
                         });
                     }
-                },
-                EdgeReqPortID::StableID(_id) => {
-                    // TODO: Stable IDs for ports on graph in/out nodes?
-                    return Err(ConnectEdgeError {
-                        error_type: ConnectEdgeErrorType::SrcPortDoesNotExist,
-                        edge: edge.clone(),
-                    });
                 }
             }
-        } else if let Some(plugin_host) = self.shared_pools.plugin_hosts.get(src_plugin_id) {
+        } else if let Some(plugin_host) = self.plugin_hosts.get(src_plugin_id) {
             match &edge.src_port_id {
                 EdgeReqPortID::Main => match edge.edge_type {
                     PortType::Audio => {
@@ -446,12 +884,26 @@ impl AudioGraph {
             });
         };
 
-        let (dst_port_id, dst_port_stable_id) = if dst_plugin_id == &self.graph_out_id {
+        Ok((port_id, stable_id))
+    }
+
+    /// Find the port in the graph that the destination of the given edge
+    /// refers to.
+    ///
+    /// This returns the ID of the port along with its stable ID.
+    fn edge_dst_port(
+        &self,
+        edge: &ConnectEdgeReq,
+        dst_plugin_id: &PluginInstanceID,
+    ) -> Result<(PortID, u32), ConnectEdgeError> {
+        let (port_id, stable_id) = if dst_plugin_id == &self.graph_out_id {
             match &edge.dst_port_id {
                 EdgeReqPortID::Main => match edge.edge_type {
                     PortType::Audio => {
-                        if usize::from(edge.dst_port_channel) < self.graph_out_num_audio_channels {
-                            (PortID(edge.dst_port_channel as u32), edge.dst_port_channel as u32)
+                        if let Some((stable_id, _)) =
+                            graph_io_port(&self.graph_out_ports, usize::from(edge.dst_port_channel))
+                        {
+                            (PortID(edge.dst_port_channel as u32), stable_id)
                         } else {
                             return Err(ConnectEdgeError {
                                 error_type: ConnectEdgeErrorType::DstPortDoesNotExist,
@@ -460,28 +912,49 @@ impl AudioGraph {
                         }
                     }
                     PortType::Note => {
-                        // TODO: Note in/out ports on graph in/out nodes.
+                        // The note ports come after the audio ports, and are
+                        // addressed by their index.
+                        if usize::from(edge.dst_port_channel) < self.graph_out_num_note_ports {
+                            (
+                                PortID(
+                                    (self.graph_out_num_audio_channels
+                                        + usize::from(edge.dst_port_channel))
+                                        as u32,
+                                ),
+                                edge.dst_port_channel as u32,
+                            )
+                        } else {
+                            return Err(ConnectEdgeError {
+                                error_type: ConnectEdgeErrorType::DstPortDoesNotExist,
+                                edge: edge.clone(),
+                            });
+                        }
+                    }
+                    PortType::Automation => {
                         return Err(ConnectEdgeError {
                             error_type: ConnectEdgeErrorType::DstPortDoesNotExist,
                             edge: edge.clone(),
                         });
                     }
-                    PortType::Automation => {
+                },
+                EdgeReqPortID::StableID(id) => {
+                    let channel = if edge.edge_type == PortType::Audio {
+                        graph_io_channel(&self.graph_out_ports, *id, edge.dst_port_channel)
+                    } else {
+                        None
+                    };
+
+                    if let Some(channel) = channel {
+                        (PortID(channel as u32), *id)
+                    } else {
                         return Err(ConnectEdgeError {
                             error_type: ConnectEdgeErrorType::DstPortDoesNotExist,
                             edge: edge.clone(),
                         });
                     }
-                },
-                EdgeReqPortID::StableID(_id) => {
-                    // TODO: Stable IDs for ports on graph in/out nodes?
-                    return Err(ConnectEdgeError {
-                        error_type: ConnectEdgeErrorType::DstPortDoesNotExist,
-                        edge: edge.clone(),
-                    });
                 }
             }
-        } else if let Some(plugin_host) = self.shared_pools.plugin_hosts.get(dst_plugin_id) {
+        } else if let Some(plugin_host) = self.plugin_hosts.get(dst_plugin_id) {
             match &edge.dst_port_id {
                 EdgeReqPortID::Main => match edge.edge_type {
                     PortType::Audio => {
@@ -562,54 +1035,30 @@ impl AudioGraph {
             });
         };
 
-        match self.graph_helper.add_edge(
-            src_plugin_id._node_id().into(),
-            src_port_id,
-            dst_plugin_id._node_id().into(),
-            dst_port_id,
-            edge.check_for_cycles,
-        ) {
-            Ok(edge_id) => {
-                let ds_edge_id = DSEdgeID { unique_id: self.next_ds_edge_id, edge_id };
-                self.next_ds_edge_id += 1;
-
-                if self.edge_id_to_ds_edge_id.insert(edge_id, ds_edge_id).is_some() {
-                    panic!("Something went wrong while connecting edge {:?}", edge_id);
-                }
-
-                Ok(Edge {
-                    id: ds_edge_id,
+        Ok((port_id, stable_id))
+    }
 
-                    edge_type: edge.edge_type,
+    pub fn disconnect_edge(&mut self, ds_edge_id: DSEdgeID) -> bool {
+        if self.feedback_edges.contains_key(&ds_edge_id) {
+            let is_connected = self.is_edge_connected(&ds_edge_id);
 
-                    src_plugin_id: src_plugin_id.clone(),
-                    dst_plugin_id: dst_plugin_id.clone(),
+            self.edges.remove(&ds_edge_id);
+            self.remove_feedback_nodes(&ds_edge_id);
 
-                    src_port_id: src_port_stable_id,
-                    dst_port_id: dst_port_stable_id,
-                })
+            if is_connected {
+                log::trace!("Successfully disconnected feedback edge: {:?}", ds_edge_id);
+            } else {
+                log::warn!(
+                    "Could not disconnect edge: {:?}: Edge was not found in the graph",
+                    ds_edge_id
+                );
             }
-            Err(AddEdgeError::CycleDetected) => Err(ConnectEdgeError {
-                error_type: ConnectEdgeErrorType::Cycle,
-                edge: edge.clone(),
-            }),
-            Err(AddEdgeError::EdgeAlreadyExists(_)) => Err(ConnectEdgeError {
-                error_type: ConnectEdgeErrorType::EdgeAlreadyExists,
-                edge: edge.clone(),
-            }),
-            Err(e) => {
-                log::error!("Unexpected error while connecting edge: {}", e);
 
-                Err(ConnectEdgeError {
-                    error_type: ConnectEdgeErrorType::Unkown,
-                    edge: edge.clone(),
-                })
-            }
+            return is_connected;
         }
-    }
 
-    pub fn disconnect_edge(&mut self, ds_edge_id: DSEdgeID) -> bool {
         if self.edge_id_to_ds_edge_id.remove(&ds_edge_id.edge_id).is_some() {
+            self.edges.remove(&ds_edge_id);
             if self.graph_helper.remove_edge(ds_edge_id.edge_id).is_ok() {
                 log::trace!("Successfully disconnected edge: {:?}", ds_edge_id);
                 true
@@ -625,9 +1074,157 @@ impl AudioGraph {
         }
     }
 
+    /// Check that all of the plugins and edges that the given request
+    /// refers to exist, without modifying the graph.
+    pub fn validate_modify_request(
+        &self,
+        request: &ModifyGraphRequest,
+    ) -> Result<(), ModifyGraphError> {
+        for ds_edge_id in request.disconnect_edges.iter() {
+            if !self.is_edge_connected(ds_edge_id) {
+                return Err(ModifyGraphError::EdgeDoesNotExist(*ds_edge_id));
+            }
+        }
+
+        let mut plugins_to_remove: FnvHashSet<&PluginInstanceID> = FnvHashSet::default();
+        for plugin_id in request.remove_plugin_instances.iter() {
+            if plugin_id == &self.graph_in_id || plugin_id == &self.graph_out_id {
+                return Err(ModifyGraphError::CannotRemoveGraphInOut);
+            }
+            if !self.plugin_exists(plugin_id) {
+                return Err(ModifyGraphError::PluginDoesNotExist(plugin_id.clone()));
+            }
+            if !plugins_to_remove.insert(plugin_id) {
+                return Err(ModifyGraphError::PluginRemovedTwice(plugin_id.clone()));
+            }
+        }
+
+        let plugin_id_req_exists = |plugin_id: &PluginIDReq| match plugin_id {
+            PluginIDReq::Added(index) => *index < request.add_plugin_instances.len(),
+            PluginIDReq::Existing(id) => self.plugin_exists(id) && !plugins_to_remove.contains(id),
+        };
+
+        for edge in request.connect_new_edges.iter() {
+            if !plugin_id_req_exists(&edge.src_plugin_id) {
+                return Err(ModifyGraphError::ConnectEdgeError(ConnectEdgeError {
+                    error_type: ConnectEdgeErrorType::SrcPluginDoesNotExist,
+                    edge: edge.clone(),
+                }));
+            }
+            if !plugin_id_req_exists(&edge.dst_plugin_id) {
+                return Err(ModifyGraphError::ConnectEdgeError(ConnectEdgeError {
+                    error_type: ConnectEdgeErrorType::DstPluginDoesNotExist,
+                    edge: edge.clone(),
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that all of the new edges in the given request can be
+    /// connected once the rest of the request has been applied, including
+    /// checking for cycles.
+    ///
+    /// This is done on a copy of the graph, so the graph is not modified.
+    /// The new plugins in the request must already be added to the graph,
+    /// and the request must have passed `AudioGraph::validate_modify_request()`.
+    pub fn validate_new_edges(
+        &self,
+        request: &ModifyGraphRequest,
+        new_plugin_ids: &[PluginInstanceID],
+    ) -> Result<(), ConnectEdgeError> {
+        let mut graph_helper = self.graph_helper.clone();
+
+        for ds_edge_id in request.disconnect_edges.iter() {
+            let _ = graph_helper.remove_edge(ds_edge_id.edge_id);
+        }
+        for plugin_id in request.remove_plugin_instances.iter() {
+            let _ = graph_helper.remove_node(self.stored_plugin_id(plugin_id)._node_id().into());
+        }
+
+        for edge in request.connect_new_edges.iter() {
+            let src_plugin_id = match &edge.src_plugin_id {
+                PluginIDReq::Added(index) => &new_plugin_ids[*index],
+                PluginIDReq::Existing(id) => id,
+            };
+            let dst_plugin_id = match &edge.dst_plugin_id {
+                PluginIDReq::Added(index) => &new_plugin_ids[*index],
+                PluginIDReq::Existing(id) => id,
+            };
+
+            let (src_port_id, _) = self.edge_src_port(edge, src_plugin_id)?;
+            let (dst_port_id, _) = self.edge_dst_port(edge, dst_plugin_id)?;
+
+            if let Some(delay) = edge.feedback {
+                // Feedback edges never create a cycle.
+                if edge.edge_type != PortType::Audio {
+                    return Err(ConnectEdgeError {
+                        error_type: ConnectEdgeErrorType::UnsupportedFeedbackEdge,
+                        edge: edge.clone(),
+                    });
+                }
+                if !delay.is_valid(self.max_frames) {
+                    return Err(ConnectEdgeError {
+                        error_type: ConnectEdgeErrorType::FeedbackDelayTooShort,
+                        edge: edge.clone(),
+                    });
+                }
+
+                continue;
+            }
+
+            graph_helper
+                .add_edge(
+                    self.stored_plugin_id(src_plugin_id)._node_id().into(),
+                    src_port_id,
+                    self.stored_plugin_id(dst_plugin_id)._node_id().into(),
+                    dst_port_id,
+                    true,
+                )
+                .map_err(|e| add_edge_error(e, edge))?;
+        }
+
+        Ok(())
+    }
+
+    /// The ID of the given plugin as it is stored in this graph.
+    ///
+    /// Plugin IDs are compared by their unique ID only. A plugin that
+    /// reuses the ID of a removed plugin is placed on a new node in the
+    /// abstract graph, so the node in an ID that came from outside of this
+    /// graph may be out of date.
+    fn stored_plugin_id<'a>(&'a self, plugin_id: &'a PluginInstanceID) -> &'a PluginInstanceID {
+        if plugin_id == &self.graph_in_id {
+            &self.graph_in_id
+        } else if plugin_id == &self.graph_out_id {
+            &self.graph_out_id
+        } else {
+            self.plugin_hosts
+                .get(plugin_id)
+                .map(|plugin_host| plugin_host.id())
+                .unwrap_or(plugin_id)
+        }
+    }
+
+    /// Returns `true` if the given plugin is in the graph and is not being
+    /// removed.
+    fn plugin_exists(&self, plugin_id: &PluginInstanceID) -> bool {
+        plugin_id == &self.graph_in_id
+            || plugin_id == &self.graph_out_id
+            || self
+                .plugin_hosts
+                .get(plugin_id)
+                .map(|plugin_host| !plugin_host.is_remove_requested())
+                .unwrap_or(false)
+    }
+
     pub fn reset(&mut self, engine_timer: &mut EngineTimerWheel) {
+        // Any schedules that are still being compiled are no longer relevant.
+        self.compiler_thread.wait_until_idle();
+
         // Try to gracefully remove all existing plugins.
-        for plugin_host in self.shared_pools.plugin_hosts.iter_mut() {
+        for plugin_host in self.plugin_hosts.iter_mut() {
             if let Some(processor_to_drop) =
                 plugin_host.schedule_remove(&self.coll_handle, engine_timer)
             {
@@ -635,16 +1232,7 @@ impl AudioGraph {
             }
         }
 
-        self.schedule_version += 1;
-        self.shared_pools.shared_schedule.set_new_schedule(
-            ProcessorSchedule::new_empty(
-                self.max_frames as usize,
-                self.shared_pools.transports.transport.clone(),
-                self.plugin_processors_to_drop.drain(..).collect(),
-                self.schedule_version,
-            ),
-            &self.coll_handle,
-        );
+        self.set_empty_schedule();
 
         // TODO: Check that the process thread is still alive.
         let process_thread_is_alive = true;
@@ -652,25 +1240,27 @@ impl AudioGraph {
         if process_thread_is_alive {
             let start_time = std::time::Instant::now();
 
-            const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
-
             // Wait for all plugins to be removed.
-            while !self.shared_pools.plugin_hosts.is_empty() && start_time.elapsed() < TIMEOUT {
-                std::thread::sleep(std::time::Duration::from_millis(10));
+            while !self.plugin_hosts.is_empty() && start_time.elapsed() < DROP_PROCESSORS_TIMEOUT {
+                self.wait_for_process_thread();
 
                 let mut _events_out: SmallVec<[OnIdleEvent; 32]> = SmallVec::new();
 
                 let _ = self.on_idle(&mut _events_out, engine_timer);
             }
 
-            if !self.shared_pools.plugin_hosts.is_empty() {
+            if !self.plugin_hosts.is_empty() {
                 log::error!("Timed out while removing all plugins");
             }
         }
 
-        self.shared_pools.plugin_hosts.clear();
-        self.shared_pools.buffers.set_num_buffers(0, 0, 0);
+        self.plugin_hosts.clear();
+        self.racks.clear();
+        self.replaced_racks.clear();
+        self.compiler_thread.lock().shared_pools.buffers.set_num_buffers(0, 0, 0);
         self.edge_id_to_ds_edge_id.clear();
+        self.feedback_edges.clear();
+        self.edges.clear();
 
         self.graph_helper = AudioGraphHelper::new(PortType::NUM_TYPES);
 
@@ -702,52 +1292,305 @@ impl AudioGraph {
                 .add_port(graph_out_node_id, PortID(i as u32), PortType::Audio.as_type_idx(), true)
                 .unwrap();
         }
+        for i in 0..self.graph_in_num_note_ports {
+            self.graph_helper
+                .add_port(
+                    graph_in_node_id,
+                    PortID((self.graph_in_num_audio_channels + i) as u32),
+                    PortType::Note.as_type_idx(),
+                    false,
+                )
+                .unwrap();
+        }
+        for i in 0..self.graph_out_num_note_ports {
+            self.graph_helper
+                .add_port(
+                    graph_out_node_id,
+                    PortID((self.graph_out_num_audio_channels + i) as u32),
+                    PortType::Note.as_type_idx(),
+                    true,
+                )
+                .unwrap();
+        }
+        if self.graph_in_has_automation_port {
+            self.graph_helper
+                .add_port(
+                    graph_in_node_id,
+                    self.graph_in_automation_port_id(),
+                    PortType::Automation.as_type_idx(),
+                    false,
+                )
+                .unwrap();
+        }
     }
 
-    /// Compile the audio graph into a schedule that is sent to the audio thread.
+    fn graph_in_automation_port_id(&self) -> PortID {
+        PortID((self.graph_in_num_audio_channels + self.graph_in_num_note_ports) as u32)
+    }
+
+    /// Deactivate every plugin that is currently active, and return the IDs
+    /// of those plugins.
     ///
-    /// If an error is returned then the graph **MUST** be restored with the previous
-    /// working save state.
-    pub fn compile(&mut self) -> Result<(), GraphCompilerError> {
-        self.schedule_version += 1;
+    /// Unlike a plugin that was deactivated by the user, the save states of
+    /// these plugins are still marked as active.
+    ///
+    /// This blocks until the process thread has dropped the processors of
+    /// these plugins.
+    pub fn deactivate_all_plugins(&mut self) -> Vec<PluginInstanceID> {
+        // Any schedules that are still being compiled are no longer relevant.
+        self.compiler_thread.wait_until_idle();
+
+        let mut deactivated_plugins: Vec<PluginInstanceID> = Vec::new();
+        for plugin_host in self.plugin_hosts.iter_mut() {
+            if plugin_host.is_remove_requested() {
+                continue;
+            }
 
-        match compiler::compile_graph(
-            &mut self.shared_pools,
-            &mut self.graph_helper,
-            &self.graph_in_id,
-            &self.graph_out_id,
-            self.graph_in_num_audio_channels,
-            self.graph_out_num_audio_channels,
-            self.plugin_processors_to_drop.drain(..).collect(),
-            &mut self.verifier,
-            self.schedule_version,
-            &self.coll_handle,
-        ) {
-            Ok(schedule) => {
-                log::debug!("Successfully compiled new schedule:\n{:?}", &schedule);
-                self.shared_pools.shared_schedule.set_new_schedule(schedule, &self.coll_handle);
-                Ok(())
+            if let Some(processor_to_drop) = plugin_host.schedule_deactivate(&self.coll_handle) {
+                self.plugin_processors_to_drop.push(processor_to_drop);
+                deactivated_plugins.push(plugin_host.id().clone());
             }
-            Err(e) => {
-                // Replace the current schedule with an emtpy one now that the graph
-                // is in an invalid state.
-                self.shared_pools.shared_schedule.set_new_schedule(
-                    ProcessorSchedule::new_empty(
-                        self.max_frames as usize,
-                        self.shared_pools.transports.transport.clone(),
-                        self.plugin_processors_to_drop.drain(..).collect(),
-                        self.schedule_version,
-                    ),
+        }
+
+        self.set_empty_schedule();
+
+        let start_time = std::time::Instant::now();
+        while self.plugin_hosts.iter().any(|plugin_host| plugin_host.is_waiting_to_drop())
+            && start_time.elapsed() < DROP_PROCESSORS_TIMEOUT
+        {
+            self.wait_for_process_thread();
+        }
+
+        for plugin_id in deactivated_plugins.iter() {
+            let plugin_host = self.plugin_hosts.get_mut(plugin_id).unwrap();
+
+            if !plugin_host.finish_deactivate() {
+                log::error!("Timed out while deactivating plugin {:?}", plugin_id);
+            }
+        }
+
+        deactivated_plugins
+    }
+
+    /// Replace the shared pools (and therefore the transport, the buffers,
+    /// and the delay compensation nodes) with ones that use the given
+    /// configuration, and then re-activate the given plugins.
+    ///
+    /// The new transport uses the given tempo map, or the default tempo map
+    /// if it is `None`.
+    ///
+    /// All plugins must be deactivated with `AudioGraph::deactivate_all_plugins()`
+    /// and the process thread must be stopped before calling this.
+    ///
+    /// The graph must be compiled again after calling this.
+    pub fn reconfigure(
+        &mut self,
+        sample_rate: SampleRate,
+        min_frames: u32,
+        max_frames: u32,
+        note_buffer_size: usize,
+        event_buffer_size: usize,
+        transport_save_state: TransportSaveState,
+        tempo_map: Option<TempoMap>,
+        transport_declick_time: Option<Seconds>,
+        plugin_watchdog: Option<PluginWatchdogSettings>,
+        worker_pool: Option<WorkerPoolHandle>,
+        telemetry: Arc<SharedEngineTelemetry>,
+        plugins_to_activate: &[PluginInstanceID],
+    ) -> (
+        SharedProcessorSchedule,
+        TransportHandle,
+        Vec<(PluginInstanceID, Result<PluginActivatedStatus, ActivatePluginError>)>,
+    ) {
+        self.sample_rate = sample_rate;
+        self.min_frames = min_frames;
+        self.max_frames = max_frames;
+        self.note_buffer_size = note_buffer_size;
+        self.event_buffer_size = event_buffer_size;
+        self.plugin_watchdog = plugin_watchdog;
+
+        let (transport_task, transport_handle) = TransportTask::new(
+            Some(transport_save_state),
+            tempo_map,
+            sample_rate,
+            max_frames as usize,
+            transport_declick_time,
+            self.coll_handle.clone(),
+        );
+
+        // Keep the steady clock running across the reconfiguration.
+        let steady_clock = self.compiler_thread.lock().shared_pools.transports.steady_clock.clone();
+
+        let (shared_pools, shared_schedule) = GraphSharedPools::new(
+            self.thread_ids.clone(),
+            max_frames as usize,
+            note_buffer_size,
+            event_buffer_size,
+            transport_task,
+            steady_clock,
+            self.schedule_version,
+            worker_pool,
+            telemetry,
+            self.coll_handle.clone(),
+        );
+
+        // This stops the old compiler thread.
+        self.compiler_thread = GraphCompilerThread::new(shared_pools, self.coll_handle.clone());
+
+        let activation_results = plugins_to_activate
+            .iter()
+            .filter_map(|plugin_id| {
+                let plugin_host = self.plugin_hosts.get_mut(plugin_id)?;
+
+                let res = plugin_host.activate(
+                    sample_rate,
+                    min_frames,
+                    max_frames,
+                    &mut self.graph_helper,
+                    &mut self.edge_id_to_ds_edge_id,
+                    self.thread_ids.clone(),
+                    self.schedule_version,
+                    plugin_watchdog,
                     &self.coll_handle,
                 );
-                Err(e)
-            }
+
+                Some((plugin_id.clone(), res))
+            })
+            .collect();
+
+        (shared_schedule, transport_handle, activation_results)
+    }
+
+    /// Send an empty schedule to the process thread, along with any plugin
+    /// processors that need to be dropped.
+    fn set_empty_schedule(&mut self) {
+        self.schedule_version += 1;
+
+        let mut compiler_state = self.compiler_thread.lock();
+        let transport = compiler_state.shared_pools.transports.transport.clone();
+        let steady_clock = compiler_state.shared_pools.transports.steady_clock.clone();
+        let telemetry = Arc::clone(&compiler_state.shared_pools.telemetry);
+        compiler_state.set_new_schedule(ProcessorSchedule::new_empty(
+            self.max_frames as usize,
+            transport,
+            steady_clock,
+            self.plugin_processors_to_drop.drain(..).collect(),
+            telemetry,
+            self.schedule_version,
+        ));
+    }
+
+    /// Give the process thread a chance to drop the plugin processors that
+    /// were sent to it.
+    fn wait_for_process_thread(&mut self) {
+        let parent_processor_alive = self
+            .parent_processor
+            .as_ref()
+            .map(|alive| alive.load(Ordering::SeqCst))
+            .unwrap_or(true);
+
+        if self.thread_ids.is_process_thread() || !parent_processor_alive {
+            // The schedule is being driven from this thread (offline mode),
+            // or this is the graph of a rack that is no longer processed, so
+            // there is no separate process thread to drop the processors for
+            // us.
+            self.compiler_thread.lock().shared_pools.shared_schedule.deactivate();
+        } else {
+            std::thread::sleep(std::time::Duration::from_millis(10));
         }
     }
 
+    /// Queue the audio graph to be compiled into a schedule in the compiler
+    /// thread. Once compiled, the schedule is sent to the audio thread.
+    ///
+    /// The result is retrieved with `AudioGraph::poll_compiler()` or
+    /// `AudioGraph::wait_for_compiler()`.
+    ///
+    /// This returns the version of the schedule that will be compiled.
+    pub fn compile(&mut self) -> u64 {
+        self.schedule_version += 1;
+
+        let mut plugins: FnvHashMap<NodeID, PluginCompileInfo> =
+            FnvHashMap::with_capacity_and_hasher(
+                self.plugin_hosts.num_plugins(),
+                Default::default(),
+            );
+        for plugin_host in self.plugin_hosts.iter() {
+            plugins.insert(plugin_host.id()._node_id().into(), PluginCompileInfo::new(plugin_host));
+        }
+
+        // Plugins may have removed edges while syncing their ports.
+        self.remove_broken_feedback_edges();
+
+        let mut feedback_nodes: FnvHashMap<NodeID, FeedbackCompileInfo> = FnvHashMap::default();
+        for (ds_edge_id, nodes) in self.feedback_edges.iter() {
+            let delay = nodes.delay.frames(self.max_frames);
+
+            feedback_nodes.insert(
+                nodes.send_node,
+                FeedbackCompileInfo {
+                    edge_id: *ds_edge_id,
+                    delay,
+                    max_frames: self.max_frames,
+                    is_send: true,
+                },
+            );
+            feedback_nodes.insert(
+                nodes.return_node,
+                FeedbackCompileInfo {
+                    edge_id: *ds_edge_id,
+                    delay,
+                    max_frames: self.max_frames,
+                    is_send: false,
+                },
+            );
+        }
+
+        self.compiler_thread.compile(CompileRequest {
+            graph_helper: self.graph_helper.clone(),
+            plugins,
+            feedback_nodes,
+            graph_in_id: self.graph_in_id.clone(),
+            graph_out_id: self.graph_out_id.clone(),
+            num_graph_in_audio_ports: self.graph_in_num_audio_channels,
+            num_graph_out_audio_ports: self.graph_out_num_audio_channels,
+            num_graph_in_note_ports: self.graph_in_num_note_ports,
+            num_graph_out_note_ports: self.graph_out_num_note_ports,
+            graph_in_has_automation_port: self.graph_in_has_automation_port,
+            plugins_to_drop: self.plugin_processors_to_drop.drain(..).collect(),
+            schedule_version: self.schedule_version,
+        });
+
+        self.schedule_version
+    }
+
+    /// Returns the version and the result of a schedule that has finished
+    /// compiling, if there is one.
+    ///
+    /// If an error is returned then the graph **MUST** be restored with the previous
+    /// working save state.
+    pub fn poll_compiler(&mut self) -> Option<(u64, Result<(), GraphCompilerError>)> {
+        self.compiler_thread.poll()
+    }
+
+    /// Returns the version and the result of the next schedule to finish
+    /// compiling, blocking until it is done. This returns `None` once there is nothing left to
+    /// compile.
+    ///
+    /// If an error is returned then the graph **MUST** be restored with the previous
+    /// working save state.
+    pub fn wait_for_compiler(&mut self) -> Option<(u64, Result<(), GraphCompilerError>)> {
+        self.compiler_thread.wait()
+    }
+
+    /// Block until all queued schedules have finished compiling, discarding
+    /// their results.
+    pub fn discard_pending_compiles(&mut self) {
+        self.compiler_thread.wait_until_idle();
+    }
+
     pub fn collect_save_states(&mut self) -> Vec<(PluginInstanceID, DSPluginSaveState)> {
-        self.shared_pools
-            .plugin_hosts
+        self.plugin_hosts
             .iter_mut()
             .filter_map(|plugin_host| {
                 if plugin_host.is_save_state_dirty() {
@@ -759,6 +1602,16 @@ impl AudioGraph {
             .collect()
     }
 
+    /// Collect the save states of all plugins in the graph, whether they
+    /// have changed or not.
+    pub fn collect_all_save_states(&mut self) -> Vec<(PluginInstanceID, DSPluginSaveState)> {
+        self.plugin_hosts
+            .iter_mut()
+            .filter(|plugin_host| !plugin_host.is_remove_requested())
+            .map(|plugin_host| (plugin_host.id().clone(), plugin_host.collect_save_state()))
+            .collect()
+    }
+
     pub fn on_idle(
         &mut self,
         events_out: &mut SmallVec<[OnIdleEvent; 32]>,
@@ -767,7 +1620,7 @@ impl AudioGraph {
         let mut plugins_to_remove: SmallVec<[PluginInstanceID; 4]> = SmallVec::new();
         let mut recompile_graph = false;
 
-        for plugin_host in self.shared_pools.plugin_hosts.iter_mut() {
+        for plugin_host in self.plugin_hosts.iter_mut() {
             let (res, modified_params, processor_to_drop) = plugin_host.on_idle(
                 self.sample_rate,
                 self.min_frames,
@@ -778,6 +1631,7 @@ impl AudioGraph {
                 &mut self.edge_id_to_ds_edge_id,
                 &self.thread_ids,
                 self.schedule_version,
+                self.plugin_watchdog,
                 engine_timer,
             );
 
@@ -825,34 +1679,289 @@ impl AudioGraph {
         }
 
         for plugin in plugins_to_remove.iter() {
-            self.shared_pools.plugin_hosts.remove(plugin);
+            self.plugin_hosts.remove(plugin);
+
+            // The oldest rack with this ID is removed first, just like the
+            // oldest plugin host.
+            let rack = if let Some(i) = self.replaced_racks.iter().position(|(id, _)| id == plugin)
+            {
+                Some(self.replaced_racks.remove(i).1)
+            } else {
+                self.racks.remove(plugin)
+            };
+            if let Some(rack) = rack {
+                rack.borrow_mut().remove(engine_timer);
+            }
         }
 
         recompile_graph
     }
 
+    /// Handle the requests of the plugins inside of the racks in this
+    /// graph (including nested racks).
+    ///
+    /// If `block` is `true`, then this will wait until the graphs of the
+    /// racks have finished compiling.
+    pub fn poll_racks(
+        &mut self,
+        events_out: &mut SmallVec<[OnIdleEvent; 32]>,
+        plugin_scanner: &mut PluginScanner,
+        engine_timer: &mut EngineTimerWheel,
+        block: bool,
+    ) {
+        for rack in self.racks.values() {
+            rack.borrow_mut().on_idle(events_out, plugin_scanner, engine_timer, block);
+        }
+    }
+
+    /// The graph inside of the given rack, which may be nested inside of
+    /// another rack.
+    pub fn rack(&self, id: &PluginInstanceID) -> Option<Rc<RefCell<RackGraph>>> {
+        if let Some(rack) = self.racks.get(id) {
+            return Some(Rc::clone(rack));
+        }
+
+        self.racks.values().find_map(|rack| rack.borrow().graph().rack(id))
+    }
+
+    /// Call the timer with the given ID on the plugin with the given unique
+    /// ID, which may be inside of a rack.
+    ///
+    /// This returns `false` if the plugin was not found.
+    pub fn on_plugin_timer(
+        &mut self,
+        plugin_unique_id: u64,
+        timer_id: TimerID,
+        engine_timer: &mut EngineTimerWheel,
+    ) -> bool {
+        if let Some(plugin_host) = self.plugin_hosts.get_by_unique_id_mut(plugin_unique_id) {
+            plugin_host.on_timer(timer_id, engine_timer);
+            return true;
+        }
+
+        self.racks.values().any(|rack| {
+            rack.borrow_mut().graph_mut().on_plugin_timer(plugin_unique_id, timer_id, engine_timer)
+        })
+    }
+
+    /// Set the flag that tells whether the processor of the rack containing
+    /// this graph exists.
+    ///
+    /// While the rack has no processor, the processors of the plugins in
+    /// this graph are dropped in the main thread instead.
+    pub fn set_parent_processor(&mut self, alive: Arc<AtomicBool>) {
+        self.parent_processor = Some(alive);
+    }
+
+    /// Returns `true` if the save state of any of the plugins in the graph
+    /// has changed since it was last collected.
+    pub fn is_save_state_dirty(&self) -> bool {
+        self.plugin_hosts.iter().any(|plugin_host| {
+            !plugin_host.is_remove_requested() && plugin_host.is_save_state_dirty()
+        })
+    }
+
     pub fn update_tempo_map(&mut self, new_tempo_map: Shared<TempoMap>) {
-        for plugin_host in self.shared_pools.plugin_hosts.iter_mut() {
+        for plugin_host in self.plugin_hosts.iter_mut() {
             plugin_host.update_tempo_map(&new_tempo_map);
         }
     }
 
     pub fn get_plugin_host(&self, id: &PluginInstanceID) -> Option<&PluginHostMainThread> {
-        self.shared_pools.plugin_hosts.get(id)
+        self.plugin_hosts.get(id)
     }
 
     pub fn get_plugin_host_mut(
         &mut self,
         id: &PluginInstanceID,
     ) -> Option<&mut PluginHostMainThread> {
-        self.shared_pools.plugin_hosts.get_mut(id)
+        self.plugin_hosts.get_mut(id)
     }
 
     pub fn get_plugin_host_by_unique_id_mut(
         &mut self,
         id: u64,
     ) -> Option<&mut PluginHostMainThread> {
-        self.shared_pools.plugin_hosts.get_by_unique_id_mut(id)
+        self.plugin_hosts.get_by_unique_id_mut(id)
+    }
+
+    /// The IDs of all plugins in the graph, not including the plugins that
+    /// are scheduled to be removed or the graph input/output nodes.
+    pub fn plugin_ids(&self) -> Vec<PluginInstanceID> {
+        self.plugin_hosts
+            .iter()
+            .filter(|plugin_host| !plugin_host.is_remove_requested())
+            .map(|plugin_host| plugin_host.id().clone())
+            .collect()
+    }
+
+    /// Return the time spent in each plugin's process method since the last
+    /// call, and reset the counters.
+    pub fn take_plugin_process_times(&self) -> Vec<(PluginInstanceID, ProcessTimes)> {
+        self.plugin_hosts
+            .iter()
+            .filter(|plugin_host| !plugin_host.is_remove_requested())
+            .map(|plugin_host| (plugin_host.id().clone(), plugin_host.take_process_times()))
+            .collect()
+    }
+
+    /// All of the edges that are currently connected in the graph.
+    pub fn edges(&mut self) -> Vec<Edge> {
+        // Plugins may have removed edges while syncing their ports.
+        self.remove_broken_feedback_edges();
+        let edge_id_to_ds_edge_id = &self.edge_id_to_ds_edge_id;
+        self.edges.retain(|ds_edge_id, _| {
+            edge_id_to_ds_edge_id.get(&ds_edge_id.edge_id) == Some(ds_edge_id)
+        });
+
+        self.edges.values().cloned().collect()
+    }
+
+    /// All of the edges that are currently connected in the graph.
+    ///
+    /// Unlike `AudioGraph::edges()`, this does not clean up the edges that
+    /// were removed when a plugin changed its ports.
+    pub fn connected_edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.values().filter(move |edge| self.is_edge_connected(&edge.id))
+    }
+
+    /// Returns `true` if none of the feedback edges in the graph have a
+    /// delay that is shorter than the given maximum block size.
+    pub fn feedback_delays_fit(&self, max_frames: u32) -> bool {
+        self.connected_edges()
+            .filter_map(|edge| edge.feedback)
+            .all(|delay| delay.is_valid(max_frames))
+    }
+
+    /// The plugins that are connected to the inputs of the given plugin.
+    pub fn upstream_plugins(&self, plugin_id: &PluginInstanceID) -> Vec<PluginInstanceID> {
+        let mut plugins: Vec<PluginInstanceID> = Vec::new();
+        for edge in self.connected_edges() {
+            if &edge.dst_plugin_id == plugin_id && !plugins.contains(&edge.src_plugin_id) {
+                plugins.push(edge.src_plugin_id.clone());
+            }
+        }
+        plugins
+    }
+
+    /// The plugins that are connected to the outputs of the given plugin.
+    pub fn downstream_plugins(&self, plugin_id: &PluginInstanceID) -> Vec<PluginInstanceID> {
+        let mut plugins: Vec<PluginInstanceID> = Vec::new();
+        for edge in self.connected_edges() {
+            if &edge.src_plugin_id == plugin_id && !plugins.contains(&edge.dst_plugin_id) {
+                plugins.push(edge.dst_plugin_id.clone());
+            }
+        }
+        plugins
+    }
+
+    /// All of the plugins in the graph (including the graph input/output
+    /// nodes) ordered such that every plugin comes after all of the plugins
+    /// connected to its inputs.
+    ///
+    /// Feedback edges are ignored. Plugins that are part of a cycle are left
+    /// out.
+    pub fn topological_order(&self) -> Vec<PluginInstanceID> {
+        let mut plugin_ids = vec![self.graph_in_id.clone()];
+        plugin_ids.append(&mut self.plugin_ids());
+        plugin_ids.push(self.graph_out_id.clone());
+
+        let mut num_inputs: FnvHashMap<&PluginInstanceID, usize> =
+            plugin_ids.iter().map(|plugin_id| (plugin_id, 0)).collect();
+        let mut outputs: FnvHashMap<&PluginInstanceID, Vec<&PluginInstanceID>> =
+            FnvHashMap::default();
+        for edge in self.connected_edges().filter(|edge| edge.feedback.is_none()) {
+            if !num_inputs.contains_key(&edge.src_plugin_id) {
+                continue;
+            }
+            if let Some(n) = num_inputs.get_mut(&edge.dst_plugin_id) {
+                *n += 1;
+                outputs.entry(&edge.src_plugin_id).or_default().push(&edge.dst_plugin_id);
+            }
+        }
+
+        let mut queue: VecDeque<&PluginInstanceID> =
+            plugin_ids.iter().filter(|plugin_id| num_inputs[plugin_id] == 0).collect();
+        let mut order: Vec<PluginInstanceID> = Vec::with_capacity(plugin_ids.len());
+        while let Some(plugin_id) = queue.pop_front() {
+            order.push(plugin_id.clone());
+
+            for dst_plugin_id in outputs.get(plugin_id).into_iter().flatten() {
+                let n = num_inputs.get_mut(dst_plugin_id).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    queue.push_back(*dst_plugin_id);
+                }
+            }
+        }
+
+        order
+    }
+
+    /// The latency of the longest path from the graph input node to the
+    /// graph output node in frames.
+    ///
+    /// Paths through feedback edges are not included.
+    pub fn latency(&self) -> i64 {
+        let mut path_latencies: FnvHashMap<PluginInstanceID, i64> = FnvHashMap::default();
+
+        for plugin_id in self.topological_order() {
+            let input_latency = self
+                .connected_edges()
+                .filter(|edge| edge.dst_plugin_id == plugin_id && edge.feedback.is_none())
+                .filter_map(|edge| path_latencies.get(&edge.src_plugin_id).copied())
+                .max()
+                .unwrap_or(0);
+
+            let plugin_latency = self
+                .plugin_hosts
+                .get(&plugin_id)
+                .map(|plugin_host| plugin_host.latency())
+                .unwrap_or(0);
+
+            path_latencies.insert(plugin_id, input_latency + plugin_latency);
+        }
+
+        path_latencies.get(&self.graph_out_id).copied().unwrap_or(0)
+    }
+
+    /// A description of the latest schedule that was sent to the process
+    /// thread.
+    ///
+    /// This blocks the current thread while a schedule is being compiled.
+    pub fn schedule_snapshot(&self) -> Option<ScheduleSnapshot> {
+        self.compiler_thread.lock().latest_schedule.clone()
+    }
+
+    /// The channel of the given port in the graph.
+    fn port_channel(&self, plugin_id: &PluginInstanceID, port_id: PortID, default: u16) -> u16 {
+        if plugin_id == &self.graph_in_id {
+            // Note ports on the graph input/output nodes are addressed by
+            // their index instead.
+            graph_io_port(&self.graph_in_ports, port_id.0 as usize)
+                .map(|(_, channel)| channel)
+                .unwrap_or(default)
+        } else if plugin_id == &self.graph_out_id {
+            graph_io_port(&self.graph_out_ports, port_id.0 as usize)
+                .map(|(_, channel)| channel)
+                .unwrap_or(default)
+        } else {
+            self.plugin_hosts
+                .get(plugin_id)
+                .and_then(|plugin_host| plugin_host.port_ids().port_id_to_channel_id.get(&port_id))
+                .map(|channel_id| channel_id.channel)
+                .unwrap_or(default)
+        }
+    }
+
+    /// The audio ports on the graph input node.
+    pub fn graph_in_ports(&self) -> &[GraphPortInfo] {
+        &self.graph_in_ports
+    }
+
+    /// The audio ports on the graph output node.
+    pub fn graph_out_ports(&self) -> &[GraphPortInfo] {
+        &self.graph_out_ports
     }
 
     pub fn graph_in_id(&self) -> &PluginInstanceID {
@@ -864,6 +1973,57 @@ impl AudioGraph {
     }
 }
 
+fn add_edge_error(e: AddEdgeError, edge: &ConnectEdgeReq) -> ConnectEdgeError {
+    let error_type = match e {
+        AddEdgeError::CycleDetected => ConnectEdgeErrorType::Cycle,
+        AddEdgeError::EdgeAlreadyExists(_) => ConnectEdgeErrorType::EdgeAlreadyExists,
+        e => {
+            log::error!("Unexpected error while connecting edge: {}", e);
+            ConnectEdgeErrorType::Unkown
+        }
+    };
+
+    ConnectEdgeError { error_type, edge: edge.clone() }
+}
+
+/// Find the channel on a graph input/output node that the given channel on
+/// the audio port with the given stable ID refers to.
+fn graph_io_channel(ports: &[GraphPortInfo], stable_id: u32, channel: u16) -> Option<usize> {
+    let mut offset = 0;
+    for port in ports.iter() {
+        if port.stable_id == stable_id {
+            return if channel < port.channels {
+                Some(offset + usize::from(channel))
+            } else {
+                None
+            };
+        }
+
+        offset += usize::from(port.channels);
+    }
+
+    None
+}
+
+/// Find the audio port that the given channel on a graph input/output node
+/// belongs to.
+///
+/// This returns the stable ID of the port along with the channel on that
+/// port.
+fn graph_io_port(ports: &[GraphPortInfo], channel: usize) -> Option<(u32, u16)> {
+    let mut offset = 0;
+    for port in ports.iter() {
+        let channels = usize::from(port.channels);
+        if channel < offset + channels {
+            return Some((port.stable_id, (channel - offset) as u16));
+        }
+
+        offset += channels;
+    }
+
+    None
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct DSEdgeID {
     pub(crate) unique_id: u64,
@@ -895,6 +2055,26 @@ pub struct Edge {
 
     pub src_port_id: u32,
     pub dst_port_id: u32,
+
+    /// The channel on the source port. This is irrelevant if this is not
+    /// an audio edge.
+    pub src_port_channel: u16,
+    /// The channel on the destination port. This is irrelevant if this is
+    /// not an audio edge.
+    pub dst_port_channel: u16,
+
+    /// The delay of the edge if this is a feedback edge.
+    pub feedback: Option<FeedbackDelay>,
+}
+
+/// The nodes that are added to the abstract graph in place of a feedback
+/// edge (see `AudioGraph::add_feedback_nodes()`).
+struct FeedbackEdgeNodes {
+    send_node: NodeID,
+    return_node: NodeID,
+    /// The ID of the edge from the return node to the destination port.
+    return_edge_id: EdgeID,
+    delay: FeedbackDelay,
 }
 
 impl PartialEq for Edge {
@@ -1,37 +1,117 @@
-use audio_graph::{AudioGraphHelper, ScheduleEntry};
+use audio_graph::{AudioGraphHelper, NodeID, PortID, ScheduleEntry};
 use basedrop::Shared;
+use dropseed_plugin_api::ext::audio_ports::PluginAudioPortsExt;
+use dropseed_plugin_api::ext::note_ports::PluginNotePortsExt;
+use fnv::FnvHashMap;
+use std::sync::Arc;
 
-use crate::plugin_host::PluginHostProcessorWrapper;
+use crate::plugin_host::{
+    PluginHostMainThread, PluginHostProcessorWrapper, SharedPluginHostProcessor,
+};
 use crate::processor_schedule::tasks::{GraphInTask, GraphOutTask, Task};
 
 mod delay_comp_task;
+mod feedback_task;
 mod graph_in_out_task;
 mod plugin_task;
 mod sum_task;
+mod task_dependencies;
 
+pub(super) mod compiler_thread;
 pub(super) mod verifier;
 
 use verifier::Verifier;
 
 use super::error::GraphCompilerError;
 use super::shared_pools::GraphSharedPools;
-use super::{PluginInstanceID, PortType, ProcessorSchedule};
+use super::{DSEdgeID, PluginInstanceID, PortChannelID, PortType, ProcessorSchedule};
+
+/// Everything the compiler needs to know about a plugin in order to
+/// construct its task.
+///
+/// This is copied from the plugin host when a compile is requested so that
+/// the compiler thread never needs to access the plugin hosts themselves.
+pub(super) struct PluginCompileInfo {
+    pub plugin_id: PluginInstanceID,
+    pub port_id_to_channel_id: FnvHashMap<PortID, PortChannelID>,
+    pub shared_processor: SharedPluginHostProcessor,
+    pub audio_ports_ext: Option<PluginAudioPortsExt>,
+    pub note_ports_ext: Option<PluginNotePortsExt>,
+    pub is_loaded: bool,
+}
+
+impl PluginCompileInfo {
+    pub fn new(plugin_host: &PluginHostMainThread) -> Self {
+        Self {
+            plugin_id: plugin_host.id().clone(),
+            port_id_to_channel_id: plugin_host.port_ids().port_id_to_channel_id.clone(),
+            shared_processor: plugin_host.shared_processor().clone(),
+            audio_ports_ext: plugin_host.audio_ports_ext().cloned(),
+            note_ports_ext: plugin_host.note_ports_ext().cloned(),
+            is_loaded: plugin_host.is_loaded(),
+        }
+    }
+}
+
+/// One of the two nodes that are added to the abstract graph in place of a
+/// feedback edge.
+pub(super) struct FeedbackCompileInfo {
+    pub edge_id: DSEdgeID,
+    /// The delay in frames.
+    pub delay: u32,
+    /// The maximum number of frames in a block.
+    pub max_frames: u32,
+    /// `true` if this node writes into the delay line, `false` if it reads
+    /// from the delay line.
+    pub is_send: bool,
+}
+
+/// A request to compile the current state of the audio graph.
+pub(super) struct CompileRequest {
+    pub graph_helper: AudioGraphHelper,
+    pub plugins: FnvHashMap<NodeID, PluginCompileInfo>,
+    pub feedback_nodes: FnvHashMap<NodeID, FeedbackCompileInfo>,
+    pub graph_in_id: PluginInstanceID,
+    pub graph_out_id: PluginInstanceID,
+    pub num_graph_in_audio_ports: usize,
+    pub num_graph_out_audio_ports: usize,
+    pub num_graph_in_note_ports: usize,
+    pub num_graph_out_note_ports: usize,
+    pub graph_in_has_automation_port: bool,
+    /// For the plugins that are queued to be removed, make sure that
+    /// the plugin's processor part is dropped in the process thread.
+    pub plugins_to_drop: Vec<Shared<PluginHostProcessorWrapper>>,
+    pub schedule_version: u64,
+}
 
 pub(super) fn compile_graph(
     shared_pool: &mut GraphSharedPools,
-    graph_helper: &mut AudioGraphHelper,
-    graph_in_id: &PluginInstanceID,
-    graph_out_id: &PluginInstanceID,
-    num_graph_in_audio_ports: usize,
-    num_graph_out_audio_ports: usize,
-    // For the plugins that are queued to be removed, make sure that
-    // the plugin's processor part is dropped in the process thread.
-    plugins_to_drop: Vec<Shared<PluginHostProcessorWrapper>>,
+    request: &mut CompileRequest,
     verifier: &mut Verifier,
-    schedule_version: u64,
     coll_handle: &basedrop::Handle,
 ) -> Result<ProcessorSchedule, GraphCompilerError> {
-    let mut tasks: Vec<Task> = Vec::with_capacity(shared_pool.plugin_hosts.num_plugins() * 2);
+    let CompileRequest {
+        graph_helper,
+        plugins,
+        feedback_nodes,
+        graph_in_id,
+        graph_out_id,
+        num_graph_in_audio_ports,
+        num_graph_out_audio_ports,
+        num_graph_in_note_ports,
+        num_graph_out_note_ports,
+        graph_in_has_automation_port,
+        plugins_to_drop,
+        schedule_version,
+    } = request;
+    let num_graph_in_audio_ports = *num_graph_in_audio_ports;
+    let num_graph_out_audio_ports = *num_graph_out_audio_ports;
+    let num_graph_in_note_ports = *num_graph_in_note_ports;
+    let num_graph_out_note_ports = *num_graph_out_note_ports;
+    let graph_in_has_automation_port = *graph_in_has_automation_port;
+    let schedule_version = *schedule_version;
+
+    let mut tasks: Vec<Task> = Vec::with_capacity(plugins.len() * 2);
     let mut graph_in_task: Option<GraphInTask> = None;
     let mut graph_out_task: Option<GraphOutTask> = None;
 
@@ -55,6 +135,9 @@ pub(super) fn compile_graph(
     for node in shared_pool.delay_comp_nodes.automation.values_mut() {
         node.active = false;
     }
+    for node in shared_pool.feedback_nodes.audio.values_mut() {
+        node.active = false;
+    }
 
     // Allocate/truncate the list of shared buffers based on how many exist
     // in the new abstract schedule.
@@ -75,6 +158,8 @@ pub(super) fn compile_graph(
                         scheduled_node,
                         shared_pool,
                         num_graph_in_audio_ports,
+                        num_graph_in_note_ports,
+                        graph_in_has_automation_port,
                     )?);
                 } else if scheduled_node.id.0 == graph_out_id._node_id() {
                     // The `graph out` node is a special node that handles outputting
@@ -84,10 +169,24 @@ pub(super) fn compile_graph(
                         scheduled_node,
                         shared_pool,
                         num_graph_out_audio_ports,
+                        num_graph_out_note_ports,
+                    )?);
+                } else if let Some(feedback) = feedback_nodes.get(&scheduled_node.id) {
+                    // Construct a task that writes to or reads from the delay
+                    // line of a feedback edge.
+                    tasks.push(feedback_task::construct_feedback_task(
+                        scheduled_node,
+                        feedback,
+                        shared_pool,
+                        coll_handle,
                     )?);
                 } else {
                     // Construct a task for a plugin.
-                    tasks.push(plugin_task::construct_plugin_task(scheduled_node, shared_pool)?);
+                    tasks.push(plugin_task::construct_plugin_task(
+                        scheduled_node,
+                        plugins,
+                        shared_pool,
+                    )?);
                 };
             }
             ScheduleEntry::Delay(inserted_delay) => {
@@ -138,14 +237,25 @@ pub(super) fn compile_graph(
     shared_pool.delay_comp_nodes.automation =
         shared_pool.delay_comp_nodes.automation.drain().filter(|(_, node)| node.active).collect();
 
+    // Remove all feedback delay lines that are no longer being used.
+    shared_pool.feedback_nodes.audio =
+        shared_pool.feedback_nodes.audio.drain().filter(|(_, node)| node.active).collect();
+
+    // Find which tasks can be processed in parallel.
+    let dependencies = task_dependencies::compute_task_dependencies(&tasks);
+
     // Construct the new schedule object.
     let new_schedule = ProcessorSchedule::new(
         tasks,
+        dependencies,
+        shared_pool.worker_pool.clone(),
         graph_in_task,
         graph_out_task,
         shared_pool.transports.transport.clone(),
-        plugins_to_drop,
+        shared_pool.transports.steady_clock.clone(),
+        plugins_to_drop.drain(..).collect(),
         shared_pool.buffers.audio_buffer_pool.buffer_size(),
+        Arc::clone(&shared_pool.telemetry),
         schedule_version,
     );
 
@@ -160,7 +270,7 @@ pub(super) fn compile_graph(
     //
     // However, it is still very possible to have race condition bugs in the schedule, such as
     // the same buffer being assigned multiple times within the same task, or the same buffer
-    // appearing multiple times between parallel tasks (once we have multithreaded scheduling).
+    // appearing multiple times between parallel tasks.
     if let Err(e) = verifier.verify_schedule_for_race_conditions(&new_schedule) {
         return Err(GraphCompilerError::VerifierError(
             e,
@@ -0,0 +1,219 @@
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use crate::processor_schedule::ProcessorSchedule;
+
+use super::super::error::GraphCompilerError;
+use super::super::export::ScheduleSnapshot;
+use super::super::shared_pools::GraphSharedPools;
+use super::verifier::Verifier;
+use super::{compile_graph, CompileRequest};
+
+/// The state that is owned by whichever thread is currently compiling the
+/// audio graph.
+///
+/// Every field of this is `Send` (the shared pointers to the buffers and
+/// nodes in the shared pools only require their contents to be `Send` and
+/// `Sync`), so this can be moved into the compiler thread without any
+/// `unsafe` code.
+pub(in crate::graph) struct CompilerState {
+    pub shared_pools: GraphSharedPools,
+    /// A description of the latest schedule that was sent to the process
+    /// thread.
+    pub latest_schedule: Option<ScheduleSnapshot>,
+    verifier: Verifier,
+    coll_handle: basedrop::Handle,
+}
+
+impl CompilerState {
+    fn compile(&mut self, mut request: CompileRequest) -> Result<(), GraphCompilerError> {
+        match compile_graph(
+            &mut self.shared_pools,
+            &mut request,
+            &mut self.verifier,
+            &self.coll_handle,
+        ) {
+            Ok(schedule) => {
+                log::debug!("Successfully compiled new schedule:\n{:?}", &schedule);
+                self.set_new_schedule(schedule);
+                Ok(())
+            }
+            Err(e) => {
+                // Replace the current schedule with an emtpy one now that the graph
+                // is in an invalid state.
+                self.set_new_schedule(ProcessorSchedule::new_empty(
+                    self.shared_pools.buffers.audio_buffer_pool.buffer_size(),
+                    self.shared_pools.transports.transport.clone(),
+                    self.shared_pools.transports.steady_clock.clone(),
+                    request.plugins_to_drop.drain(..).collect(),
+                    Arc::clone(&self.shared_pools.telemetry),
+                    request.schedule_version,
+                ));
+                Err(e)
+            }
+        }
+    }
+
+    /// Send a new schedule to the process thread.
+    pub fn set_new_schedule(&mut self, schedule: ProcessorSchedule) {
+        self.latest_schedule = Some(schedule.snapshot());
+        self.shared_pools.shared_schedule.set_new_schedule(schedule, &self.coll_handle);
+    }
+}
+
+struct CompileResult {
+    schedule_version: u64,
+    res: Result<(), GraphCompilerError>,
+}
+
+/// Compiles the audio graph in a separate thread so that large graphs don't
+/// stall the main thread.
+///
+/// Once a new schedule is compiled, the compiler thread sends it to the
+/// process thread directly.
+pub(in crate::graph) struct GraphCompilerThread {
+    state: Arc<Mutex<CompilerState>>,
+
+    request_tx: Option<Sender<CompileRequest>>,
+    result_rx: Receiver<CompileResult>,
+    handle: Option<JoinHandle<()>>,
+
+    /// The version of the latest schedule that was requested, if it
+    /// hasn't finished compiling yet.
+    pending_version: Option<u64>,
+}
+
+impl GraphCompilerThread {
+    pub fn new(shared_pools: GraphSharedPools, coll_handle: basedrop::Handle) -> Self {
+        let state = Arc::new(Mutex::new(CompilerState {
+            shared_pools,
+            latest_schedule: None,
+            verifier: Verifier::new(),
+            coll_handle,
+        }));
+
+        let (request_tx, request_rx) = mpsc::channel();
+        let (result_tx, result_rx) = mpsc::channel();
+
+        let state_clone = Arc::clone(&state);
+        let handle = std::thread::Builder::new()
+            .name("dropseed-graph-compiler".into())
+            .spawn(move || run(state_clone, request_rx, result_tx))
+            .expect("Failed to spawn the audio graph compiler thread");
+
+        Self {
+            state,
+            request_tx: Some(request_tx),
+            result_rx,
+            handle: Some(handle),
+            pending_version: None,
+        }
+    }
+
+    /// Queue the graph to be compiled in the compiler thread.
+    pub fn compile(&mut self, request: CompileRequest) {
+        let schedule_version = request.schedule_version;
+
+        if self.request_tx.as_ref().unwrap().send(request).is_ok() {
+            self.pending_version = Some(schedule_version);
+        } else {
+            log::error!("Failed to compile audio graph: The compiler thread has stopped");
+        }
+    }
+
+    /// Returns the schedule version and the result of a finished compile, if
+    /// there is one.
+    ///
+    /// When multiple requests are queued up before the compiler thread gets
+    /// to them, only the latest request is compiled.
+    pub fn poll(&mut self) -> Option<(u64, Result<(), GraphCompilerError>)> {
+        match self.result_rx.try_recv() {
+            Ok(result) => Some(self.on_result(result)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.pending_version = None;
+                None
+            }
+        }
+    }
+
+    /// Returns the schedule version and the result of the next finished
+    /// compile, blocking the current thread until it is done.
+    ///
+    /// This returns `None` once there are no more requests left to
+    /// compile.
+    pub fn wait(&mut self) -> Option<(u64, Result<(), GraphCompilerError>)> {
+        if let Some(res) = self.poll() {
+            return Some(res);
+        }
+
+        self.pending_version?;
+
+        match self.result_rx.recv() {
+            Ok(result) => Some(self.on_result(result)),
+            Err(_) => {
+                self.pending_version = None;
+                None
+            }
+        }
+    }
+
+    /// Block the current thread until all queued requests have been
+    /// compiled, discarding their results.
+    pub fn wait_until_idle(&mut self) {
+        while self.wait().is_some() {}
+    }
+
+    /// Lock the state of the compiler.
+    ///
+    /// Call `Self::wait_until_idle()` first to avoid blocking on a compile
+    /// that is in progress.
+    pub fn lock(&self) -> MutexGuard<'_, CompilerState> {
+        self.state.lock().unwrap()
+    }
+
+    fn on_result(&mut self, result: CompileResult) -> (u64, Result<(), GraphCompilerError>) {
+        if self.pending_version == Some(result.schedule_version) {
+            self.pending_version = None;
+        }
+
+        (result.schedule_version, result.res)
+    }
+}
+
+impl Drop for GraphCompilerThread {
+    fn drop(&mut self) {
+        // Dropping the sender stops the compiler thread.
+        self.request_tx = None;
+
+        if let Some(handle) = self.handle.take() {
+            if let Err(e) = handle.join() {
+                log::error!("Failed to join audio graph compiler thread: {:?}", e);
+            }
+        }
+    }
+}
+
+fn run(
+    state: Arc<Mutex<CompilerState>>,
+    request_rx: Receiver<CompileRequest>,
+    result_tx: Sender<CompileResult>,
+) {
+    while let Ok(mut request) = request_rx.recv() {
+        // Only the latest state of the graph needs to be compiled, but the
+        // processors from the skipped requests still need to be dropped in
+        // the process thread.
+        while let Ok(mut newer_request) = request_rx.try_recv() {
+            newer_request.plugins_to_drop.append(&mut request.plugins_to_drop);
+            request = newer_request;
+        }
+
+        let schedule_version = request.schedule_version;
+        let res = state.lock().unwrap().compile(request);
+
+        if result_tx.send(CompileResult { schedule_version, res }).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use audio_graph::ScheduledNode;
+use dropseed_plugin_api::buffer::SharedBuffer;
+
+use crate::processor_schedule::tasks::{
+    AudioFeedbackNode, AudioFeedbackReturnTask, AudioFeedbackSendTask, SharedAudioFeedbackNode,
+    Task,
+};
+
+use super::super::error::GraphCompilerError;
+use super::super::shared_pools::{FeedbackKey, GraphSharedPools};
+use super::super::{PortType, FEEDBACK_SIGNAL_PORT};
+use super::FeedbackCompileInfo;
+
+pub(super) fn construct_feedback_task(
+    scheduled_node: &ScheduledNode,
+    feedback: &FeedbackCompileInfo,
+    shared_pool: &mut GraphSharedPools,
+    coll_handle: &basedrop::Handle,
+) -> Result<Task, GraphCompilerError> {
+    // The send node only has input ports, and the return node only has
+    // output ports.
+    let assigned_buffers = if feedback.is_send {
+        &scheduled_node.input_buffers
+    } else {
+        &scheduled_node.output_buffers
+    };
+
+    let mut audio_buffer: Option<SharedBuffer<f32>> = None;
+    for assigned_buffer in assigned_buffers.iter() {
+        if assigned_buffer.port_id == FEEDBACK_SIGNAL_PORT
+            && assigned_buffer.type_index == PortType::AUDIO_TYPE_IDX
+        {
+            audio_buffer = Some(
+                shared_pool
+                    .buffers
+                    .audio_buffer_pool
+                    .initialized_buffer_at_index(assigned_buffer.buffer_index.0),
+            );
+        } else {
+            return Err(GraphCompilerError::UnexpectedError(format!(
+                "Abstract schedule assigned buffer to feedback node with invalid port id {:?}",
+                scheduled_node
+            )));
+        }
+    }
+
+    let audio_buffer = audio_buffer.ok_or_else(|| {
+        GraphCompilerError::UnexpectedError(format!(
+            "Abstract schedule did not assign a buffer to feedback node {:?}",
+            scheduled_node
+        ))
+    })?;
+
+    // The send and return tasks of the same edge share the same delay line.
+    let feedback_key = FeedbackKey { edge: feedback.edge_id, delay: feedback.delay };
+    let shared_node = shared_pool.feedback_nodes.audio.entry(feedback_key).or_insert_with(|| {
+        SharedAudioFeedbackNode::new(
+            AudioFeedbackNode::new(feedback.delay, feedback.max_frames),
+            feedback.edge_id.unique_id,
+            coll_handle,
+        )
+    });
+    shared_node.active = true;
+
+    let task = if feedback.is_send {
+        Task::AudioFeedbackSend(AudioFeedbackSendTask {
+            shared_node: shared_node.clone(),
+            audio_in: audio_buffer,
+        })
+    } else {
+        Task::AudioFeedbackReturn(AudioFeedbackReturnTask {
+            shared_node: shared_node.clone(),
+            audio_out: audio_buffer,
+        })
+    };
+
+    Ok(task)
+}
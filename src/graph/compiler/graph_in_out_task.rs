@@ -1,7 +1,9 @@
 use audio_graph::ScheduledNode;
+use dropseed_plugin_api::automation::AutomationIoEvent;
 use dropseed_plugin_api::buffer::SharedBuffer;
 use smallvec::{smallvec, SmallVec};
 
+use crate::plugin_host::event_io_buffers::NoteIoEvent;
 use crate::processor_schedule::tasks::{GraphInTask, GraphOutTask};
 
 use super::super::error::GraphCompilerError;
@@ -12,11 +14,16 @@ pub(super) fn construct_graph_in_task(
     scheduled_node: &ScheduledNode,
     shared_pool: &mut GraphSharedPools,
     num_graph_in_audio_ports: usize,
+    num_graph_in_note_ports: usize,
+    has_automation_port: bool,
 ) -> Result<GraphInTask, GraphCompilerError> {
     // --- Construct a map that maps the index (channel) of each port to its assigned buffer
 
     let mut audio_out_slots: SmallVec<[Option<SharedBuffer<f32>>; 8]> =
         smallvec![None; num_graph_in_audio_ports];
+    let mut note_out_slots: SmallVec<[Option<SharedBuffer<NoteIoEvent>>; 2]> =
+        smallvec![None; num_graph_in_note_ports];
+    let mut automation_out_slot: Option<SharedBuffer<AutomationIoEvent>> = None;
     for output_buffer in scheduled_node.output_buffers.iter() {
         match output_buffer.type_index {
             PortType::AUDIO_TYPE_IDX => {
@@ -36,7 +43,42 @@ pub(super) fn construct_graph_in_task(
                 *buffer_slot = Some(buffer);
             }
             PortType::NOTE_TYPE_IDX => {
-                // TODO: Note buffers in graph input.
+                let buffer = shared_pool
+                    .buffers
+                    .note_buffer_pool
+                    .buffer_at_index(output_buffer.buffer_index.0);
+
+                // The note ports come after the audio ports.
+                let buffer_slot = (output_buffer.port_id.0 as usize)
+                    .checked_sub(num_graph_in_audio_ports)
+                    .and_then(|i| note_out_slots.get_mut(i))
+                    .ok_or_else(|| {
+                        GraphCompilerError::UnexpectedError(format!(
+                    "Abstract schedule assigned buffer to graph in node with invalid port id {:?}",
+                    output_buffer
+                ))
+                    })?;
+
+                *buffer_slot = Some(buffer);
+            }
+            PortType::AUTOMATION_TYPE_IDX => {
+                // The automation port comes after the note ports.
+                if !has_automation_port
+                    || output_buffer.port_id.0 as usize
+                        != num_graph_in_audio_ports + num_graph_in_note_ports
+                {
+                    return Err(GraphCompilerError::UnexpectedError(format!(
+                    "Abstract schedule assigned buffer to graph in node with invalid port id {:?}",
+                    output_buffer
+                )));
+                }
+
+                automation_out_slot = Some(
+                    shared_pool
+                        .buffers
+                        .automation_buffer_pool
+                        .buffer_at_index(output_buffer.buffer_index.0),
+                );
             }
             _ => {
                 return Err(GraphCompilerError::UnexpectedError(format!(
@@ -62,18 +104,41 @@ pub(super) fn construct_graph_in_task(
         audio_in.push(buffer);
     }
 
-    Ok(GraphInTask { audio_in })
+    let mut note_in: SmallVec<[SharedBuffer<NoteIoEvent>; 2]> =
+        SmallVec::with_capacity(num_graph_in_note_ports);
+    for buffer_slot in note_out_slots.drain(..) {
+        let buffer = buffer_slot.ok_or_else(|| {
+            GraphCompilerError::UnexpectedError(format!(
+                "Abstract schedule did not assign a buffer to all ports on graph in node {:?}",
+                scheduled_node
+            ))
+        })?;
+
+        note_in.push(buffer);
+    }
+
+    if has_automation_port && automation_out_slot.is_none() {
+        return Err(GraphCompilerError::UnexpectedError(format!(
+            "Abstract schedule did not assign a buffer to all ports on graph in node {:?}",
+            scheduled_node
+        )));
+    }
+
+    Ok(GraphInTask { audio_in, note_in, automation_in: automation_out_slot })
 }
 
 pub(super) fn construct_graph_out_task(
     scheduled_node: &ScheduledNode,
     shared_pool: &mut GraphSharedPools,
     num_graph_out_audio_ports: usize,
+    num_graph_out_note_ports: usize,
 ) -> Result<GraphOutTask, GraphCompilerError> {
     // --- Construct a map that maps the index (channel) of each port to its assigned buffer
 
     let mut audio_in_slots: SmallVec<[Option<SharedBuffer<f32>>; 8]> =
         smallvec![None; num_graph_out_audio_ports];
+    let mut note_in_slots: SmallVec<[Option<(SharedBuffer<NoteIoEvent>, bool)>; 2]> =
+        smallvec![None; num_graph_out_note_ports];
     for input_buffer in scheduled_node.input_buffers.iter() {
         match input_buffer.type_index {
             PortType::AUDIO_TYPE_IDX => {
@@ -93,7 +158,23 @@ pub(super) fn construct_graph_out_task(
                 *buffer_slot = Some(buffer);
             }
             PortType::NOTE_TYPE_IDX => {
-                // TODO: Note buffers in graph input.
+                let buffer = shared_pool
+                    .buffers
+                    .note_buffer_pool
+                    .buffer_at_index(input_buffer.buffer_index.0);
+
+                // The note ports come after the audio ports.
+                let buffer_slot = (input_buffer.port_id.0 as usize)
+                    .checked_sub(num_graph_out_audio_ports)
+                    .and_then(|i| note_in_slots.get_mut(i))
+                    .ok_or_else(|| {
+                        GraphCompilerError::UnexpectedError(format!(
+                    "Abstract schedule assigned buffer to graph out node with invalid port id {:?}",
+                    input_buffer
+                ))
+                    })?;
+
+                *buffer_slot = Some((buffer, input_buffer.should_clear));
             }
             _ => {
                 return Err(GraphCompilerError::UnexpectedError(format!(
@@ -119,5 +200,18 @@ pub(super) fn construct_graph_out_task(
         audio_out.push(buffer);
     }
 
-    Ok(GraphOutTask { audio_out })
+    let mut note_out: SmallVec<[(SharedBuffer<NoteIoEvent>, bool); 2]> =
+        SmallVec::with_capacity(num_graph_out_note_ports);
+    for buffer_slot in note_in_slots.drain(..) {
+        let buffer = buffer_slot.ok_or_else(|| {
+            GraphCompilerError::UnexpectedError(format!(
+                "Abstract schedule did not assign a buffer to all ports on graph out node {:?}",
+                scheduled_node
+            ))
+        })?;
+
+        note_out.push(buffer);
+    }
+
+    Ok(GraphOutTask { audio_out, note_out })
 }
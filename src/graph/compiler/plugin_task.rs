@@ -1,4 +1,4 @@
-use audio_graph::ScheduledNode;
+use audio_graph::{NodeID, ScheduledNode};
 use dropseed_plugin_api::automation::AutomationIoEvent;
 use dropseed_plugin_api::buffer::SharedBuffer;
 use fnv::FnvHashMap;
@@ -9,29 +9,30 @@ use crate::processor_schedule::tasks::Task;
 use super::super::error::GraphCompilerError;
 use super::super::shared_pools::GraphSharedPools;
 use super::super::{PortChannelID, PortType};
+use super::PluginCompileInfo;
 
 mod loaded_plugin_task;
 mod unloaded_plugin_task;
 
 pub(super) fn construct_plugin_task(
     scheduled_node: &ScheduledNode,
+    plugins: &FnvHashMap<NodeID, PluginCompileInfo>,
     shared_pool: &mut GraphSharedPools,
 ) -> Result<Task, GraphCompilerError> {
     // --- Get port info and processor from the plugin host ---------------------------------
 
-    let plugin_host =
-        shared_pool.plugin_hosts.get_by_node_id(&scheduled_node.id).ok_or_else(|| {
-            GraphCompilerError::UnexpectedError(format!(
-                "Abstract schedule assigned a node that doesn't exist: {:?}",
-                scheduled_node
-            ))
-        })?;
+    let plugin = plugins.get(&scheduled_node.id).ok_or_else(|| {
+        GraphCompilerError::UnexpectedError(format!(
+            "Abstract schedule assigned a node that doesn't exist: {:?}",
+            scheduled_node
+        ))
+    })?;
 
-    let plugin_id = plugin_host.id();
-    let port_ids = plugin_host.port_ids();
-    let shared_processor = plugin_host.shared_processor();
-    let maybe_audio_ports_ext = plugin_host.audio_ports_ext();
-    let maybe_note_ports_ext = plugin_host.note_ports_ext();
+    let plugin_id = &plugin.plugin_id;
+    let port_id_to_channel_id = &plugin.port_id_to_channel_id;
+    let shared_processor = &plugin.shared_processor;
+    let maybe_audio_ports_ext = plugin.audio_ports_ext.as_ref();
+    let maybe_note_ports_ext = plugin.note_ports_ext.as_ref();
 
     // --- Construct a map that maps the PortChannelID of each port to its assigned buffer ------
 
@@ -45,13 +46,12 @@ pub(super) fn construct_plugin_task(
     for assigned_buffer in
         scheduled_node.input_buffers.iter().chain(scheduled_node.output_buffers.iter())
     {
-        let channel_id =
-            port_ids.port_id_to_channel_id.get(&assigned_buffer.port_id).ok_or_else(|| {
-                GraphCompilerError::UnexpectedError(format!(
-                    "Abstract schedule assigned a buffer for port that doesn't exist {:?}",
-                    scheduled_node
-                ))
-            })?;
+        let channel_id = port_id_to_channel_id.get(&assigned_buffer.port_id).ok_or_else(|| {
+            GraphCompilerError::UnexpectedError(format!(
+                "Abstract schedule assigned a buffer for port that doesn't exist {:?}",
+                scheduled_node
+            ))
+        })?;
 
         if assigned_buffer.type_index != channel_id.port_type.as_type_idx() {
             return Err(GraphCompilerError::UnexpectedError(format!(
@@ -122,14 +122,14 @@ pub(super) fn construct_plugin_task(
 
     // --- Construct the final task using the constructed map from above --------------------
 
-    if plugin_host.is_loaded() {
+    if plugin.is_loaded {
         loaded_plugin_task::construct_loaded_plugin_task(
             scheduled_node,
             shared_pool,
             plugin_id,
             shared_processor,
-            maybe_audio_ports_ext.as_ref().unwrap(),
-            maybe_note_ports_ext.as_ref().unwrap(),
+            maybe_audio_ports_ext.unwrap(),
+            maybe_note_ports_ext.unwrap(),
             assigned_audio_buffers,
             assigned_note_buffers,
             assigned_automation_in_buffer,
@@ -139,6 +139,7 @@ pub(super) fn construct_plugin_task(
         // Plugin is unloaded
         unloaded_plugin_task::construct_unloaded_plugin_task(
             scheduled_node,
+            plugin_id,
             maybe_audio_ports_ext,
             maybe_note_ports_ext,
             assigned_audio_buffers,
@@ -3,6 +3,7 @@ use dropseed_plugin_api::automation::AutomationIoEvent;
 use dropseed_plugin_api::buffer::SharedBuffer;
 use dropseed_plugin_api::ext::audio_ports::{MainPortsLayout, PluginAudioPortsExt};
 use dropseed_plugin_api::ext::note_ports::PluginNotePortsExt;
+use dropseed_plugin_api::PluginInstanceID;
 use fnv::FnvHashMap;
 use smallvec::SmallVec;
 
@@ -16,6 +17,7 @@ use super::super::super::{PortChannelID, PortType};
 /// has main in/out ports), and then all the other output buffers are cleared.
 pub(super) fn construct_unloaded_plugin_task(
     scheduled_node: &ScheduledNode,
+    plugin_id: &PluginInstanceID,
     maybe_audio_ports_ext: Option<&PluginAudioPortsExt>,
     maybe_note_ports_ext: Option<&PluginNotePortsExt>,
     mut assigned_audio_buffers: FnvHashMap<PortChannelID, (SharedBuffer<f32>, bool)>,
@@ -128,6 +130,7 @@ pub(super) fn construct_unloaded_plugin_task(
     }
 
     Ok(Task::UnloadedPlugin(UnloadedPluginTask {
+        plugin_id: plugin_id.clone(),
         audio_through,
         note_through,
         clear_audio_out,
@@ -0,0 +1,84 @@
+use dropseed_plugin_api::buffer::DebugBufferID;
+use fnv::FnvHashMap;
+use smallvec::SmallVec;
+
+use crate::processor_schedule::parallel::TaskDependencies;
+use crate::processor_schedule::tasks::Task;
+
+#[derive(Default)]
+struct BufferState {
+    /// The last task that wrote to this buffer.
+    last_writer: Option<u32>,
+    /// The tasks that have read from this buffer since it was last written to.
+    readers: SmallVec<[u32; 4]>,
+}
+
+/// Find the dependencies between the tasks in a schedule.
+///
+/// The abstract compiler reuses buffers between tasks, so the order of the
+/// tasks matters even for tasks that aren't connected in the graph. A task
+/// depends on an earlier task if:
+/// * it reads from a buffer the earlier task wrote to, or
+/// * it writes to a buffer the earlier task read from or wrote to, or
+/// * it is the send or return task of the same feedback edge as the earlier
+/// task, since both tasks use the same delay line.
+///
+/// Tasks that only read from the same buffer don't depend on each other.
+pub(super) fn compute_task_dependencies(tasks: &[Task]) -> TaskDependencies {
+    let mut buffer_states: FnvHashMap<DebugBufferID, BufferState> = FnvHashMap::default();
+    let mut task_buffers: FnvHashMap<DebugBufferID, bool> = FnvHashMap::default();
+    // For each feedback edge, the last task that used its delay line.
+    let mut feedback_tasks: FnvHashMap<u64, u32> = FnvHashMap::default();
+
+    let mut dependencies: Vec<SmallVec<[u32; 4]>> = Vec::with_capacity(tasks.len());
+    let mut dependents: Vec<SmallVec<[u32; 4]>> = vec![SmallVec::new(); tasks.len()];
+
+    for (task_i, task) in tasks.iter().enumerate() {
+        let task_i = task_i as u32;
+
+        // If a task both reads from and writes to the same buffer, treat it as
+        // a write.
+        task_buffers.clear();
+        task.for_each_buffer(|buffer_id, is_write| {
+            *task_buffers.entry(buffer_id).or_insert(false) |= is_write;
+        });
+
+        let mut task_deps: SmallVec<[u32; 4]> = SmallVec::new();
+        let add_dep = |dep_i: u32, task_deps: &mut SmallVec<[u32; 4]>| {
+            if dep_i != task_i && !task_deps.contains(&dep_i) {
+                task_deps.push(dep_i);
+            }
+        };
+
+        for (buffer_id, is_write) in task_buffers.iter() {
+            let state = buffer_states.entry(*buffer_id).or_default();
+
+            if let Some(last_writer) = state.last_writer {
+                add_dep(last_writer, &mut task_deps);
+            }
+
+            if *is_write {
+                for reader_i in state.readers.drain(..) {
+                    add_dep(reader_i, &mut task_deps);
+                }
+
+                state.last_writer = Some(task_i);
+            } else {
+                state.readers.push(task_i);
+            }
+        }
+
+        if let Some(edge_id) = task.feedback_edge_id() {
+            if let Some(other_i) = feedback_tasks.insert(edge_id, task_i) {
+                add_dep(other_i, &mut task_deps);
+            }
+        }
+
+        for dep_i in task_deps.iter() {
+            dependents[*dep_i as usize].push(task_i);
+        }
+        dependencies.push(task_deps);
+    }
+
+    TaskDependencies { dependents, dependencies }
+}
@@ -1,5 +1,6 @@
 use dropseed_plugin_api::buffer::{DebugBufferID, RawAudioChannelBuffers};
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
+use smallvec::SmallVec;
 
 use crate::processor_schedule::{tasks::Task, ProcessorSchedule};
 
@@ -8,6 +9,15 @@ use super::super::error::VerifyScheduleError;
 pub(crate) struct Verifier {
     plugin_instances: FnvHashSet<u64>,
     buffer_instances: FnvHashSet<DebugBufferID>,
+
+    /// For each buffer, the tasks that use it (in order) along with whether
+    /// or not that task writes to it.
+    buffer_accesses: FnvHashMap<DebugBufferID, SmallVec<[(u32, bool); 4]>>,
+    /// For each feedback edge, the tasks that use its delay line (in order).
+    feedback_accesses: FnvHashMap<u64, SmallVec<[u32; 2]>>,
+    /// For each task, a bitset of all the tasks that are guaranteed to
+    /// have finished before it starts.
+    task_ancestors: Vec<u64>,
 }
 
 impl Verifier {
@@ -17,7 +27,13 @@ impl Verifier {
         plugin_instances.reserve(1024);
         buffer_instances.reserve(1024);
 
-        Verifier { plugin_instances, buffer_instances }
+        Verifier {
+            plugin_instances,
+            buffer_instances,
+            buffer_accesses: FnvHashMap::default(),
+            feedback_accesses: FnvHashMap::default(),
+            task_ancestors: Vec::new(),
+        }
     }
 
     /// Verify that the schedule is sound (no race conditions).
@@ -31,14 +47,16 @@ impl Verifier {
     ///
     /// However, it is still very possible to have race condition bugs in the schedule, such as
     /// the same buffer being assigned multiple times within the same task, or the same buffer
-    /// appearing multiple times between parallel tasks (once we have multithreaded scheduling).
+    /// appearing multiple times between parallel tasks.
     pub fn verify_schedule_for_race_conditions(
         &mut self,
         schedule: &ProcessorSchedule,
     ) -> Result<(), VerifyScheduleError> {
-        // TODO: verifying that there are not data races between parallel threads once we
-        // have multithreaded scheduling.
+        self.verify_tasks(schedule)?;
+        self.verify_parallel_tasks(schedule)
+    }
 
+    fn verify_tasks(&mut self, schedule: &ProcessorSchedule) -> Result<(), VerifyScheduleError> {
         self.plugin_instances.clear();
 
         for task in schedule.tasks().iter() {
@@ -209,6 +227,9 @@ impl Verifier {
                         });
                     }
                 }
+                Task::AudioFeedbackSend(_) | Task::AudioFeedbackReturn(_) => {
+                    // These tasks only use one buffer.
+                }
                 Task::UnloadedPlugin(t) => {
                     for (b_in, b_out) in t.audio_through.iter() {
                         if !self.buffer_instances.insert(b_in.id()) {
@@ -267,6 +288,134 @@ impl Verifier {
             }
         }
 
+        Ok(())
+    }
+    /// Verify that any two tasks which use the same buffer (where at least one
+    /// of them writes to it) can never be processed at the same time.
+    ///
+    /// Two tasks can never be processed at the same time if one of them
+    /// depends on the other, either directly or through other tasks.
+    fn verify_parallel_tasks(
+        &mut self,
+        schedule: &ProcessorSchedule,
+    ) -> Result<(), VerifyScheduleError> {
+        let tasks = schedule.tasks();
+        let dependencies = schedule.dependencies();
+
+        if dependencies.num_tasks() != tasks.len() {
+            return Err(VerifyScheduleError::InvalidTaskDependencies {
+                task_info: format!(
+                    "expected dependencies for {} tasks, got {}",
+                    tasks.len(),
+                    dependencies.num_tasks()
+                ),
+            });
+        }
+
+        let num_words = (tasks.len() + 63) / 64;
+        self.task_ancestors.clear();
+        self.task_ancestors.resize(tasks.len() * num_words, 0);
+
+        for (task_i, deps) in dependencies.dependencies.iter().enumerate() {
+            for dep_i in deps.iter() {
+                let dep_i = *dep_i as usize;
+
+                // Only allowing tasks to depend on earlier tasks guarantees that
+                // there are no cycles, and that the tasks are processed in the
+                // same order as they would be in a single thread.
+                if dep_i >= task_i {
+                    return Err(VerifyScheduleError::InvalidTaskDependencies {
+                        task_info: format!("{:?}", &tasks[task_i]),
+                    });
+                }
+
+                if !dependencies.dependents[dep_i].contains(&(task_i as u32)) {
+                    return Err(VerifyScheduleError::InvalidTaskDependencies {
+                        task_info: format!("{:?}", &tasks[task_i]),
+                    });
+                }
+
+                // Everything that finished before the dependency also finished
+                // before this task.
+                let (earlier, later) = self.task_ancestors.split_at_mut(task_i * num_words);
+                let dep_ancestors = &earlier[dep_i * num_words..(dep_i + 1) * num_words];
+                let task_ancestors = &mut later[0..num_words];
+                for (word, dep_word) in task_ancestors.iter_mut().zip(dep_ancestors.iter()) {
+                    *word |= *dep_word;
+                }
+                task_ancestors[dep_i / 64] |= 1 << (dep_i % 64);
+            }
+        }
+
+        for accesses in self.buffer_accesses.values_mut() {
+            accesses.clear();
+        }
+        for (task_i, task) in tasks.iter().enumerate() {
+            let task_i = task_i as u32;
+
+            task.for_each_buffer(|buffer_id, is_write| {
+                let accesses = self.buffer_accesses.entry(buffer_id).or_default();
+
+                if let Some((last_task_i, last_is_write)) = accesses.last_mut() {
+                    if *last_task_i == task_i {
+                        *last_is_write |= is_write;
+                        return;
+                    }
+                }
+
+                accesses.push((task_i, is_write));
+            });
+        }
+
+        for (buffer_id, accesses) in self.buffer_accesses.iter() {
+            for (i, (task_a, a_is_write)) in accesses.iter().enumerate() {
+                for (task_b, b_is_write) in accesses.iter().skip(i + 1) {
+                    if !*a_is_write && !*b_is_write {
+                        continue;
+                    }
+
+                    // `task_b` always comes after `task_a` in the schedule.
+                    let task_a = *task_a as usize;
+                    let task_b = *task_b as usize;
+                    let ancestors = &self.task_ancestors[task_b * num_words..];
+                    if ancestors[task_a / 64] & (1 << (task_a % 64)) == 0 {
+                        return Err(VerifyScheduleError::BufferAppearsTwiceInParallelTasks {
+                            buffer_id: *buffer_id,
+                            task_a_info: format!("{:?}", &tasks[task_a]),
+                            task_b_info: format!("{:?}", &tasks[task_b]),
+                        });
+                    }
+                }
+            }
+        }
+
+        // The send and return tasks of a feedback edge share the same delay
+        // line, so one of them must always finish before the other starts.
+        for accesses in self.feedback_accesses.values_mut() {
+            accesses.clear();
+        }
+        for (task_i, task) in tasks.iter().enumerate() {
+            if let Some(edge_id) = task.feedback_edge_id() {
+                self.feedback_accesses.entry(edge_id).or_default().push(task_i as u32);
+            }
+        }
+
+        for accesses in self.feedback_accesses.values() {
+            for (i, task_a) in accesses.iter().enumerate() {
+                for task_b in accesses.iter().skip(i + 1) {
+                    let task_a = *task_a as usize;
+                    let task_b = *task_b as usize;
+                    let ancestors = &self.task_ancestors[task_b * num_words..];
+                    if ancestors[task_a / 64] & (1 << (task_a % 64)) == 0 {
+                        return Err(VerifyScheduleError::DelayLineAppearsTwiceInParallelTasks {
+                            task_a_info: format!("{:?}", &tasks[task_a]),
+                            task_b_info: format!("{:?}", &tasks[task_b]),
+                        });
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -14,6 +14,10 @@ pub enum ConnectEdgeErrorType {
     DstPortDoesNotExist,
     EdgeAlreadyExists,
     Cycle,
+    /// Only audio edges can be feedback edges.
+    UnsupportedFeedbackEdge,
+    /// The delay of a feedback edge is shorter than one block (`max_frames`).
+    FeedbackDelayTooShort,
     Unkown,
 }
 
@@ -62,6 +66,20 @@ impl std::fmt::Display for ConnectEdgeError {
             ConnectEdgeErrorType::Cycle => {
                 write!(f, "Could not add edge {:?} to graph: Cycle detected", &self.edge)
             }
+            ConnectEdgeErrorType::UnsupportedFeedbackEdge => {
+                write!(
+                    f,
+                    "Could not add edge {:?} to graph: Only audio edges can be feedback edges",
+                    &self.edge
+                )
+            }
+            ConnectEdgeErrorType::FeedbackDelayTooShort => {
+                write!(
+                    f,
+                    "Could not add edge {:?} to graph: The delay of a feedback edge cannot be shorter than the maximum block size",
+                    &self.edge
+                )
+            }
             ConnectEdgeErrorType::Unkown => {
                 write!(f, "Could not add edge {:?} to graph: Unkown error", &self.edge)
             }
@@ -100,7 +118,6 @@ impl From<audio_graph::error::CompileGraphError> for GraphCompilerError {
     }
 }
 
-#[allow(unused)]
 #[derive(Debug, Clone)]
 pub enum VerifyScheduleError {
     BufferAppearsTwiceInSameTask {
@@ -109,6 +126,17 @@ pub enum VerifyScheduleError {
     },
     BufferAppearsTwiceInParallelTasks {
         buffer_id: DebugBufferID,
+        task_a_info: String,
+        task_b_info: String,
+    },
+    /// The send and return tasks of the same feedback edge can be processed
+    /// at the same time.
+    DelayLineAppearsTwiceInParallelTasks {
+        task_a_info: String,
+        task_b_info: String,
+    },
+    InvalidTaskDependencies {
+        task_info: String,
     },
     PluginInstanceAppearsTwiceInSchedule {
         plugin_id: PluginInstanceID,
@@ -129,8 +157,25 @@ impl std::fmt::Display for VerifyScheduleError {
             VerifyScheduleError::BufferAppearsTwiceInSameTask { buffer_id, task_info } => {
                 write!(f, "Error detected in compiled audio graph: The buffer with ID {:?} appears more than once within the same task {}", buffer_id, task_info)
             }
-            VerifyScheduleError::BufferAppearsTwiceInParallelTasks { buffer_id } => {
-                write!(f, "Error detected in compiled audio graph: The buffer with ID {:?} appears more than once between the parallel tasks", buffer_id)
+            VerifyScheduleError::BufferAppearsTwiceInParallelTasks {
+                buffer_id,
+                task_a_info,
+                task_b_info,
+            } => {
+                write!(f, "Error detected in compiled audio graph: The buffer with ID {:?} appears more than once between the parallel tasks {} and {}", buffer_id, task_a_info, task_b_info)
+            }
+            VerifyScheduleError::DelayLineAppearsTwiceInParallelTasks {
+                task_a_info,
+                task_b_info,
+            } => {
+                write!(f, "Error detected in compiled audio graph: The same feedback delay line appears in the parallel tasks {} and {}", task_a_info, task_b_info)
+            }
+            VerifyScheduleError::InvalidTaskDependencies { task_info } => {
+                write!(
+                    f,
+                    "Error detected in compiled audio graph: Invalid dependencies for the task {}",
+                    task_info
+                )
             }
             VerifyScheduleError::PluginInstanceAppearsTwiceInSchedule { plugin_id } => {
                 write!(f, "Error detected in compiled audio graph: The plugin instance with ID {:?} appears more than once in the schedule", plugin_id)
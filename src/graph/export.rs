@@ -0,0 +1,390 @@
+//! Exporters for the audio graph and the compiled schedule, used for
+//! debugging delay compensation, sum insertion and buffer assignments.
+
+use dropseed_plugin_api::buffer::DebugBufferID;
+use dropseed_plugin_api::PluginInstanceID;
+use fnv::FnvHashSet;
+use std::fmt::Write;
+
+use crate::engine::modify_request::FeedbackDelay;
+
+use super::{Edge, PortType};
+
+/// The format to export the audio graph or the compiled schedule in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Graphviz DOT
+    Dot,
+    /// JSON
+    Json,
+}
+
+/// A description of a compiled schedule.
+#[derive(Debug, Clone)]
+pub struct ScheduleSnapshot {
+    /// The version of the schedule.
+    pub version: u64,
+
+    /// The buffers assigned to each channel of the audio graph input.
+    pub graph_in_audio: Vec<DebugBufferID>,
+    /// The buffers assigned to each channel of the audio graph output.
+    pub graph_out_audio: Vec<DebugBufferID>,
+    /// The buffers assigned to each note port of the audio graph input.
+    pub graph_in_note: Vec<DebugBufferID>,
+    /// The buffers assigned to each note port of the audio graph output.
+    pub graph_out_note: Vec<DebugBufferID>,
+    /// The buffer assigned to the automation port of the audio graph input.
+    pub graph_in_automation: Option<DebugBufferID>,
+
+    /// The tasks in the order they appear in the schedule.
+    pub tasks: Vec<ScheduledTask>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduledTask {
+    pub task_type: ScheduledTaskType,
+
+    /// The buffers this task reads from.
+    pub reads: Vec<DebugBufferID>,
+    /// The buffers this task writes to or clears.
+    pub writes: Vec<DebugBufferID>,
+
+    /// The indices of the tasks that must finish before this task can
+    /// start.
+    pub dependencies: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduledTaskType {
+    Plugin(PluginInstanceID),
+    /// A plugin that failed to load. Its main ports are passed through.
+    UnloadedPlugin(PluginInstanceID),
+    /// Sums the inputs into a single output.
+    Sum(PortType),
+    /// Delays the input by the given number of frames.
+    DelayComp {
+        port_type: PortType,
+        delay: u32,
+    },
+    /// Writes the source of a feedback edge into a delay line of the given
+    /// number of frames.
+    FeedbackSend {
+        delay: u32,
+    },
+    /// Reads the delayed signal of a feedback edge from its delay line.
+    FeedbackReturn {
+        delay: u32,
+    },
+}
+
+/// Render the audio graph with the given plugins and edges.
+pub fn export_graph(
+    plugin_ids: &[PluginInstanceID],
+    edges: &[Edge],
+    format: ExportFormat,
+) -> String {
+    match format {
+        ExportFormat::Dot => graph_to_dot(plugin_ids, edges),
+        ExportFormat::Json => graph_to_json(plugin_ids, edges),
+    }
+}
+
+impl ScheduleSnapshot {
+    /// Render this schedule.
+    pub fn export(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Dot => self.to_dot(),
+            ExportFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut s = String::new();
+
+        let _ = writeln!(s, "digraph schedule {{");
+        let _ = writeln!(s, "    label=\"schedule version {}\";", self.version);
+        let _ = writeln!(s, "    rankdir=LR;");
+        let _ = writeln!(s, "    node [shape=box];");
+
+        let _ = writeln!(
+            s,
+            "    graph_in [label=\"graph in\\nwrites: {}\"];",
+            buffer_list(&self.graph_in_buffers())
+        );
+        let _ = writeln!(
+            s,
+            "    graph_out [label=\"graph out\\nreads: {}\"];",
+            buffer_list(&[&self.graph_out_audio[..], &self.graph_out_note[..]].concat())
+        );
+
+        let graph_in_buffers: FnvHashSet<DebugBufferID> =
+            self.graph_in_buffers().into_iter().collect();
+        let graph_out_buffers: FnvHashSet<DebugBufferID> =
+            self.graph_out_audio.iter().chain(self.graph_out_note.iter()).copied().collect();
+
+        for (i, task) in self.tasks.iter().enumerate() {
+            let title = match &task.task_type {
+                ScheduledTaskType::Plugin(plugin_id) => escape(&format!("{:?}", plugin_id)),
+                ScheduledTaskType::UnloadedPlugin(plugin_id) => {
+                    escape(&format!("unloaded {:?}", plugin_id))
+                }
+                ScheduledTaskType::Sum(port_type) => format!("{:?} sum", port_type),
+                ScheduledTaskType::DelayComp { port_type, delay } => {
+                    format!("{:?} delay comp ({} frames)", port_type, delay)
+                }
+                ScheduledTaskType::FeedbackSend { delay } => {
+                    format!("feedback send ({} frames)", delay)
+                }
+                ScheduledTaskType::FeedbackReturn { delay } => {
+                    format!("feedback return ({} frames)", delay)
+                }
+            };
+
+            let _ = writeln!(
+                s,
+                "    t{} [label=\"{}: {}\\nreads: {}\\nwrites: {}\"];",
+                i,
+                i,
+                title,
+                buffer_list(&task.reads),
+                buffer_list(&task.writes)
+            );
+
+            for dependency in task.dependencies.iter() {
+                let _ = writeln!(s, "    t{} -> t{};", dependency, i);
+            }
+
+            if task.reads.iter().any(|b| graph_in_buffers.contains(b)) {
+                let _ = writeln!(s, "    graph_in -> t{};", i);
+            }
+            if task.writes.iter().any(|b| graph_out_buffers.contains(b)) {
+                let _ = writeln!(s, "    t{} -> graph_out;", i);
+            }
+        }
+
+        let _ = writeln!(s, "}}");
+
+        s
+    }
+
+    fn to_json(&self) -> String {
+        let mut s = String::new();
+
+        let _ = write!(
+            s,
+            "{{\"version\":{},\"graph_in_audio\":{},\"graph_out_audio\":{},\"graph_in_note\":{},\"graph_out_note\":{},\"graph_in_automation\":{},\"tasks\":[",
+            self.version,
+            buffers_to_json(&self.graph_in_audio),
+            buffers_to_json(&self.graph_out_audio),
+            buffers_to_json(&self.graph_in_note),
+            buffers_to_json(&self.graph_out_note),
+            buffers_to_json(&self.graph_in_automation.iter().copied().collect::<Vec<_>>())
+        );
+
+        for (i, task) in self.tasks.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+
+            let _ = write!(s, "{{\"index\":{},", i);
+            match &task.task_type {
+                ScheduledTaskType::Plugin(plugin_id) => {
+                    let _ =
+                        write!(s, "\"type\":\"plugin\",\"plugin\":{},", plugin_to_json(plugin_id));
+                }
+                ScheduledTaskType::UnloadedPlugin(plugin_id) => {
+                    let _ = write!(
+                        s,
+                        "\"type\":\"unloaded_plugin\",\"plugin\":{},",
+                        plugin_to_json(plugin_id)
+                    );
+                }
+                ScheduledTaskType::Sum(port_type) => {
+                    let _ = write!(
+                        s,
+                        "\"type\":\"sum\",\"port_type\":{},",
+                        port_type_to_json(*port_type)
+                    );
+                }
+                ScheduledTaskType::DelayComp { port_type, delay } => {
+                    let _ = write!(
+                        s,
+                        "\"type\":\"delay_comp\",\"port_type\":{},\"delay\":{},",
+                        port_type_to_json(*port_type),
+                        delay
+                    );
+                }
+                ScheduledTaskType::FeedbackSend { delay } => {
+                    let _ = write!(s, "\"type\":\"feedback_send\",\"delay\":{},", delay);
+                }
+                ScheduledTaskType::FeedbackReturn { delay } => {
+                    let _ = write!(s, "\"type\":\"feedback_return\",\"delay\":{},", delay);
+                }
+            }
+
+            let _ = write!(
+                s,
+                "\"reads\":{},\"writes\":{},\"dependencies\":[{}]}}",
+                buffers_to_json(&task.reads),
+                buffers_to_json(&task.writes),
+                task.dependencies.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(",")
+            );
+        }
+
+        s.push_str("]}");
+
+        s
+    }
+
+    /// All of the buffers written to by the audio graph input.
+    fn graph_in_buffers(&self) -> Vec<DebugBufferID> {
+        self.graph_in_audio
+            .iter()
+            .chain(self.graph_in_note.iter())
+            .chain(self.graph_in_automation.iter())
+            .copied()
+            .collect()
+    }
+}
+
+fn graph_to_dot(plugin_ids: &[PluginInstanceID], edges: &[Edge]) -> String {
+    let mut s = String::new();
+
+    let _ = writeln!(s, "digraph audio_graph {{");
+    let _ = writeln!(s, "    rankdir=LR;");
+    let _ = writeln!(s, "    node [shape=box];");
+
+    for plugin_id in plugin_ids.iter() {
+        let _ = writeln!(
+            s,
+            "    p{} [label=\"{}\"];",
+            plugin_id.unique_id(),
+            escape(&format!("{:?}", plugin_id))
+        );
+    }
+
+    for edge in edges.iter() {
+        let style = match edge.edge_type {
+            PortType::Audio => "solid",
+            PortType::Note => "dashed",
+            PortType::Automation => "dotted",
+        };
+
+        // Feedback edges should not affect the layout of the graph.
+        let (feedback_label, feedback_attrs) = if edge.feedback.is_some() {
+            (" (feedback)", ", color=red, constraint=false")
+        } else {
+            ("", "")
+        };
+
+        let _ = writeln!(
+            s,
+            "    p{} -> p{} [label=\"{:?} {}:{} -> {}:{}{}\", style={}{}];",
+            edge.src_plugin_id.unique_id(),
+            edge.dst_plugin_id.unique_id(),
+            edge.edge_type,
+            edge.src_port_id,
+            edge.src_port_channel,
+            edge.dst_port_id,
+            edge.dst_port_channel,
+            feedback_label,
+            style,
+            feedback_attrs
+        );
+    }
+
+    let _ = writeln!(s, "}}");
+
+    s
+}
+
+fn graph_to_json(plugin_ids: &[PluginInstanceID], edges: &[Edge]) -> String {
+    let mut s = String::new();
+
+    s.push_str("{\"plugins\":[");
+    for (i, plugin_id) in plugin_ids.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push_str(&plugin_to_json(plugin_id));
+    }
+
+    s.push_str("],\"edges\":[");
+    for (i, edge) in edges.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+
+        let _ = write!(
+            s,
+            "{{\"type\":{},\"src\":{{\"plugin\":{},\"port\":{},\"channel\":{}}},\"dst\":{{\"plugin\":{},\"port\":{},\"channel\":{}}},\"feedback\":{}}}",
+            port_type_to_json(edge.edge_type),
+            edge.src_plugin_id.unique_id(),
+            edge.src_port_id,
+            edge.src_port_channel,
+            edge.dst_plugin_id.unique_id(),
+            edge.dst_port_id,
+            edge.dst_port_channel,
+            feedback_to_json(edge.feedback)
+        );
+    }
+    s.push_str("]}");
+
+    s
+}
+
+fn buffer_list(buffers: &[DebugBufferID]) -> String {
+    buffers.iter().map(|b| format!("{:?}", b)).collect::<Vec<_>>().join(", ")
+}
+
+fn buffers_to_json(buffers: &[DebugBufferID]) -> String {
+    let buffers: Vec<String> = buffers
+        .iter()
+        .map(|b| format!("{{\"type\":\"{:?}\",\"index\":{}}}", b.buffer_type, b.index))
+        .collect();
+
+    format!("[{}]", buffers.join(","))
+}
+
+fn plugin_to_json(plugin_id: &PluginInstanceID) -> String {
+    format!(
+        "{{\"id\":{},\"format\":\"{:?}\",\"rdn\":\"{}\"}}",
+        plugin_id.unique_id(),
+        plugin_id.format(),
+        escape(plugin_id.rdn())
+    )
+}
+
+fn feedback_to_json(feedback: Option<FeedbackDelay>) -> String {
+    match feedback {
+        None => "null".into(),
+        Some(FeedbackDelay::OneBlock) => "\"one_block\"".into(),
+        Some(FeedbackDelay::Frames(frames)) => format!("{{\"frames\":{}}}", frames),
+    }
+}
+
+fn port_type_to_json(port_type: PortType) -> &'static str {
+    match port_type {
+        PortType::Audio => "\"audio\"",
+        PortType::Note => "\"note\"",
+        PortType::Automation => "\"automation\"",
+    }
+}
+
+/// Escape a string so it can be used inside of a quoted string in both
+/// DOT and JSON.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,22 @@
+use fnv::FnvHashMap;
+
+use crate::processor_schedule::tasks::SharedAudioFeedbackNode;
+
+use super::super::DSEdgeID;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FeedbackKey {
+    pub edge: DSEdgeID,
+    pub delay: u32,
+}
+
+/// The delay lines of the feedback edges in the graph.
+pub(crate) struct FeedbackNodePool {
+    pub audio: FnvHashMap<FeedbackKey, SharedAudioFeedbackNode>,
+}
+
+impl FeedbackNodePool {
+    pub fn new() -> Self {
+        Self { audio: FnvHashMap::default() }
+    }
+}
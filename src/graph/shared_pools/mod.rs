@@ -1,17 +1,22 @@
 mod buffer_pool;
 mod delay_comp_node_pool;
+mod feedback_node_pool;
 mod plugin_host_pool;
 mod shared_schedule;
 mod transport_pool;
 
 pub(crate) use buffer_pool::SharedBufferPool;
 pub(crate) use delay_comp_node_pool::{DelayCompKey, DelayCompNodePool};
+pub(crate) use feedback_node_pool::{FeedbackKey, FeedbackNodePool};
 pub(crate) use plugin_host_pool::PluginHostPool;
 pub(crate) use shared_schedule::SharedProcessorSchedule;
-pub(crate) use transport_pool::{SharedTransportTask, TransportPool};
+pub(crate) use transport_pool::{SharedSteadyClock, SharedTransportTask, TransportPool};
+
+use std::sync::Arc;
 
 use crate::{
-    processor_schedule::{tasks::TransportTask, ProcessorSchedule},
+    engine::telemetry::SharedEngineTelemetry,
+    processor_schedule::{parallel::WorkerPoolHandle, tasks::TransportTask, ProcessorSchedule},
     utils::thread_id::SharedThreadIDs,
 };
 
@@ -19,9 +24,14 @@ pub(super) struct GraphSharedPools {
     pub shared_schedule: SharedProcessorSchedule,
 
     pub buffers: SharedBufferPool,
-    pub plugin_hosts: PluginHostPool,
     pub delay_comp_nodes: DelayCompNodePool,
+    pub feedback_nodes: FeedbackNodePool,
     pub transports: TransportPool,
+
+    /// The pool of worker threads used to process the schedule in parallel.
+    pub worker_pool: Option<WorkerPoolHandle>,
+
+    pub telemetry: Arc<SharedEngineTelemetry>,
 }
 
 impl GraphSharedPools {
@@ -31,7 +41,10 @@ impl GraphSharedPools {
         note_buffer_size: usize,
         event_buffer_size: usize,
         transport: TransportTask,
+        steady_clock: SharedSteadyClock,
         schedule_version: u64,
+        worker_pool: Option<WorkerPoolHandle>,
+        telemetry: Arc<SharedEngineTelemetry>,
         coll_handle: basedrop::Handle,
     ) -> (Self, SharedProcessorSchedule) {
         let shared_transport_task = SharedTransportTask::new(transport, &coll_handle);
@@ -39,7 +52,9 @@ impl GraphSharedPools {
         let empty_schedule = ProcessorSchedule::new_empty(
             audio_buffer_size,
             shared_transport_task.clone(),
+            steady_clock.clone(),
             Vec::new(),
+            Arc::clone(&telemetry),
             schedule_version,
         );
 
@@ -55,9 +70,11 @@ impl GraphSharedPools {
                     event_buffer_size,
                     coll_handle,
                 ),
-                plugin_hosts: PluginHostPool::new(),
                 delay_comp_nodes: DelayCompNodePool::new(),
-                transports: TransportPool { transport: shared_transport_task },
+                feedback_nodes: FeedbackNodePool::new(),
+                transports: TransportPool { transport: shared_transport_task, steady_clock },
+                worker_pool,
+                telemetry,
             },
             shared_schedule_clone,
         )
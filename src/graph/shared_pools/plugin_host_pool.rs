@@ -1,4 +1,3 @@
-use audio_graph::NodeID;
 use dropseed_plugin_api::PluginInstanceID;
 use fnv::FnvHashMap;
 
@@ -6,26 +5,46 @@ use crate::plugin_host::PluginHostMainThread;
 
 pub(crate) struct PluginHostPool {
     pool: FnvHashMap<u64, PluginHostMainThread>,
-    node_id_to_plugin_id: FnvHashMap<NodeID, PluginInstanceID>,
+
+    /// Plugins that are still being removed, but whose ID has been reused
+    /// by a new plugin (i.e. when undoing their removal), from oldest to
+    /// newest.
+    replaced: Vec<PluginHostMainThread>,
 }
 
 impl PluginHostPool {
     pub fn new() -> Self {
-        Self { pool: FnvHashMap::default(), node_id_to_plugin_id: FnvHashMap::default() }
+        Self { pool: FnvHashMap::default(), replaced: Vec::new() }
     }
 
+    /// Insert a new plugin host.
+    ///
+    /// If a plugin with the same ID is still being removed, then it is kept
+    /// around until it is ready to be removed. This returns the old host if
+    /// a plugin with the same ID exists that is not being removed.
     pub fn insert(
         &mut self,
         id: PluginInstanceID,
         host: PluginHostMainThread,
     ) -> Option<PluginHostMainThread> {
-        let old_host = self.pool.insert(id.unique_id(), host);
-        self.node_id_to_plugin_id.insert(id._node_id().into(), id);
-        old_host
+        match self.pool.insert(id.unique_id(), host) {
+            Some(old_host) if old_host.is_remove_requested() => {
+                self.replaced.push(old_host);
+                None
+            }
+            res => res,
+        }
     }
 
+    /// Remove the oldest host with the given ID.
+    ///
+    /// A plugin is always ready to be removed before any plugin that
+    /// reused its ID, since it was removed from the graph first.
     pub fn remove(&mut self, id: &PluginInstanceID) -> Option<PluginHostMainThread> {
-        self.node_id_to_plugin_id.remove(&id._node_id().into());
+        if let Some(i) = self.replaced.iter().position(|host| host.id() == id) {
+            return Some(self.replaced.remove(i));
+        }
+
         self.pool.remove(&id.unique_id())
     }
 
@@ -37,28 +56,28 @@ impl PluginHostPool {
         self.pool.get_mut(&id.unique_id())
     }
 
-    pub fn get_by_node_id(&self, id: &NodeID) -> Option<&PluginHostMainThread> {
-        self.node_id_to_plugin_id.get(id).map(|id| self.pool.get(&id.unique_id()).unwrap())
-    }
-
     pub fn get_by_unique_id_mut(&mut self, id: u64) -> Option<&mut PluginHostMainThread> {
         self.pool.get_mut(&id)
     }
 
     pub fn num_plugins(&self) -> usize {
-        self.pool.len()
+        self.pool.len() + self.replaced.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'_ PluginHostMainThread> {
+        self.pool.values().chain(self.replaced.iter())
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &'_ mut PluginHostMainThread> {
-        self.pool.values_mut()
+        self.pool.values_mut().chain(self.replaced.iter_mut())
     }
 
     pub fn clear(&mut self) {
         self.pool.clear();
-        self.node_id_to_plugin_id.clear();
+        self.replaced.clear();
     }
 
     pub fn is_empty(&self) -> bool {
-        self.pool.is_empty()
+        self.pool.is_empty() && self.replaced.is_empty()
     }
 }
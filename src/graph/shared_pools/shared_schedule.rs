@@ -1,7 +1,8 @@
 use atomic_refcell::AtomicRefCell;
 use basedrop::{Shared, SharedCell};
+use dropseed_plugin_api::ProcInfo;
 
-use crate::processor_schedule::ProcessorSchedule;
+use crate::processor_schedule::{GraphAutomationEvent, GraphNoteEvent, ProcessorSchedule};
 use crate::utils::thread_id::SharedThreadIDs;
 
 // Required so we can send the schedule from the main thread to the process
@@ -22,6 +23,8 @@ unsafe impl Send for ProcessorSchedule {}
 // schedules with them. The main thread never dereferences these pointers.
 unsafe impl Sync for ProcessorSchedule {}
 
+// Only one of the clones may be used to process the schedule at a time.
+#[derive(Clone)]
 pub(crate) struct SharedProcessorSchedule {
     pub(super) schedule: Shared<SharedCell<AtomicRefCell<ProcessorSchedule>>>,
     thread_ids: SharedThreadIDs,
@@ -64,11 +67,52 @@ impl SharedProcessorSchedule {
         self.schedule.set(Shared::new(coll_handle, AtomicRefCell::new(schedule)));
     }
 
-    pub fn process_interleaved(&mut self, audio_in: &[f32], audio_out: &mut [f32]) {
+    pub fn process_interleaved(
+        &mut self,
+        frames: usize,
+        audio_in: &[f32],
+        audio_out: &mut [f32],
+        note_in: &[GraphNoteEvent],
+        automation_in: &[GraphAutomationEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+    ) {
+        let latest_schedule = self.schedule.get();
+
+        let mut schedule = latest_schedule.borrow_mut();
+
+        self.update_process_thread_id();
+
+        schedule.process_interleaved(frames, audio_in, audio_out, note_in, automation_in, note_out);
+    }
+
+    /// Process `total_frames` frames of the schedule, where `read_in` and
+    /// `write_out` copy the audio straight into and out of the buffers of
+    /// the graph input and output nodes.
+    ///
+    /// See `ProcessorSchedule::process_with()` for more details.
+    pub fn process_with<I, O>(
+        &mut self,
+        total_frames: usize,
+        note_in: &[GraphNoteEvent],
+        automation_in: &[GraphAutomationEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+        read_in: I,
+        write_out: O,
+    ) where
+        I: FnMut(usize, usize, &mut [f32]),
+        O: FnMut(usize, usize, &[f32]),
+    {
         let latest_schedule = self.schedule.get();
 
         let mut schedule = latest_schedule.borrow_mut();
 
+        self.update_process_thread_id();
+
+        schedule.process_with(total_frames, note_in, automation_in, note_out, read_in, write_out);
+    }
+
+    /// Make sure the current thread is registered as the process thread.
+    fn update_process_thread_id(&self) {
         if let Some(process_thread_id) = self.thread_ids.process_thread_id() {
             if std::thread::current().id() != process_thread_id {
                 self.thread_ids
@@ -77,8 +121,30 @@ impl SharedProcessorSchedule {
         } else {
             self.thread_ids.set_process_thread_id(std::thread::current().id(), &self.coll_handle);
         }
+    }
+
+    /// Process a single block of the schedule of the inner graph of a rack.
+    ///
+    /// Unlike the other process methods, this does not register the current
+    /// thread as the process thread, since this is called from within the
+    /// schedule of the outer graph (which may be running in a worker
+    /// thread).
+    pub fn process_nested<I, O>(
+        &mut self,
+        outer_proc_info: &ProcInfo,
+        note_in: &[GraphNoteEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+        read_in: I,
+        write_out: O,
+    ) where
+        I: FnMut(usize, &mut [f32]),
+        O: FnMut(usize, &[f32]),
+    {
+        let latest_schedule = self.schedule.get();
+
+        let mut schedule = latest_schedule.borrow_mut();
 
-        schedule.process_interleaved(audio_in, audio_out);
+        schedule.process_nested(outer_proc_info, note_in, note_out, read_in, write_out);
     }
 
     pub fn deactivate(&mut self) {
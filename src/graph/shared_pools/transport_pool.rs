@@ -1,5 +1,6 @@
 use atomic_refcell::{AtomicRefCell, AtomicRefMut};
 use basedrop::Shared;
+use std::sync::atomic::{AtomicI64, Ordering};
 
 use crate::processor_schedule::tasks::TransportTask;
 
@@ -18,7 +19,30 @@ impl SharedTransportTask {
     }
 }
 
+/// A steady sample clock that keeps counting for as long as the engine is
+/// running, regardless of the state of the transport. It is shared by every
+/// schedule so that it survives recompiles.
+#[derive(Clone)]
+pub struct SharedSteadyClock {
+    shared: Shared<AtomicI64>,
+}
+
+impl SharedSteadyClock {
+    pub fn new(coll_handle: &basedrop::Handle) -> Self {
+        Self { shared: Shared::new(coll_handle, AtomicI64::new(0)) }
+    }
+
+    /// Returns the current time of the clock, and then advances the clock
+    /// by the given number of frames.
+    pub fn advance(&self, frames: usize) -> i64 {
+        // Only the process thread ever advances the clock.
+        self.shared.fetch_add(frames as i64, Ordering::Relaxed)
+    }
+}
+
 pub(crate) struct TransportPool {
     // TODO: Add the ability to have more than one tranport.
     pub transport: SharedTransportTask,
+
+    pub steady_clock: SharedSteadyClock,
 }
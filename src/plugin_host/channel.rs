@@ -8,18 +8,21 @@ use dropseed_plugin_api::{
     ParamID, PluginProcessor,
 };
 use std::sync::{
-    atomic::{AtomicBool, AtomicU32, Ordering},
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     Arc,
 };
+use std::time::Duration;
 
 use dropseed_plugin_api::automation::AutomationIoEventType;
 
+use crate::engine::telemetry::{ProcessTimeCounter, ProcessTimes};
 use crate::utils::reducing_queue::{
     ReducFnvConsumer, ReducFnvProducer, ReducFnvValue, ReducingFnvQueue,
 };
 use crate::utils::thread_id::SharedThreadIDs;
 
 use super::processor::PluginHostProcessor;
+use super::watchdog::{ProcessWatchdog, PEAK_LOAD_SCALE};
 
 pub(super) struct PlugHostChannelMainThread {
     pub param_queues: Option<ParamQueuesMainThread>,
@@ -46,6 +49,7 @@ impl PlugHostChannelMainThread {
         thread_ids: SharedThreadIDs,
         schedule_version: u64,
         bypass_declick_frames: usize,
+        watchdog: Option<ProcessWatchdog>,
         coll_handle: &basedrop::Handle,
     ) {
         let (param_queues_main_thread, param_queues_proc_thread) = if num_params > 0 {
@@ -88,6 +92,7 @@ impl PlugHostChannelMainThread {
                 thread_ids,
                 schedule_version,
                 bypass_declick_frames,
+                watchdog,
             ),
             coll_handle,
         );
@@ -226,6 +231,8 @@ pub(crate) struct SharedPluginHostState {
     process_requested: AtomicBool,
     param_flush_requested: AtomicBool,
     bypassed: AtomicBool,
+    process_times: ProcessTimeCounter,
+    watchdog_peak_load: AtomicU64,
 }
 
 impl SharedPluginHostState {
@@ -235,6 +242,8 @@ impl SharedPluginHostState {
             process_requested: AtomicBool::new(false),
             param_flush_requested: AtomicBool::new(false),
             bypassed: AtomicBool::new(bypassed),
+            process_times: ProcessTimeCounter::default(),
+            watchdog_peak_load: AtomicU64::new(0),
         }
     }
 
@@ -270,6 +279,27 @@ impl SharedPluginHostState {
     pub fn set_bypassed(&self, bypassed: bool) {
         self.bypassed.store(bypassed, Ordering::SeqCst);
     }
+
+    pub fn record_process_time(&self, elapsed: Duration) {
+        self.process_times.record(elapsed);
+    }
+
+    pub fn take_process_times(&self) -> ProcessTimes {
+        self.process_times.take()
+    }
+
+    pub fn set_watchdog_triggered(&self, peak_load: f64) {
+        // Make sure a triggered watchdog is never stored as `0`.
+        let peak_load = ((peak_load * PEAK_LOAD_SCALE) as u64).max(1);
+        self.watchdog_peak_load.store(peak_load, Ordering::SeqCst);
+    }
+
+    /// Returns the peak load of the plugin if the watchdog has been
+    /// triggered since the last call.
+    pub fn watchdog_triggered(&self) -> Option<f64> {
+        let peak_load = self.watchdog_peak_load.swap(0, Ordering::SeqCst);
+        (peak_load != 0).then(|| peak_load as f64 / PEAK_LOAD_SCALE)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -21,6 +21,7 @@ use raw_window_handle::RawWindowHandle;
 use smallvec::SmallVec;
 use std::error::Error;
 
+use crate::engine::telemetry::ProcessTimes;
 use crate::engine::{timer_wheel::EngineTimerWheel, OnIdleEvent, PluginActivatedStatus};
 use crate::graph::{DSEdgeID, PortChannelID};
 use crate::utils::thread_id::SharedThreadIDs;
@@ -32,6 +33,7 @@ use super::channel::{
 use super::error::{ActivatePluginError, RescanParamListError, SetParamValueError};
 use super::event_io_buffers::{PluginEventOutputSanitizer, PluginIoEvent};
 use super::processor::BYPASS_DECLICK_SECS;
+use super::watchdog::{PluginWatchdogSettings, ProcessWatchdog, WatchdogAction};
 use super::PluginHostProcessorWrapper;
 
 mod sync_ports;
@@ -221,6 +223,18 @@ impl PluginHostMainThread {
         self.save_state_dirty
     }
 
+    /// Returns `true` if this plugin has been scheduled to be removed from
+    /// the audio graph.
+    pub(crate) fn is_remove_requested(&self) -> bool {
+        self.remove_requested
+    }
+
+    /// Return the time spent in this plugin's process method since the last
+    /// call, and reset the counter.
+    pub(crate) fn take_process_times(&self) -> ProcessTimes {
+        self.channel.shared_state.take_process_times()
+    }
+
     /// Collect the save state of this plugin.
     pub fn collect_save_state(&mut self) -> DSPluginSaveState {
         if self.save_state_dirty {
@@ -646,6 +660,32 @@ impl PluginHostMainThread {
         plug_proc_to_drop
     }
 
+    /// Returns `true` if this plugin is waiting for the process thread to
+    /// drop its processor.
+    pub(crate) fn is_waiting_to_drop(&self) -> bool {
+        self.channel.shared_state.get_active_state() == PluginActiveState::WaitingToDrop
+    }
+
+    /// Fully deactivate this plugin after its processor has been dropped in
+    /// the process thread.
+    ///
+    /// Unlike deactivating the plugin in `Self::on_idle()`, this does not
+    /// mark the plugin as deactivated in its save state.
+    ///
+    /// This returns `false` if the processor has not been dropped yet.
+    pub(crate) fn finish_deactivate(&mut self) -> bool {
+        if self.channel.shared_state.get_active_state()
+            != PluginActiveState::DroppedAndReadyToDeactivate
+        {
+            return false;
+        }
+
+        self.plug_main_thread.deactivate();
+        self.channel.shared_state.set_active_state(PluginActiveState::Inactive);
+
+        true
+    }
+
     /// Schedule this plugin to be removed.
     ///
     /// This plugin will not be fully removed/dropped until the plugin host's
@@ -701,6 +741,7 @@ impl PluginHostMainThread {
         edge_id_to_ds_edge_id: &mut FnvHashMap<EdgeID, DSEdgeID>,
         thread_ids: SharedThreadIDs,
         schedule_version: u64,
+        watchdog: Option<PluginWatchdogSettings>,
         coll_handle: &basedrop::Handle,
     ) -> Result<PluginActivatedStatus, ActivatePluginError> {
         // Return an error if this plugin cannot be activated right now.
@@ -846,6 +887,7 @@ impl PluginHostMainThread {
                     thread_ids,
                     sched_version,
                     bypass_declick_frames,
+                    watchdog.map(|s| ProcessWatchdog::new(s, sample_rate)),
                     coll_handle,
                 );
 
@@ -1070,6 +1112,7 @@ impl PluginHostMainThread {
         edge_id_to_ds_edge_id: &mut FnvHashMap<EdgeID, DSEdgeID>,
         thread_ids: &SharedThreadIDs,
         schedule_version: u64,
+        watchdog: Option<PluginWatchdogSettings>,
         engine_timer: &mut EngineTimerWheel,
     ) -> (OnIdleResult, SmallVec<[ParamModifiedInfo; 4]>, Option<Shared<PluginHostProcessorWrapper>>)
     {
@@ -1115,6 +1158,29 @@ impl PluginHostMainThread {
             });
         }
 
+        if let Some(peak_load) = self.channel.shared_state.watchdog_triggered() {
+            let action = watchdog.map(|w| w.action).unwrap_or(WatchdogAction::Bypass);
+
+            log::warn!(
+                "Plugin {:?} was disabled by the watchdog with a peak load of {:.2}",
+                &self.id,
+                peak_load
+            );
+
+            if action == WatchdogAction::Bypass && !self.save_state.bypassed {
+                // Keep the save state in sync so that the user can unbypass the
+                // plugin with `set_bypassed()`.
+                self.save_state.bypassed = true;
+                self.save_state_dirty = true;
+            }
+
+            events_out.push(OnIdleEvent::PluginDisabledByWatchdog {
+                plugin_id: self.id.clone(),
+                action,
+                peak_load,
+            });
+        }
+
         // More often than not, these flags will be empty. So optimize by only checking
         // individual flags when necessary.
         if !request_flags.is_empty() {
@@ -1311,6 +1377,7 @@ impl PluginHostMainThread {
                         edge_id_to_ds_edge_id,
                         thread_ids.clone(),
                         schedule_version,
+                        watchdog,
                         coll_handle,
                     ) {
                         Ok(r) => {
@@ -1348,6 +1415,7 @@ impl PluginHostMainThread {
                         edge_id_to_ds_edge_id,
                         thread_ids.clone(),
                         schedule_version,
+                        watchdog,
                         coll_handle,
                     ) {
                         Ok(r) => {
@@ -3,11 +3,14 @@ pub mod error;
 mod channel;
 mod main_thread;
 mod processor;
+mod watchdog;
 
 pub(crate) mod event_io_buffers;
 pub(crate) mod external;
 
+pub use event_io_buffers::{NoteIoEvent, NoteIoEventType};
 pub use main_thread::{ParamModifiedInfo, ParamState, PluginHostMainThread};
+pub use watchdog::{PluginWatchdogSettings, WatchdogAction};
 
 pub(crate) use channel::{PluginHostProcessorWrapper, SharedPluginHostProcessor};
 pub(crate) use main_thread::OnIdleResult;
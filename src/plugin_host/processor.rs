@@ -2,11 +2,13 @@ use clack_host::events::Event;
 use dropseed_plugin_api::buffer::EventBuffer;
 use dropseed_plugin_api::{PluginProcessor, ProcBuffers, ProcInfo, ProcessStatus};
 use meadowlark_core_types::time::Seconds;
+use std::time::Instant;
 
 use crate::utils::thread_id::SharedThreadIDs;
 
 use super::channel::{PlugHostChannelProcThread, PluginActiveState};
 use super::event_io_buffers::{PluginEventIoBuffers, PluginEventOutputSanitizer};
+use super::watchdog::{ProcessWatchdog, WatchdogAction};
 
 // The amount of time to smooth/declick the audio outputs when
 // bypassing/unbypassing the plugin.
@@ -42,6 +44,10 @@ pub(crate) struct PluginHostProcessor {
     bypass_declick_inc: f32,
     bypass_declick_frames: usize,
     bypass_declick_frames_left: usize,
+
+    watchdog: Option<ProcessWatchdog>,
+    /// Whether the plugin is currently bypassed by the watchdog.
+    watchdog_bypassed: bool,
 }
 
 impl PluginHostProcessor {
@@ -53,6 +59,7 @@ impl PluginHostProcessor {
         thread_ids: SharedThreadIDs,
         schedule_version: u64,
         bypass_declick_frames: usize,
+        watchdog: Option<ProcessWatchdog>,
     ) -> Self {
         debug_assert_ne!(bypass_declick_frames, 0);
 
@@ -75,6 +82,8 @@ impl PluginHostProcessor {
             bypass_declick_inc,
             bypass_declick_frames,
             bypass_declick_frames_left: 0,
+            watchdog,
+            watchdog_bypassed: false,
         }
     }
 
@@ -151,6 +160,17 @@ impl PluginHostProcessor {
             do_process = false;
         }
 
+        // --- Check if the plugin has been bypassed by the watchdog -----------------------------
+
+        if self.watchdog_bypassed {
+            if self.channel.shared_state.bypassed() {
+                // Don't call the plugin again until the user unbypasses it.
+                do_process = false;
+            } else {
+                self.watchdog_bypassed = false;
+            }
+        }
+
         // --- Check if the plugin should be put to sleep ----------------------------------------
 
         if self.processing_state == ProcessingState::Started(ProcessStatus::ContinueIfNotQuiet)
@@ -196,6 +216,8 @@ impl PluginHostProcessor {
         self.out_events.clear();
 
         if do_process {
+            let process_start = Instant::now();
+
             let new_status =
                 if let Some(automation_out_buffer) = &mut event_buffers.automation_out_buffer {
                     let automation_out_buffer = &mut *automation_out_buffer.borrow_mut();
@@ -216,6 +238,9 @@ impl PluginHostProcessor {
                     )
                 };
 
+            let process_time = process_start.elapsed();
+            self.channel.shared_state.record_process_time(process_time);
+
             // --- Update the processing state -------------------------------------------------------
 
             self.processing_state = match new_status {
@@ -232,6 +257,27 @@ impl PluginHostProcessor {
                 }
                 good_status => ProcessingState::Started(good_status),
             };
+
+            // --- Check if the plugin has blown its realtime deadline too often -----------------
+
+            if let Some(watchdog) = &mut self.watchdog {
+                if let Some(peak_load) = watchdog.check(proc_info.frames, process_time) {
+                    match watchdog.action() {
+                        WatchdogAction::Bypass => {
+                            self.channel.shared_state.set_bypassed(true);
+                            self.watchdog_bypassed = true;
+                        }
+                        WatchdogAction::Sleep => {
+                            if let ProcessingState::Started(_) = self.processing_state {
+                                self.plugin_processor.stop_processing();
+                                self.processing_state = ProcessingState::Stopped;
+                            }
+                        }
+                    }
+
+                    self.channel.shared_state.set_watchdog_triggered(peak_load);
+                }
+            }
         } else {
             buffers.clear_all_outputs(proc_info);
 
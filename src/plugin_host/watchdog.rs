@@ -0,0 +1,113 @@
+use meadowlark_core_types::time::SampleRate;
+use std::time::Duration;
+
+/// Peak load values are stored in the shared state as fixed-point numbers
+/// with this scale.
+pub(super) static PEAK_LOAD_SCALE: f64 = 1_000_000.0;
+
+/// What to do with a plugin that keeps blowing its realtime deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Bypass the plugin and stop calling its process method until the
+    /// user unbypasses it with `PluginHostMainThread::set_bypassed()`.
+    Bypass,
+
+    /// Put the plugin to sleep. The plugin will be woken up again the next
+    /// time it receives a note event or it requests to be processed.
+    Sleep,
+}
+
+/// The settings for the watchdog that disables plugins which take too long
+/// to process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PluginWatchdogSettings {
+    /// A process call counts as an overrun when the plugin spends more than
+    /// this fraction of the duration of the block in its process method.
+    ///
+    /// By default this is set to `0.5`.
+    pub max_block_fraction: f64,
+
+    /// The plugin is disabled once it has overrun this many times within
+    /// `window_blocks` process calls.
+    ///
+    /// By default this is set to `8`.
+    pub max_overruns: u32,
+
+    /// The number of process calls over which overruns are counted.
+    ///
+    /// By default this is set to `64`.
+    pub window_blocks: u32,
+
+    /// What to do with the offending plugin.
+    ///
+    /// By default this is set to `WatchdogAction::Bypass`.
+    pub action: WatchdogAction,
+}
+
+impl Default for PluginWatchdogSettings {
+    fn default() -> Self {
+        Self {
+            max_block_fraction: 0.5,
+            max_overruns: 8,
+            window_blocks: 64,
+            action: WatchdogAction::Bypass,
+        }
+    }
+}
+
+/// The process thread's side of the watchdog of a single plugin.
+pub(super) struct ProcessWatchdog {
+    settings: PluginWatchdogSettings,
+    ns_per_frame: f64,
+
+    num_blocks: u32,
+    num_overruns: u32,
+    peak_load: f64,
+}
+
+impl ProcessWatchdog {
+    pub fn new(settings: PluginWatchdogSettings, sample_rate: SampleRate) -> Self {
+        Self {
+            settings,
+            ns_per_frame: 1_000_000_000.0 / sample_rate.as_f64(),
+            num_blocks: 0,
+            num_overruns: 0,
+            peak_load: 0.0,
+        }
+    }
+
+    pub fn action(&self) -> WatchdogAction {
+        self.settings.action
+    }
+
+    /// Called after every call to the plugin's process method.
+    ///
+    /// Returns the highest load of the plugin in the current window if the
+    /// plugin should be disabled.
+    pub fn check(&mut self, frames: usize, elapsed: Duration) -> Option<f64> {
+        let load = elapsed.as_nanos() as f64 / (frames.max(1) as f64 * self.ns_per_frame);
+
+        self.num_blocks += 1;
+        if load > self.settings.max_block_fraction {
+            self.num_overruns += 1;
+            self.peak_load = self.peak_load.max(load);
+        }
+
+        if self.num_overruns >= self.settings.max_overruns.max(1) {
+            let peak_load = self.peak_load;
+            self.reset();
+            Some(peak_load)
+        } else {
+            if self.num_blocks >= self.settings.window_blocks {
+                self.reset();
+            }
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.num_blocks = 0;
+        self.num_overruns = 0;
+        self.peak_load = 0.0;
+    }
+}
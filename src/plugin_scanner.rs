@@ -8,8 +8,8 @@ use walkdir::WalkDir;
 
 use dropseed_plugin_api::plugin_scanner::{PluginFormat, ScannedPluginKey};
 use dropseed_plugin_api::{
-    DSPluginSaveState, HostInfo, HostRequestChannelReceiver, PluginDescriptor, PluginFactory,
-    PluginInstanceID, PluginInstanceType,
+    DSPluginSaveState, HostInfo, HostRequestChannelReceiver, HostRequestChannelSender,
+    PluginDescriptor, PluginFactory, PluginInstanceID, PluginInstanceType, PluginMainThread,
 };
 
 use crate::engine::error::NewPluginInstanceError;
@@ -322,10 +322,16 @@ impl PluginScanner {
         ScanExternalPluginsRes { scanned_plugins, failed_plugins }
     }
 
+    /// Create a new plugin instance from the given save state.
+    ///
+    /// If `unique_id` is `Some`, then the new instance reuses that unique
+    /// ID instead of getting a new one (i.e. when undoing the removal of a
+    /// plugin).
     pub(crate) fn create_plugin(
         &mut self,
         mut save_state: DSPluginSaveState,
         node_id: NodeID,
+        unique_id: Option<u64>,
         fallback_to_other_formats: bool,
     ) -> CreatePluginResult {
         // TODO: return an actual result
@@ -458,11 +464,10 @@ impl PluginScanner {
 
             let id = PluginInstanceID::_new(
                 node_id.into(),
-                self.next_plug_unique_id,
+                self.unique_id(unique_id),
                 format,
                 Shared::clone(&plugin_factory.shared_rdn),
             );
-            self.next_plug_unique_id += 1;
 
             let plug_main_thread = match plugin_factory.factory.instantiate(
                 channel_send,
@@ -501,8 +506,7 @@ impl PluginScanner {
         } else {
             let rdn = Shared::new(&self.coll_handle, save_state.key.rdn.clone());
 
-            let id = PluginInstanceID::_new(node_id.into(), self.next_plug_unique_id, format, rdn);
-            self.next_plug_unique_id += 1;
+            let id = PluginInstanceID::_new(node_id.into(), self.unique_id(unique_id), format, rdn);
 
             if status.is_ok() {
                 status = Err(NewPluginInstanceError::NotFound(save_state.key.rdn.clone()));
@@ -527,6 +531,79 @@ impl PluginScanner {
         CreatePluginResult { plugin_host, status }
     }
 
+    /// Create a plugin that is implemented by the engine itself (i.e. a
+    /// rack) instead of by a plugin factory.
+    ///
+    /// See `Self::create_plugin()` for details on `unique_id`.
+    pub(crate) fn create_engine_plugin<F>(
+        &mut self,
+        save_state: DSPluginSaveState,
+        node_id: NodeID,
+        unique_id: Option<u64>,
+        create: F,
+    ) -> CreatePluginResult
+    where
+        F: FnOnce(
+            &PluginInstanceID,
+            HostRequestChannelSender,
+        ) -> Result<Box<dyn PluginMainThread>, String>,
+    {
+        let (host_request_rx, channel_send) =
+            HostRequestChannelReceiver::new_channel(self.thread_ids.main_thread_id().unwrap());
+
+        let rdn = Shared::new(&self.coll_handle, save_state.key.rdn.clone());
+
+        let id = PluginInstanceID::_new(
+            node_id.into(),
+            self.unique_id(unique_id),
+            save_state.key.format.into(),
+            rdn,
+        );
+
+        let (plug_main_thread, status, loaded) = match (create)(&id, channel_send) {
+            Ok(plug_main_thread) => (plug_main_thread, Ok(()), true),
+            Err(e) => {
+                let plug_main_thread: Box<dyn PluginMainThread> =
+                    Box::new(MissingPluginMainThread::new(
+                        save_state.key.clone(),
+                        save_state.backup_audio_ports_ext.clone(),
+                        save_state.backup_note_ports_ext.clone(),
+                    ));
+
+                (
+                    plug_main_thread,
+                    Err(NewPluginInstanceError::FactoryFailedToCreateNewInstance(
+                        save_state.key.rdn.clone(),
+                        e,
+                    )),
+                    false,
+                )
+            }
+        };
+
+        let plugin_host = PluginHostMainThread::new(
+            id,
+            save_state,
+            plug_main_thread,
+            host_request_rx,
+            loaded,
+            &self.coll_handle,
+        );
+
+        CreatePluginResult { plugin_host, status }
+    }
+
+    /// Returns the given unique ID to reuse, or allocates a new one.
+    fn unique_id(&mut self, reuse: Option<u64>) -> u64 {
+        if let Some(unique_id) = reuse {
+            return unique_id;
+        }
+
+        let unique_id = self.next_plug_unique_id;
+        self.next_plug_unique_id += 1;
+        unique_id
+    }
+
     pub(crate) fn unload_unused_binaries(&mut self) {
         // TODO: Unload all external plugin binaries that are no longer being
         // used. (Perhaps by counting how many references are left in the `Shared`
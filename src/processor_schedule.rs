@@ -1,21 +1,34 @@
 use basedrop::Shared;
 use dropseed_plugin_api::ProcInfo;
 use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Instant;
 
+pub(crate) mod parallel;
 pub(crate) mod tasks;
 
-pub use tasks::TransportHandle;
+pub use tasks::{GraphAutomationEvent, GraphNoteEvent, TransportHandle, TransportSaveState};
 
-use crate::{graph::shared_pools::SharedTransportTask, plugin_host::PluginHostProcessorWrapper};
+use crate::engine::telemetry::SharedEngineTelemetry;
+use crate::graph::export::ScheduleSnapshot;
+use crate::graph::shared_pools::{SharedSteadyClock, SharedTransportTask};
+use crate::plugin_host::PluginHostProcessorWrapper;
 
+use parallel::{ParallelExecutor, TaskDependencies, WorkerPoolHandle};
 use tasks::{GraphInTask, GraphOutTask, Task};
 
 pub struct ProcessorSchedule {
     tasks: Vec<Task>,
+    dependencies: TaskDependencies,
+
+    /// Used to process independent tasks in parallel. This is `None` if
+    /// the schedule is processed entirely in the process thread.
+    parallel_executor: Option<ParallelExecutor>,
 
     graph_in_task: GraphInTask,
     graph_out_task: GraphOutTask,
     transport_task: SharedTransportTask,
+    steady_clock: SharedSteadyClock,
 
     /// For the plugins that are queued to be removed, make sure that
     /// the plugin's processor part is dropped in the process thread.
@@ -23,28 +36,43 @@ pub struct ProcessorSchedule {
 
     max_block_size: usize,
 
+    telemetry: Arc<SharedEngineTelemetry>,
+
     version: u64,
 }
 
 impl ProcessorSchedule {
     pub(crate) fn new(
         tasks: Vec<Task>,
+        dependencies: TaskDependencies,
+        worker_pool: Option<WorkerPoolHandle>,
         graph_in_task: GraphInTask,
         graph_out_task: GraphOutTask,
         transport_task: SharedTransportTask,
+        steady_clock: SharedSteadyClock,
         // For the plugins that are queued to be removed, make sure that
         // the plugin's processor part is dropped in the process thread.
         plugin_processors_to_stop: Vec<Shared<PluginHostProcessorWrapper>>,
         max_block_size: usize,
+        telemetry: Arc<SharedEngineTelemetry>,
         version: u64,
     ) -> Self {
+        // Only use the worker pool if there is anything to gain from it.
+        let parallel_executor = worker_pool
+            .filter(|_| tasks.len() > 1)
+            .map(|pool| ParallelExecutor::new(pool, tasks.len()));
+
         Self {
             tasks,
+            dependencies,
+            parallel_executor,
             graph_in_task,
             graph_out_task,
             transport_task,
+            steady_clock,
             plugin_processors_to_stop,
             max_block_size,
+            telemetry,
             version,
         }
     }
@@ -52,16 +80,22 @@ impl ProcessorSchedule {
     pub(crate) fn new_empty(
         max_block_size: usize,
         transport_task: SharedTransportTask,
+        steady_clock: SharedSteadyClock,
         plugin_processors_to_stop: Vec<Shared<PluginHostProcessorWrapper>>,
+        telemetry: Arc<SharedEngineTelemetry>,
         version: u64,
     ) -> Self {
         Self {
             tasks: Vec::new(),
+            dependencies: TaskDependencies::default(),
+            parallel_executor: None,
             graph_in_task: GraphInTask::default(),
             graph_out_task: GraphOutTask::default(),
             transport_task,
+            steady_clock,
             plugin_processors_to_stop,
             max_block_size,
+            telemetry,
             version,
         }
     }
@@ -69,6 +103,32 @@ impl ProcessorSchedule {
     pub(crate) fn tasks(&self) -> &[Task] {
         &self.tasks
     }
+
+    pub(crate) fn dependencies(&self) -> &TaskDependencies {
+        &self.dependencies
+    }
+
+    /// A description of this schedule, used for debugging.
+    pub(crate) fn snapshot(&self) -> ScheduleSnapshot {
+        ScheduleSnapshot {
+            version: self.version,
+            graph_in_audio: self.graph_in_task.audio_in.iter().map(|b| b.id()).collect(),
+            graph_out_audio: self.graph_out_task.audio_out.iter().map(|b| b.id()).collect(),
+            graph_in_note: self.graph_in_task.note_in.iter().map(|b| b.id()).collect(),
+            graph_out_note: self.graph_out_task.note_out.iter().map(|(b, _)| b.id()).collect(),
+            graph_in_automation: self.graph_in_task.automation_in.as_ref().map(|b| b.id()),
+            tasks: self
+                .tasks
+                .iter()
+                .enumerate()
+                .map(|(i, task)| {
+                    task.snapshot(
+                        self.dependencies.dependencies.get(i).map(|d| d.as_slice()).unwrap_or(&[]),
+                    )
+                })
+                .collect(),
+        }
+    }
 }
 
 impl std::fmt::Debug for ProcessorSchedule {
@@ -86,85 +146,375 @@ impl std::fmt::Debug for ProcessorSchedule {
 }
 
 impl ProcessorSchedule {
-    pub fn process_interleaved(&mut self, audio_in: &[f32], audio_out: &mut [f32]) {
-        // For the plugins that are queued to be removed, make sure that
-        // their processors are dropped on the process thread.
-        for plugin_proc in self.plugin_processors_to_stop.drain(..) {
-            let mut plugin_proc = plugin_proc.borrow_mut();
-            *plugin_proc = None;
-        }
-
+    /// Process `frames` frames of the schedule with interleaved buffers.
+    ///
+    /// `audio_in` and `audio_out` must hold exactly `frames` frames for
+    /// every channel of the graph input and output nodes. The schedule is
+    /// still processed when the graph has no audio channels at all (i.e.
+    /// a graph that only processes notes).
+    ///
+    /// `note_in` are the events to send to the note ports on the graph
+    /// input node, and `automation_in` are the events to send to the
+    /// automation port on the graph input node, both sorted by frame. Any
+    /// events generated on the note ports of the graph output node are
+    /// appended to `note_out`, as long as `note_out` has the capacity for
+    /// them.
+    pub fn process_interleaved(
+        &mut self,
+        frames: usize,
+        audio_in: &[f32],
+        audio_out: &mut [f32],
+        note_in: &[GraphNoteEvent],
+        automation_in: &[GraphAutomationEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+    ) {
         let audio_in_channels = self.graph_in_task.audio_in.len();
         let audio_out_channels = self.graph_out_task.audio_out.len();
 
-        if audio_in_channels != 0 && audio_out_channels != 0 {
-            assert_eq!(audio_in.len() / audio_in_channels, audio_out.len() / audio_out_channels);
-        }
+        assert_eq!(audio_in.len(), audio_in_channels * frames);
+        assert_eq!(audio_out.len(), audio_out_channels * frames);
 
-        let total_frames = if audio_in_channels > 0 {
-            let total_frames = audio_in.len() / audio_in_channels;
+        self.process_with(
+            frames,
+            note_in,
+            automation_in,
+            note_out,
+            |channel_i, processed_frames, buffer| {
+                // De-interlace the audio in stream to the graph input buffers.
+                // TODO: Check that the compiler is properly eliding bounds checking.
+                for i in 0..buffer.len() {
+                    buffer[i] = audio_in[((i + processed_frames) * audio_in_channels) + channel_i];
+                }
+            },
+            |channel_i, processed_frames, buffer| {
+                // Interlace the graph output buffers to the audio out stream.
+                // TODO: Check that the compiler is properly eliding bounds checking.
+                for i in 0..buffer.len() {
+                    audio_out[((i + processed_frames) * audio_out_channels) + channel_i] =
+                        buffer[i];
+                }
+            },
+        );
+    }
+
+    /// Process `frames` frames of the schedule with non-interleaved (planar)
+    /// buffers.
+    ///
+    /// Every channel in `audio_in` and `audio_out` must be at least `frames`
+    /// frames long. Any graph input channels not in `audio_in` are filled
+    /// with silence, and any graph output channels not in `audio_out` are
+    /// discarded.
+    ///
+    /// See `Self::process_interleaved()` for details on `note_in`,
+    /// `automation_in` and `note_out`.
+    pub fn process_planar(
+        &mut self,
+        frames: usize,
+        audio_in: &[&[f32]],
+        audio_out: &mut [&mut [f32]],
+        note_in: &[GraphNoteEvent],
+        automation_in: &[GraphAutomationEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+    ) {
+        assert!(audio_in.iter().all(|ch| ch.len() >= frames));
+        assert!(audio_out.iter().all(|ch| ch.len() >= frames));
+
+        self.process_with(
+            frames,
+            note_in,
+            automation_in,
+            note_out,
+            |channel_i, processed_frames, buffer| {
+                if let Some(ch) = audio_in.get(channel_i) {
+                    buffer.copy_from_slice(&ch[processed_frames..processed_frames + buffer.len()]);
+                } else {
+                    buffer.fill(0.0);
+                }
+            },
+            |channel_i, processed_frames, buffer| {
+                if let Some(ch) = audio_out.get_mut(channel_i) {
+                    ch[processed_frames..processed_frames + buffer.len()].copy_from_slice(buffer);
+                }
+            },
+        );
+    }
 
-            assert_eq!(audio_out.len(), audio_out_channels * total_frames);
+    /// Process the schedule with non-interleaved (planar) 64 bit buffers.
+    ///
+    /// See `Self::process_planar()` for more details.
+    pub fn process_planar_f64(
+        &mut self,
+        frames: usize,
+        audio_in: &[&[f64]],
+        audio_out: &mut [&mut [f64]],
+        note_in: &[GraphNoteEvent],
+        automation_in: &[GraphAutomationEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+    ) {
+        assert!(audio_in.iter().all(|ch| ch.len() >= frames));
+        assert!(audio_out.iter().all(|ch| ch.len() >= frames));
+
+        self.process_with(
+            frames,
+            note_in,
+            automation_in,
+            note_out,
+            |channel_i, processed_frames, buffer| {
+                if let Some(ch) = audio_in.get(channel_i) {
+                    let ch = &ch[processed_frames..processed_frames + buffer.len()];
+                    for (s_out, s_in) in buffer.iter_mut().zip(ch.iter()) {
+                        *s_out = *s_in as f32;
+                    }
+                } else {
+                    buffer.fill(0.0);
+                }
+            },
+            |channel_i, processed_frames, buffer| {
+                if let Some(ch) = audio_out.get_mut(channel_i) {
+                    let ch = &mut ch[processed_frames..processed_frames + buffer.len()];
+                    for (s_out, s_in) in ch.iter_mut().zip(buffer.iter()) {
+                        *s_out = f64::from(*s_in);
+                    }
+                }
+            },
+        );
+    }
 
-            total_frames
-        } else if audio_out_channels > 0 {
-            audio_out.len() / audio_out_channels
-        } else {
-            return;
-        };
+    /// Process `total_frames` frames of the schedule in blocks of at most
+    /// `max_block_size` frames.
+    ///
+    /// This lets the caller copy the audio straight into and out of the
+    /// buffers of the graph input and output nodes.
+    ///
+    /// * `read_in` - Fill the given graph input buffer for the given channel,
+    /// starting at the given frame offset.
+    /// * `write_out` - Read the given graph output buffer for the given
+    /// channel, starting at the given frame offset.
+    ///
+    /// See `Self::process_interleaved()` for details on `note_in`,
+    /// `automation_in` and `note_out`.
+    pub(crate) fn process_with<I, O>(
+        &mut self,
+        total_frames: usize,
+        note_in: &[GraphNoteEvent],
+        automation_in: &[GraphAutomationEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+        mut read_in: I,
+        mut write_out: O,
+    ) where
+        I: FnMut(usize, usize, &mut [f32]),
+        O: FnMut(usize, usize, &[f32]),
+    {
+        self.stop_plugin_processors();
 
         if total_frames == 0 {
             return;
         }
 
+        let mut note_in_i = 0;
+        let mut automation_in_i = 0;
         let mut processed_frames = 0;
         while processed_frames < total_frames {
             let frames = (total_frames - processed_frames).min(self.max_block_size);
 
-            // De-interlace the audio in stream to the graph input buffers.
+            let block_start = Instant::now();
+
             for (channel_i, buffer) in self.graph_in_task.audio_in.iter().enumerate() {
                 let buffer = &mut buffer.borrow_mut()[0..frames];
 
-                // TODO: Check that the compiler is properly eliding bounds checking.
-                for i in 0..frames {
-                    buffer[i] = audio_in[((i + processed_frames) * audio_in_channels) + channel_i];
-                }
+                (read_in)(channel_i, processed_frames, buffer);
             }
 
+            self.write_graph_in_events(
+                processed_frames,
+                frames,
+                note_in,
+                &mut note_in_i,
+                automation_in,
+                &mut automation_in_i,
+            );
+
             let transport = self.transport_task.borrow_mut().process(frames);
 
             let proc_info = ProcInfo {
-                steady_time: -1, // TODO
+                steady_time: self.steady_clock.advance(frames),
                 frames,
                 transport,
                 schedule_version: self.version,
             };
 
-            for task in self.tasks.iter_mut() {
-                task.process(&proc_info)
-            }
+            self.process_tasks(&proc_info);
 
-            // Interlace the graph output buffers to the audio out stream.
             for (channel_i, buffer) in self.graph_out_task.audio_out.iter().enumerate() {
                 let buffer = &buffer.borrow()[0..frames];
 
-                // TODO: Check that the compiler is properly eliding bounds checking.
-                for i in 0..frames {
-                    audio_out[((i + processed_frames) * audio_out_channels) + channel_i] =
-                        buffer[i];
-                }
+                (write_out)(channel_i, processed_frames, buffer);
             }
 
+            self.read_graph_out_events(processed_frames, note_out);
+
+            self.telemetry.record_block(frames, block_start.elapsed());
+
             processed_frames += frames;
         }
     }
 
-    pub fn deactivate(&mut self) {
-        // Make sure we drop all plugin processors in the process thread
-        // when deactivating the engine.
+    /// Process a single block of this schedule as part of the schedule of
+    /// another graph (the inner graph of a rack).
+    ///
+    /// The transport and the steady time of the outer graph are used
+    /// instead of the ones in this schedule, so that the plugins in both
+    /// graphs stay in sync.
+    ///
+    /// * `read_in` - Fill the given graph input buffer for the given channel.
+    /// * `write_out` - Read the given graph output buffer for the given
+    /// channel.
+    pub(crate) fn process_nested<I, O>(
+        &mut self,
+        outer_proc_info: &ProcInfo,
+        note_in: &[GraphNoteEvent],
+        note_out: &mut Vec<GraphNoteEvent>,
+        mut read_in: I,
+        mut write_out: O,
+    ) where
+        I: FnMut(usize, &mut [f32]),
+        O: FnMut(usize, &[f32]),
+    {
+        self.stop_plugin_processors();
+
+        let frames = outer_proc_info.frames.min(self.max_block_size);
+        if frames == 0 {
+            return;
+        }
+
+        for (channel_i, buffer) in self.graph_in_task.audio_in.iter().enumerate() {
+            let buffer = &mut buffer.borrow_mut()[0..frames];
+
+            (read_in)(channel_i, buffer);
+        }
+
+        self.write_graph_in_events(0, frames, note_in, &mut 0, &[], &mut 0);
+
+        let proc_info = ProcInfo {
+            steady_time: outer_proc_info.steady_time,
+            frames,
+            transport: outer_proc_info.transport.clone(),
+            schedule_version: self.version,
+        };
+
+        self.process_tasks(&proc_info);
+
+        for (channel_i, buffer) in self.graph_out_task.audio_out.iter().enumerate() {
+            let buffer = &buffer.borrow()[0..frames];
+
+            (write_out)(channel_i, buffer);
+        }
+
+        self.read_graph_out_events(0, note_out);
+    }
+
+    /// Fill the note and automation buffers of the graph input node with
+    /// the events that occur in the block starting at `processed_frames`.
+    ///
+    /// `note_in_i` and `automation_in_i` are the indexes of the next events
+    /// to read, and are advanced past the events in this block.
+    fn write_graph_in_events(
+        &mut self,
+        processed_frames: usize,
+        frames: usize,
+        note_in: &[GraphNoteEvent],
+        note_in_i: &mut usize,
+        automation_in: &[GraphAutomationEvent],
+        automation_in_i: &mut usize,
+    ) {
+        for buffer in self.graph_in_task.note_in.iter() {
+            buffer.truncate();
+        }
+        while let Some(event) = note_in.get(*note_in_i) {
+            if event.frame as usize >= processed_frames + frames {
+                break;
+            }
+
+            if let Some(buffer) = self.graph_in_task.note_in.get(usize::from(event.port)) {
+                let mut note_event = event.event;
+                note_event.header.time =
+                    (event.frame as usize).saturating_sub(processed_frames) as u32;
+
+                buffer.borrow_mut().push(note_event);
+            }
+
+            *note_in_i += 1;
+        }
+
+        if let Some(buffer) = &self.graph_in_task.automation_in {
+            buffer.truncate();
+        }
+        while let Some(event) = automation_in.get(*automation_in_i) {
+            if event.frame as usize >= processed_frames + frames {
+                break;
+            }
+
+            if let Some(buffer) = &self.graph_in_task.automation_in {
+                let mut automation_event = event.event;
+                automation_event.header.time =
+                    (event.frame as usize).saturating_sub(processed_frames) as u32;
+
+                buffer.borrow_mut().push(automation_event);
+            }
+
+            *automation_in_i += 1;
+        }
+    }
+
+    fn process_tasks(&mut self, proc_info: &ProcInfo) {
+        if let Some(parallel_executor) = &self.parallel_executor {
+            parallel_executor.process(
+                &mut self.tasks,
+                &self.dependencies,
+                proc_info,
+                &self.telemetry,
+            );
+        } else {
+            for task in self.tasks.iter_mut() {
+                task.process(proc_info, &self.telemetry)
+            }
+        }
+    }
+
+    /// Append the events on the note ports of the graph output node to
+    /// `note_out`, as long as `note_out` has the capacity for them.
+    fn read_graph_out_events(&self, processed_frames: usize, note_out: &mut Vec<GraphNoteEvent>) {
+        for (port_i, (buffer, is_unconnected)) in self.graph_out_task.note_out.iter().enumerate() {
+            if *is_unconnected {
+                continue;
+            }
+
+            for event in buffer.borrow().iter() {
+                if note_out.len() >= note_out.capacity() {
+                    break;
+                }
+
+                note_out.push(GraphNoteEvent {
+                    port: port_i as u16,
+                    frame: processed_frames as u32 + event.header.time,
+                    event: *event,
+                });
+            }
+        }
+    }
+
+    fn stop_plugin_processors(&mut self) {
+        // For the plugins that are queued to be removed, make sure that
+        // their processors are dropped on the process thread.
         for plugin_proc in self.plugin_processors_to_stop.drain(..) {
             let mut plugin_proc = plugin_proc.borrow_mut();
             *plugin_proc = None;
         }
     }
+
+    pub fn deactivate(&mut self) {
+        // Make sure we drop all plugin processors in the process thread
+        // when deactivating the engine.
+        self.stop_plugin_processors();
+    }
 }
@@ -0,0 +1,378 @@
+use dropseed_plugin_api::ProcInfo;
+use smallvec::SmallVec;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thread_priority::ThreadPriority;
+
+use crate::engine::telemetry::SharedEngineTelemetry;
+use crate::utils::thread_id::SharedThreadIDs;
+use crate::utils::wake_signal::WakeSignal;
+
+use super::tasks::Task;
+
+/// The maximum amount of time an idle worker thread waits for a new job
+/// before checking if it should stop running.
+static WORKER_WAIT_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// The number of times a thread checks for a ready task before it goes to
+/// sleep. Tasks usually become ready very quickly, so this avoids the cost
+/// of sleeping and waking up again.
+static NUM_SPINS_BEFORE_WAIT: usize = 256;
+
+/// The dependencies between the tasks in a schedule.
+///
+/// A task may only start once all of the tasks it depends on have
+/// finished. Tasks that don't depend on each other (directly or
+/// indirectly) never use the same buffer, so they can be processed in
+/// parallel.
+#[derive(Debug, Default)]
+pub(crate) struct TaskDependencies {
+    /// For each task, the indices of the tasks that depend on it.
+    pub dependents: Vec<SmallVec<[u32; 4]>>,
+    /// For each task, the indices of the tasks it depends on.
+    pub dependencies: Vec<SmallVec<[u32; 4]>>,
+}
+
+impl TaskDependencies {
+    pub fn num_tasks(&self) -> usize {
+        self.dependencies.len()
+    }
+}
+
+/// The state used to process the tasks in a schedule across the worker
+/// pool. This is allocated when the schedule is compiled so that nothing
+/// is allocated in the process thread.
+pub(crate) struct ParallelExecutor {
+    pool: WorkerPoolHandle,
+
+    remaining_dependencies: Vec<AtomicU32>,
+
+    /// A queue of the indices of tasks that are ready to be processed.
+    ///
+    /// Each task is pushed exactly once per process cycle, so the queue
+    /// never wraps around. A value of `0` means the slot has been reserved
+    /// but not written to yet, otherwise the value is the task index + 1.
+    ready_queue: Vec<AtomicU32>,
+    queue_push_index: AtomicUsize,
+    queue_pop_index: AtomicUsize,
+
+    num_completed: AtomicUsize,
+
+    /// Used to wake up threads that are waiting for a task to become ready.
+    ready_signal: WakeSignal,
+}
+
+impl ParallelExecutor {
+    pub fn new(pool: WorkerPoolHandle, num_tasks: usize) -> Self {
+        Self {
+            pool,
+            remaining_dependencies: (0..num_tasks).map(|_| AtomicU32::new(0)).collect(),
+            ready_queue: (0..num_tasks).map(|_| AtomicU32::new(0)).collect(),
+            queue_push_index: AtomicUsize::new(0),
+            queue_pop_index: AtomicUsize::new(0),
+            num_completed: AtomicUsize::new(0),
+            ready_signal: WakeSignal::new(),
+        }
+    }
+
+    /// Process all tasks in the schedule, using the worker pool to process
+    /// independent tasks in parallel.
+    ///
+    /// This returns once every task has been processed.
+    pub fn process(
+        &self,
+        tasks: &mut [Task],
+        dependencies: &TaskDependencies,
+        proc_info: &ProcInfo,
+        telemetry: &SharedEngineTelemetry,
+    ) {
+        debug_assert_eq!(tasks.len(), dependencies.num_tasks());
+        debug_assert_eq!(tasks.len(), self.ready_queue.len());
+
+        // Reset the state from the previous process cycle.
+        for (remaining, deps) in
+            self.remaining_dependencies.iter().zip(dependencies.dependencies.iter())
+        {
+            remaining.store(deps.len() as u32, Ordering::Relaxed);
+        }
+        for slot in self.ready_queue.iter() {
+            slot.store(0, Ordering::Relaxed);
+        }
+        self.queue_push_index.store(0, Ordering::Relaxed);
+        self.queue_pop_index.store(0, Ordering::Relaxed);
+        self.num_completed.store(0, Ordering::Relaxed);
+
+        for (task_i, deps) in dependencies.dependencies.iter().enumerate() {
+            if deps.is_empty() {
+                self.push_ready(task_i as u32);
+            }
+        }
+
+        let job =
+            Job { executor: self, tasks: tasks.as_mut_ptr(), dependencies, proc_info, telemetry };
+
+        self.pool.run_job(&job);
+    }
+
+    fn push_ready(&self, task_i: u32) {
+        let slot = self.queue_push_index.fetch_add(1, Ordering::AcqRel);
+        self.ready_queue[slot].store(task_i + 1, Ordering::Release);
+    }
+
+    fn pop_ready(&self) -> Option<usize> {
+        loop {
+            let slot = self.queue_pop_index.load(Ordering::Acquire);
+            if slot >= self.queue_push_index.load(Ordering::Acquire) {
+                return None;
+            }
+
+            if self
+                .queue_pop_index
+                .compare_exchange_weak(slot, slot + 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                // The slot has been reserved by the thread that pushed it, but
+                // that thread may not have written the task index yet.
+                loop {
+                    let value = self.ready_queue[slot].load(Ordering::Acquire);
+                    if value != 0 {
+                        return Some(value as usize - 1);
+                    }
+
+                    std::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+/// A single process cycle of a schedule that is shared with the worker
+/// threads.
+struct Job<'a> {
+    executor: &'a ParallelExecutor,
+    tasks: *mut Task,
+    dependencies: &'a TaskDependencies,
+    proc_info: &'a ProcInfo,
+    telemetry: &'a SharedEngineTelemetry,
+}
+
+impl<'a> Job<'a> {
+    /// Process tasks until every task in the job has been processed.
+    ///
+    /// # Safety
+    ///
+    /// This is safe to call from multiple threads at once because each task
+    /// is only ever popped from the ready queue once per job, and the
+    /// verifier has proven that no two tasks which can run at the same time
+    /// use the same buffer.
+    unsafe fn work(&self) {
+        let num_tasks = self.dependencies.num_tasks();
+        let mut num_spins = 0;
+
+        loop {
+            // Get the token before checking for a ready task so we don't miss
+            // a notification from another thread.
+            let token = self.executor.ready_signal.token();
+
+            if let Some(task_i) = self.executor.pop_ready() {
+                let task = &mut *self.tasks.add(task_i);
+                task.process(self.proc_info, self.telemetry);
+
+                let mut pushed = false;
+                for dependent_i in self.dependencies.dependents[task_i].iter() {
+                    if self.executor.remaining_dependencies[*dependent_i as usize]
+                        .fetch_sub(1, Ordering::AcqRel)
+                        == 1
+                    {
+                        self.executor.push_ready(*dependent_i);
+                        pushed = true;
+                    }
+                }
+
+                if self.executor.num_completed.fetch_add(1, Ordering::AcqRel) + 1 == num_tasks {
+                    // Wake up any threads still waiting on this job.
+                    self.executor.ready_signal.notify();
+                    return;
+                }
+
+                if pushed {
+                    self.executor.ready_signal.notify();
+                }
+
+                num_spins = 0;
+                continue;
+            }
+
+            if self.executor.num_completed.load(Ordering::Acquire) == num_tasks {
+                return;
+            }
+
+            if num_spins < NUM_SPINS_BEFORE_WAIT {
+                num_spins += 1;
+                std::hint::spin_loop();
+                continue;
+            }
+
+            self.executor.ready_signal.wait(token, None);
+        }
+    }
+}
+
+struct WorkerPoolShared {
+    run: AtomicBool,
+
+    /// The job that is currently being processed, or null if there is none.
+    ///
+    /// The lifetime is erased here. The process thread makes sure that no
+    /// worker is still using a job before it returns from
+    /// `WorkerPoolHandle::run_job()`.
+    job: AtomicPtr<Job<'static>>,
+    /// Incremented every time a new job is started.
+    job_generation: AtomicU64,
+    /// Used to wake up the worker threads when a new job has started.
+    job_signal: WakeSignal,
+
+    /// The number of worker threads that may currently be using the job.
+    num_active_workers: AtomicUsize,
+    /// Used to wake up the process thread when a worker has finished with
+    /// a job.
+    idle_signal: WakeSignal,
+}
+
+/// A fixed pool of realtime worker threads used to process a schedule in
+/// parallel.
+pub(crate) struct WorkerPool {
+    shared: Arc<WorkerPoolShared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(
+        num_workers: usize,
+        thread_ids: &SharedThreadIDs,
+        coll_handle: &basedrop::Handle,
+    ) -> Self {
+        let shared = Arc::new(WorkerPoolShared {
+            run: AtomicBool::new(true),
+            job: AtomicPtr::new(std::ptr::null_mut()),
+            job_generation: AtomicU64::new(0),
+            job_signal: WakeSignal::new(),
+            num_active_workers: AtomicUsize::new(0),
+            idle_signal: WakeSignal::new(),
+        });
+
+        let handles: Vec<JoinHandle<()>> = (0..num_workers)
+            .map(|worker_i| {
+                let shared = Arc::clone(&shared);
+
+                thread_priority::spawn(ThreadPriority::Max, move |priority_res| {
+                    if let Err(e) = priority_res {
+                        log::error!(
+                            "Failed to set worker thread {} priority to max: {:?}",
+                            worker_i,
+                            e
+                        );
+                    }
+
+                    run_worker(&shared);
+                })
+            })
+            .collect();
+
+        thread_ids
+            .set_worker_thread_ids(handles.iter().map(|h| h.thread().id()).collect(), coll_handle);
+
+        log::info!("Started {} audio worker threads", num_workers);
+
+        Self { shared, handles }
+    }
+
+    pub fn handle(&self) -> WorkerPoolHandle {
+        WorkerPoolHandle { shared: Arc::clone(&self.shared) }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.run.store(false, Ordering::Relaxed);
+        self.shared.job_signal.notify();
+
+        for handle in self.handles.drain(..) {
+            if let Err(e) = handle.join() {
+                log::error!("Failed to join audio worker thread: {:?}", e);
+            }
+        }
+    }
+}
+
+fn run_worker(shared: &WorkerPoolShared) {
+    let mut last_generation = 0;
+
+    while shared.run.load(Ordering::Relaxed) {
+        // Get the token before checking for a new job so we don't miss a
+        // notification from the process thread.
+        let token = shared.job_signal.token();
+
+        let generation = shared.job_generation.load(Ordering::SeqCst);
+        if generation != last_generation {
+            shared.num_active_workers.fetch_add(1, Ordering::SeqCst);
+
+            let job = shared.job.load(Ordering::SeqCst);
+            if shared.job_generation.load(Ordering::SeqCst) == generation {
+                if !job.is_null() {
+                    // Safety: The process thread waits for `num_active_workers`
+                    // to reach zero before the job goes out of scope.
+                    unsafe { (*job).work() };
+                }
+
+                last_generation = generation;
+            }
+
+            shared.num_active_workers.fetch_sub(1, Ordering::SeqCst);
+            shared.idle_signal.notify();
+
+            continue;
+        }
+
+        shared.job_signal.wait(token, Some(Instant::now() + WORKER_WAIT_TIMEOUT));
+    }
+}
+
+/// A handle to a `WorkerPool` that is held by a schedule.
+#[derive(Clone)]
+pub(crate) struct WorkerPoolHandle {
+    shared: Arc<WorkerPoolShared>,
+}
+
+impl WorkerPoolHandle {
+    /// Process the given job on the current thread along with all worker
+    /// threads, and wait until it is done.
+    fn run_job(&self, job: &Job) {
+        // Safety: The lifetime is only erased while the job is published. The
+        // pointer is cleared and all workers have stopped using it before
+        // this method returns.
+        let job_ptr = job as *const Job as *mut Job<'static>;
+
+        self.shared.job.store(job_ptr, Ordering::SeqCst);
+        self.shared.job_generation.fetch_add(1, Ordering::SeqCst);
+        self.shared.job_signal.notify();
+
+        // The process thread works on the job too.
+        unsafe { job.work() };
+
+        self.shared.job.store(std::ptr::null_mut(), Ordering::SeqCst);
+
+        // Wait for any workers that are still using the job.
+        loop {
+            let token = self.shared.idle_signal.token();
+
+            if self.shared.num_active_workers.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+
+            self.shared.idle_signal.wait(token, None);
+        }
+    }
+}
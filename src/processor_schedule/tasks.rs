@@ -1,20 +1,31 @@
+use dropseed_plugin_api::buffer::{DebugBufferID, RawAudioChannelBuffers};
 use dropseed_plugin_api::ProcInfo;
 use std::fmt::{Debug, Error, Formatter, Write};
+use std::time::Instant;
+
+use crate::engine::telemetry::SharedEngineTelemetry;
+use crate::graph::export::{ScheduledTask, ScheduledTaskType};
+use crate::graph::PortType;
 
 mod delay_comp_task;
+mod feedback_task;
 mod graph_in_out_task;
 mod plugin_task;
 mod sum_task;
 mod transport_task;
 mod unloaded_plugin_task;
 
-pub use transport_task::TransportHandle;
+pub use graph_in_out_task::{GraphAutomationEvent, GraphNoteEvent};
+pub use transport_task::{TransportHandle, TransportSaveState};
 
 pub(crate) use delay_comp_task::{
     AudioDelayCompNode, AudioDelayCompTask, AutomationDelayCompNode, AutomationDelayCompTask,
     NoteDelayCompNode, NoteDelayCompTask, SharedAudioDelayCompNode, SharedAutomationDelayCompNode,
     SharedNoteDelayCompNode,
 };
+pub(crate) use feedback_task::{
+    AudioFeedbackNode, AudioFeedbackReturnTask, AudioFeedbackSendTask, SharedAudioFeedbackNode,
+};
 pub(crate) use graph_in_out_task::{GraphInTask, GraphOutTask};
 pub(crate) use plugin_task::PluginTask;
 pub(crate) use sum_task::{AudioSumTask, AutomationSumTask, NoteSumTask};
@@ -29,6 +40,8 @@ pub(crate) enum Task {
     AudioDelayComp(AudioDelayCompTask),
     NoteDelayComp(NoteDelayCompTask),
     AutomationDelayComp(AutomationDelayCompTask),
+    AudioFeedbackSend(AudioFeedbackSendTask),
+    AudioFeedbackReturn(AudioFeedbackReturnTask),
     UnloadedPlugin(UnloadedPluginTask),
 }
 
@@ -176,9 +189,27 @@ impl Debug for Task {
 
                 f.finish()
             }
+            Task::AudioFeedbackSend(t) => {
+                let mut f = f.debug_struct("AudioFeedbackSend");
+
+                f.field("audio_in", &t.audio_in.id());
+                f.field("delay", &t.shared_node.delay);
+
+                f.finish()
+            }
+            Task::AudioFeedbackReturn(t) => {
+                let mut f = f.debug_struct("AudioFeedbackReturn");
+
+                f.field("audio_out", &t.audio_out.id());
+                f.field("delay", &t.shared_node.delay);
+
+                f.finish()
+            }
             Task::UnloadedPlugin(t) => {
                 let mut f = f.debug_struct("UnloadedPlugin");
 
+                f.field("id", &t.plugin_id);
+
                 let mut s = String::new();
                 for (b_in, b_out) in t.audio_through.iter() {
                     let _ = write!(s, "(in: {:?}, out: {:?})", b_in.id(), b_out.id());
@@ -215,16 +246,218 @@ impl Debug for Task {
 }
 
 impl Task {
-    pub fn process(&mut self, proc_info: &ProcInfo) {
+    pub fn process(&mut self, proc_info: &ProcInfo, telemetry: &SharedEngineTelemetry) {
+        let start = Instant::now();
+
+        let counter = match self {
+            Task::Plugin(task) => {
+                task.process(proc_info);
+                &telemetry.plugin_tasks
+            }
+            Task::AudioSum(task) => {
+                task.process(proc_info);
+                &telemetry.sum_tasks
+            }
+            Task::NoteSum(task) => {
+                task.process();
+                &telemetry.sum_tasks
+            }
+            Task::AutomationSum(task) => {
+                task.process();
+                &telemetry.sum_tasks
+            }
+            Task::AudioDelayComp(task) => {
+                task.process(proc_info);
+                &telemetry.delay_comp_tasks
+            }
+            Task::NoteDelayComp(task) => {
+                task.process(proc_info);
+                &telemetry.delay_comp_tasks
+            }
+            Task::AutomationDelayComp(task) => {
+                task.process(proc_info);
+                &telemetry.delay_comp_tasks
+            }
+            Task::AudioFeedbackSend(task) => {
+                task.process(proc_info);
+                &telemetry.delay_comp_tasks
+            }
+            Task::AudioFeedbackReturn(task) => {
+                task.process(proc_info);
+                &telemetry.delay_comp_tasks
+            }
+            Task::UnloadedPlugin(task) => {
+                task.process(proc_info);
+                &telemetry.plugin_tasks
+            }
+        };
+
+        counter.record(start.elapsed());
+    }
+
+    /// Call `f` with the ID of every buffer this task uses, along with
+    /// `true` if the task writes to (or clears) that buffer.
+    ///
+    /// The same buffer may be reported more than once.
+    pub fn for_each_buffer<F: FnMut(DebugBufferID, bool)>(&self, mut f: F) {
+        match self {
+            Task::Plugin(t) => {
+                for port_buffer in t.buffers.audio_in.iter() {
+                    for_each_raw_channel(&port_buffer._raw_channels, |id| f(id, false));
+                }
+                for port_buffer in t.buffers.audio_out.iter() {
+                    for_each_raw_channel(&port_buffer._raw_channels, |id| f(id, true));
+                }
+                for b in t.clear_audio_in_buffers.iter() {
+                    f(b.id(), true);
+                }
+
+                for b in t.event_buffers.note_in_buffers.iter() {
+                    f(b.id(), false);
+                }
+                for b in t.event_buffers.note_out_buffers.iter() {
+                    f(b.id(), true);
+                }
+                for b in t.event_buffers.clear_note_in_buffers.iter() {
+                    f(b.id(), true);
+                }
+                if let Some((b, do_clear)) = &t.event_buffers.automation_in_buffer {
+                    f(b.id(), *do_clear);
+                }
+                if let Some(b) = &t.event_buffers.automation_out_buffer {
+                    f(b.id(), true);
+                }
+            }
+            Task::AudioSum(t) => {
+                for b in t.audio_in.iter() {
+                    f(b.id(), false);
+                }
+                f(t.audio_out.id(), true);
+            }
+            Task::NoteSum(t) => {
+                for b in t.note_in.iter() {
+                    f(b.id(), false);
+                }
+                f(t.note_out.id(), true);
+            }
+            Task::AutomationSum(t) => {
+                for b in t.input.iter() {
+                    f(b.id(), false);
+                }
+                f(t.output.id(), true);
+            }
+            Task::AudioDelayComp(t) => {
+                f(t.audio_in.id(), false);
+                f(t.audio_out.id(), true);
+            }
+            Task::NoteDelayComp(t) => {
+                f(t.note_in.id(), false);
+                f(t.note_out.id(), true);
+            }
+            Task::AutomationDelayComp(t) => {
+                f(t.input.id(), false);
+                f(t.output.id(), true);
+            }
+            Task::AudioFeedbackSend(t) => {
+                f(t.audio_in.id(), false);
+            }
+            Task::AudioFeedbackReturn(t) => {
+                f(t.audio_out.id(), true);
+            }
+            Task::UnloadedPlugin(t) => {
+                for (b_in, b_out) in t.audio_through.iter() {
+                    f(b_in.id(), false);
+                    f(b_out.id(), true);
+                }
+                if let Some((b_in, b_out)) = &t.note_through {
+                    f(b_in.id(), false);
+                    f(b_out.id(), true);
+                }
+                for b in t.clear_audio_out.iter() {
+                    f(b.id(), true);
+                }
+                for b in t.clear_note_out.iter() {
+                    f(b.id(), true);
+                }
+                if let Some(b) = &t.clear_automation_out {
+                    f(b.id(), true);
+                }
+            }
+        }
+    }
+}
+
+impl Task {
+    /// If this task reads from or writes to the delay line of a feedback
+    /// edge, then this returns the unique ID of that edge.
+    ///
+    /// The send and return tasks of the same edge share the same delay
+    /// line, so they must never be processed at the same time.
+    pub fn feedback_edge_id(&self) -> Option<u64> {
         match self {
-            Task::Plugin(task) => task.process(proc_info),
-            Task::AudioSum(task) => task.process(proc_info),
-            Task::NoteSum(task) => task.process(),
-            Task::AutomationSum(task) => task.process(),
-            Task::AudioDelayComp(task) => task.process(proc_info),
-            Task::NoteDelayComp(task) => task.process(proc_info),
-            Task::AutomationDelayComp(task) => task.process(proc_info),
-            Task::UnloadedPlugin(task) => task.process(proc_info),
+            Task::AudioFeedbackSend(t) => Some(t.shared_node.edge_id),
+            Task::AudioFeedbackReturn(t) => Some(t.shared_node.edge_id),
+            _ => None,
+        }
+    }
+
+    /// A description of this task, used for debugging.
+    pub fn snapshot(&self, dependencies: &[u32]) -> ScheduledTask {
+        let task_type = match self {
+            Task::Plugin(t) => ScheduledTaskType::Plugin(t.plugin_id.clone()),
+            Task::AudioSum(_) => ScheduledTaskType::Sum(PortType::Audio),
+            Task::NoteSum(_) => ScheduledTaskType::Sum(PortType::Note),
+            Task::AutomationSum(_) => ScheduledTaskType::Sum(PortType::Automation),
+            Task::AudioDelayComp(t) => ScheduledTaskType::DelayComp {
+                port_type: PortType::Audio,
+                delay: t.shared_node.delay,
+            },
+            Task::NoteDelayComp(t) => ScheduledTaskType::DelayComp {
+                port_type: PortType::Note,
+                delay: t.shared_node.delay,
+            },
+            Task::AutomationDelayComp(t) => ScheduledTaskType::DelayComp {
+                port_type: PortType::Automation,
+                delay: t.shared_node.delay,
+            },
+            Task::AudioFeedbackSend(t) => {
+                ScheduledTaskType::FeedbackSend { delay: t.shared_node.delay }
+            }
+            Task::AudioFeedbackReturn(t) => {
+                ScheduledTaskType::FeedbackReturn { delay: t.shared_node.delay }
+            }
+            Task::UnloadedPlugin(t) => ScheduledTaskType::UnloadedPlugin(t.plugin_id.clone()),
+        };
+
+        let mut reads: Vec<DebugBufferID> = Vec::new();
+        let mut writes: Vec<DebugBufferID> = Vec::new();
+        self.for_each_buffer(|id, is_write| {
+            let buffers = if is_write { &mut writes } else { &mut reads };
+            if !buffers.contains(&id) {
+                buffers.push(id);
+            }
+        });
+
+        ScheduledTask {
+            task_type,
+            reads,
+            writes,
+            dependencies: dependencies.iter().map(|i| *i as usize).collect(),
+        }
+    }
+}
+
+fn for_each_raw_channel<F: FnMut(DebugBufferID)>(raw_channels: &RawAudioChannelBuffers, mut f: F) {
+    match raw_channels {
+        RawAudioChannelBuffers::F32(buffers) => {
+            for b in buffers.iter() {
+                f(b.id());
+            }
+        }
+        RawAudioChannelBuffers::F64(buffers) => {
+            for b in buffers.iter() {
+                f(b.id());
+            }
         }
     }
 }
@@ -0,0 +1,207 @@
+use atomic_refcell::{AtomicRefCell, AtomicRefMut};
+use basedrop::Shared;
+use dropseed_plugin_api::buffer::SharedBuffer;
+use dropseed_plugin_api::ProcInfo;
+
+/// Writes the signal on the source port of a feedback edge into the delay
+/// line of that edge.
+pub(crate) struct AudioFeedbackSendTask {
+    pub shared_node: SharedAudioFeedbackNode,
+
+    pub audio_in: SharedBuffer<f32>,
+}
+
+impl AudioFeedbackSendTask {
+    pub fn process(&mut self, proc_info: &ProcInfo) {
+        let mut feedback_node = self.shared_node.borrow_mut();
+
+        let input_ref = self.audio_in.borrow();
+        feedback_node.write(&input_ref[0..proc_info.frames]);
+    }
+}
+
+/// Reads the delayed signal from the delay line of a feedback edge into the
+/// destination port of that edge.
+pub(crate) struct AudioFeedbackReturnTask {
+    pub shared_node: SharedAudioFeedbackNode,
+
+    pub audio_out: SharedBuffer<f32>,
+}
+
+impl AudioFeedbackReturnTask {
+    pub fn process(&mut self, proc_info: &ProcInfo) {
+        let mut feedback_node = self.shared_node.borrow_mut();
+
+        let mut output_ref = self.audio_out.borrow_mut();
+        let out_buf = &mut output_ref[0..proc_info.frames];
+
+        feedback_node.read(out_buf);
+
+        // TODO: More efficient way to check if the output is constant?
+        let val = out_buf[0];
+        let is_constant = out_buf.iter().skip(1).all(|x| *x == val);
+
+        self.audio_out.set_constant(is_constant);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SharedAudioFeedbackNode {
+    pub active: bool,
+    pub delay: u32,
+    /// The unique ID of the feedback edge this delay line belongs to. The
+    /// send and return tasks of the same edge share the same delay line.
+    pub edge_id: u64,
+
+    shared: Shared<AtomicRefCell<AudioFeedbackNode>>,
+}
+
+impl SharedAudioFeedbackNode {
+    pub fn new(d: AudioFeedbackNode, edge_id: u64, coll_handle: &basedrop::Handle) -> Self {
+        Self {
+            active: true,
+            delay: d.delay(),
+            edge_id,
+            shared: Shared::new(coll_handle, AtomicRefCell::new(d)),
+        }
+    }
+
+    pub fn borrow_mut(&self) -> AtomicRefMut<'_, AudioFeedbackNode> {
+        self.shared.borrow_mut()
+    }
+}
+
+/// The delay line of a feedback edge.
+///
+/// The read and write positions move independently of each other, and the
+/// delay line holds one extra block on top of the delay. As long as the delay
+/// is at least as long as the longest block, the frames that are written in a
+/// block never overlap the frames that are read in that same block, so the
+/// signal is delayed by exactly `delay` frames no matter which of the two
+/// tasks is processed first.
+pub(crate) struct AudioFeedbackNode {
+    buf: Vec<f32>,
+    read_pointer: usize,
+    write_pointer: usize,
+    delay: u32,
+}
+
+impl AudioFeedbackNode {
+    pub fn new(delay: u32, max_frames: u32) -> Self {
+        debug_assert!(delay >= max_frames);
+
+        Self {
+            buf: vec![0.0; delay as usize + max_frames as usize],
+            read_pointer: 0,
+            write_pointer: delay as usize,
+            delay,
+        }
+    }
+
+    pub fn read(&mut self, out_buf: &mut [f32]) {
+        let frames = out_buf.len();
+
+        if self.read_pointer + frames <= self.buf.len() {
+            // Only one copy operation is needed.
+            out_buf.copy_from_slice(&self.buf[self.read_pointer..self.read_pointer + frames]);
+        } else {
+            // Two copy operations are needed.
+            let first_len = self.buf.len() - self.read_pointer;
+            let second_len = frames - first_len;
+
+            out_buf[0..first_len].copy_from_slice(&self.buf[self.read_pointer..self.buf.len()]);
+            out_buf[first_len..frames].copy_from_slice(&self.buf[0..second_len]);
+        }
+
+        self.read_pointer = self.advance(self.read_pointer, frames);
+    }
+
+    pub fn write(&mut self, in_buf: &[f32]) {
+        let frames = in_buf.len();
+
+        if self.write_pointer + frames <= self.buf.len() {
+            // Only one copy operation is needed.
+            self.buf[self.write_pointer..self.write_pointer + frames].copy_from_slice(in_buf);
+        } else {
+            // Two copy operations are needed.
+            let first_len = self.buf.len() - self.write_pointer;
+            let second_len = frames - first_len;
+
+            let buf_len = self.buf.len();
+            self.buf[self.write_pointer..buf_len].copy_from_slice(&in_buf[0..first_len]);
+            self.buf[0..second_len].copy_from_slice(&in_buf[first_len..frames]);
+        }
+
+        self.write_pointer = self.advance(self.write_pointer, frames);
+    }
+
+    /// Get the next position of the given pointer.
+    fn advance(&self, pointer: usize, frames: usize) -> usize {
+        let pointer = pointer + frames;
+        if pointer >= self.buf.len() {
+            pointer - self.buf.len()
+        } else {
+            pointer
+        }
+    }
+
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send an impulse through a delay line in blocks of varying sizes and
+    /// return the frame at which it comes out.
+    fn impulse_arrival(delay: u32, max_frames: u32, blocks: &[usize], write_first: bool) -> usize {
+        let mut node = AudioFeedbackNode::new(delay, max_frames);
+
+        let mut frame = 0;
+        let mut arrival = None;
+        for frames in blocks.iter().copied() {
+            let mut input = vec![0.0; frames];
+            if frame == 0 {
+                input[0] = 1.0;
+            }
+            let mut output = vec![0.0; frames];
+
+            if write_first {
+                node.write(&input);
+                node.read(&mut output);
+            } else {
+                node.read(&mut output);
+                node.write(&input);
+            }
+
+            if let Some(i) = output.iter().position(|x| *x != 0.0) {
+                assert!(arrival.is_none());
+                arrival = Some(frame + i);
+            }
+
+            frame += frames;
+        }
+
+        arrival.unwrap()
+    }
+
+    #[test]
+    fn delay_is_exact() {
+        let blocks = [4, 3, 4, 1, 4, 2, 4, 4, 4, 4];
+
+        for delay in 4..20 {
+            assert_eq!(impulse_arrival(delay, 4, &blocks, false), delay as usize);
+            assert_eq!(impulse_arrival(delay, 4, &blocks, true), delay as usize);
+        }
+    }
+
+    #[test]
+    fn delay_of_one_block() {
+        let blocks = [64; 4];
+
+        assert_eq!(impulse_arrival(64, 64, &blocks, false), 64);
+        assert_eq!(impulse_arrival(64, 64, &blocks, true), 64);
+    }
+}
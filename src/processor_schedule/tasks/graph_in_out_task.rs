@@ -1,12 +1,158 @@
+use dropseed_plugin_api::automation::{AutomationIoEvent, IoEventHeader};
 use dropseed_plugin_api::buffer::SharedBuffer;
 use smallvec::SmallVec;
 
+use crate::plugin_host::event_io_buffers::{NoteIoEvent, NoteIoEventType};
+
+/// A note event sent to a note port on the graph input node, or received
+/// from a note port on the graph output node.
+#[derive(Clone, Copy)]
+pub struct GraphNoteEvent {
+    /// The index of the note port on the graph input/output node.
+    pub port: u16,
+
+    /// The frame this event occurs on, relative to the start of the
+    /// buffer being processed.
+    ///
+    /// Note that the time in the header of `event` is ignored.
+    pub frame: u32,
+
+    pub event: NoteIoEvent,
+}
+
+impl std::fmt::Debug for GraphNoteEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("GraphNoteEvent");
+
+        f.field("port", &self.port);
+        f.field("frame", &self.frame);
+        f.field("channel", &self.event.channel);
+        f.field("key", &self.event.key);
+
+        f.finish()
+    }
+}
+
+impl GraphNoteEvent {
+    /// Convert a raw MIDI 1.0 message into a note event on the given note
+    /// port.
+    ///
+    /// This returns `None` if the message is not a note on or a note off
+    /// message. A note on message with a velocity of `0` is treated as a
+    /// note off message.
+    pub fn from_midi(port: u16, frame: u32, bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 3 {
+            return None;
+        }
+
+        let channel = i16::from(bytes[0] & 0x0F);
+        let key = i16::from(bytes[1] & 0x7F);
+        let velocity = f64::from(bytes[2] & 0x7F) / 127.0;
+
+        let event_type = match bytes[0] & 0xF0 {
+            0x90 if velocity > 0.0 => NoteIoEventType::On { velocity },
+            0x80 | 0x90 => NoteIoEventType::Off { velocity },
+            _ => return None,
+        };
+
+        Some(Self {
+            port,
+            frame,
+            event: NoteIoEvent { header: IoEventHeader { time: frame }, channel, key, event_type },
+        })
+    }
+
+    /// Convert this event into a raw 3 byte MIDI 1.0 message.
+    ///
+    /// This returns `None` if the event has no equivalent MIDI message
+    /// (note chokes and note expressions), or if the channel or the key
+    /// of the event is out of range (i.e. a wildcard of `-1`).
+    pub fn to_midi(&self) -> Option<[u8; 3]> {
+        if !(0..16).contains(&self.event.channel) || !(0..128).contains(&self.event.key) {
+            return None;
+        }
+
+        let channel = self.event.channel as u8;
+        let key = self.event.key as u8;
+        let velocity = |v: f64| (v.clamp(0.0, 1.0) * 127.0).round() as u8;
+
+        match self.event.event_type {
+            NoteIoEventType::On { velocity: v } => Some([0x90 | channel, key, velocity(v)]),
+            NoteIoEventType::Off { velocity: v } => Some([0x80 | channel, key, velocity(v)]),
+            NoteIoEventType::Choke | NoteIoEventType::Expression { .. } => None,
+        }
+    }
+}
+
+/// An automation event sent to the automation port on the graph input
+/// node.
+#[derive(Clone, Copy)]
+pub struct GraphAutomationEvent {
+    /// The frame this event occurs on, relative to the start of the
+    /// buffer being processed.
+    ///
+    /// Note that the time in the header of `event` is ignored.
+    pub frame: u32,
+
+    /// Only the plugin whose `PluginInstanceID::unique_id()` is equal to
+    /// `event.plugin_instance_id` will receive this event.
+    pub event: AutomationIoEvent,
+}
+
+impl std::fmt::Debug for GraphAutomationEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("GraphAutomationEvent");
+
+        f.field("frame", &self.frame);
+        f.field("parameter_id", &self.event.parameter_id);
+        f.field("plugin_instance_id", &self.event.plugin_instance_id);
+
+        f.finish()
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct GraphInTask {
     pub audio_in: SmallVec<[SharedBuffer<f32>; 8]>,
+    pub note_in: SmallVec<[SharedBuffer<NoteIoEvent>; 2]>,
+    pub automation_in: Option<SharedBuffer<AutomationIoEvent>>,
 }
 
 #[derive(Default)]
 pub(crate) struct GraphOutTask {
     pub audio_out: SmallVec<[SharedBuffer<f32>; 8]>,
+    /// The buffers for each note port, along with whether or not the port
+    /// is unconnected (in which case the buffer should not be read).
+    pub note_out: SmallVec<[(SharedBuffer<NoteIoEvent>, bool); 2]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn midi_round_trip() {
+        for bytes in [[0x90, 60, 100], [0x83, 64, 0], [0x9F, 127, 127], [0x80, 0, 64]] {
+            let event = GraphNoteEvent::from_midi(1, 32, &bytes).unwrap();
+
+            assert_eq!(event.port, 1);
+            assert_eq!(event.frame, 32);
+            assert_eq!(event.to_midi(), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn midi_note_on_with_zero_velocity_is_note_off() {
+        let event = GraphNoteEvent::from_midi(0, 0, &[0x91, 60, 0]).unwrap();
+
+        assert_eq!(event.to_midi(), Some([0x81, 60, 0]));
+    }
+
+    #[test]
+    fn midi_non_note_messages_are_ignored() {
+        // Control change, pitch bend, and a truncated note on message.
+        assert!(GraphNoteEvent::from_midi(0, 0, &[0xB0, 7, 100]).is_none());
+        assert!(GraphNoteEvent::from_midi(0, 0, &[0xE0, 0, 64]).is_none());
+        assert!(GraphNoteEvent::from_midi(0, 0, &[0x90, 60]).is_none());
+    }
 }
@@ -13,7 +13,10 @@ use meadowlark_core_types::time::{Frames, MusicalTime, SampleRate, Seconds};
 mod declick;
 use declick::{JumpInfo, TransportDeclick};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TransportSaveState {
+    #[cfg_attr(feature = "serde", serde(with = "dropseed_plugin_api::serde_impls::musical_time"))]
     pub seek_to: MusicalTime,
     pub loop_state: LoopState,
 }
@@ -73,9 +76,35 @@ impl TransportHandle {
         self.playhead_musical
     }
 
+    /// Returns `true` if the transport is currently set to play.
+    pub(crate) fn is_playing(&self) -> bool {
+        self.parameters.get().is_playing
+    }
+
+    /// The current playhead position and looping state of the transport.
+    pub(crate) fn save_state(&mut self) -> TransportSaveState {
+        TransportSaveState {
+            seek_to: self.playhead_position(),
+            loop_state: self.parameters.get().loop_state.0,
+        }
+    }
+
     pub(crate) fn tempo_map_shared(&self) -> Shared<SharedCell<(Shared<TempoMap>, u64)>> {
         Shared::clone(&self.tempo_map_shared)
     }
+
+    /// Create another handle to the same transport.
+    pub(crate) fn clone_handle(&self) -> Self {
+        Self {
+            parameters: Shared::clone(&self.parameters),
+            tempo_map_shared: Shared::clone(&self.tempo_map_shared),
+            playhead_frame_shared: Arc::clone(&self.playhead_frame_shared),
+            playhead_frame: self.playhead_frame,
+            playhead_musical: self.playhead_musical,
+            seek_to: self.seek_to,
+            coll_handle: self.coll_handle.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -126,6 +155,7 @@ pub struct TransportTask {
 impl TransportTask {
     pub fn new(
         save_state: Option<TransportSaveState>,
+        tempo_map: Option<TempoMap>,
         sample_rate: SampleRate,
         max_frames: usize,
         declick_time: Option<Seconds>,
@@ -145,7 +175,7 @@ impl TransportTask {
             )),
         );
 
-        let tempo_map = TempoMap::new(120.0, 4, 4, sample_rate);
+        let tempo_map = tempo_map.unwrap_or_else(|| TempoMap::new(120.0, 4, 4, sample_rate));
 
         let playhead_frame = tempo_map.musical_to_nearest_frame_round(save_state.seek_to);
         let playhead_frame_shared = Arc::new(AtomicU64::new(playhead_frame.0));
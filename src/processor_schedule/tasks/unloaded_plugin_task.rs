@@ -2,11 +2,13 @@ use smallvec::SmallVec;
 
 use dropseed_plugin_api::automation::AutomationIoEvent;
 use dropseed_plugin_api::buffer::SharedBuffer;
-use dropseed_plugin_api::ProcInfo;
+use dropseed_plugin_api::{PluginInstanceID, ProcInfo};
 
 use crate::plugin_host::event_io_buffers::NoteIoEvent;
 
 pub(crate) struct UnloadedPluginTask {
+    pub plugin_id: PluginInstanceID,
+
     pub audio_through: SmallVec<[(SharedBuffer<f32>, SharedBuffer<f32>); 4]>,
     pub note_through: Option<(SharedBuffer<NoteIoEvent>, SharedBuffer<NoteIoEvent>)>,
 
@@ -1,2 +1,3 @@
 pub mod reducing_queue;
 pub mod thread_id;
+pub mod wake_signal;
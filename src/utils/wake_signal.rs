@@ -0,0 +1,160 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use std::time::Duration;
+
+#[cfg(all(not(any(target_os = "linux", target_os = "android")), not(target_os = "windows")))]
+static FALLBACK_POLL_INTERVAL: Duration = Duration::from_micros(100);
+#[cfg(all(not(any(target_os = "linux", target_os = "android")), not(target_os = "windows")))]
+// Handle worst-case scenario for thread sleep.
+static FALLBACK_POLL_INTERVAL_BUFFERED: Duration = Duration::from_micros(140);
+#[cfg(target_os = "windows")]
+// The best we can do on Windows is around 1ms.
+static FALLBACK_POLL_INTERVAL: Duration = Duration::from_micros(1200);
+#[cfg(target_os = "windows")]
+// Handle worst-case scenario for Windows thread sleep.
+static FALLBACK_POLL_INTERVAL_BUFFERED: Duration = Duration::from_micros(1500);
+
+/// A realtime-safe signal used to wake up a thread that is waiting on
+/// another thread.
+///
+/// On Linux and Android this is backed by a futex, so waiting never
+/// involves a lock and notifying is a single atomic operation when there
+/// is no waiter. On other platforms this falls back to polling with a
+/// short sleep.
+///
+/// To avoid missing a notification, get a token with `Self::token()`
+/// *before* checking the condition being waited on, and then pass that
+/// token to `Self::wait()`.
+pub struct WakeSignal {
+    seq: AtomicU32,
+    num_waiters: AtomicU32,
+}
+
+impl WakeSignal {
+    pub fn new() -> Self {
+        Self { seq: AtomicU32::new(0), num_waiters: AtomicU32::new(0) }
+    }
+
+    /// Get the token to pass to `Self::wait()`.
+    pub fn token(&self) -> u32 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    /// Wake up any thread that is waiting on this signal.
+    pub fn notify(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+
+        if self.num_waiters.load(Ordering::SeqCst) > 0 {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            futex::wake_all(&self.seq);
+        }
+    }
+
+    /// Block the current thread until `Self::notify()` has been called
+    /// since `token` was retrieved, or until `deadline` has passed.
+    ///
+    /// This may also return spuriously, so the caller should always check
+    /// the condition being waited on again.
+    ///
+    /// This returns `false` if the deadline has already passed (or if there
+    /// is not enough time left to wait on this platform), in which case
+    /// the caller should stop waiting.
+    pub fn wait(&self, token: u32, deadline: Option<Instant>) -> bool {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let timeout = if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return false;
+                }
+
+                Some(deadline - now)
+            } else {
+                None
+            };
+
+            self.num_waiters.fetch_add(1, Ordering::SeqCst);
+
+            if self.seq.load(Ordering::SeqCst) == token {
+                futex::wait(&self.seq, token, timeout);
+            }
+
+            self.num_waiters.fetch_sub(1, Ordering::SeqCst);
+
+            true
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            if let Some(deadline) = deadline {
+                if Instant::now() + FALLBACK_POLL_INTERVAL_BUFFERED >= deadline {
+                    return false;
+                }
+            }
+
+            if self.seq.load(Ordering::SeqCst) == token {
+                #[cfg(not(target_os = "windows"))]
+                std::thread::sleep(FALLBACK_POLL_INTERVAL);
+
+                #[cfg(target_os = "windows")]
+                spin_sleep::SpinSleeper::default().sleep(FALLBACK_POLL_INTERVAL);
+            }
+
+            true
+        }
+    }
+}
+
+impl Default for WakeSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for WakeSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WakeSignal")
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod futex {
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    pub(super) fn wait(futex: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        let timespec = timeout.map(|t| libc::timespec {
+            tv_sec: t.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_nsec: t.subsec_nanos() as libc::c_long,
+        });
+        let timespec_ptr =
+            timespec.as_ref().map(|t| t as *const libc::timespec).unwrap_or(std::ptr::null());
+
+        // This returns early with `EAGAIN` if the value no longer matches
+        // `expected`, with `ETIMEDOUT` if the timeout elapsed, or with
+        // `EINTR` on a spurious wakeup. The caller handles all of these by
+        // checking its condition again, so the result is ignored.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                futex as *const AtomicU32 as *const u32,
+                libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                expected,
+                timespec_ptr,
+            );
+        }
+    }
+
+    pub(super) fn wake_all(futex: &AtomicU32) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                futex as *const AtomicU32 as *const u32,
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                i32::MAX,
+            );
+        }
+    }
+}